@@ -0,0 +1,771 @@
+//! Estimate print time, filament usage and model dimensions from G-code.
+//!
+//! The print time estimation plans moves like the firmware does: each move accelerates and
+//! decelerates with a constant acceleration, and the speed at the junction between two moves
+//! is limited by the angle between them (junction deviation, as in Grbl and Marlin).
+
+use std::f64::consts::PI;
+use std::io::BufRead;
+use std::time::Duration;
+
+use super::{parse_line, Command};
+
+/// Machine parameters used for the analysis.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnalyzerSettings {
+    /// Acceleration, in mm/s².
+    pub acceleration: f64,
+    /// Maximum speed, in mm/s.
+    pub max_speed: f64,
+    /// Feed rate used before the first `F` parameter, in mm/min.
+    pub default_feedrate: f64,
+    /// Junction deviation, in mm.
+    pub junction_deviation: f64,
+    /// Filament diameter, in mm.
+    pub filament_diameter: f64,
+}
+
+impl Default for AnalyzerSettings {
+    fn default() -> Self {
+        AnalyzerSettings {
+            acceleration: 1000.0,
+            max_speed: 300.0,
+            default_feedrate: 3000.0,
+            junction_deviation: 0.05,
+            filament_diameter: 1.75,
+        }
+    }
+}
+
+impl AnalyzerSettings {
+    /// Check the values are usable: the acceleration, maximum speed and filament diameter must
+    /// be positive.
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("acceleration", self.acceleration),
+            ("maximum speed", self.max_speed),
+            ("filament diameter", self.filament_diameter),
+        ] {
+            if !(value.is_finite() && value > 0.0) {
+                return Err(format!("Invalid {} {}, must be positive", name, value));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Number of tools handled, the commands for higher tool numbers are ignored.
+pub const MAX_TOOLS: usize = 16;
+
+/// Axis-aligned box, coordinates in mm.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoundingBox {
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+}
+
+impl BoundingBox {
+    fn point(p: [f64; 3]) -> Self {
+        BoundingBox { min: p, max: p }
+    }
+
+    fn extend(&mut self, p: [f64; 3]) {
+        for (i, v) in p.iter().enumerate() {
+            self.min[i] = self.min[i].min(*v);
+            self.max[i] = self.max[i].max(*v);
+        }
+    }
+
+    /// Width, depth and height.
+    pub fn size(&self) -> [f64; 3] {
+        [
+            self.max[0] - self.min[0],
+            self.max[1] - self.min[1],
+            self.max[2] - self.min[2],
+        ]
+    }
+}
+
+/// Filament used by one tool.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FilamentUsage {
+    /// Length, in mm.
+    pub length: f64,
+    /// Volume, in cm³.
+    pub volume: f64,
+}
+
+/// Result of the analysis of a G-code file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Analysis {
+    /// Estimated print time.
+    pub print_time: Duration,
+    /// Filament usage, indexed by tool number.
+    pub filament: Vec<FilamentUsage>,
+    /// Box containing all extruding moves.
+    pub bounding_box: Option<BoundingBox>,
+    /// Box containing the end points of all moves, including travel moves.
+    pub extents: Option<BoundingBox>,
+    /// Largest distance of the extruding moves from X0 Y0 (the center of circular beds).
+    pub radius: Option<f64>,
+    /// Largest distance of the end points of all moves from X0 Y0.
+    pub extents_radius: Option<f64>,
+    /// Height of each layer (Z of the extruding moves), in increasing order.
+    pub layers: Vec<f64>,
+    /// Number of retractions.
+    pub retractions: u32,
+    /// Number of G-code commands.
+    pub commands: usize,
+    /// Tools selected or used for extrusion, in increasing order.
+    pub tools: Vec<usize>,
+    /// Highest extruder target temperature, indexed by tool number (0.0 if never set).
+    pub tool_temperatures: Vec<f64>,
+    /// Highest bed target temperature.
+    pub bed_temperature: Option<f64>,
+    /// Highest chamber target temperature.
+    pub chamber_temperature: Option<f64>,
+}
+
+impl Analysis {
+    pub fn layer_count(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Thickness of the first layer.
+    pub fn first_layer_height(&self) -> Option<f64> {
+        self.layers.first().copied()
+    }
+
+    /// Most common thickness of the layers above the first.
+    pub fn layer_height(&self) -> Option<f64> {
+        let mut counts: Vec<(f64, usize)> = Vec::new();
+        for pair in self.layers.windows(2) {
+            let height = round(pair[1] - pair[0], 1000.0);
+            match counts.iter_mut().find(|(h, _)| *h == height) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((height, 1)),
+            }
+        }
+        counts
+            .into_iter()
+            .max_by_key(|(_, n)| *n)
+            .map(|(height, _)| height)
+    }
+
+    pub fn total_filament(&self) -> FilamentUsage {
+        self.filament
+            .iter()
+            .fold(FilamentUsage::default(), |acc, f| FilamentUsage {
+                length: acc.length + f.length,
+                volume: acc.volume + f.volume,
+            })
+    }
+}
+
+fn extend_box(bounding_box: &mut Option<BoundingBox>, p: [f64; 3]) {
+    match bounding_box {
+        Some(b) => b.extend(p),
+        None => *bounding_box = Some(BoundingBox::point(p)),
+    }
+}
+
+fn max_option(current: Option<f64>, value: f64) -> Option<f64> {
+    Some(current.map_or(value, |c| c.max(value)))
+}
+
+fn round(value: f64, precision: f64) -> f64 {
+    (value * precision).round() / precision
+}
+
+/// A move to be planned.
+#[derive(Clone, Debug)]
+struct Block {
+    distance: f64,
+    /// Requested speed, in mm/s.
+    nominal_speed: f64,
+    /// Highest possible speed at the start of the block, in mm/s.
+    max_entry_speed: f64,
+}
+
+/// Streaming G-code analyzer, feed it lines with `process_line()` and call `finish()`.
+#[derive(Debug)]
+pub struct Analyzer {
+    settings: AnalyzerSettings,
+    position: [f64; 3],
+    extruder: f64,
+    absolute: bool,
+    absolute_extrusion: bool,
+    /// Unit scale, 1.0 for mm, 25.4 for inches.
+    scale: f64,
+    /// Feed rate in mm/s.
+    feedrate: f64,
+    tool: usize,
+    blocks: Vec<Block>,
+    /// Unit vector of the previous XYZ move, if the machine did not stop since.
+    previous_direction: Option<[f64; 3]>,
+    dwell: f64,
+    analysis: Analysis,
+}
+
+impl Analyzer {
+    pub fn new(settings: AnalyzerSettings) -> Self {
+        let feedrate = settings.default_feedrate / 60.0;
+        Analyzer {
+            settings,
+            position: [0.0; 3],
+            extruder: 0.0,
+            absolute: true,
+            absolute_extrusion: true,
+            scale: 1.0,
+            feedrate,
+            tool: 0,
+            blocks: Vec::new(),
+            previous_direction: None,
+            dwell: 0.0,
+            analysis: Analysis::default(),
+        }
+    }
+
+    pub fn process_line(&mut self, line: &str) {
+        if let Some(cmd) = parse_line(line) {
+            self.process(&cmd);
+        }
+    }
+
+    pub fn process(&mut self, cmd: &Command) {
+        self.analysis.commands += 1;
+        match (cmd.letter, cmd.number) {
+            ('G', 0) | ('G', 1) => self.linear_move(cmd),
+            ('G', 2) | ('G', 3) => self.arc_move(cmd, cmd.number == 2),
+            ('G', 4) => {
+                self.stop();
+                let seconds =
+                    cmd.param('S').unwrap_or(0.0) + cmd.param('P').unwrap_or(0.0) / 1000.0;
+                self.dwell += seconds.max(0.0);
+            }
+            ('G', 20) => self.scale = 25.4,
+            ('G', 21) => self.scale = 1.0,
+            ('G', 28) => {
+                self.stop();
+                let all = !['X', 'Y', 'Z'].iter().any(|l| cmd.has_param(*l));
+                for (i, letter) in ['X', 'Y', 'Z'].iter().enumerate() {
+                    if all || cmd.has_param(*letter) {
+                        self.position[i] = 0.0;
+                    }
+                }
+            }
+            ('G', 90) => {
+                self.absolute = true;
+                self.absolute_extrusion = true;
+            }
+            ('G', 91) => {
+                self.absolute = false;
+                self.absolute_extrusion = false;
+            }
+            ('G', 92) => {
+                for (i, letter) in ['X', 'Y', 'Z'].iter().enumerate() {
+                    if let Some(v) = cmd.param(*letter) {
+                        self.position[i] = v * self.scale;
+                    }
+                }
+                if let Some(e) = cmd.param('E') {
+                    self.extruder = e * self.scale;
+                }
+                if cmd.params.is_empty() {
+                    self.position = [0.0; 3];
+                    self.extruder = 0.0;
+                }
+            }
+            ('M', 82) => self.absolute_extrusion = true,
+            ('M', 83) => self.absolute_extrusion = false,
+            ('M', 104) | ('M', 109) => {
+                if let Some(t) = cmd.param('S').or(cmd.param('R')) {
+                    let tool = cmd.param('T').map_or(self.tool, |t| t as usize);
+                    if tool >= MAX_TOOLS {
+                        return;
+                    }
+                    let temperatures = &mut self.analysis.tool_temperatures;
+                    if temperatures.len() <= tool {
+                        temperatures.resize(tool + 1, 0.0);
+                    }
+                    temperatures[tool] = temperatures[tool].max(t);
+                }
+            }
+            ('M', 140) | ('M', 190) => {
+                if let Some(t) = cmd.param('S').or(cmd.param('R')) {
+                    self.analysis.bed_temperature = max_option(self.analysis.bed_temperature, t);
+                }
+            }
+            ('M', 141) | ('M', 191) => {
+                if let Some(t) = cmd.param('S').or(cmd.param('R')) {
+                    self.analysis.chamber_temperature =
+                        max_option(self.analysis.chamber_temperature, t);
+                }
+            }
+            ('T', n) if (n as usize) < MAX_TOOLS => {
+                self.stop();
+                self.tool = n as usize;
+                self.use_tool();
+            }
+            _ => {}
+        }
+    }
+
+    fn use_tool(&mut self) {
+        if let Err(idx) = self.analysis.tools.binary_search(&self.tool) {
+            self.analysis.tools.insert(idx, self.tool);
+        }
+    }
+
+    fn set_feedrate(&mut self, cmd: &Command) {
+        if let Some(f) = cmd.param('F') {
+            if f > 0.0 {
+                self.feedrate = f * self.scale / 60.0;
+            }
+        }
+    }
+
+    /// Target position of a move command, axes which would overflow do not move.
+    fn target(&self, cmd: &Command) -> [f64; 3] {
+        let mut target = self.position;
+        for (i, letter) in ['X', 'Y', 'Z'].iter().enumerate() {
+            if let Some(v) = cmd.param(*letter) {
+                let v = v * self.scale;
+                let v = if self.absolute { v } else { target[i] + v };
+                if v.is_finite() {
+                    target[i] = v;
+                }
+            }
+        }
+        target
+    }
+
+    /// Extruder movement of a move command.
+    fn extrusion(&mut self, cmd: &Command) -> f64 {
+        match cmd.param('E') {
+            Some(e) => {
+                let e = e * self.scale;
+                let delta = if self.absolute_extrusion {
+                    e - self.extruder
+                } else {
+                    e
+                };
+                self.extruder += delta;
+                delta
+            }
+            None => 0.0,
+        }
+    }
+
+    fn linear_move(&mut self, cmd: &Command) {
+        self.set_feedrate(cmd);
+        let target = self.target(cmd);
+        let extrusion = self.extrusion(cmd);
+        self.add_segment(target, extrusion);
+    }
+
+    fn arc_move(&mut self, cmd: &Command, clockwise: bool) {
+        self.set_feedrate(cmd);
+        let start = self.position;
+        let target = self.target(cmd);
+        let extrusion = self.extrusion(cmd);
+
+        // Center of the arc, from I/J offsets or from the radius
+        let center = match (cmd.param('I'), cmd.param('J'), cmd.param('R')) {
+            (None, None, Some(r)) => {
+                let r = r * self.scale;
+                let (dx, dy) = (target[0] - start[0], target[1] - start[1]);
+                let d = (dx * dx + dy * dy).sqrt();
+                if d == 0.0 || d > 2.0 * r.abs() {
+                    self.add_segment(target, extrusion);
+                    return;
+                }
+                let h = (r * r - d * d / 4.0).sqrt();
+                let sign = if clockwise == (r > 0.0) { -1.0 } else { 1.0 };
+                [
+                    start[0] + dx / 2.0 - sign * h * dy / d,
+                    start[1] + dy / 2.0 + sign * h * dx / d,
+                ]
+            }
+            (i, j, _) => [
+                start[0] + i.unwrap_or(0.0) * self.scale,
+                start[1] + j.unwrap_or(0.0) * self.scale,
+            ],
+        };
+
+        let radius = ((start[0] - center[0]).powi(2) + (start[1] - center[1]).powi(2)).sqrt();
+        let start_angle = (start[1] - center[1]).atan2(start[0] - center[0]);
+        let end_angle = (target[1] - center[1]).atan2(target[0] - center[0]);
+        let mut sweep = end_angle - start_angle;
+        if clockwise && sweep >= 0.0 {
+            sweep -= 2.0 * PI;
+        } else if !clockwise && sweep <= 0.0 {
+            sweep += 2.0 * PI;
+        }
+
+        // Split the arc in segments of about 1mm
+        let segments = ((sweep.abs() * radius).ceil() as usize).clamp(1, 720);
+        for s in 1..=segments {
+            let fraction = s as f64 / segments as f64;
+            let point = if s == segments {
+                target
+            } else {
+                let angle = start_angle + sweep * fraction;
+                [
+                    center[0] + radius * angle.cos(),
+                    center[1] + radius * angle.sin(),
+                    start[2] + (target[2] - start[2]) * fraction,
+                ]
+            };
+            self.add_segment(point, extrusion / segments as f64);
+        }
+    }
+
+    /// The machine comes to a full stop.
+    fn stop(&mut self) {
+        self.previous_direction = None;
+    }
+
+    fn add_segment(&mut self, target: [f64; 3], extrusion: f64) {
+        let delta = [
+            target[0] - self.position[0],
+            target[1] - self.position[1],
+            target[2] - self.position[2],
+        ];
+        let distance = (delta[0] * delta[0] + delta[1] * delta[1] + delta[2] * delta[2]).sqrt();
+        let nominal_speed = self.feedrate.min(self.settings.max_speed);
+
+        // Filament
+        if extrusion != 0.0 {
+            self.use_tool();
+            if self.analysis.filament.len() <= self.tool {
+                self.analysis
+                    .filament
+                    .resize(self.tool + 1, FilamentUsage::default());
+            }
+            let usage = &mut self.analysis.filament[self.tool];
+            usage.length += extrusion;
+            let radius = self.settings.filament_diameter / 2.0;
+            usage.volume += extrusion * PI * radius * radius / 1000.0;
+        }
+
+        if distance == 0.0 {
+            if extrusion != 0.0 {
+                // Extruder only move (retraction or priming)
+                if extrusion < 0.0 {
+                    self.analysis.retractions += 1;
+                }
+                self.stop();
+                self.blocks.push(Block {
+                    distance: extrusion.abs(),
+                    nominal_speed,
+                    max_entry_speed: 0.0,
+                });
+            }
+            return;
+        }
+
+        // Bounding box and layers
+        extend_box(&mut self.analysis.extents, target);
+        let radius = |p: [f64; 3]| (p[0] * p[0] + p[1] * p[1]).sqrt();
+        self.analysis.extents_radius = max_option(self.analysis.extents_radius, radius(target));
+        if extrusion > 0.0 && (delta[0] != 0.0 || delta[1] != 0.0) {
+            extend_box(&mut self.analysis.bounding_box, self.position);
+            extend_box(&mut self.analysis.bounding_box, target);
+            // The farthest point of a straight move is one of its ends
+            let farthest = radius(self.position).max(radius(target));
+            self.analysis.radius = max_option(self.analysis.radius, farthest);
+            let z = round(target[2], 1000.0);
+            if let Err(idx) = self.analysis.layers.binary_search_by(|l| l.total_cmp(&z)) {
+                self.analysis.layers.insert(idx, z);
+            }
+        }
+
+        // Junction speed with the previous move
+        let direction = [
+            delta[0] / distance,
+            delta[1] / distance,
+            delta[2] / distance,
+        ];
+        let max_entry_speed = match self.previous_direction {
+            Some(previous) => {
+                let previous_speed = self.blocks.last().map_or(0.0, |b| b.nominal_speed);
+                let cos_theta = -(previous[0] * direction[0]
+                    + previous[1] * direction[1]
+                    + previous[2] * direction[2]);
+                let limit = nominal_speed.min(previous_speed);
+                if cos_theta <= -0.999999 {
+                    // Straight line
+                    limit
+                } else if cos_theta >= 0.999999 {
+                    // Reversal
+                    0.0
+                } else {
+                    let sin_theta_d2 = (0.5 * (1.0 - cos_theta)).sqrt();
+                    let v2 = self.settings.acceleration
+                        * self.settings.junction_deviation
+                        * sin_theta_d2
+                        / (1.0 - sin_theta_d2);
+                    v2.sqrt().min(limit)
+                }
+            }
+            None => 0.0,
+        };
+
+        self.blocks.push(Block {
+            distance,
+            nominal_speed,
+            max_entry_speed,
+        });
+        self.previous_direction = Some(direction);
+        self.position = target;
+    }
+
+    /// Plan all moves and return the result.
+    pub fn finish(mut self) -> Analysis {
+        let accel = self.settings.acceleration;
+        let n = self.blocks.len();
+        let mut entry: Vec<f64> = self.blocks.iter().map(|b| b.max_entry_speed).collect();
+
+        // Backward pass, each block must be able to decelerate to the next entry speed
+        let mut exit = 0.0;
+        for i in (0..n).rev() {
+            let b = &self.blocks[i];
+            entry[i] = entry[i].min((exit * exit + 2.0 * accel * b.distance).sqrt());
+            exit = entry[i];
+        }
+        // Forward pass, each block must be able to accelerate from its entry speed
+        for i in 0..n.saturating_sub(1) {
+            let b = &self.blocks[i];
+            let reachable = (entry[i] * entry[i] + 2.0 * accel * b.distance).sqrt();
+            entry[i + 1] = entry[i + 1].min(reachable);
+        }
+
+        let mut time = self.dwell;
+        for (i, b) in self.blocks.iter().enumerate() {
+            let v0 = entry[i].min(b.nominal_speed);
+            let v1 = entry
+                .get(i + 1)
+                .copied()
+                .unwrap_or(0.0)
+                .min(b.nominal_speed);
+            time += trapezoid_time(b.distance, v0, b.nominal_speed, v1, accel);
+        }
+
+        // Not finite with invalid settings, see `AnalyzerSettings::validate()`
+        self.analysis.print_time = Duration::try_from_secs_f64(time).unwrap_or_default();
+        self.analysis
+    }
+}
+
+/// Time to travel `distance` starting at `v0`, cruising at most at `v` and ending at `v1`.
+fn trapezoid_time(distance: f64, v0: f64, v: f64, v1: f64, accel: f64) -> f64 {
+    if v <= 0.0 {
+        return 0.0;
+    }
+    let accel_distance = (v * v - v0 * v0) / (2.0 * accel);
+    let decel_distance = (v * v - v1 * v1) / (2.0 * accel);
+    if accel_distance + decel_distance <= distance {
+        (v - v0) / accel + (v - v1) / accel + (distance - accel_distance - decel_distance) / v
+    } else {
+        // Triangle profile, the nominal speed is never reached
+        let peak = ((2.0 * accel * distance + v0 * v0 + v1 * v1) / 2.0).sqrt();
+        (peak - v0) / accel + (peak - v1) / accel
+    }
+}
+
+/// Analyze G-code read from `reader`, fails with `InvalidInput` if the settings are invalid.
+pub fn analyze<R: BufRead>(reader: R, settings: AnalyzerSettings) -> std::io::Result<Analysis> {
+    settings
+        .validate()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut analyzer = Analyzer::new(settings);
+    for line in reader.split(b'\n') {
+        analyzer.process_line(&String::from_utf8_lossy(&line?));
+    }
+    Ok(analyzer.finish())
+}
+
+/// Analyze G-code given as a string.
+pub fn analyze_str(gcode: &str, settings: AnalyzerSettings) -> Analysis {
+    let mut analyzer = Analyzer::new(settings);
+    gcode.lines().for_each(|l| analyzer.process_line(l));
+    analyzer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_trapezoid_time() {
+        // Reaches 10mm/s after 0.1s and 0.5mm, cruise 9mm in 0.9s, decelerates in 0.1s.
+        assert_close(trapezoid_time(10.0, 0.0, 10.0, 0.0, 100.0), 1.1);
+        // Too short to reach nominal speed: peak at 10mm/s
+        assert_close(trapezoid_time(1.0, 0.0, 100.0, 0.0, 100.0), 0.2);
+        // Already at speed
+        assert_close(trapezoid_time(10.0, 10.0, 10.0, 10.0, 100.0), 1.0);
+    }
+
+    #[test]
+    fn test_single_move_time() {
+        let settings = AnalyzerSettings {
+            acceleration: 100.0,
+            ..Default::default()
+        };
+        let analysis = analyze_str("G1 X10 F600", settings);
+
+        assert_close(analysis.print_time.as_secs_f64(), 1.1);
+    }
+
+    #[test]
+    fn test_straight_line_keeps_speed() {
+        let settings = AnalyzerSettings {
+            acceleration: 100.0,
+            ..Default::default()
+        };
+        let analysis = analyze_str("G1 X5 F600\nG1 X10", settings.clone());
+
+        // Same as a single 10mm move
+        assert_close(analysis.print_time.as_secs_f64(), 1.1);
+
+        // A reversal forces a full stop
+        let analysis = analyze_str("G1 X10 F600\nG1 X0", settings);
+        assert_close(analysis.print_time.as_secs_f64(), 2.2);
+    }
+
+    #[test]
+    fn test_filament_and_bounding_box() {
+        let gcode = "G21\nG90\nM82\nG92 E0\n\
+            G1 Z0.3 F600\n\
+            G1 X10 Y10 F3000\n\
+            G1 X60 Y10 E5\n\
+            G1 X60 Y50 E9\n\
+            G1 E7 ; retract\n\
+            G1 Z0.5\n\
+            G1 E9 ; prime\n\
+            G1 X10 Y50 E14\n\
+            G92 E0\n\
+            G1 Z0.7\n\
+            G1 X10 Y10 E4\n\
+            G1 X100 Y100 ; travel\n";
+        let analysis = analyze_str(gcode, AnalyzerSettings::default());
+
+        assert_close(analysis.filament[0].length, 18.0);
+        let area = PI * 0.875 * 0.875;
+        assert_close(analysis.filament[0].volume, 18.0 * area / 1000.0);
+        assert_eq!(analysis.retractions, 1);
+
+        let bb = analysis.bounding_box.clone().unwrap();
+        assert_eq!(bb.min, [10.0, 10.0, 0.3]);
+        assert_eq!(bb.max, [60.0, 50.0, 0.7]);
+        assert_eq!(analysis.layers, vec![0.3, 0.5, 0.7]);
+        assert_eq!(analysis.extents.as_ref().unwrap().max, [100.0, 100.0, 0.7]);
+        assert_eq!(analysis.first_layer_height(), Some(0.3));
+        assert_eq!(analysis.layer_height(), Some(0.2));
+    }
+
+    #[test]
+    fn test_relative_extrusion_and_tools() {
+        let gcode = "M83\nG1 X10 E1\nG1 X20 E1\nT1\nG1 X30 E2.5\nG91\nG1 X10 E1\n";
+        let analysis = analyze_str(gcode, AnalyzerSettings::default());
+
+        assert_eq!(analysis.filament.len(), 2);
+        assert_close(analysis.filament[0].length, 2.0);
+        assert_close(analysis.filament[1].length, 3.5);
+        assert_close(analysis.total_filament().length, 5.5);
+        assert_eq!(analysis.bounding_box.unwrap().max[0], 40.0);
+    }
+
+    #[test]
+    fn test_temperatures() {
+        let gcode = "M140 S60\nM104 S200\nM190 S65\nM109 S215\nM104 T1 S240\nT1\nM104 S0\n";
+        let analysis = analyze_str(gcode, AnalyzerSettings::default());
+
+        assert_eq!(analysis.bed_temperature, Some(65.0));
+        assert_eq!(analysis.chamber_temperature, None);
+        assert_eq!(analysis.tool_temperatures, vec![215.0, 240.0]);
+        assert_eq!(analysis.tools, vec![1]);
+
+        // Unrealistic tool numbers are ignored
+        let gcode = "M104 T4000000000 S200\nT4000000000\nG1 X10 E1\n";
+        let analysis = analyze_str(gcode, AnalyzerSettings::default());
+        assert!(analysis.tool_temperatures.is_empty());
+        assert_eq!(analysis.tools, vec![0]);
+        assert_eq!(analysis.filament.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        for settings in [
+            AnalyzerSettings {
+                acceleration: 0.0,
+                ..Default::default()
+            },
+            AnalyzerSettings {
+                acceleration: -100.0,
+                ..Default::default()
+            },
+            AnalyzerSettings {
+                filament_diameter: f64::NAN,
+                ..Default::default()
+            },
+        ] {
+            assert!(settings.validate().is_err(), "{:?}", settings);
+            assert!(analyze("G1 X10 F600\n".as_bytes(), settings.clone()).is_err());
+            // Does not panic when used anyway
+            analyze_str("G1 X10 F600\n", settings);
+        }
+        assert!(AnalyzerSettings::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_overflowing_coordinates() {
+        // Parsed as infinity, then a sum which overflows
+        let infinite = "9".repeat(400);
+        let huge = format!("1{}", "0".repeat(308));
+        let gcode = format!(
+            "G91\nG1 X10 Z{0} E1\nG1 X10 Z-{0} E1\nG1 X10 Z{1} E1\nG1 X10 Z{1} E1\nG1 X10 E1",
+            infinite, huge
+        );
+        let analysis = analyze_str(&gcode, AnalyzerSettings::default());
+        let bb = analysis.bounding_box.unwrap();
+        assert_close(bb.max[0], 50.0);
+        assert_eq!(bb.min[2], 0.0);
+        assert_eq!(bb.max[2], 1e308);
+    }
+
+    #[test]
+    fn test_arc() {
+        // Half circle of radius 10 from (0,0) to (20,0), clockwise through (10,10)
+        let analysis = analyze_str(
+            "G1 X0 Y0 F600\nG2 X20 Y0 I10 J0 E1",
+            AnalyzerSettings::default(),
+        );
+        let bb = analysis.bounding_box.unwrap();
+        assert_close(bb.max[1], 10.0);
+        assert_close(bb.min[1], 0.0);
+        assert_close(bb.max[0], 20.0);
+
+        // Same arc given by its radius
+        let analysis = analyze_str(
+            "G1 X0 Y0 F600\nG2 X20 Y0 R10 E1",
+            AnalyzerSettings::default(),
+        );
+        assert_close(analysis.bounding_box.unwrap().max[1], 10.0);
+    }
+
+    #[test]
+    fn test_dwell_and_units() {
+        let analysis = analyze_str("G4 P500\nG4 S2", AnalyzerSettings::default());
+        assert_close(analysis.print_time.as_secs_f64(), 2.5);
+
+        let analysis = analyze_str("G20\nG1 X1 Y1 E0.1", AnalyzerSettings::default());
+        assert_close(analysis.bounding_box.unwrap().max[0], 25.4);
+        assert_close(analysis.filament[0].length, 2.54);
+    }
+}
@@ -0,0 +1,354 @@
+//! Check a G-code file against the printer profile before printing it.
+
+use std::fmt;
+
+use super::analysis::{Analysis, BoundingBox};
+use crate::octoprintclient::datamodel::Profile;
+
+/// Distance a move may exceed the build volume before being reported, in mm.
+const TOLERANCE: f64 = 0.01;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "INFO",
+            Severity::Warning => "WARNING",
+            Severity::Error => "ERROR",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Result of `check()`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CheckReport {
+    pub findings: Vec<Finding>,
+}
+
+impl CheckReport {
+    fn add(&mut self, severity: Severity, message: String) {
+        self.findings.push(Finding { severity, message });
+    }
+
+    /// True if there is no error (warnings are allowed).
+    pub fn is_ok(&self) -> bool {
+        !self.has(Severity::Error)
+    }
+
+    pub fn has(&self, severity: Severity) -> bool {
+        self.findings.iter().any(|f| f.severity == severity)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Finding> {
+        self.findings
+            .iter()
+            .filter(|f| f.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Finding> {
+        self.findings
+            .iter()
+            .filter(|f| f.severity == Severity::Warning)
+    }
+}
+
+/// Describe where the moves exceed the printable area, if they do.
+///
+/// `distance` is the largest distance of the moves from X0 Y0, used for circular beds centered
+/// on the origin (as OctoPrint sets them up), instead of the corners of the box.
+fn out_of_bounds(b: &BoundingBox, distance: Option<f64>, profile: &Profile) -> Vec<String> {
+    let mut problems = Vec::new();
+    let bounds = profile.volume.bounds();
+
+    if profile.volume.is_circular() {
+        let radius = profile.volume.width / 2.0;
+        let (cx, cy) = (
+            (bounds.x_min + bounds.x_max) / 2.0,
+            (bounds.y_min + bounds.y_max) / 2.0,
+        );
+        let farthest = match distance {
+            Some(distance) if cx.abs() < TOLERANCE && cy.abs() < TOLERANCE => distance,
+            // Off-center bed: the corners of the box are an upper bound
+            _ => [
+                (b.min[0], b.min[1]),
+                (b.min[0], b.max[1]),
+                (b.max[0], b.min[1]),
+                (b.max[0], b.max[1]),
+            ]
+            .iter()
+            .map(|(x, y)| ((x - cx).powi(2) + (y - cy).powi(2)).sqrt())
+            .fold(0.0, f64::max),
+        };
+        if farthest > radius + TOLERANCE {
+            problems.push(format!(
+                "reaches {:.1} mm from the center, bed radius is {:.1} mm",
+                farthest, radius
+            ));
+        }
+    } else {
+        let limits = [
+            ('X', bounds.x_min, bounds.x_max),
+            ('Y', bounds.y_min, bounds.y_max),
+        ];
+        for (i, (axis, min, max)) in limits.iter().enumerate() {
+            if b.min[i] < min - TOLERANCE || b.max[i] > max + TOLERANCE {
+                problems.push(format!(
+                    "{} {:.1}..{:.1} outside of {:.1}..{:.1}",
+                    axis, b.min[i], b.max[i], min, max
+                ));
+            }
+        }
+    }
+    if b.min[2] < bounds.z_min - TOLERANCE || b.max[2] > bounds.z_max + TOLERANCE {
+        problems.push(format!(
+            "Z {:.1}..{:.1} outside of {:.1}..{:.1}",
+            b.min[2], b.max[2], bounds.z_min, bounds.z_max
+        ));
+    }
+    problems
+}
+
+/// Check the analysis of a G-code file against a printer profile.
+///
+/// Reports an error when the model does not fit in the build volume, when tools missing
+/// on the printer are used, or when the bed/chamber is heated but the printer has none.
+/// Travel moves outside of the volume and unusual settings are reported as warnings.
+pub fn check(analysis: &Analysis, profile: &Profile) -> CheckReport {
+    let mut report = CheckReport::default();
+
+    // Build volume
+    match &analysis.bounding_box {
+        Some(model) => {
+            for problem in out_of_bounds(model, analysis.radius, profile) {
+                report.add(
+                    Severity::Error,
+                    format!("Model exceeds the build volume: {}", problem),
+                );
+            }
+            if let Some(extents) = &analysis.extents {
+                for problem in out_of_bounds(extents, analysis.extents_radius, profile) {
+                    report.add(
+                        Severity::Warning,
+                        format!("Moves exceed the build volume: {}", problem),
+                    );
+                }
+            }
+            let size = model.size();
+            report.add(
+                Severity::Info,
+                format!(
+                    "Model is {:.1} x {:.1} x {:.1} mm, build volume is {:.1} x {:.1} x {:.1} mm",
+                    size[0],
+                    size[1],
+                    size[2],
+                    profile.volume.width,
+                    profile.volume.depth,
+                    profile.volume.height
+                ),
+            );
+        }
+        None => report.add(
+            Severity::Warning,
+            "File does not extrude anything".to_string(),
+        ),
+    }
+
+    // Tools
+    let extruders = profile.extruder.count as usize;
+    let heated_tools = analysis
+        .tool_temperatures
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| **t > 0.0)
+        .map(|(tool, _)| tool);
+    let highest_tool = analysis.tools.iter().copied().chain(heated_tools).max();
+    if let Some(tool) = highest_tool {
+        if tool >= extruders {
+            report.add(
+                Severity::Error,
+                format!(
+                    "Uses tool {} but the printer has {} extruder(s)",
+                    tool, extruders
+                ),
+            );
+        }
+    }
+
+    // Temperatures
+    if analysis.tool_temperatures.iter().all(|t| *t <= 0.0) {
+        report.add(
+            Severity::Warning,
+            "No extruder temperature is set".to_string(),
+        );
+    }
+    for (tool, t) in analysis.tool_temperatures.iter().enumerate() {
+        if *t > 300.0 {
+            report.add(
+                Severity::Warning,
+                format!("Tool {} is heated to {:.0}°C", tool, t),
+            );
+        }
+    }
+    match analysis.bed_temperature {
+        Some(t) if t > 0.0 && !profile.heated_bed => report.add(
+            Severity::Error,
+            format!(
+                "Bed is heated to {:.0}°C but the printer has no heated bed",
+                t
+            ),
+        ),
+        Some(t) if t > 120.0 => {
+            report.add(Severity::Warning, format!("Bed is heated to {:.0}°C", t))
+        }
+        _ => {}
+    }
+    if let Some(t) = analysis.chamber_temperature {
+        if t > 0.0 && !profile.heated_chamber {
+            report.add(
+                Severity::Error,
+                format!(
+                    "Chamber is heated to {:.0}°C but the printer has no heated chamber",
+                    t
+                ),
+            );
+        }
+    }
+
+    // Layers
+    let nozzle = profile.extruder.nozzle_diameter;
+    if let Some(height) = analysis.layer_height() {
+        if height > nozzle * 0.8 {
+            report.add(
+                Severity::Warning,
+                format!(
+                    "Layer height {:.2} mm is too thick for a {:.2} mm nozzle",
+                    height, nozzle
+                ),
+            );
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode::analysis::{analyze_str, AnalyzerSettings};
+    use crate::octoprintclient::datamodel::ProfileList;
+
+    fn profiles() -> ProfileList {
+        serde_json::from_str(include_str!("../../tests/responses/printerprofiles.json")).unwrap()
+    }
+
+    fn square(x: f64, y: f64, size: f64) -> String {
+        format!(
+            "M140 S60\nM104 S210\nG28\nG1 Z0.2\nG1 X{x} Y{y}\nG1 X{x2} Y{y} E1\nG1 X{x2} Y{y2} E2\n\
+             G1 Z0.4\nG1 X{x} Y{y2} E3\nG1 X{x} Y{y} E4\n",
+            x = x,
+            y = y,
+            x2 = x + size,
+            y2 = y + size
+        )
+    }
+
+    #[test]
+    fn test_check_ok() {
+        let profile = &profiles().profiles["_default"];
+        let analysis = analyze_str(&square(50.0, 50.0, 100.0), AnalyzerSettings::default());
+        let report = check(&analysis, profile);
+
+        assert!(report.is_ok(), "{:?}", report);
+        assert!(!report.has(Severity::Warning), "{:?}", report);
+    }
+
+    #[test]
+    fn test_check_out_of_volume() {
+        let profile = &profiles().profiles["_default"];
+        let analysis = analyze_str(&square(150.0, 50.0, 100.0), AnalyzerSettings::default());
+        let report = check(&analysis, profile);
+
+        assert!(!report.is_ok());
+        let error = report.errors().next().unwrap();
+        assert!(
+            error.message.contains("X 150.0..250.0"),
+            "{}",
+            error.message
+        );
+
+        // Travel only outside of the volume is a warning
+        let gcode = square(50.0, 50.0, 100.0) + "G1 X-10 Y0\n";
+        let report = check(&analyze_str(&gcode, AnalyzerSettings::default()), profile);
+        assert!(report.is_ok());
+        assert!(report.has(Severity::Warning));
+    }
+
+    #[test]
+    fn test_check_delta() {
+        let profile = &profiles().profiles["delta"];
+
+        // Fits in the box, but not in the circle
+        let analysis = analyze_str(&square(-80.0, -80.0, 160.0), AnalyzerSettings::default());
+        let report = check(&analysis, profile);
+        assert!(!report.is_ok());
+        // Heated bed on a printer without
+        assert_eq!(report.errors().count(), 2, "{:?}", report);
+
+        let analysis = analyze_str(
+            &square(-50.0, -50.0, 100.0).replace("M140 S60", "M140 S0"),
+            AnalyzerSettings::default(),
+        );
+        assert!(check(&analysis, profile).is_ok());
+    }
+
+    #[test]
+    fn test_check_delta_circle() {
+        let profile = &profiles().profiles["delta"];
+        // Disc of the size of the bed: its bounding box corners are outside of the circle
+        let circle = |radius: f64| {
+            let mut gcode = format!("M104 S210\nG28\nG1 Z0.2\nG1 X{} Y0\n", radius);
+            for i in 1..=72 {
+                let angle = f64::from(i) * 5.0_f64.to_radians();
+                gcode += &format!(
+                    "G1 X{:.3} Y{:.3} E{}\n",
+                    radius * angle.cos(),
+                    radius * angle.sin(),
+                    i
+                );
+            }
+            gcode
+        };
+
+        let analysis = analyze_str(&circle(84.0), AnalyzerSettings::default());
+        let report = check(&analysis, profile);
+        assert!(report.is_ok(), "{:?}", report);
+        assert!(!report.has(Severity::Warning), "{:?}", report);
+
+        let analysis = analyze_str(&circle(90.0), AnalyzerSettings::default());
+        let report = check(&analysis, profile);
+        let error = report.errors().next().unwrap();
+        assert!(error.message.contains("90.0 mm"), "{}", error.message);
+    }
+
+    #[test]
+    fn test_check_tools() {
+        let profile = &profiles().profiles["_default"];
+        let gcode = square(50.0, 50.0, 100.0) + "T1\nG1 X60 Y60 E5\n";
+        let report = check(&analyze_str(&gcode, AnalyzerSettings::default()), profile);
+
+        assert!(!report.is_ok());
+        assert!(report.errors().any(|e| e.message.contains("tool 1")));
+    }
+}
@@ -0,0 +1,400 @@
+//! Metadata written by the slicers in the comments of the G-code.
+//!
+//! PrusaSlicer, SuperSlicer and OrcaSlicer write `; key = value` lines, mostly at the end of
+//! the file. Cura writes `;KEY:value` lines in the header and Simplify3D writes its settings as
+//! `;   key,value` in the header and a build summary at the end.
+
+use std::fmt;
+use std::io::BufRead;
+use std::time::Duration;
+
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Slicer {
+    PrusaSlicer,
+    SuperSlicer,
+    OrcaSlicer,
+    Cura,
+    Simplify3D,
+}
+
+impl Slicer {
+    /// Identify the slicer from the line naming it, e.g. `; generated by PrusaSlicer 2.6.0`.
+    fn from_line(line: &str) -> Option<(Slicer, Option<String>)> {
+        let slicers = [
+            ("PrusaSlicer", Slicer::PrusaSlicer),
+            ("SuperSlicer", Slicer::SuperSlicer),
+            ("OrcaSlicer", Slicer::OrcaSlicer),
+            ("Cura_SteamEngine", Slicer::Cura),
+            ("Simplify3D(R) Version", Slicer::Simplify3D),
+        ];
+        slicers.iter().find_map(|(name, slicer)| {
+            let rest = &line[line.find(name)? + name.len()..];
+            let version = rest
+                .split_whitespace()
+                .next()
+                .map(|v| v.split('+').next().unwrap_or(v).to_string());
+            Some((*slicer, version))
+        })
+    }
+}
+
+impl fmt::Display for Slicer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Slicer::PrusaSlicer => "PrusaSlicer",
+            Slicer::SuperSlicer => "SuperSlicer",
+            Slicer::OrcaSlicer => "OrcaSlicer",
+            Slicer::Cura => "Cura",
+            Slicer::Simplify3D => "Simplify3D",
+        })
+    }
+}
+
+/// Metadata found in a G-code file, fields are `None` when the slicer did not write them.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub slicer: Option<Slicer>,
+    pub slicer_version: Option<String>,
+    pub printer_model: Option<String>,
+    /// G-code flavor, e.g. "marlin2", "RepRap".
+    pub flavor: Option<String>,
+    /// Print time estimated by the slicer.
+    #[serde(
+        default,
+        with = "crate::octoprintclient::datamodel::optional_seconds",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub estimated_time: Option<Duration>,
+    /// Filament length, in mm.
+    pub filament_length: Option<f64>,
+    /// Filament weight, in g.
+    pub filament_weight: Option<f64>,
+    pub filament_type: Option<String>,
+    /// Layer height, in mm.
+    pub layer_height: Option<f64>,
+    /// First layer height, in mm.
+    pub first_layer_height: Option<f64>,
+    /// Nozzle diameter, in mm.
+    pub nozzle_diameter: Option<f64>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Metadata::default()
+    }
+
+    /// Handle one line of the file, lines which are not comments are ignored.
+    pub fn process_line(&mut self, line: &str) {
+        let comment = match line.trim().strip_prefix(';') {
+            Some(comment) => comment.trim(),
+            None => return,
+        };
+
+        if self.slicer.is_none() {
+            if let Some((slicer, version)) = Slicer::from_line(comment) {
+                self.slicer = Some(slicer);
+                self.slicer_version = version;
+                return;
+            }
+        }
+
+        // PrusaSlicer style: "key = value"
+        if let Some((key, value)) = comment.split_once(" = ") {
+            self.set_prusa(key.trim(), value.trim());
+        // Cura style: "KEY:value"
+        } else if let Some((key, value)) = comment.split_once(':') {
+            self.set_cura(key.trim(), value.trim());
+        // Simplify3D settings: "key,value"
+        } else if let Some((key, value)) = comment.split_once(',') {
+            self.set_simplify3d(key.trim(), value.trim());
+        }
+    }
+
+    fn set_prusa(&mut self, key: &str, value: &str) {
+        match key {
+            "estimated printing time (normal mode)" => self.estimated_time = parse_time(value),
+            "filament used [mm]" => self.filament_length = parse_sum(value),
+            "filament used [g]" => self.filament_weight = parse_sum(value),
+            "filament_type" => self.filament_type = Some(value.replace(';', ", ")),
+            "layer_height" => self.layer_height = parse_first(value),
+            "first_layer_height" => self.first_layer_height = parse_first(value),
+            "nozzle_diameter" => self.nozzle_diameter = parse_first(value),
+            "printer_model" if !value.is_empty() => self.printer_model = Some(value.to_string()),
+            "gcode_flavor" => self.flavor = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn set_cura(&mut self, key: &str, value: &str) {
+        match key {
+            "FLAVOR" => self.flavor = Some(value.to_string()),
+            "TIME" => {
+                self.estimated_time = value
+                    .parse()
+                    .ok()
+                    .and_then(|s| Duration::try_from_secs_f64(s).ok())
+            }
+            "Filament used" => {
+                // In meters, one value per extruder
+                self.filament_length = parse_sum(&value.replace('m', "")).map(|m| m * 1000.0)
+            }
+            "Layer height" => self.layer_height = value.parse().ok(),
+            "PRINTER_MODEL" | "TARGET_MACHINE.NAME" => self.printer_model = Some(value.to_string()),
+            "EXTRUDER_TRAIN.0.NOZZLE.DIAMETER" => self.nozzle_diameter = value.parse().ok(),
+            // Simplify3D build summary
+            "Build time" => self.estimated_time = parse_time(value),
+            "Filament length" => self.filament_length = parse_first(value),
+            "Plastic weight" => self.filament_weight = parse_first(value),
+            // OrcaSlicer header
+            "total estimated time" => self.estimated_time = parse_time(value),
+            _ => {}
+        }
+        // OrcaSlicer: "model printing time: 1h 2m; total estimated time: 1h 5m"
+        if let Some(total) = value
+            .split(';')
+            .find_map(|part| part.trim().strip_prefix("total estimated time:"))
+        {
+            self.estimated_time = parse_time(total);
+        }
+    }
+
+    fn set_simplify3d(&mut self, key: &str, value: &str) {
+        match key {
+            "layerHeight" => self.layer_height = parse_first(value),
+            "firstLayerHeightPercentage" => {
+                if let (Some(height), Some(percent)) = (self.layer_height, parse_first(value)) {
+                    self.first_layer_height = Some(height * percent / 100.0);
+                }
+            }
+            "extruderDiameter" => self.nozzle_diameter = parse_first(value),
+            "filamentTypes" => {
+                self.filament_type = Some(value.split(',').next().unwrap_or(value).to_string())
+            }
+            "printerModel" => self.printer_model = Some(value.to_string()),
+            _ => {}
+        }
+    }
+}
+
+/// First number of a list like "0.4,0.4" or "1234.5 mm (1.23 m)".
+fn parse_first(value: &str) -> Option<f64> {
+    value
+        .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+        .find(|v| !v.is_empty())?
+        .parse()
+        .ok()
+}
+
+/// Sum of a list like "1234.5, 56.7", one value per extruder.
+fn parse_sum(value: &str) -> Option<f64> {
+    value
+        .split([',', ';'])
+        .map(|v| v.trim().parse::<f64>().ok())
+        .sum()
+}
+
+/// Parse a time like "1d 2h 3m 4s" or "1 hours 2 minutes".
+pub fn parse_time(value: &str) -> Option<Duration> {
+    let mut seconds = 0.0;
+    let mut found = false;
+    let mut words = value.split_whitespace().peekable();
+    while let Some(word) = words.next() {
+        let split = word
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(word.len());
+        let (number, mut unit) = word.split_at(split);
+        let number: f64 = match number.parse() {
+            Ok(n) => n,
+            Err(_) => continue,
+        };
+        if unit.is_empty() {
+            unit = words.next().unwrap_or("s");
+        }
+        let factor = match unit.chars().next() {
+            Some('d') => 86400.0,
+            Some('h') => 3600.0,
+            Some('m') => 60.0,
+            Some('s') => 1.0,
+            _ => continue,
+        };
+        seconds += number * factor;
+        found = true;
+    }
+    // Huge values do not fit in a duration
+    found
+        .then(|| Duration::try_from_secs_f64(seconds).ok())
+        .flatten()
+}
+
+/// Read the metadata of a G-code file.
+pub fn extract_metadata<R: BufRead>(reader: R) -> std::io::Result<Metadata> {
+    let mut metadata = Metadata::default();
+    for line in reader.split(b'\n') {
+        let line = line?;
+        if line.first() == Some(&b';') {
+            metadata.process_line(&String::from_utf8_lossy(&line));
+        }
+    }
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(gcode: &str) -> Metadata {
+        extract_metadata(gcode.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("1h 2m 3s"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_time("1d 0h 5m 0s"), Some(Duration::from_secs(86700)));
+        assert_eq!(
+            parse_time("2 hours 12 minutes"),
+            Some(Duration::from_secs(7920))
+        );
+        assert_eq!(parse_time("45s"), Some(Duration::from_secs(45)));
+        assert_eq!(parse_time("unknown"), None);
+        assert_eq!(parse_time(&format!("{}d", "9".repeat(400))), None);
+    }
+
+    #[test]
+    fn test_prusaslicer() {
+        let m = metadata(
+            "; generated by PrusaSlicer 2.6.1+linux-x64-GTK3 on 2023-09-27 at 12:01:02 UTC\n\
+             ;\n\
+             G28\n\
+             G1 X10 E1 ; move\n\
+             ; filament used [mm] = 1234.56, 100.44\n\
+             ; filament used [g] = 3.70\n\
+             ; estimated printing time (normal mode) = 1h 2m 3s\n\
+             ; estimated printing time (silent mode) = 1h 10m 0s\n\
+             ; prusaslicer_config = begin\n\
+             ; filament_type = PLA;PETG\n\
+             ; first_layer_height = 0.2\n\
+             ; gcode_flavor = marlin2\n\
+             ; layer_height = 0.15\n\
+             ; nozzle_diameter = 0.4,0.4\n\
+             ; printer_model = MK3S\n\
+             ; prusaslicer_config = end\n",
+        );
+        assert_eq!(m.slicer, Some(Slicer::PrusaSlicer));
+        assert_eq!(m.slicer_version.as_deref(), Some("2.6.1"));
+        assert_eq!(m.printer_model.as_deref(), Some("MK3S"));
+        assert_eq!(m.flavor.as_deref(), Some("marlin2"));
+        assert_eq!(m.estimated_time, Some(Duration::from_secs(3723)));
+        assert_eq!(m.filament_length, Some(1335.0));
+        assert_eq!(m.filament_weight, Some(3.7));
+        assert_eq!(m.filament_type.as_deref(), Some("PLA, PETG"));
+        assert_eq!(m.layer_height, Some(0.15));
+        assert_eq!(m.first_layer_height, Some(0.2));
+        assert_eq!(m.nozzle_diameter, Some(0.4));
+
+        // Invalid times are ignored
+        let cura = |time: &str| {
+            metadata(&format!(
+                ";FLAVOR:Marlin\n;TIME:{}\n;Generated with Cura_SteamEngine 5.2.1\n",
+                time
+            ))
+        };
+        assert_eq!(cura("60").estimated_time, Some(Duration::from_secs(60)));
+        for time in ["-1", "1e300", "NaN", "inf"] {
+            let m = cura(time);
+            assert_eq!(m.estimated_time, None, "{}", time);
+        }
+    }
+
+    #[test]
+    fn test_superslicer_orcaslicer() {
+        let m = metadata(
+            "; generated by SuperSlicer 2.5.59 on 2023-03-01 at 10:00:00 UTC\n\
+             ; estimated printing time (normal mode) = 25m 3s\n\
+             ; layer_height = 0.2\n",
+        );
+        assert_eq!(m.slicer, Some(Slicer::SuperSlicer));
+        assert_eq!(m.estimated_time, Some(Duration::from_secs(1503)));
+
+        let m = metadata(
+            "; HEADER_BLOCK_START\n\
+             ; generated by OrcaSlicer 1.8.0 on 2023-11-02 at 20:15:00\n\
+             ; total layer number: 120\n\
+             ; HEADER_BLOCK_END\n\
+             ; model printing time: 1h 1m 2s; total estimated time: 1h 5m 2s\n\
+             ; filament used [g] = 12.34\n\
+             ; filament_type = PETG\n\
+             ; printer_model = Bambu Lab X1 Carbon\n",
+        );
+        assert_eq!(m.slicer, Some(Slicer::OrcaSlicer));
+        assert_eq!(m.slicer_version.as_deref(), Some("1.8.0"));
+        assert_eq!(m.estimated_time, Some(Duration::from_secs(3902)));
+        assert_eq!(m.filament_weight, Some(12.34));
+        assert_eq!(m.printer_model.as_deref(), Some("Bambu Lab X1 Carbon"));
+    }
+
+    #[test]
+    fn test_cura() {
+        let m = metadata(
+            ";FLAVOR:Marlin\n\
+             ;TIME:6666\n\
+             ;Filament used: 1.23456m, 0.5m\n\
+             ;Layer height: 0.2\n\
+             ;Generated with Cura_SteamEngine 5.2.1\n\
+             ;TARGET_MACHINE.NAME:Creality Ender-3\n\
+             ;EXTRUDER_TRAIN.0.NOZZLE.DIAMETER:0.4\n\
+             M140 S60\n\
+             ;TIME_ELAPSED:10.5\n",
+        );
+        assert_eq!(m.slicer, Some(Slicer::Cura));
+        assert_eq!(m.slicer_version.as_deref(), Some("5.2.1"));
+        assert_eq!(m.flavor.as_deref(), Some("Marlin"));
+        assert_eq!(m.estimated_time, Some(Duration::from_secs(6666)));
+        assert_eq!(m.filament_length.map(|l| l.round()), Some(1735.0));
+        assert_eq!(m.layer_height, Some(0.2));
+        assert_eq!(m.printer_model.as_deref(), Some("Creality Ender-3"));
+        assert_eq!(m.nozzle_diameter, Some(0.4));
+    }
+
+    #[test]
+    fn test_simplify3d() {
+        let m = metadata(
+            "; G-Code generated by Simplify3D(R) Version 4.1.2\n\
+             ; Jun 10, 2021 at 9:42:03 PM\n\
+             ; Settings Summary\n\
+             ;   extruderDiameter,0.4\n\
+             ;   layerHeight,0.2\n\
+             ;   firstLayerHeightPercentage,150\n\
+             ;   filamentTypes,PLA\n\
+             G28\n\
+             ; Build Summary\n\
+             ;   Build time: 2 hours 12 minutes\n\
+             ;   Filament length: 5404.2 mm (5.40 m)\n\
+             ;   Plastic volume: 12998.47 mm^3 (13.00 cc)\n\
+             ;   Plastic weight: 16.25 g (0.04 lb)\n",
+        );
+        assert_eq!(m.slicer, Some(Slicer::Simplify3D));
+        assert_eq!(m.slicer_version.as_deref(), Some("4.1.2"));
+        assert_eq!(m.nozzle_diameter, Some(0.4));
+        assert_eq!(m.layer_height, Some(0.2));
+        assert_eq!(
+            m.first_layer_height.map(|h| (h * 100.0).round()),
+            Some(30.0)
+        );
+        assert_eq!(m.filament_type.as_deref(), Some("PLA"));
+        assert_eq!(m.estimated_time, Some(Duration::from_secs(7920)));
+        assert_eq!(m.filament_length, Some(5404.2));
+        assert_eq!(m.filament_weight, Some(16.25));
+    }
+
+    #[test]
+    fn test_userdata_round_trip() {
+        let m = metadata(";FLAVOR:Marlin\n;TIME:60\n;Generated with Cura_SteamEngine 5.2.1\n");
+        let json = serde_json::to_value(&m).unwrap();
+        assert_eq!(json["slicer"], "Cura");
+        assert_eq!(json["estimated_time"], 60.0);
+        assert_eq!(serde_json::from_value::<Metadata>(json).unwrap(), m);
+        assert!(metadata("G28\n").is_empty());
+    }
+}
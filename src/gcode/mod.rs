@@ -0,0 +1,146 @@
+//! Offline G-code parsing and analysis.
+
+use std::fmt;
+
+pub mod analysis;
+pub mod check;
+pub mod metadata;
+pub mod thumbnail;
+
+/// A single G-code command, e.g. `G1 X10 Y20 E0.5 F1200`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Command {
+    /// Command letter, upper case ('G', 'M', 'T', ...).
+    pub letter: char,
+    pub number: u32,
+    /// Parameters in order of appearance, letters are upper case.
+    pub params: Vec<(char, f64)>,
+}
+
+impl Command {
+    pub fn is(&self, letter: char, number: u32) -> bool {
+        self.letter == letter && self.number == number
+    }
+
+    pub fn param(&self, letter: char) -> Option<f64> {
+        self.params
+            .iter()
+            .find(|(l, _)| *l == letter)
+            .map(|(_, v)| *v)
+    }
+
+    pub fn has_param(&self, letter: char) -> bool {
+        self.params.iter().any(|(l, _)| *l == letter)
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.letter, self.number)?;
+        for (letter, value) in &self.params {
+            write!(f, " {}{}", letter, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Remove the comments (`; ...` and `( ... )`) from a line.
+pub fn strip_comments(line: &str) -> String {
+    let line = line.split(';').next().unwrap_or("");
+    let mut result = String::with_capacity(line.len());
+    let mut in_comment = false;
+    for c in line.chars() {
+        match c {
+            '(' => in_comment = true,
+            ')' => in_comment = false,
+            c if !in_comment => result.push(c),
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Parse a line of G-code, returns `None` for empty lines, comments and unparsable lines.
+///
+/// Line numbers (`N123`) and checksums (`*45`) are ignored, as are the parameters which are not
+/// a finite number.
+pub fn parse_line(line: &str) -> Option<Command> {
+    let code = strip_comments(line);
+    let code = code.split('*').next().unwrap_or("");
+
+    let mut words = Vec::new();
+    let mut chars = code.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        if !c.is_ascii_alphabetic() {
+            continue;
+        }
+        let mut value = String::new();
+        while let Some(&d) = chars.peek() {
+            if d.is_ascii_digit() || d == '.' || d == '-' || d == '+' {
+                value.push(d);
+                chars.next();
+            } else if d == ' ' && value.is_empty() {
+                // Allow "G1 X 10"
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        words.push((c.to_ascii_uppercase(), value));
+    }
+
+    let mut words = words.into_iter().skip_while(|(l, _)| *l == 'N');
+    let (letter, number) = words.next()?;
+    let number = number.split('.').next()?.parse::<u32>().ok()?;
+    let params = words
+        .filter_map(|(l, v)| Some((l, if v.is_empty() { 0.0 } else { v.parse().ok()? })))
+        .filter(|(_, v)| f64::is_finite(*v))
+        .collect();
+
+    Some(Command {
+        letter,
+        number,
+        params,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let cmd = parse_line("G1 X10.5 Y-2 E0.25 F1200 ; move").unwrap();
+        assert!(cmd.is('G', 1));
+        assert_eq!(cmd.param('X'), Some(10.5));
+        assert_eq!(cmd.param('Y'), Some(-2.0));
+        assert_eq!(cmd.param('E'), Some(0.25));
+        assert_eq!(cmd.param('F'), Some(1200.0));
+        assert_eq!(cmd.param('Z'), None);
+    }
+
+    #[test]
+    fn test_parse_line_variants() {
+        assert_eq!(parse_line("; only a comment"), None);
+        assert_eq!(parse_line("   "), None);
+
+        let cmd = parse_line("N12 g28 x y*57").unwrap();
+        assert!(cmd.is('G', 28));
+        assert!(cmd.has_param('X'));
+        assert!(cmd.has_param('Y'));
+        assert!(!cmd.has_param('Z'));
+
+        let cmd = parse_line("M104 (set temp) S210").unwrap();
+        assert!(cmd.is('M', 104));
+        assert_eq!(cmd.param('S'), Some(210.0));
+
+        let cmd = parse_line("T1").unwrap();
+        assert!(cmd.is('T', 1));
+        assert_eq!(cmd.to_string(), "T1");
+
+        // Overflows to infinity
+        let cmd = parse_line(&format!("G1 X1 Z{}", "9".repeat(400))).unwrap();
+        assert_eq!(cmd.param('X'), Some(1.0));
+        assert!(!cmd.has_param('Z'));
+    }
+}
@@ -0,0 +1,404 @@
+//! Thumbnails embedded in G-code files by the slicers.
+//!
+//! PrusaSlicer, SuperSlicer and Cura write the thumbnails as base64 comments before the
+//! first command:
+//!
+//! ```text
+//! ; thumbnail begin 16x16 412
+//! ; iVBORw0KGgoAAAANSUhEUgAAABAAAAAQCAYAAAAf8/9hAAAA...
+//! ; thumbnail end
+//! ```
+//!
+//! The block is called `thumbnail_QOI` or `thumbnail_JPG` for the other image formats.
+
+use std::fmt::{self, Write as _};
+use std::io::{self, BufRead, Cursor};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use image::imageops::FilterType;
+use image::{ImageFormat, RgbaImage};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThumbnailFormat {
+    Png,
+    Jpg,
+    Qoi,
+}
+
+impl ThumbnailFormat {
+    fn from_keyword(keyword: &str) -> Option<ThumbnailFormat> {
+        match keyword {
+            "thumbnail" => Some(ThumbnailFormat::Png),
+            "thumbnail_JPG" => Some(ThumbnailFormat::Jpg),
+            "thumbnail_QOI" => Some(ThumbnailFormat::Qoi),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ThumbnailFormat::Png => "png",
+            ThumbnailFormat::Jpg => "jpg",
+            ThumbnailFormat::Qoi => "qoi",
+        }
+    }
+
+    fn image_format(&self) -> ImageFormat {
+        match self {
+            ThumbnailFormat::Png => ImageFormat::Png,
+            ThumbnailFormat::Jpg => ImageFormat::Jpeg,
+            ThumbnailFormat::Qoi => ImageFormat::Qoi,
+        }
+    }
+}
+
+impl fmt::Display for ThumbnailFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// A thumbnail, `data` is the encoded image file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    pub format: ThumbnailFormat,
+    pub data: Vec<u8>,
+}
+
+impl Thumbnail {
+    pub fn decode(&self) -> image::ImageResult<RgbaImage> {
+        Ok(image::load_from_memory_with_format(&self.data, self.format.image_format())?.to_rgba8())
+    }
+
+    /// The image encoded in `format`, converted if needed.
+    pub fn encode(&self, format: ThumbnailFormat) -> image::ImageResult<Vec<u8>> {
+        if format == self.format {
+            return Ok(self.data.clone());
+        }
+        let mut image = image::DynamicImage::ImageRgba8(self.decode()?);
+        if format == ThumbnailFormat::Jpg {
+            // JPEG has no alpha channel
+            image = image::DynamicImage::ImageRgb8(image.to_rgb8());
+        }
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, format.image_format())?;
+        Ok(data.into_inner())
+    }
+}
+
+/// Parse the `; thumbnail begin WxH LEN` line.
+fn parse_begin(comment: &str) -> Option<(ThumbnailFormat, u32, u32)> {
+    let mut words = comment.split_whitespace();
+    let format = ThumbnailFormat::from_keyword(words.next()?)?;
+    if words.next()? != "begin" {
+        return None;
+    }
+    let (width, height) = words.next()?.split_once('x')?;
+    Some((format, width.parse().ok()?, height.parse().ok()?))
+}
+
+/// Read the thumbnails of a G-code file.
+///
+/// Only the header is read, the parsing stops at the first G-code command.
+/// Malformed thumbnails are skipped.
+pub fn extract_thumbnails<R: BufRead>(reader: R) -> io::Result<Vec<Thumbnail>> {
+    let mut thumbnails = Vec::new();
+    let mut current: Option<(ThumbnailFormat, u32, u32, String)> = None;
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let comment = match line.strip_prefix(';') {
+            Some(comment) => comment.trim(),
+            None => break,
+        };
+
+        if let Some((format, width, height, mut encoded)) = current.take() {
+            if comment.ends_with(" end")
+                && ThumbnailFormat::from_keyword(&comment[..comment.len() - 4]).is_some()
+            {
+                if let Ok(data) = BASE64.decode(&encoded) {
+                    thumbnails.push(Thumbnail {
+                        width,
+                        height,
+                        format,
+                        data,
+                    });
+                }
+            } else {
+                encoded.push_str(comment);
+                current = Some((format, width, height, encoded));
+            }
+        } else if let Some((format, width, height)) = parse_begin(comment) {
+            current = Some((format, width, height, String::new()));
+        }
+    }
+
+    Ok(thumbnails)
+}
+
+/// The smallest thumbnail at least `min_width` wide, or the largest one.
+pub fn best_thumbnail(thumbnails: &[Thumbnail], min_width: u32) -> Option<&Thumbnail> {
+    thumbnails
+        .iter()
+        .filter(|t| t.width >= min_width)
+        .min_by_key(|t| t.width)
+        .or_else(|| thumbnails.iter().max_by_key(|t| t.width))
+}
+
+/// How to draw images in the terminal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Graphics {
+    /// Upper half block characters with 24-bit colors, works in most terminals.
+    Blocks,
+    /// Kitty graphics protocol.
+    Kitty,
+    /// DEC sixel graphics.
+    Sixel,
+}
+
+impl Graphics {
+    pub const NAMES: [&'static str; 3] = ["blocks", "kitty", "sixel"];
+
+    pub fn from_name(name: &str) -> Option<Graphics> {
+        match name {
+            "blocks" => Some(Graphics::Blocks),
+            "kitty" => Some(Graphics::Kitty),
+            "sixel" => Some(Graphics::Sixel),
+            _ => None,
+        }
+    }
+}
+
+/// Blend a pixel on a black background.
+fn blend(pixel: &image::Rgba<u8>) -> [u8; 3] {
+    let alpha = pixel[3] as u16;
+    [0, 1, 2].map(|i| (pixel[i] as u16 * alpha / 255) as u8)
+}
+
+/// Render the image with `▀` characters, `columns` characters wide.
+pub fn render_blocks(image: &RgbaImage, columns: u32) -> String {
+    let columns = columns.min(image.width()).max(1);
+    // A character holds two square pixels, one above the other
+    let height = (image.height() * columns / image.width()).max(1);
+    let image = image::imageops::resize(image, columns, height, FilterType::Triangle);
+
+    let mut out = String::new();
+    for y in (0..image.height()).step_by(2) {
+        for x in 0..image.width() {
+            let [r, g, b] = blend(image.get_pixel(x, y));
+            if y + 1 < image.height() {
+                let [br, bg, bb] = blend(image.get_pixel(x, y + 1));
+                let _ = write!(
+                    out,
+                    "\x1b[38;2;{};{};{};48;2;{};{};{}m▀",
+                    r, g, b, br, bg, bb
+                );
+            } else {
+                // Last line of an odd height, on the default background
+                let _ = write!(out, "\x1b[38;2;{};{};{};49m▀", r, g, b);
+            }
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+/// Render a PNG file with the kitty graphics protocol.
+pub fn render_kitty(png: &[u8]) -> String {
+    let encoded = BASE64.encode(png);
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(4096).collect();
+    let mut out = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = if i + 1 < chunks.len() { 1 } else { 0 };
+        if i == 0 {
+            let _ = write!(out, "\x1b_Gf=100,a=T,m={};", more);
+        } else {
+            let _ = write!(out, "\x1b_Gm={};", more);
+        }
+        // Chunks of base64 are ASCII
+        out.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        out.push_str("\x1b\\");
+    }
+    out.push('\n');
+    out
+}
+
+/// Render the image as sixels, with a 6x6x6 color cube palette.
+pub fn render_sixel(image: &RgbaImage) -> String {
+    let level = |v: u8| (v as u16 * 5 / 255) as usize;
+    let index = |p: &image::Rgba<u8>| {
+        let [r, g, b] = blend(p);
+        level(r) * 36 + level(g) * 6 + level(b)
+    };
+
+    let mut out = String::from("\x1bPq");
+    let _ = write!(out, "\"1;1;{};{}", image.width(), image.height());
+    for i in 0..216 {
+        let (r, g, b) = (i / 36, i / 6 % 6, i % 6);
+        let _ = write!(out, "#{};2;{};{};{}", i, r * 20, g * 20, b * 20);
+    }
+
+    for band in (0..image.height()).step_by(6) {
+        let band_height = (image.height() - band).min(6);
+        let mut colors: Vec<usize> = (0..image.width())
+            .flat_map(|x| (0..band_height).map(move |dy| (x, band + dy)))
+            .map(|(x, y)| index(image.get_pixel(x, y)))
+            .collect();
+        colors.sort_unstable();
+        colors.dedup();
+
+        for color in colors {
+            let _ = write!(out, "#{}", color);
+            for x in 0..image.width() {
+                let bits = (0..band_height)
+                    .filter(|dy| index(image.get_pixel(x, band + dy)) == color)
+                    .fold(0u8, |bits, dy| bits | 1 << dy);
+                out.push((b'?' + bits) as char);
+            }
+            // Back to the start of the band
+            out.push('$');
+        }
+        // Next band
+        out.push('-');
+    }
+    out.push_str("\x1b\\\n");
+    out
+}
+
+/// Render the thumbnail in the terminal, `columns` is the width for the block rendering.
+pub fn render(
+    thumbnail: &Thumbnail,
+    graphics: Graphics,
+    columns: u32,
+) -> image::ImageResult<String> {
+    Ok(match graphics {
+        Graphics::Blocks => render_blocks(&thumbnail.decode()?, columns),
+        Graphics::Kitty => render_kitty(&thumbnail.encode(ThumbnailFormat::Png)?),
+        Graphics::Sixel => render_sixel(&thumbnail.decode()?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbaImage::from_fn(width, height, |x, _| {
+            if x < width / 2 {
+                image::Rgba([255, 0, 0, 255])
+            } else {
+                image::Rgba([0, 0, 255, 255])
+            }
+        });
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, ImageFormat::Png).unwrap();
+        data.into_inner()
+    }
+
+    fn gcode_with_thumbnail(keyword: &str, width: u32, height: u32, data: &[u8]) -> String {
+        let encoded = BASE64.encode(data);
+        let mut gcode = format!(
+            "; generated by PrusaSlicer\n\n; {} begin {}x{} {}\n",
+            keyword,
+            width,
+            height,
+            encoded.len()
+        );
+        for chunk in encoded.as_bytes().chunks(78) {
+            gcode += &format!("; {}\n", std::str::from_utf8(chunk).unwrap());
+        }
+        gcode += &format!("; {} end\n;\n", keyword);
+        gcode
+    }
+
+    #[test]
+    fn test_extract_thumbnails() {
+        let small = png(16, 12);
+        let large = png(64, 48);
+        let gcode = gcode_with_thumbnail("thumbnail", 16, 12, &small)
+            + &gcode_with_thumbnail("thumbnail", 64, 48, &large)
+            + "G28\n"
+            + &gcode_with_thumbnail("thumbnail", 8, 8, &small);
+
+        let thumbnails = extract_thumbnails(gcode.as_bytes()).unwrap();
+        assert_eq!(thumbnails.len(), 2);
+        assert_eq!(thumbnails[0].data, small);
+        assert_eq!((thumbnails[1].width, thumbnails[1].height), (64, 48));
+        assert_eq!(thumbnails[1].format, ThumbnailFormat::Png);
+
+        assert_eq!(best_thumbnail(&thumbnails, 32).unwrap().width, 64);
+        assert_eq!(best_thumbnail(&thumbnails, 10).unwrap().width, 16);
+        assert_eq!(best_thumbnail(&thumbnails, 100).unwrap().width, 64);
+
+        let image = thumbnails[0].decode().unwrap();
+        assert_eq!(image.dimensions(), (16, 12));
+        assert_eq!(image.get_pixel(0, 0), &image::Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_extract_other_formats() {
+        let thumbnail = Thumbnail {
+            width: 16,
+            height: 12,
+            format: ThumbnailFormat::Png,
+            data: png(16, 12),
+        };
+        let qoi = thumbnail.encode(ThumbnailFormat::Qoi).unwrap();
+        let jpg = thumbnail.encode(ThumbnailFormat::Jpg).unwrap();
+        let gcode = gcode_with_thumbnail("thumbnail_QOI", 16, 12, &qoi)
+            + &gcode_with_thumbnail("thumbnail_JPG", 16, 12, &jpg)
+            // Broken base64 is skipped
+            + "; thumbnail begin 4x4 10\n; !!!\n; thumbnail end\n";
+
+        let thumbnails = extract_thumbnails(gcode.as_bytes()).unwrap();
+        assert_eq!(thumbnails.len(), 2);
+        assert_eq!(thumbnails[0].format, ThumbnailFormat::Qoi);
+        assert_eq!(thumbnails[1].format, ThumbnailFormat::Jpg);
+
+        let image = thumbnails[0].decode().unwrap();
+        assert_eq!(image.get_pixel(15, 11), &image::Rgba([0, 0, 255, 255]));
+        assert_eq!(thumbnails[1].decode().unwrap().dimensions(), (16, 12));
+        assert_eq!(
+            thumbnails[0].encode(ThumbnailFormat::Png).unwrap()[1..4],
+            *b"PNG"
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let thumbnail = Thumbnail {
+            width: 16,
+            height: 12,
+            format: ThumbnailFormat::Png,
+            data: png(16, 12),
+        };
+
+        let blocks = render(&thumbnail, Graphics::Blocks, 8).unwrap();
+        // 8x6 pixels, two per character
+        assert_eq!(blocks.lines().count(), 3);
+        assert_eq!(blocks.lines().next().unwrap().matches('▀').count(), 8);
+        // Odd height: 5x3 pixels
+        let blocks = render_blocks(&thumbnail.decode().unwrap(), 5);
+        assert_eq!(blocks.lines().count(), 2);
+        assert_eq!(blocks.lines().last().unwrap().matches("49m▀").count(), 5);
+        assert!(blocks.starts_with("\x1b[38;2;255;0;0;48;2;255;0;0m▀"));
+
+        let kitty = render(&thumbnail, Graphics::Kitty, 8).unwrap();
+        assert!(kitty.starts_with("\x1b_Gf=100,a=T,m=0;iVBOR"));
+
+        let sixel = render(&thumbnail, Graphics::Sixel, 8).unwrap();
+        assert!(sixel.starts_with("\x1bPq\"1;1;16;12"));
+        // 2 bands of 6 pixels
+        assert_eq!(sixel.matches('-').count(), 2);
+        // Red: 5*36, blue: 5
+        assert!(sixel.contains("#180~~~~~~~~????????$"));
+        assert!(sixel.contains("#5????????~~~~~~~~$"));
+    }
+}
@@ -1,13 +1,126 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
 use anyhow::{anyhow, Context, Result};
-use clap::{command, value_parser, Arg, ArgAction, Command};
-use confy;
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::{command, value_parser, Arg, ArgAction, ArgGroup, ArgMatches, Command};
 use console::Style;
-use dialoguer::Input;
+use dialoguer::{Confirm, Input, Password};
 use time_humanize::HumanTime;
 
-mod octoprintclient;
-use octoprintclient::datamodel::ConnectionCommand;
-use octoprintclient::{Configuration, OctoPrintClient};
+use octoprint_client::gcode;
+use octoprint_client::gcode::analysis::{Analysis, AnalyzerSettings};
+use octoprint_client::gcode::check::{CheckReport, Severity};
+use octoprint_client::gcode::metadata::{self, Metadata};
+use octoprint_client::gcode::thumbnail::{self, Graphics, Thumbnail, ThumbnailFormat};
+use octoprint_client::octoprintclient::access::{parse_users_csv, ImportResult};
+use octoprint_client::octoprintclient::autoupload::{
+    AfterUpload, AutoUploadEvent, AutoUploadOptions,
+};
+use octoprint_client::octoprintclient::connection::{
+    ConnectionBuilder, DetectionEvent, KnownPort, KnownPorts, AUTO_BAUDRATE,
+};
+use octoprint_client::octoprintclient::datamodel::{FileEntry, SliceCommand, TimelapseConfig};
+use octoprint_client::octoprintclient::datamodel::{NewUser, UserUpdate};
+use octoprint_client::octoprintclient::datamodel::{State, TemperatureData};
+use octoprint_client::octoprintclient::events::PrinterEvent;
+use octoprint_client::octoprintclient::fleet::{Fleet, FleetResult, Outcome};
+#[cfg(feature = "history")]
+use octoprint_client::octoprintclient::history::{write_csv, History, HistoryFilter, JobResult};
+use octoprint_client::octoprintclient::hooks::{HookLog, HookRunner};
+#[cfg(feature = "mqtt")]
+use octoprint_client::octoprintclient::mqtt::{AllowList, Bridge, BridgeEvent, BridgeOptions};
+use octoprint_client::octoprintclient::queue::{
+    Constraints, PrintQueue, QueueFile, Scheduler, SchedulerEvent,
+};
+use octoprint_client::octoprintclient::sync::{SyncMode, SyncOptions};
+use octoprint_client::octoprintclient::wait::WaitOptions;
+use octoprint_client::octoprintclient::webcam::RecordOptions;
+use octoprint_client::octoprintclient::{
+    Configuration, OctoPrintClient, OctoPrintClientError, UploadOptions,
+};
+
+/// Exit code used by the `wait` sub-command when the timeout expires.
+const EXIT_TIMEOUT: i32 = 2;
+/// Exit code of `wait --job-done` when the job failed.
+const EXIT_JOB_FAILED: i32 = 3;
+/// Exit code of `wait --job-done` when the job was cancelled.
+const EXIT_JOB_CANCELLED: i32 = 4;
+
+/// Bytes downloaded from the start of a remote file to find its thumbnails.
+const THUMBNAIL_HEADER_SIZE: usize = 1 << 20;
+
+/// Width of the thumbnails drawn in the terminal, in characters.
+const THUMBNAIL_COLUMNS: u32 = 32;
+
+/// Formats accepted by `--time-format`.
+#[cfg(feature = "chrono")]
+const TIME_FORMATS: [&str; 3] = ["relative", "local", "iso"];
+#[cfg(not(feature = "chrono"))]
+const TIME_FORMATS: [&str; 1] = ["relative"];
+
+/// Format a point in time as requested by `--time-format`.
+fn format_time(time: SystemTime, format: &str) -> String {
+    match format {
+        #[cfg(feature = "chrono")]
+        "local" => chrono::DateTime::<chrono::Local>::from(time)
+            .format("at %Y-%m-%d %H:%M:%S")
+            .to_string(),
+        #[cfg(feature = "chrono")]
+        "iso" => format!(
+            "at {}",
+            chrono::DateTime::<chrono::Utc>::from(time)
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        ),
+        _ => {
+            let seconds = match time.duration_since(SystemTime::now()) {
+                Ok(d) => d.as_secs() as i64,
+                Err(e) => -(e.duration().as_secs() as i64),
+            };
+            HumanTime::from_seconds(seconds).to_string()
+        }
+    }
+}
+
+/// Parse a duration like "500ms", "60s", "5m", "1h", "7d" or "30" (seconds).
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (value, unit) = match s.find(|c: char| !(c.is_ascii_digit() || c == '.')) {
+        Some(idx) => s.split_at(idx),
+        None => (s, "s"),
+    };
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("Invalid duration \"{}\"", s))?;
+    let seconds = match unit {
+        "ms" => value / 1000.0,
+        "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        "d" => value * 86400.0,
+        _ => return Err(format!("Invalid duration unit \"{}\"", unit)),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("Invalid duration \"{}\"", s))
+}
+
+/// Parse a duration greater than 0, for the polling intervals.
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    match parse_duration(s)? {
+        Duration::ZERO => Err(format!(
+            "Invalid interval \"{}\", must be positive",
+            s.trim()
+        )),
+        interval => Ok(interval),
+    }
+}
+
+/// Parse a number greater than 0.
+fn parse_positive(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(value) if value.is_finite() && value > 0.0 => Ok(value),
+        _ => Err(format!("\"{}\" is not a positive number", s)),
+    }
+}
 
 async fn get_configuration() -> Result<Configuration> {
     // Try to get configuration using "confy"
@@ -23,6 +136,7 @@ async fn get_configuration() -> Result<Configuration> {
         let mut new_config = Configuration {
             server_url: "".to_string(),
             api_key: "".to_string(),
+            ..Default::default()
         };
         new_config.server_url = Input::new().with_prompt("Server URL").interact_text()?;
 
@@ -47,18 +161,376 @@ async fn get_configuration() -> Result<Configuration> {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Try to get configuration using "confy"
-    let cfg = get_configuration().await?;
-
     // Parse command line
-    let matches = command!()
+    let cli = command!()
+        .arg(
+            Arg::new("time-format")
+                .long("time-format")
+                .help("How to display the estimated end of print")
+                .value_parser(TIME_FORMATS)
+                .default_value("relative")
+                .global(true),
+        )
+        .arg(
+            Arg::new("thumbnail")
+                .long("thumbnail")
+                .help("Draw the thumbnail of the printed file in the terminal")
+                .value_parser(["none", "blocks", "kitty", "sixel"])
+                .default_value("none")
+                .global(true),
+        )
         .subcommand(
             Command::new("upload")
                 .about("Upload a file to Octoprint instance")
                 .arg(Arg::new("dir").short('d').help("Specify upload dir"))
-                .arg(Arg::new("file").required(true).help("File to upload")),
+                .arg(Arg::new("file").required(true).help("File to upload"))
+                .arg(
+                    Arg::new("check")
+                        .long("check")
+                        .help("Check the file against the printer profile before uploading")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Upload even if the check fails")
+                        .action(ArgAction::SetTrue)
+                        .requires("check"),
+                ),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Check a G-code file against the printer profile")
+                .arg(Arg::new("file").required(true).help("G-code file"))
+                .arg(
+                    Arg::new("profile")
+                        .short('t')
+                        .long("profile")
+                        .help("Printer profile to check against (default: current one)"),
+                ),
         )
         .subcommand(Command::new("connection").about("Print printer connection state"))
+        .subcommand(
+            Command::new("files")
+                .about("Manage the files stored on the server")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("ls").about("List files").arg(
+                        Arg::new("recursive")
+                            .short('r')
+                            .long("recursive")
+                            .help("Include the content of the folders")
+                            .action(ArgAction::SetTrue),
+                    ),
+                )
+                .subcommand(
+                    Command::new("info")
+                        .about("Show the information and slicer metadata of a file")
+                        .arg(Arg::new("path").required(true).help("Path on the server")),
+                ),
+        )
+        .subcommand(
+            Command::new("analyze")
+                .about("Estimate print time, filament usage and dimensions of a G-code file")
+                .arg(Arg::new("file").required(true).help("G-code file"))
+                .arg(
+                    Arg::new("acceleration")
+                        .short('a')
+                        .long("acceleration")
+                        .help("Printer acceleration in mm/s²")
+                        .value_parser(parse_positive)
+                        .default_value("1000"),
+                )
+                .arg(
+                    Arg::new("filament-diameter")
+                        .long("filament-diameter")
+                        .help("Filament diameter in mm")
+                        .value_parser(parse_positive)
+                        .default_value("1.75"),
+                ),
+        )
+        .subcommand(
+            Command::new("sync")
+                .about("Synchronize a local directory to a folder of the server")
+                .arg(Arg::new("local-dir").required(true).help("Local directory"))
+                .arg(
+                    Arg::new("remote-folder")
+                        .help("Folder on the server (default: root of the storage)")
+                        .default_value(""),
+                )
+                .arg(
+                    Arg::new("mirror")
+                        .long("mirror")
+                        .help("Delete the files of the server not present locally")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("include")
+                        .short('i')
+                        .long("include")
+                        .help("Only synchronize the files matching this pattern (e.g. \"*.gcode\")")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("exclude")
+                        .short('x')
+                        .long("exclude")
+                        .help("Ignore the files matching this pattern")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .short('n')
+                        .long("dry-run")
+                        .help("Only show what would be done")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("yes")
+                        .short('y')
+                        .long("yes")
+                        .help("Do not ask for confirmation")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("autoupload")
+                .about("Watch a directory and upload the files written into it")
+                .arg(Arg::new("dir").required(true).help("Directory to watch"))
+                .arg(
+                    Arg::new("remote-folder")
+                        .short('d')
+                        .long("remote-folder")
+                        .help("Folder of the server to upload to"),
+                )
+                .arg(
+                    Arg::new("select")
+                        .long("select")
+                        .help("Select the uploaded file if the printer is idle")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("print")
+                        .long("print")
+                        .help("Print the uploaded file if the printer is idle")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("select"),
+                )
+                .arg(
+                    Arg::new("archive")
+                        .short('a')
+                        .long("archive")
+                        .help("Move the uploaded files to this directory"),
+                )
+                .arg(
+                    Arg::new("settle")
+                        .long("settle")
+                        .help("Consider a file complete when its size did not change for this delay")
+                        .value_parser(parse_duration)
+                        .default_value("2s"),
+                )
+                .arg(
+                    Arg::new("include")
+                        .short('i')
+                        .long("include")
+                        .help("Only upload the files matching this pattern (default: *.gcode, *.gco, *.g)")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("existing")
+                        .long("existing")
+                        .help("Also upload the files already in the directory")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("fleet")
+                .about("Status and commands for all the printers of the configuration")
+                .subcommand(Command::new("status").about("Show the state of all printers (default)"))
+                .subcommand(
+                    Command::new("disconnect")
+                        .about("Disconnect all the printers without a job in progress")
+                        .arg(
+                            Arg::new("force")
+                                .long("force")
+                                .help("Also disconnect the printers with a job in progress, stopping the print")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("gcode")
+                        .about("Send G-code commands to all the printers without a job in progress")
+                        .arg(
+                            Arg::new("commands")
+                                .required(true)
+                                .num_args(1..)
+                                .help("Commands, e.g. \"M84\""),
+                        )
+                        .arg(
+                            Arg::new("force")
+                                .long("force")
+                                .help("Also send the commands to the printers with a job in progress")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("cancel")
+                        .about("Cancel the print on all printers")
+                        .arg(
+                            Arg::new("confirm")
+                                .long("confirm")
+                                .required(true)
+                                .help("Required, to avoid cancelling prints by accident")
+                                .action(ArgAction::SetTrue),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("exporter")
+                .about("Serve Prometheus metrics for the printers of the configuration")
+                .arg(
+                    Arg::new("listen")
+                        .short('l')
+                        .long("listen")
+                        .help("Address and port of the HTTP server")
+                        .default_value("0.0.0.0:9877"),
+                ),
+        )
+        .subcommand(
+            Command::new("hooks")
+                .about("Run the hooks of the configuration on printer events")
+                .arg(
+                    Arg::new("interval")
+                        .short('i')
+                        .long("interval")
+                        .help("Time between two polls of the printers")
+                        .value_parser(parse_interval)
+                        .default_value("5s"),
+                ),
+        )
+        .subcommand(
+            Command::new("queue")
+                .about("Local print queue, dispatched to the printers of the configuration")
+                .subcommand_required(true)
+                .arg(
+                    Arg::new("queue-file")
+                        .long("queue-file")
+                        .help("Queue file, default is in the user data directory")
+                        .global(true),
+                )
+                .subcommand(
+                    Command::new("add")
+                        .about("Add a file at the end of the queue")
+                        .arg(
+                            Arg::new("file")
+                                .required(true)
+                                .help("Path on the server, or local file with --local"),
+                        )
+                        .arg(
+                            Arg::new("local")
+                                .long("local")
+                                .help("The file is local, it is uploaded when the print starts")
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("copies")
+                                .short('n')
+                                .long("copies")
+                                .value_parser(value_parser!(u32).range(1..))
+                                .default_value("1"),
+                        )
+                        .arg(
+                            Arg::new("printer")
+                                .long("printer")
+                                .help("Only print on this printer"),
+                        )
+                        .arg(
+                            Arg::new("group")
+                                .long("group")
+                                .help("Only print on the printers of this group"),
+                        )
+                        .arg(
+                            Arg::new("profile")
+                                .long("profile")
+                                .help("Only print on printers using this profile id"),
+                        )
+                        .arg(
+                            Arg::new("nozzle")
+                                .long("nozzle")
+                                .value_parser(value_parser!(f64))
+                                .help("Only print on printers with this nozzle diameter (mm)"),
+                        ),
+                )
+                .subcommand(Command::new("ls").about("List the queued files"))
+                .subcommand(
+                    Command::new("rm")
+                        .about("Remove a file from the queue")
+                        .arg(
+                            Arg::new("id")
+                                .required(true)
+                                .value_parser(value_parser!(u64)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("reorder")
+                        .about("Move a file in the queue")
+                        .arg(
+                            Arg::new("id")
+                                .required(true)
+                                .value_parser(value_parser!(u64)),
+                        )
+                        .arg(
+                            Arg::new("position")
+                                .required(true)
+                                .value_parser(value_parser!(usize))
+                                .help("New position, 1 is the head of the queue"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("run")
+                        .about("Start the queued jobs as printers become ready")
+                        .arg(
+                            Arg::new("interval")
+                                .long("interval")
+                                .help("Time between two polls of the printers")
+                                .value_parser(parse_interval)
+                                .default_value("30s"),
+                        )
+                        .arg(
+                            Arg::new("ask-again")
+                                .long("ask-again")
+                                .help("Delay before asking again for a printer declined as not ready")
+                                .value_parser(parse_duration)
+                                .default_value("10m"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("thumbnail")
+                .about("Extract the thumbnail embedded in a G-code file by the slicer")
+                .arg(Arg::new("file").required(true).help("G-code file"))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("Save to this file (.png, .jpg or .qoi) instead of drawing it"),
+                )
+                .arg(
+                    Arg::new("remote")
+                        .short('r')
+                        .long("remote")
+                        .help("The file is a path on the server")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("size")
+                        .short('s')
+                        .long("size")
+                        .help("Minimal width of the thumbnail, the largest is used if none is wide enough")
+                        .value_parser(value_parser!(u32))
+                        .default_value("0"),
+                ),
+        )
         .subcommand(
             Command::new("connect")
                 .about("Connect to printer (open serial connection)")
@@ -66,13 +538,13 @@ async fn main() -> Result<()> {
                     Arg::new("port")
                         .short('p')
                         .long("port")
-                        .help("Specify serial port"),
+                        .help("Specify serial port (\"AUTO\" to detect it)"),
                 )
                 .arg(
                     Arg::new("baudrate")
                         .short('b')
                         .long("baudrate")
-                        .help("Set baudrate")
+                        .help("Set baudrate (0 to detect it)")
                         .value_parser(value_parser!(u32))
                         .action(ArgAction::Set),
                 )
@@ -81,15 +553,464 @@ async fn main() -> Result<()> {
                         .short('t')
                         .long("profile")
                         .help("Select profile (must exists)"),
+                )
+                .arg(
+                    Arg::new("no-save")
+                        .long("no-save")
+                        .help("Do not make the port, baudrate and profile the preferred ones")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("autoconnect")
+                        .long("autoconnect")
+                        .help("Connect to the printer when OctoPrint starts")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("detect")
+                        .short('d')
+                        .long("detect")
+                        .help("Try the last working port, \"AUTO\" then each port until the printer answers")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["port", "no-save", "autoconnect"]),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .help("Maximum time to wait for each port with --detect")
+                        .value_parser(parse_duration)
+                        .default_value("30s")
+                        .requires("detect"),
+                )
+                .arg(
+                    Arg::new("ports-file")
+                        .long("ports-file")
+                        .help("File of the working ports (default in the user data directory)")
+                        .requires("detect"),
                 ),
         )
+        .subcommand(
+            Command::new("fake-ack")
+                .about("Send a fake acknowledgment to a printer stuck waiting for one"),
+        )
         .subcommand(
             Command::new("disconnect").about("Disconnect from printer (close serial connection)"),
         )
-        .get_matches();
+        .subcommand(
+            Command::new("wait")
+                .about("Wait until a condition is met (exit code 2 on timeout, 3 if the job failed, 4 if it was cancelled)")
+                .arg(
+                    Arg::new("state")
+                        .short('s')
+                        .long("state")
+                        .help("Wait for connection state (e.g. \"Operational\", \"Closed\")")
+                        .value_parser(
+                            PossibleValuesParser::new(
+                                State::KNOWN.iter().map(|s| s.as_str().to_string()),
+                            )
+                            .map(|s| State::from(s.as_str())),
+                        ),
+                )
+                .arg(
+                    Arg::new("job-done")
+                        .long("job-done")
+                        .help("Wait until the current job is finished")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("wait-start")
+                        .long("wait-start")
+                        .help("With --job-done, first wait for a job to start")
+                        .conflicts_with_all(["state", "bed", "tool"])
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("bed")
+                        .long("bed")
+                        .help("Wait until the bed reached the given temperature")
+                        .value_parser(value_parser!(f32)),
+                )
+                .arg(
+                    Arg::new("tool")
+                        .long("tool")
+                        .help("Wait until the extruder reached the given temperature")
+                        .value_parser(value_parser!(f32)),
+                )
+                .arg(
+                    Arg::new("tool-index")
+                        .long("tool-index")
+                        .help("Extruder watched by --tool, the first one by default")
+                        .conflicts_with_all(["state", "job-done", "bed"])
+                        .value_parser(value_parser!(usize)),
+                )
+                .group(
+                    ArgGroup::new("condition")
+                        .args(["state", "job-done", "bed", "tool"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("tolerance")
+                        .long("tolerance")
+                        .help("Temperature tolerance in °C")
+                        .value_parser(value_parser!(f32))
+                        .default_value("1.0"),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .short('t')
+                        .long("timeout")
+                        .help("Give up after this delay (e.g. \"60s\", \"5m\")")
+                        .value_parser(parse_duration),
+                )
+                .arg(
+                    Arg::new("interval")
+                        .short('i')
+                        .long("interval")
+                        .help("Polling interval")
+                        .value_parser(parse_interval)
+                        .default_value("1s"),
+                ),
+        )
+        .subcommand(
+            Command::new("snapshot")
+                .about("Save a snapshot of the webcam")
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("JPEG file to write")
+                        .default_value("snapshot.jpg"),
+                )
+                .arg(
+                    Arg::new("raw")
+                        .long("raw")
+                        .help("Keep the image as sent by the webcam, ignoring the flip/rotate settings")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("record")
+                .about("Save frames of the webcam stream, e.g. for a time-lapse")
+                .arg(
+                    Arg::new("dir")
+                        .short('d')
+                        .long("dir")
+                        .help("Directory of the frames")
+                        .default_value("frames"),
+                )
+                .arg(
+                    Arg::new("frames")
+                        .short('n')
+                        .long("frames")
+                        .help("Number of frames to save, until Ctrl-C if not given")
+                        .value_parser(value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("interval")
+                        .short('i')
+                        .long("interval")
+                        .help("Time between two saved frames (e.g. \"10s\"), every frame if not given")
+                        .value_parser(parse_duration),
+                )
+                .arg(
+                    Arg::new("raw")
+                        .long("raw")
+                        .help("Keep the frames as sent by the webcam, ignoring the flip/rotate settings")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("timelapse")
+                .about("Timelapses recorded by OctoPrint")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("ls")
+                        .about("List the rendered and unrendered timelapses"),
+                )
+                .subcommand(
+                    Command::new("download")
+                        .about("Download a rendered timelapse")
+                        .arg(Arg::new("name").required(true))
+                        .arg(
+                            Arg::new("output")
+                                .short('o')
+                                .long("output")
+                                .help("File to write, the name of the timelapse by default"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("rm")
+                        .about("Delete a timelapse")
+                        .arg(Arg::new("name").required(true))
+                        .arg(
+                            Arg::new("unrendered")
+                                .long("unrendered")
+                                .help("Delete the frames of an unrendered timelapse")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("render")
+                        .about("Render an unrendered timelapse")
+                        .arg(Arg::new("name").required(true)),
+                )
+                .subcommand(
+                    Command::new("config")
+                        .about("Show or change the timelapse configuration")
+                        .arg(
+                            Arg::new("type")
+                                .long("type")
+                                .help("When frames are captured")
+                                .value_parser(["off", "zchange", "timed"]),
+                        )
+                        .arg(
+                            Arg::new("interval")
+                                .long("interval")
+                                .help("Seconds between two frames of a \"timed\" timelapse")
+                                .value_parser(value_parser!(u32)),
+                        )
+                        .arg(
+                            Arg::new("fps")
+                                .long("fps")
+                                .help("Frames per second of the video")
+                                .value_parser(value_parser!(u32)),
+                        )
+                        .arg(
+                            Arg::new("post-roll")
+                                .long("post-roll")
+                                .help("Seconds the last frame is shown at the end of the video")
+                                .value_parser(value_parser!(u32)),
+                        )
+                        .arg(
+                            Arg::new("no-save")
+                                .long("no-save")
+                                .help("Only change the configuration until OctoPrint restarts")
+                                .action(ArgAction::SetTrue),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("slice")
+                .about("Slice a model (STL file) stored on the server")
+                .arg(Arg::new("path").help("Path of the model on the server"))
+                .arg(
+                    Arg::new("list")
+                        .long("list")
+                        .help("List the slicers and their profiles")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("path"),
+                )
+                .arg(
+                    Arg::new("slicer")
+                        .long("slicer")
+                        .help("Slicer to use (default: the default slicer of the server)"),
+                )
+                .arg(
+                    Arg::new("profile")
+                        .short('p')
+                        .long("profile")
+                        .help("Slicing profile (default: the default profile of the slicer)"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("Name of the G-code file (default: the model with a .gcode extension)"),
+                )
+                .arg(
+                    Arg::new("set")
+                        .long("set")
+                        .help("Override a setting of the profile (e.g. \"layer_height=0.2\")")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("printer-profile")
+                        .long("printer-profile")
+                        .help("Printer profile to slice for (default: current one)"),
+                )
+                .arg(
+                    Arg::new("select")
+                        .long("select")
+                        .help("Select the G-code file once sliced")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("print")
+                        .long("print")
+                        .help("Print the G-code file once sliced")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("no-wait")
+                        .long("no-wait")
+                        .help("Do not wait for the end of the slicing")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .help("Maximum time to wait for the slicing")
+                        .value_parser(parse_duration)
+                        .default_value("10m"),
+                )
+                .group(ArgGroup::new("after").args(["select", "print"])),
+        )
+        .subcommand(
+            Command::new("users")
+                .about("Manage the users, groups and permissions of the server")
+                .subcommand_required(true)
+                .subcommand(Command::new("ls").about("List the users"))
+                .subcommand(Command::new("whoami").about("Show the user of the API key"))
+                .subcommand(
+                    Command::new("add")
+                        .about("Create a user")
+                        .arg(Arg::new("name").required(true))
+                        .arg(
+                            Arg::new("password")
+                                .long("password")
+                                .help("Password of the user, asked if not given"),
+                        )
+                        .arg(
+                            Arg::new("group")
+                                .short('g')
+                                .long("group")
+                                .help("Group of the user (default: the default groups)")
+                                .action(ArgAction::Append),
+                        )
+                        .arg(
+                            Arg::new("inactive")
+                                .long("inactive")
+                                .help("Create the account deactivated")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about("Change the groups of a user or (de)activate it")
+                        .arg(Arg::new("name").required(true))
+                        .arg(
+                            Arg::new("group")
+                                .short('g')
+                                .long("group")
+                                .help("New groups of the user, replacing the current ones")
+                                .action(ArgAction::Append),
+                        )
+                        .arg(
+                            Arg::new("active")
+                                .long("active")
+                                .value_parser(value_parser!(bool)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("rm")
+                        .about("Delete a user")
+                        .arg(Arg::new("name").required(true)),
+                )
+                .subcommand(
+                    Command::new("passwd")
+                        .about("Change the password of a user")
+                        .arg(Arg::new("name").required(true))
+                        .arg(
+                            Arg::new("password")
+                                .long("password")
+                                .help("New password, asked if not given"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("apikey")
+                        .about("Generate a new API key for a user")
+                        .arg(Arg::new("name").required(true))
+                        .arg(
+                            Arg::new("revoke")
+                                .long("revoke")
+                                .help("Delete the API key of the user instead")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(Command::new("groups").about("List the groups and their permissions"))
+                .subcommand(Command::new("permissions").about("List the permissions"))
+                .subcommand(
+                    Command::new("import")
+                        .about("Create users from a CSV file (columns: name, password, groups, permissions, active)")
+                        .arg(Arg::new("file").required(true).help("CSV file, with a header line"))
+                        .arg(
+                            Arg::new("group")
+                                .short('g')
+                                .long("group")
+                                .help("Group added to every user")
+                                .action(ArgAction::Append),
+                        ),
+                ),
+        );
+    #[cfg(feature = "history")]
+    let cli = cli.subcommand(history_command());
+    #[cfg(feature = "mqtt")]
+    let cli = cli.subcommand(mqtt_bridge_command());
+    let matches = cli.get_matches();
+
+    // Commands working offline
+    let graphics = Graphics::from_name(matches.get_one::<String>("thumbnail").unwrap());
+    match matches.subcommand() {
+        Some(("analyze", sub_match)) => return analyze(sub_match),
+        Some(("thumbnail", sub_match)) if !sub_match.get_flag("remote") => {
+            let file_name = sub_match.get_one::<String>("file").unwrap();
+            let file =
+                std::fs::File::open(file_name).with_context(|| format!("Opening {}", file_name))?;
+            let thumbnails = thumbnail::extract_thumbnails(std::io::BufReader::new(file))?;
+            return save_thumbnail(&thumbnails, sub_match, graphics);
+        }
+        Some(("queue", sub_match)) if sub_match.subcommand_name() != Some("run") => {
+            return queue(sub_match);
+        }
+        #[cfg(feature = "history")]
+        Some(("history", sub_match)) if sub_match.subcommand_name() != Some("record") => {
+            return history(sub_match, matches.get_one::<String>("time-format").unwrap());
+        }
+        _ => {}
+    }
+
+    // Try to get configuration using "confy"
+    let cfg = get_configuration().await?;
+
+    if let Some(("fleet", sub_match)) = matches.subcommand() {
+        let time_format = matches.get_one::<String>("time-format").unwrap();
+        return fleet(printers(cfg), sub_match, time_format).await;
+    }
+    if let Some(("queue", sub_match)) = matches.subcommand() {
+        return queue_run(&printers(cfg), sub_match).await;
+    }
+    if let Some(("exporter", sub_match)) = matches.subcommand() {
+        return exporter(printers(cfg), sub_match).await;
+    }
+    if let Some(("hooks", sub_match)) = matches.subcommand() {
+        return hooks(cfg, sub_match).await;
+    }
+    #[cfg(feature = "history")]
+    if let Some(("history", sub_match)) = matches.subcommand() {
+        return history_record(&printers(cfg), sub_match).await;
+    }
+    #[cfg(feature = "mqtt")]
+    if let Some(("mqtt-bridge", sub_match)) = matches.subcommand() {
+        return mqtt_bridge(printers(cfg), sub_match).await;
+    }
 
-    // Create the client object
+    // Create the client object, logging in if there is no API key
+    let login = match (&cfg.username, cfg.api_key.is_empty()) {
+        (Some(user), true) => Some((user.clone(), cfg.password.clone())),
+        _ => None,
+    };
     let opc = OctoPrintClient::from_config(cfg);
+    if let Some((user, password)) = login {
+        let password = match password {
+            Some(password) => password,
+            None => Password::new()
+                .with_prompt(format!("Password of {}", user))
+                .interact()?,
+        };
+        opc.login(&user, &password, false)
+            .await
+            .with_context(|| format!("Login as {}", user))?;
+    }
 
     let server = opc
         .get_server_info()
@@ -102,81 +1023,707 @@ async fn main() -> Result<()> {
             let file_name = sub_matches
                 .get_one::<String>("file")
                 .ok_or(anyhow!("Bad file name given"))?;
+            let is_model = file_name.to_lowercase().ends_with(".stl");
+            if sub_matches.get_flag("check") {
+                if is_model {
+                    return Err(anyhow!("Only G-code files can be checked"));
+                }
+                let report = check_file(&opc, file_name, None).await?;
+                if !report.is_ok() {
+                    if sub_matches.get_flag("force") {
+                        eprintln!("Check failed, uploading anyway");
+                    } else {
+                        return Err(anyhow!("Check failed, use --force to upload anyway"));
+                    }
+                }
+            }
+            let metadata = if is_model {
+                Metadata::default()
+            } else {
+                let file = std::fs::File::open(file_name)?;
+                metadata::extract_metadata(std::io::BufReader::new(file))?
+            };
+            let options = UploadOptions {
+                path: sub_matches.get_one::<String>("dir").cloned(),
+                // Models can not be printed before slicing
+                select: !is_model,
+                userdata: if metadata.is_empty() {
+                    None
+                } else {
+                    Some(serde_json::to_value(&metadata)?)
+                },
+                ..Default::default()
+            };
             println!("Uploading \"{}\"", file_name);
             let file = std::fs::File::open(file_name)?;
-            opc.upload(file, file_name).await.with_context(|| "Upload")
+            opc.upload_with_options(file, file_name, &options)
+                .await
+                .with_context(|| "Upload")
         }
         Some(("connection", _)) => print_connection(opc).await,
-        Some(("connect", sub_match)) => {
-            let conn_state = opc.get_connection().await?;
-            let br: u32 = if let Some(baudrate) = sub_match.get_one::<u32>("baudrate") {
-                *baudrate
-            } else {
-                conn_state
-                    .options
-                    .baudrate_preference
-                    .ok_or(anyhow!("No default baudrate stored in server"))?
-            };
-            let port = if let Some(port) = sub_match.get_one::<String>("port") {
-                port.to_string()
-            } else {
-                conn_state
-                    .options
-                    .port_preference
-                    .ok_or(anyhow!("No default port stored in server"))?
-            };
-
-            let profile = if let Some(profile) = sub_match.get_one::<String>("profile") {
-                profile.to_string()
+        Some(("files", sub_match)) => files(&opc, sub_match).await,
+        Some(("sync", sub_match)) => sync(&opc, sub_match).await,
+        Some(("autoupload", sub_match)) => autoupload(&opc, sub_match).await,
+        Some(("check", sub_match)) => {
+            let file_name = sub_match.get_one::<String>("file").unwrap();
+            let profile = sub_match.get_one::<String>("profile");
+            let report = check_file(&opc, file_name, profile).await?;
+            if report.is_ok() {
+                Ok(())
             } else {
-                conn_state
-                    .options
-                    .printer_profile_preference
-                    .ok_or(anyhow!("No default port stored in server"))?
-            };
-
-            let connect_cmd = ConnectionCommand {
-                command: "connect".to_string(),
-                port: Some(port),
-                baudrate: Some(br),
-                printer_profile: Some(profile), //Some("_default".to_string()),
-                save: Some(true),
-                autoconnect: Some(false),
-            };
-            opc.connect(&connect_cmd).await.with_context(|| "Connect")
+                Err(anyhow!("Check failed"))
+            }
+        }
+        Some(("connect", sub_match)) => {
+            let mut builder = ConnectionBuilder::new()
+                .save(!sub_match.get_flag("no-save"))
+                .autoconnect(sub_match.get_flag("autoconnect"));
+            if let Some(port) = sub_match.get_one::<String>("port") {
+                builder = builder.port(port);
+            }
+            if let Some(baudrate) = sub_match.get_one::<u32>("baudrate") {
+                builder = builder.baudrate(*baudrate);
+            }
+            if let Some(profile) = sub_match.get_one::<String>("profile") {
+                builder = builder.printer_profile(profile);
+            }
+            if sub_match.get_flag("detect") {
+                return detect_connection(&opc, &builder, sub_match).await;
+            }
+            opc.connect_with(&builder)
+                .await
+                .with_context(|| "Connect")?;
+            Ok(())
         }
+        Some(("fake-ack", _)) => opc.fake_ack().await.with_context(|| "Fake acknowledgment"),
         Some(("disconnect", _)) => opc.disconnect().await.with_context(|| "Disconnect"),
-        _ => print_state(opc).await,
+        Some(("wait", sub_match)) => wait(opc, sub_match).await,
+        Some(("snapshot", sub_match)) => snapshot(&opc, sub_match).await,
+        Some(("record", sub_match)) => record(&opc, sub_match).await,
+        Some(("timelapse", sub_match)) => timelapse(&opc, sub_match).await,
+        Some(("slice", sub_match)) => slice(&opc, sub_match).await,
+        Some(("users", sub_match)) => users(&opc, sub_match).await,
+        Some(("thumbnail", sub_match)) => {
+            let path = sub_match.get_one::<String>("file").unwrap();
+            let thumbnails = remote_thumbnails(&opc, path).await?;
+            save_thumbnail(&thumbnails, sub_match, graphics)
+        }
+        _ => {
+            let time_format = matches.get_one::<String>("time-format").unwrap();
+            print_state(opc, time_format, graphics).await
+        }
     }
 }
 
-async fn print_state(opc: OctoPrintClient) -> Result<()> {
-    // Get jom information from the server.
-    let job = opc
-        .get_current_job()
+async fn snapshot(opc: &OctoPrintClient, args: &ArgMatches) -> Result<()> {
+    let output = args.get_one::<String>("output").unwrap();
+    let webcam = opc
+        .get_webcam()
         .await
-        .with_context(|| "Getting job state")?;
-
-    //dbg!(&job);
+        .with_context(|| "Get webcam settings")?;
+    let jpeg = webcam
+        .snapshot(!args.get_flag("raw"))
+        .await
+        .with_context(|| "Snapshot")?;
+    std::fs::write(output, jpeg).with_context(|| format!("Writing {}", output))?;
+    println!("Saved {}", output);
+    Ok(())
+}
 
-    // Print state
-    let style = if job.state.to_lowercase().contains("error") {
-        Style::new().red().bold()
-    } else if job.state.to_lowercase().contains("printing") {
-        Style::new().green().bold()
-    } else {
-        Style::new().yellow()
+async fn record(opc: &OctoPrintClient, args: &ArgMatches) -> Result<()> {
+    let dir = args.get_one::<String>("dir").unwrap();
+    let options = RecordOptions {
+        frames: args.get_one::<usize>("frames").copied(),
+        interval: args
+            .get_one::<Duration>("interval")
+            .copied()
+            .unwrap_or_default(),
+        orient: !args.get_flag("raw"),
     };
-    println!("State    : {}", style.apply_to(job.state));
-
-    // Print progress and estimate end time
-    if let (Some(completion), Some(time_left)) =
-        (job.progress.completion, job.progress.print_time_left)
-    {
-        println!(
-            "Progress : {:2.1}% , ends {}",
+    let webcam = opc
+        .get_webcam()
+        .await
+        .with_context(|| "Get webcam settings")?;
+    log(&format!("Recording to {}, press Ctrl-C to stop", dir));
+    let saved = webcam
+        .record(
+            std::path::Path::new(dir),
+            &options,
+            |path| log(&format!("Saved {}", path.display())),
+            async {
+                let _ = tokio::signal::ctrl_c().await;
+            },
+        )
+        .await
+        .with_context(|| "Recording")?;
+    println!("{} frames saved in {}", saved, dir);
+    Ok(())
+}
+
+/// Password from `--password`, or asked twice on the terminal.
+fn password_arg(args: &ArgMatches) -> Result<String> {
+    match args.get_one::<String>("password") {
+        Some(password) => Ok(password.clone()),
+        None => Ok(Password::new()
+            .with_prompt("Password")
+            .with_confirmation("Repeat password", "Passwords do not match")
+            .interact()?),
+    }
+}
+
+fn group_args(args: &ArgMatches) -> Option<Vec<String>> {
+    args.get_many::<String>("group")
+        .map(|groups| groups.cloned().collect())
+}
+
+async fn users(opc: &OctoPrintClient, args: &ArgMatches) -> Result<()> {
+    match args.subcommand() {
+        Some(("ls", _)) => {
+            let users = opc.get_users().await.with_context(|| "Listing users")?;
+            let rows = users
+                .iter()
+                .map(|u| {
+                    let style = if u.active {
+                        Style::new()
+                    } else {
+                        Style::new().dim()
+                    };
+                    let row = vec![
+                        u.name.clone(),
+                        if u.active { "yes" } else { "no" }.to_string(),
+                        u.groups.join(", "),
+                        if u.apikey.is_some() { "yes" } else { "" }.to_string(),
+                    ];
+                    (row, style)
+                })
+                .collect();
+            print_table(&["NAME", "ACTIVE", "GROUPS", "API KEY"], rows);
+            Ok(())
+        }
+        Some(("whoami", _)) => {
+            let user = opc
+                .get_current_user()
+                .await
+                .with_context(|| "Get current user")?;
+            println!(
+                "User:        {}",
+                user.name.as_deref().unwrap_or("(anonymous)")
+            );
+            println!("Groups:      {}", user.groups.join(", "));
+            println!("Permissions: {}", user.permissions.join(", "));
+            Ok(())
+        }
+        Some(("add", sub_match)) => {
+            let user = NewUser {
+                name: sub_match.get_one::<String>("name").unwrap().clone(),
+                password: password_arg(sub_match)?,
+                active: !sub_match.get_flag("inactive"),
+                groups: group_args(sub_match),
+                permissions: Vec::new(),
+            };
+            opc.create_user(&user)
+                .await
+                .with_context(|| format!("Creating {}", user.name))
+        }
+        Some(("set", sub_match)) => {
+            let name = sub_match.get_one::<String>("name").unwrap();
+            let update = UserUpdate {
+                active: sub_match.get_one::<bool>("active").copied(),
+                groups: group_args(sub_match),
+                ..Default::default()
+            };
+            opc.update_user(name, &update)
+                .await
+                .with_context(|| format!("Updating {}", name))
+        }
+        Some(("rm", sub_match)) => {
+            let name = sub_match.get_one::<String>("name").unwrap();
+            opc.delete_user(name)
+                .await
+                .with_context(|| format!("Deleting {}", name))
+        }
+        Some(("passwd", sub_match)) => {
+            let name = sub_match.get_one::<String>("name").unwrap();
+            opc.change_password(name, &password_arg(sub_match)?, None)
+                .await
+                .with_context(|| format!("Changing the password of {}", name))
+        }
+        Some(("apikey", sub_match)) => {
+            let name = sub_match.get_one::<String>("name").unwrap();
+            if sub_match.get_flag("revoke") {
+                return opc
+                    .delete_api_key(name)
+                    .await
+                    .with_context(|| format!("Revoking the API key of {}", name));
+            }
+            let key = opc
+                .generate_api_key(name)
+                .await
+                .with_context(|| format!("Generating an API key for {}", name))?;
+            println!("{}", key);
+            Ok(())
+        }
+        Some(("groups", _)) => {
+            let groups = opc.get_groups().await.with_context(|| "Listing groups")?;
+            let rows = groups
+                .iter()
+                .map(|g| {
+                    let row = vec![
+                        g.key.clone(),
+                        g.name.clone(),
+                        if g.default { "yes" } else { "" }.to_string(),
+                        g.permissions.join(", "),
+                    ];
+                    (row, Style::new())
+                })
+                .collect();
+            print_table(&["KEY", "NAME", "DEFAULT", "PERMISSIONS"], rows);
+            Ok(())
+        }
+        Some(("permissions", _)) => {
+            let permissions = opc
+                .get_permissions()
+                .await
+                .with_context(|| "Listing permissions")?;
+            let rows = permissions
+                .iter()
+                .map(|p| {
+                    let style = if p.dangerous {
+                        Style::new().yellow()
+                    } else {
+                        Style::new()
+                    };
+                    (vec![p.key.clone(), p.name.clone()], style)
+                })
+                .collect();
+            print_table(&["KEY", "NAME"], rows);
+            Ok(())
+        }
+        Some(("import", sub_match)) => {
+            let path = sub_match.get_one::<String>("file").unwrap();
+            let file = std::fs::File::open(path).with_context(|| format!("Opening {}", path))?;
+            let mut users = parse_users_csv(std::io::BufReader::new(file))
+                .map_err(|e| anyhow!("{}: {}", path, e))?;
+            if let Some(extra) = group_args(sub_match) {
+                // Users without groups would get the default ones
+                let defaults: Vec<String> = opc
+                    .get_groups()
+                    .await
+                    .with_context(|| "Listing groups")?
+                    .into_iter()
+                    .filter(|g| g.default)
+                    .map(|g| g.key)
+                    .collect();
+                for user in &mut users {
+                    let groups = user.groups.get_or_insert_with(|| defaults.clone());
+                    for group in &extra {
+                        if !groups.contains(group) {
+                            groups.push(group.clone());
+                        }
+                    }
+                }
+            }
+            let results = opc
+                .import_users(&users)
+                .await
+                .with_context(|| "Importing users")?;
+            let mut failed = 0;
+            for result in &results {
+                match result {
+                    ImportResult::Created(name) => println!("{}: created", name),
+                    ImportResult::Exists(name) => println!("{}: exists, skipped", name),
+                    ImportResult::Failed(name, e) => {
+                        failed += 1;
+                        eprintln!("{}: failed ({})", name, e)
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(anyhow!("{} of {} users not created", failed, results.len()));
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Parse a "key=value" override of a slicing profile, the value is JSON if it parses as such.
+fn parse_override(s: &str) -> Result<(String, serde_json::Value)> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or(anyhow!("Bad override \"{}\", expected key=value", s))?;
+    let value = serde_json::from_str(value).unwrap_or_else(|_| value.into());
+    Ok((key.trim().to_string(), value))
+}
+
+async fn slice(opc: &OctoPrintClient, args: &ArgMatches) -> Result<()> {
+    if args.get_flag("list") {
+        let slicers = opc.get_slicers().await.with_context(|| "Listing slicers")?;
+        for slicer in slicers.values() {
+            let mut flags = Vec::new();
+            if slicer.default {
+                flags.push("default");
+            }
+            if !slicer.configured {
+                flags.push("not configured");
+            }
+            println!(
+                "{} ({}) {}",
+                slicer.display_name,
+                slicer.key,
+                flags.join(", ")
+            );
+            for profile in slicer.profiles.values() {
+                println!(
+                    "  {}{}{}",
+                    profile.key,
+                    profile
+                        .display_name
+                        .as_ref()
+                        .map(|n| format!(" - {}", n))
+                        .unwrap_or_default(),
+                    if profile.default == Some(true) {
+                        " (default)"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    let path = args
+        .get_one::<String>("path")
+        .ok_or(anyhow!("No model given"))?;
+    let slicer = match args.get_one::<String>("slicer") {
+        Some(slicer) => slicer.clone(),
+        None => opc.get_default_slicer().await?.key,
+    };
+    let command = SliceCommand {
+        slicer,
+        gcode: args.get_one::<String>("output").cloned(),
+        profile: args.get_one::<String>("profile").cloned(),
+        overrides: args
+            .get_many::<String>("set")
+            .unwrap_or_default()
+            .map(|s| parse_override(s))
+            .collect::<Result<_>>()?,
+        printer_profile: args.get_one::<String>("printer-profile").cloned(),
+        select: args.get_flag("select"),
+        print: args.get_flag("print"),
+        ..Default::default()
+    };
+
+    // Connected before slicing, not to miss the progress
+    let mut socket = if args.get_flag("no-wait") {
+        None
+    } else {
+        Some(
+            opc.authenticated_push_socket()
+                .await
+                .with_context(|| "Connecting to the push socket")?,
+        )
+    };
+    opc.slice(path, &command)
+        .await
+        .with_context(|| format!("Slicing {}", path))?;
+    println!("Slicing {} with {}", path, command.slicer);
+    let Some(socket) = socket.as_mut() else {
+        return Ok(());
+    };
+
+    let timeout = *args.get_one::<Duration>("timeout").unwrap();
+    let progress = |p: f64| eprint!("\r{:.0}%", p);
+    let result = tokio::time::timeout(timeout, socket.wait_for_slicing(path, progress)).await;
+    eprintln!();
+    let gcode = result
+        .map_err(|_| anyhow!("{} not sliced after {:?}", path, timeout))?
+        .with_context(|| format!("Slicing {}", path))?;
+    println!("Sliced to {}", gcode);
+    Ok(())
+}
+
+fn print_timelapse_config(config: &TimelapseConfig) {
+    println!("Type:      {}", config.timelapse_type);
+    if let Some(interval) = config.interval {
+        println!("Interval:  {}s", interval);
+    }
+    println!("FPS:       {}", config.fps);
+    println!("Post roll: {}s", config.post_roll);
+}
+
+async fn timelapse(opc: &OctoPrintClient, args: &ArgMatches) -> Result<()> {
+    match args.subcommand() {
+        Some(("ls", _)) => {
+            let list = opc
+                .get_timelapses(true)
+                .await
+                .with_context(|| "Listing timelapses")?;
+            for file in &list.files {
+                println!(
+                    "{:>10}  {}  {}",
+                    format_size(file.bytes),
+                    file.date,
+                    file.name
+                );
+            }
+            for unrendered in &list.unrendered {
+                let status = if unrendered.recording {
+                    "recording"
+                } else if unrendered.rendering || unrendered.processing {
+                    "rendering"
+                } else {
+                    "unrendered"
+                };
+                println!(
+                    "{:>10}  {}  {} ({})",
+                    format_size(unrendered.bytes),
+                    unrendered.date,
+                    unrendered.name,
+                    status
+                );
+            }
+            Ok(())
+        }
+        Some(("download", sub_match)) => {
+            let name = sub_match.get_one::<String>("name").unwrap();
+            let output = sub_match
+                .get_one::<String>("output")
+                .unwrap_or(name)
+                .to_string();
+            let mut file =
+                std::fs::File::create(&output).with_context(|| format!("Creating {}", output))?;
+            let result = opc
+                .download_timelapse(name, &mut file, |received, total| match total {
+                    Some(total) if total > 0 => eprint!(
+                        "\r{} of {} ({}%)",
+                        format_size(received),
+                        format_size(total),
+                        received * 100 / total
+                    ),
+                    _ => eprint!("\r{}", format_size(received)),
+                })
+                .await;
+            eprintln!();
+            if result.is_err() {
+                drop(file);
+                let _ = std::fs::remove_file(&output);
+            }
+            result.with_context(|| format!("Downloading {}", name))?;
+            println!("Saved {}", output);
+            Ok(())
+        }
+        Some(("rm", sub_match)) => {
+            let name = sub_match.get_one::<String>("name").unwrap();
+            if sub_match.get_flag("unrendered") {
+                opc.delete_unrendered_timelapse(name).await
+            } else {
+                opc.delete_timelapse(name).await
+            }
+            .with_context(|| format!("Deleting {}", name))
+        }
+        Some(("render", sub_match)) => {
+            let name = sub_match.get_one::<String>("name").unwrap();
+            opc.render_timelapse(name)
+                .await
+                .with_context(|| format!("Rendering {}", name))?;
+            println!("Rendering {}, see \"timelapse ls\"", name);
+            Ok(())
+        }
+        Some(("config", sub_match)) => {
+            let mut config = opc
+                .get_timelapse_config()
+                .await
+                .with_context(|| "Get timelapse configuration")?;
+            let mut changed = false;
+            if let Some(t) = sub_match.get_one::<String>("type") {
+                config.timelapse_type = t.parse().map_err(|e: String| anyhow!(e))?;
+                changed = true;
+            }
+            if let Some(interval) = sub_match.get_one::<u32>("interval") {
+                config.interval = Some(*interval);
+                changed = true;
+            }
+            if let Some(fps) = sub_match.get_one::<u32>("fps") {
+                config.fps = *fps;
+                changed = true;
+            }
+            if let Some(post_roll) = sub_match.get_one::<u32>("post-roll") {
+                config.post_roll = *post_roll;
+                changed = true;
+            }
+            if changed {
+                opc.set_timelapse_config(&config, !sub_match.get_flag("no-save"))
+                    .await
+                    .with_context(|| "Set timelapse configuration")?;
+            }
+            print_timelapse_config(&config);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn wait(opc: OctoPrintClient, args: &ArgMatches) -> Result<()> {
+    let options = WaitOptions {
+        timeout: args.get_one::<Duration>("timeout").copied(),
+        poll_interval: *args.get_one::<Duration>("interval").unwrap(),
+    };
+    let tolerance = *args.get_one::<f32>("tolerance").unwrap();
+
+    let result = if let Some(state) = args.get_one::<State>("state") {
+        println!("Waiting for state \"{}\"", state);
+        opc.wait_for_connection_state(state, &options)
+            .await
+            .map(|_| ())
+    } else if args.get_flag("job-done") {
+        println!("Waiting for the job to finish");
+        match opc
+            .wait_for_job_end(args.get_flag("wait-start"), &options)
+            .await
+        {
+            Ok((_, Some(PrinterEvent::PrintFailed))) => {
+                eprintln!("{}", Style::new().red().bold().apply_to("Job failed"));
+                std::process::exit(EXIT_JOB_FAILED);
+            }
+            Ok((_, Some(PrinterEvent::PrintCancelled))) => {
+                eprintln!("{}", Style::new().red().bold().apply_to("Job cancelled"));
+                std::process::exit(EXIT_JOB_CANCELLED);
+            }
+            result => result.map(|_| ()),
+        }
+    } else if let Some(target) = args.get_one::<f32>("bed").copied() {
+        println!("Waiting for bed to reach {}°C", target);
+        opc.wait_for_printer(
+            |printer| {
+                printer
+                    .temperature
+                    .as_ref()
+                    .and_then(|t| t.bed.as_ref())
+                    .and_then(|bed| bed.actual)
+                    .is_some_and(|actual| (actual - target).abs() <= tolerance)
+            },
+            &options,
+        )
+        .await
+        .map(|_| ())
+    } else if let Some(target) = args.get_one::<f32>("tool").copied() {
+        let index = args.get_one::<usize>("tool-index").copied().unwrap_or(0);
+        println!("Waiting for extruder {} to reach {}°C", index, target);
+        opc.wait_for_printer(
+            |printer| {
+                printer
+                    .temperature
+                    .as_ref()
+                    .and_then(|t| t.tool(index))
+                    .and_then(|tool| tool.actual)
+                    .is_some_and(|actual| (actual - target).abs() <= tolerance)
+            },
+            &options,
+        )
+        .await
+        .map(|_| ())
+    } else {
+        Ok(())
+    };
+
+    match result {
+        Err(OctoPrintClientError::TimeoutError(timeout)) => {
+            eprintln!(
+                "{}",
+                Style::new()
+                    .red()
+                    .bold()
+                    .apply_to(format!("Timeout after {:?}", timeout))
+            );
+            std::process::exit(EXIT_TIMEOUT);
+        }
+        r => r.with_context(|| "Wait"),
+    }
+}
+
+/// Thumbnails of a file stored on the server.
+async fn remote_thumbnails(opc: &OctoPrintClient, path: &str) -> Result<Vec<Thumbnail>> {
+    let header = opc
+        .download(path, Some(THUMBNAIL_HEADER_SIZE))
+        .await
+        .with_context(|| format!("Downloading {}", path))?;
+    Ok(thumbnail::extract_thumbnails(header.as_slice())?)
+}
+
+/// Save the thumbnail selected by the `thumbnail` sub-command, or draw it.
+fn save_thumbnail(
+    thumbnails: &[Thumbnail],
+    args: &ArgMatches,
+    graphics: Option<Graphics>,
+) -> Result<()> {
+    let size = *args.get_one::<u32>("size").unwrap();
+    let thumbnail =
+        thumbnail::best_thumbnail(thumbnails, size).ok_or(anyhow!("File has no thumbnail"))?;
+
+    match args.get_one::<String>("output") {
+        Some(output) => {
+            let format = match output
+                .rsplit('.')
+                .next()
+                .map(|e| e.to_lowercase())
+                .as_deref()
+            {
+                Some("jpg" | "jpeg") => ThumbnailFormat::Jpg,
+                Some("qoi") => ThumbnailFormat::Qoi,
+                _ => ThumbnailFormat::Png,
+            };
+            let data = thumbnail
+                .encode(format)
+                .with_context(|| "Converting thumbnail")?;
+            std::fs::write(output, data).with_context(|| format!("Writing {}", output))?;
+            println!(
+                "Saved {}x{} {} thumbnail to {}",
+                thumbnail.width, thumbnail.height, thumbnail.format, output
+            );
+        }
+        None => print!(
+            "{}",
+            thumbnail::render(
+                thumbnail,
+                graphics.unwrap_or(Graphics::Blocks),
+                THUMBNAIL_COLUMNS
+            )?
+        ),
+    }
+    Ok(())
+}
+
+async fn print_state(
+    opc: OctoPrintClient,
+    time_format: &str,
+    graphics: Option<Graphics>,
+) -> Result<()> {
+    // Get jom information from the server.
+    let job = opc
+        .get_current_job()
+        .await
+        .with_context(|| "Getting job state")?;
+
+    //dbg!(&job);
+
+    // Print state
+    let style = if job.state.is_error() {
+        Style::new().red().bold()
+    } else if job.state.is_printing() {
+        Style::new().green().bold()
+    } else {
+        Style::new().yellow()
+    };
+    println!("State    : {}", style.apply_to(&job.state));
+
+    // Print progress and estimate end time
+    if let (Some(completion), Some(end)) = (job.progress.completion, job.estimated_completion()) {
+        println!(
+            "Progress : {:2.1}% , ends {}",
             completion,
-            HumanTime::from_seconds(time_left)
+            format_time(end, time_format)
         );
     }
 
@@ -194,6 +1741,17 @@ async fn print_state(opc: OctoPrintClient) -> Result<()> {
     // Print file name
     if let Some(path) = job.job.file.path {
         println!("File     : {}", path);
+
+        if let Some(graphics) = graphics {
+            // A missing thumbnail is not worth an error
+            let thumbnails = remote_thumbnails(&opc, &path).await.unwrap_or_default();
+            if let Some(thumbnail) = thumbnail::best_thumbnail(&thumbnails, THUMBNAIL_COLUMNS) {
+                print!(
+                    "{}",
+                    thumbnail::render(thumbnail, graphics, THUMBNAIL_COLUMNS)?
+                );
+            }
+        }
     }
 
     let printer = opc
@@ -201,21 +1759,808 @@ async fn print_state(opc: OctoPrintClient) -> Result<()> {
         .await
         .with_context(|| "Getting printer state")?;
     if let Some(temperature_state) = printer.temperature {
-        if let Some(temperature_data) = temperature_state.tool0 {
+        if let Some(temperature_data) = temperature_state.tool(0) {
+            println!("Extruder : {}", format_temperature(temperature_data));
+        }
+        if let Some(temperature_data) = &temperature_state.bed {
+            println!("Bed      : {}", format_temperature(temperature_data));
+        }
+    }
+
+    Ok(())
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}h {:02}m {:02}s",
+            seconds / 3600,
+            (seconds % 3600) / 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}m {:02}s", seconds / 60, seconds % 60)
+    }
+}
+
+fn analyze(args: &ArgMatches) -> Result<()> {
+    let file_name = args.get_one::<String>("file").unwrap();
+    let settings = AnalyzerSettings {
+        acceleration: *args.get_one::<f64>("acceleration").unwrap(),
+        filament_diameter: *args.get_one::<f64>("filament-diameter").unwrap(),
+        ..Default::default()
+    };
+
+    let file = std::fs::File::open(file_name).with_context(|| format!("Opening {}", file_name))?;
+    let analysis = gcode::analysis::analyze(std::io::BufReader::new(file), settings)
+        .with_context(|| "Analyze")?;
+
+    let file = std::fs::File::open(file_name).with_context(|| format!("Opening {}", file_name))?;
+    let metadata = metadata::extract_metadata(std::io::BufReader::new(file))?;
+
+    println!("File       : {}", file_name);
+    print_analysis(&analysis);
+    print_metadata(&metadata);
+    Ok(())
+}
+
+fn print_metadata(metadata: &Metadata) {
+    if let Some(slicer) = metadata.slicer {
+        println!(
+            "Slicer     : {} {}",
+            slicer,
+            metadata.slicer_version.as_deref().unwrap_or("")
+        );
+    }
+    if let Some(model) = &metadata.printer_model {
+        println!("Printer    : {}", model);
+    }
+    if let Some(time) = metadata.estimated_time {
+        println!("Slicer time: {}", format_duration(time));
+    }
+    let mut filament = Vec::new();
+    if let Some(filament_type) = &metadata.filament_type {
+        filament.push(filament_type.clone());
+    }
+    if let Some(length) = metadata.filament_length {
+        filament.push(format!("{:.2} m", length / 1000.0));
+    }
+    if let Some(weight) = metadata.filament_weight {
+        filament.push(format!("{:.2} g", weight));
+    }
+    if !filament.is_empty() {
+        println!("Material   : {}", filament.join(", "));
+    }
+    if let Some(height) = metadata.layer_height {
+        println!("Layer      : {:.2} mm", height);
+    }
+    if let Some(nozzle) = metadata.nozzle_diameter {
+        println!("Nozzle     : {:.2} mm", nozzle);
+    }
+}
+
+async fn files(opc: &OctoPrintClient, args: &ArgMatches) -> Result<()> {
+    match args.subcommand() {
+        Some(("ls", sub_match)) => {
+            let list = opc
+                .get_files(sub_match.get_flag("recursive"))
+                .await
+                .with_context(|| "Listing files")?;
+            for entry in list.files.iter().flat_map(|f| f.walk()) {
+                if entry.is_folder() {
+                    println!("{:>10}  {}/", "", entry.path);
+                } else {
+                    println!(
+                        "{:>10}  {}",
+                        entry.size.map(format_size).unwrap_or_default(),
+                        entry.path
+                    );
+                }
+            }
+            if let (Some(free), Some(total)) = (list.free, list.total) {
+                println!("{} free of {}", format_size(free), format_size(total));
+            }
+            Ok(())
+        }
+        Some(("info", sub_match)) => {
+            let path = sub_match.get_one::<String>("path").unwrap();
+            let entry = opc
+                .get_file(path)
+                .await
+                .with_context(|| format!("Getting {}", path))?;
+            print_file_info(&entry, sub_match.get_one::<String>("time-format").unwrap());
+
+            // Files uploaded by other clients have no userdata, read the G-code instead
+            let metadata = match entry
+                .userdata
+                .and_then(|u| serde_json::from_value::<Metadata>(u).ok())
+            {
+                Some(metadata) => metadata,
+                None if entry.file_type == "machinecode" => {
+                    let content = opc
+                        .download(path, None)
+                        .await
+                        .with_context(|| format!("Downloading {}", path))?;
+                    metadata::extract_metadata(content.as_slice())?
+                }
+                None => Metadata::default(),
+            };
+            print_metadata(&metadata);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn sync(opc: &OctoPrintClient, args: &ArgMatches) -> Result<()> {
+    let local_dir = args.get_one::<String>("local-dir").unwrap();
+    let remote_folder = args.get_one::<String>("remote-folder").unwrap();
+    let patterns = |name: &str| {
+        args.get_many::<String>(name)
+            .map(|p| p.cloned().collect())
+            .unwrap_or_default()
+    };
+    let options = SyncOptions {
+        mode: if args.get_flag("mirror") {
+            SyncMode::Mirror
+        } else {
+            SyncMode::Push
+        },
+        include: patterns("include"),
+        exclude: patterns("exclude"),
+    };
+
+    let plan = opc
+        .sync_plan(std::path::Path::new(local_dir), remote_folder, &options)
+        .await
+        .with_context(|| "Comparing files")?;
+    if plan.is_empty() {
+        println!("Up to date ({} files)", plan.unchanged.len());
+        return Ok(());
+    }
+    for action in &plan.actions {
+        println!("{}", action);
+    }
+    println!(
+        "{} actions, {} files up to date",
+        plan.actions.len(),
+        plan.unchanged.len()
+    );
+
+    if args.get_flag("dry-run") {
+        return Ok(());
+    }
+    if !args.get_flag("yes") && !Confirm::new().with_prompt("Apply?").interact()? {
+        return Ok(());
+    }
+
+    let total = plan.actions.len();
+    let mut done = 0;
+    opc.apply_sync(&plan, |action| {
+        done += 1;
+        println!("[{}/{}] {}", done, total, action);
+    })
+    .await
+    .with_context(|| "Synchronization interrupted, run it again to resume")
+}
+
+async fn fleet(fleet: Fleet, args: &ArgMatches, time_format: &str) -> Result<()> {
+    let (results, hint) = match args.subcommand() {
+        Some(("disconnect", sub_match)) => (
+            fleet.disconnect(sub_match.get_flag("force")).await,
+            Some("use --force to disconnect them anyway"),
+        ),
+        Some(("gcode", sub_match)) => {
+            let commands: Vec<&str> = sub_match
+                .get_many::<String>("commands")
+                .unwrap()
+                .map(|c| c.as_str())
+                .collect();
+            (
+                fleet
+                    .send_gcode(&commands, sub_match.get_flag("force"))
+                    .await,
+                Some("use --force to send the commands anyway"),
+            )
+        }
+        Some(("cancel", _)) => (fleet.cancel().await, None),
+        _ => return print_fleet_status(&fleet, time_format).await,
+    };
+    print_fleet_results(&results, hint)
+}
+
+/// Printers of the configuration, or the main server if no printer is listed.
+fn printers(cfg: Configuration) -> Fleet {
+    if cfg.printers.is_empty() {
+        Fleet::single("default", OctoPrintClient::from_config(cfg))
+    } else {
+        Fleet::from_configs(&cfg.printers)
+    }
+}
+
+async fn exporter(fleet: Fleet, args: &ArgMatches) -> Result<()> {
+    let address = args.get_one::<String>("listen").unwrap();
+    let listener = std::net::TcpListener::bind(address)
+        .with_context(|| format!("Listening on {}", address))?;
+    log(&format!(
+        "Serving metrics of {} printers on http://{}/metrics",
+        fleet.members().len(),
+        listener.local_addr()?
+    ));
+    octoprint_client::octoprintclient::exporter::serve(fleet, listener, async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+    .await
+    .with_context(|| "Metrics server")
+}
+
+async fn hooks(mut cfg: Configuration, args: &ArgMatches) -> Result<()> {
+    let hooks = std::mem::take(&mut cfg.hooks);
+    if hooks.is_empty() {
+        return Err(anyhow!("No hook in the configuration file"));
+    }
+    for (i, hook) in hooks.iter().enumerate() {
+        hook.validate().map_err(|e| {
+            anyhow!(
+                "Hook {}: {}",
+                hook.name.clone().unwrap_or_else(|| format!("#{}", i + 1)),
+                e
+            )
+        })?;
+    }
+    let fleet = printers(cfg);
+    log(&format!(
+        "Watching {} printers for {} hooks, press Ctrl-C to stop",
+        fleet.members().len(),
+        hooks.len()
+    ));
+    HookRunner::new(hooks)
+        .run(
+            &fleet,
+            *args.get_one::<Duration>("interval").unwrap(),
+            |entry| match entry {
+                HookLog::Done { hook, event } => {
+                    log(&format!("{}: {} on {}", hook, event.event, event.printer))
+                }
+                HookLog::RateLimited { hook, event } => log(&format!(
+                    "{}: {} on {} skipped, rate limited",
+                    hook, event.event, event.printer
+                )),
+                HookLog::Failed { hook, event, error } => log(&format!(
+                    "{}",
+                    Style::new().red().bold().apply_to(format!(
+                        "{}: {} on {} failed: {}",
+                        hook, event.event, event.printer, error
+                    ))
+                )),
+            },
+            async {
+                let _ = tokio::signal::ctrl_c().await;
+            },
+        )
+        .await
+        .with_context(|| "Hooks")
+}
+
+/// Connect on the port the printer answers on, which is remembered for the next time.
+async fn detect_connection(
+    opc: &OctoPrintClient,
+    builder: &ConnectionBuilder,
+    args: &ArgMatches,
+) -> Result<()> {
+    let path = match args.get_one::<String>("ports-file") {
+        Some(path) => PathBuf::from(path),
+        None => directories::ProjectDirs::from("rs", "", "octoprint-client")
+            .ok_or(anyhow!("No home directory, use --ports-file"))?
+            .data_dir()
+            .join("ports.json"),
+    };
+    let mut known = KnownPorts::load(&path).with_context(|| "Loading the known ports")?;
+    let options = WaitOptions::with_timeout(*args.get_one::<Duration>("timeout").unwrap());
+
+    let describe = |k: &KnownPort| match k.baudrate {
+        AUTO_BAUDRATE => format!("{} (auto baudrate)", k.port),
+        baudrate => format!("{} at {} baud", k.port, baudrate),
+    };
+    let log = |event: DetectionEvent| match event {
+        DetectionEvent::Trying(k) => eprintln!("Trying {}...", describe(&k)),
+        DetectionEvent::Failed(k, reason) => eprintln!("{} failed: {}", k.port, reason),
+        DetectionEvent::Connected(k) => println!("Connected on {}", describe(&k)),
+    };
+    let result = opc
+        .detect_connection(builder, &mut known, &options, log)
+        .await
+        .with_context(|| "Connect");
+    known
+        .save(&path)
+        .with_context(|| "Saving the known ports")?;
+    result.map(|_| ())
+}
+
+/// Queue file given by `--queue-file`, or the default one in the user data directory.
+fn queue_file(args: &ArgMatches) -> Result<PathBuf> {
+    if let Some(path) = args.get_one::<String>("queue-file") {
+        return Ok(path.into());
+    }
+    let dirs = directories::ProjectDirs::from("rs", "", "octoprint-client")
+        .ok_or(anyhow!("No home directory, use --queue-file"))?;
+    Ok(dirs.data_dir().join("queue.json"))
+}
+
+/// Queue commands which do not need a printer.
+fn queue(args: &ArgMatches) -> Result<()> {
+    let path = queue_file(args)?;
+    // Changes are made under a lock, the queue may be run at the same time
+    let update = |change: &dyn Fn(&mut PrintQueue) -> Result<()>| {
+        let queue = PrintQueue::update(&path, |queue| change(queue).map(|_| queue.clone()))
+            .with_context(|| format!("Saving {}", path.display()))??;
+        print_queue(&queue);
+        Ok(())
+    };
+    match args.subcommand() {
+        Some(("add", sub_match)) => {
+            let file = sub_match.get_one::<String>("file").unwrap();
+            let file = if sub_match.get_flag("local") {
+                QueueFile::Local(
+                    std::fs::canonicalize(file).with_context(|| format!("Opening {}", file))?,
+                )
+            } else {
+                QueueFile::Remote(file.trim_start_matches('/').to_string())
+            };
+            let constraints = Constraints {
+                printer: sub_match.get_one::<String>("printer").cloned(),
+                group: sub_match.get_one::<String>("group").cloned(),
+                profile: sub_match.get_one::<String>("profile").cloned(),
+                nozzle: sub_match.get_one::<f64>("nozzle").copied(),
+            };
+            let copies = *sub_match.get_one::<u32>("copies").unwrap();
+            update(&|queue| {
+                let id = queue.add(file.clone(), copies, constraints.clone());
+                println!("Added as #{}", id);
+                Ok(())
+            })
+        }
+        Some(("rm", sub_match)) => {
+            let id = *sub_match.get_one::<u64>("id").unwrap();
+            update(&|queue| {
+                queue
+                    .remove(id)
+                    .map(|_| ())
+                    .ok_or(anyhow!("No item #{} in the queue", id))
+            })
+        }
+        Some(("reorder", sub_match)) => {
+            let id = *sub_match.get_one::<u64>("id").unwrap();
+            let position = *sub_match.get_one::<usize>("position").unwrap();
+            update(&|queue| {
+                if queue.reorder(id, position.saturating_sub(1)) {
+                    Ok(())
+                } else {
+                    Err(anyhow!("No item #{} in the queue", id))
+                }
+            })
+        }
+        _ => {
+            let queue =
+                PrintQueue::load(&path).with_context(|| format!("Loading {}", path.display()))?;
+            print_queue(&queue);
+            Ok(())
+        }
+    }
+}
+
+fn print_queue(queue: &PrintQueue) {
+    if queue.items.is_empty() {
+        println!("Queue is empty");
+        return;
+    }
+    for item in &queue.items {
+        let mut line = format!(
+            "#{:<4} {} [{}/{}]",
+            item.id, item.file, item.started, item.copies
+        );
+        if !item.constraints.is_empty() {
+            line.push_str(&format!(" {}", item.constraints));
+        }
+        println!("{}", line);
+    }
+}
+
+async fn queue_run(fleet: &Fleet, args: &ArgMatches) -> Result<()> {
+    let path = queue_file(args)?;
+    let mut scheduler =
+        Scheduler::load(&path).with_context(|| format!("Loading {}", path.display()))?;
+    if scheduler.queue.items.is_empty() {
+        println!("Queue is empty");
+        return Ok(());
+    }
+    let interval = *args.get_one::<Duration>("interval").unwrap();
+    let ask_again = *args.get_one::<Duration>("ask-again").unwrap();
+    scheduler.ask_again = ask_again;
+
+    log("Running the queue, press Ctrl-C to stop");
+    scheduler
+        .run(
+            fleet,
+            interval,
+            |printer, item| {
+                // Nobody can check the bed for us, a start must always be confirmed
+                Confirm::new()
+                    .with_prompt(format!(
+                        "{} is ready, bed is clear to print {}?",
+                        printer, item.file
+                    ))
+                    .interact()
+                    .unwrap_or(false)
+            },
+            |event| match event {
+                SchedulerEvent::Started { printer, item } => log(&format!(
+                    "Started {} on {} [{}/{}]",
+                    item.file,
+                    printer,
+                    item.started + 1,
+                    item.copies
+                )),
+                SchedulerEvent::Declined { printer } => log(&format!(
+                    "Skipping {} until its state changes or for {:?}",
+                    printer, ask_again
+                )),
+                SchedulerEvent::Failed { printer, error } => log(&format!(
+                    "{}",
+                    Style::new().red().bold().apply_to(format!(
+                        "{}: {}",
+                        printer,
+                        format_error(&error)
+                    ))
+                )),
+            },
+            async {
+                let _ = tokio::signal::ctrl_c().await;
+            },
+        )
+        .await
+        .with_context(|| format!("Queue {}", path.display()))?;
+    if scheduler.queue.items.is_empty() {
+        log("Queue is empty");
+    }
+    Ok(())
+}
+
+/// Print "OK" or the error for each printer, fails if one of them failed.
+/// Print the result of a command on each printer, `hint` tells how to include the skipped ones.
+fn print_fleet_results(results: &[FleetResult<Outcome>], hint: Option<&str>) -> Result<()> {
+    let width = results.iter().map(|r| r.name.len()).max().unwrap_or(0);
+    let mut failed = 0;
+    let mut skipped = 0;
+    for r in results {
+        match &r.result {
+            Ok(Outcome::Done) => println!(
+                "{:width$} : {}",
+                r.name,
+                Style::new().green().apply_to("OK")
+            ),
+            Ok(Outcome::Skipped(state)) => {
+                skipped += 1;
+                println!(
+                    "{:width$} : {}",
+                    r.name,
+                    Style::new()
+                        .yellow()
+                        .apply_to(format!("Skipped ({})", state))
+                )
+            }
+            Err(e) => {
+                failed += 1;
+                println!(
+                    "{:width$} : {}",
+                    r.name,
+                    Style::new().red().apply_to(format_error(e))
+                )
+            }
+        }
+    }
+    if skipped > 0 {
+        match hint {
+            Some(hint) => println!(
+                "Skipped {} of {} printers, {}",
+                skipped,
+                results.len(),
+                hint
+            ),
+            None => println!("Skipped {} of {} printers", skipped, results.len()),
+        }
+    }
+    if failed > 0 {
+        Err(anyhow!(
+            "Failed on {} of {} printers",
+            failed,
+            results.len()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Short description of a client error, for the tables.
+fn format_error(error: &OctoPrintClientError) -> String {
+    match error {
+        OctoPrintClientError::ServerError(message)
+        | OctoPrintClientError::ConflictError(message) => message.clone(),
+        OctoPrintClientError::ClientError(e) if e.is_connect() => "Unreachable".to_string(),
+        OctoPrintClientError::TimeoutError(_) => "No answer".to_string(),
+        e => e.to_string(),
+    }
+}
+
+async fn print_fleet_status(fleet: &Fleet, time_format: &str) -> Result<()> {
+    let header = [
+        "NAME",
+        "CONNECTION",
+        "JOB",
+        "PROGRESS",
+        "ETA",
+        "TOOL",
+        "BED",
+        "FILE",
+    ];
+    let mut rows = Vec::new();
+    for status in fleet.status().await {
+        let mut row = vec![status.name];
+        let style = match &status.result {
+            Ok(s) => {
+                let job = &s.job;
+                let temperature = s.printer.as_ref().and_then(|p| p.temperature.as_ref());
+                row.extend([
+                    s.connection.current.state.to_string(),
+                    job.state.to_string(),
+                    job.progress
+                        .completion
+                        .map(|c| format!("{:.1}%", c))
+                        .unwrap_or_default(),
+                    job.estimated_completion()
+                        .map(|end| format_time(end, time_format))
+                        .unwrap_or_default(),
+                    temperature
+                        .and_then(|t| t.tool(0))
+                        .map(format_temperature)
+                        .unwrap_or_default(),
+                    temperature
+                        .and_then(|t| t.bed.as_ref())
+                        .map(format_temperature)
+                        .unwrap_or_default(),
+                    job.job.file.path.clone().unwrap_or_default(),
+                ]);
+                if job.state.is_error() {
+                    Style::new().red()
+                } else if job.state.is_printing() {
+                    Style::new().green()
+                } else {
+                    Style::new()
+                }
+            }
+            Err(e) => {
+                row.push(format_error(e));
+                Style::new().red()
+            }
+        };
+        rows.push((row, style));
+    }
+    print_table(&header, rows);
+    Ok(())
+}
+
+/// Print rows aligned under a bold header, rows shorter than the header are not aligned.
+fn print_table(header: &[&str], rows: Vec<(Vec<String>, Style)>) {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for (row, _) in &rows {
+        // Errors span the whole line
+        if row.len() == header.len() {
+            for (w, cell) in widths.iter_mut().zip(row) {
+                *w = (*w).max(cell.chars().count());
+            }
+        }
+    }
+    let format_row = |row: &[String]| {
+        row.iter()
+            .zip(&widths)
+            .map(|(cell, w)| format!("{:w$}", cell, w = w))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+    println!("{}", Style::new().bold().apply_to(format_row(&header)));
+    for (row, style) in rows {
+        println!("{}", style.apply_to(format_row(&row)));
+    }
+}
+
+async fn autoupload(opc: &OctoPrintClient, args: &ArgMatches) -> Result<()> {
+    let dir = args.get_one::<String>("dir").unwrap();
+    let mut options = AutoUploadOptions {
+        remote_folder: args.get_one::<String>("remote-folder").cloned(),
+        after_upload: if args.get_flag("print") {
+            AfterUpload::Print
+        } else if args.get_flag("select") {
+            AfterUpload::Select
+        } else {
+            AfterUpload::Nothing
+        },
+        archive_dir: args.get_one::<String>("archive").map(Into::into),
+        settle: *args.get_one::<Duration>("settle").unwrap(),
+        ..Default::default()
+    };
+    if let Some(include) = args.get_many::<String>("include") {
+        options.include = include.cloned().collect();
+    }
+
+    log(&format!("Watching {}, press Ctrl-C to stop", dir));
+    opc.auto_upload(
+        std::path::Path::new(dir),
+        &options,
+        args.get_flag("existing"),
+        |event| match event {
+            AutoUploadEvent::Uploaded { path, remote_path } => {
+                log(&format!("Uploaded {} to {}", path.display(), remote_path))
+            }
+            AutoUploadEvent::Selected(path) => log(&format!("Selected {}", path)),
+            AutoUploadEvent::Printing(path) => log(&format!("Printing {}", path)),
+            AutoUploadEvent::PrinterNotReady(path) => {
+                log(&format!("Printer is not ready, {} not selected", path))
+            }
+            AutoUploadEvent::Archived { path, archive } => log(&format!(
+                "Moved {} to {}",
+                path.display(),
+                archive.display()
+            )),
+            AutoUploadEvent::Failed { path, error } => log(&format!(
+                "{}",
+                Style::new().red().bold().apply_to(format!(
+                    "Upload of {} failed: {:?}",
+                    path.display(),
+                    error
+                ))
+            )),
+        },
+        async {
+            let _ = tokio::signal::ctrl_c().await;
+        },
+    )
+    .await
+    .with_context(|| format!("Watching {}", dir))
+}
+
+/// Print a line prefixed by the current time, for the long running commands.
+fn log(message: &str) {
+    #[cfg(feature = "chrono")]
+    println!(
+        "{} {}",
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+        message
+    );
+    #[cfg(not(feature = "chrono"))]
+    println!("{}", message);
+}
+
+fn format_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+fn print_file_info(entry: &FileEntry, time_format: &str) {
+    println!("Path       : {}", entry.path);
+    if let Some(size) = entry.size {
+        println!("Size       : {}", format_size(size));
+    }
+    if let Some(date) = entry.date {
+        println!("Uploaded   : {}", format_time(date, time_format));
+    }
+    if let Some(analysis) = &entry.gcode_analysis {
+        if let Some(time) = analysis.estimated_print_time {
+            println!("Print time : {}", format_duration(time));
+        }
+        if let Some(dimensions) = &analysis.dimensions {
             println!(
-                "Extruder : {}°C / {}°C",
-                temperature_data.actual, temperature_data.target
+                "Dimensions : {:.1} x {:.1} x {:.1} mm",
+                dimensions.width, dimensions.depth, dimensions.height
             );
         }
-        if let Some(temperature_data) = temperature_state.bed {
+    }
+    if let Some(prints) = &entry.prints {
+        println!(
+            "Prints     : {} succeeded, {} failed",
+            prints.success, prints.failure
+        );
+    }
+}
+
+/// Analyze a file and check it against a printer profile, print the report.
+async fn check_file(
+    opc: &OctoPrintClient,
+    file_name: &str,
+    profile: Option<&String>,
+) -> Result<CheckReport> {
+    let profile = match profile {
+        Some(id) => opc.get_printer_profile(id).await,
+        None => opc.get_current_printer_profile().await,
+    }
+    .with_context(|| "Getting printer profile")?;
+
+    let file = std::fs::File::open(file_name).with_context(|| format!("Opening {}", file_name))?;
+    let analysis =
+        gcode::analysis::analyze(std::io::BufReader::new(file), AnalyzerSettings::default())
+            .with_context(|| "Analyze")?;
+
+    println!(
+        "Checking \"{}\" for profile \"{}\"",
+        file_name, profile.name
+    );
+    let report = gcode::check::check(&analysis, &profile);
+    for finding in &report.findings {
+        let style = match finding.severity {
+            Severity::Error => Style::new().red().bold(),
+            Severity::Warning => Style::new().yellow(),
+            Severity::Info => Style::new(),
+        };
+        println!(
+            "{}",
+            style.apply_to(format!("{:7} : {}", finding.severity, finding.message))
+        );
+    }
+    Ok(report)
+}
+
+fn print_analysis(analysis: &Analysis) {
+    println!("Print time : {}", format_duration(analysis.print_time));
+    for (tool, usage) in analysis.filament.iter().enumerate() {
+        if usage.length != 0.0 {
             println!(
-                "Bed      : {}°C / {}°C",
-                temperature_data.actual, temperature_data.target
+                "Filament   : tool{} {:.2} m / {:.2} cm³",
+                tool,
+                usage.length / 1000.0,
+                usage.volume
             );
         }
     }
+    if let Some(bb) = &analysis.bounding_box {
+        let size = bb.size();
+        println!(
+            "Dimensions : {:.1} x {:.1} x {:.1} mm",
+            size[0], size[1], size[2]
+        );
+        println!(
+            "Model box  : X {:.1}..{:.1}, Y {:.1}..{:.1}, Z {:.1}..{:.1}",
+            bb.min[0], bb.max[0], bb.min[1], bb.max[1], bb.min[2], bb.max[2]
+        );
+    }
+    if let (Some(first), Some(height)) = (analysis.first_layer_height(), analysis.layer_height()) {
+        println!(
+            "Layers     : {} ({:.2} mm first layer, {:.2} mm layers)",
+            analysis.layer_count(),
+            first,
+            height
+        );
+    } else {
+        println!("Layers     : {}", analysis.layer_count());
+    }
+}
 
-    Ok(())
+fn format_temperature(data: &TemperatureData) -> String {
+    let format = |t: Option<f32>| t.map_or("-".to_string(), |t| format!("{}°C", t));
+    format!("{} / {}", format(data.actual), format(data.target))
 }
 
 async fn print_connection(opc: OctoPrintClient) -> Result<()> {
@@ -250,3 +2595,348 @@ async fn print_connection(opc: OctoPrintClient) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(feature = "history")]
+fn history_command() -> Command {
+    let printer = Arg::new("printer")
+        .short('p')
+        .long("printer")
+        .help("Only the jobs of this printer");
+    let since = Arg::new("since")
+        .long("since")
+        .help("Only the jobs started in this period (e.g. \"7d\")")
+        .value_parser(parse_duration);
+    Command::new("history")
+        .about("Local history of the print jobs, with statistics")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("db")
+                .long("db")
+                .help("History database, default is in the user data directory")
+                .global(true),
+        )
+        .subcommand(
+            Command::new("record")
+                .about("Follow the jobs of the printers and save them in the history")
+                .arg(
+                    Arg::new("interval")
+                        .long("interval")
+                        .help("Time between two polls of the printers")
+                        .value_parser(parse_interval)
+                        .default_value("10s"),
+                ),
+        )
+        .subcommand(
+            Command::new("ls")
+                .about("List the last jobs")
+                .arg(printer.clone())
+                .arg(since.clone())
+                .arg(
+                    Arg::new("limit")
+                        .short('n')
+                        .long("limit")
+                        .value_parser(value_parser!(usize))
+                        .default_value("20"),
+                ),
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("Success rate, utilization and filament used per printer and per week")
+                .arg(since.clone()),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Export the jobs as CSV")
+                .arg(printer)
+                .arg(since)
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("Output file (default: standard output)"),
+                ),
+        )
+}
+
+/// History database given by `--db`, or the default one in the user data directory.
+#[cfg(feature = "history")]
+fn history_database(args: &ArgMatches) -> Result<History> {
+    let path = match args.get_one::<String>("db") {
+        Some(path) => PathBuf::from(path),
+        None => directories::ProjectDirs::from("rs", "", "octoprint-client")
+            .ok_or(anyhow!("No home directory, use --db"))?
+            .data_dir()
+            .join("history.sqlite"),
+    };
+    History::open(&path).with_context(|| format!("Opening {}", path.display()))
+}
+
+/// History commands which do not need a printer.
+#[cfg(feature = "history")]
+fn history(args: &ArgMatches, time_format: &str) -> Result<()> {
+    let history = history_database(args)?;
+    let (sub_name, sub_match) = args.subcommand().unwrap();
+    let now = SystemTime::now();
+    let since = sub_match
+        .get_one::<Duration>("since")
+        .map(|period| now - *period);
+    match sub_name {
+        "ls" => {
+            let filter = HistoryFilter {
+                printer: sub_match.get_one::<String>("printer").cloned(),
+                since,
+                limit: sub_match.get_one::<usize>("limit").copied(),
+            };
+            let header = ["START", "PRINTER", "RESULT", "DURATION", "FILAMENT", "FILE"];
+            let rows = history
+                .jobs(&filter)?
+                .into_iter()
+                .map(|job| {
+                    let style = match job.result {
+                        JobResult::Success => Style::new().green(),
+                        JobResult::Failed => Style::new().red(),
+                        JobResult::Cancelled => Style::new().yellow(),
+                    };
+                    let row = vec![
+                        format_time(job.start, time_format),
+                        job.printer.clone(),
+                        job.result.to_string(),
+                        format_duration(job.duration()),
+                        job.filament
+                            .map(|f| format!("{:.2} m", f / 1000.0))
+                            .unwrap_or_default(),
+                        job.file,
+                    ];
+                    (row, style)
+                })
+                .collect();
+            print_table(&header, rows);
+        }
+        "stats" => {
+            let header = [
+                "PRINTER",
+                "JOBS",
+                "SUCCESS",
+                "FAILED",
+                "CANCELLED",
+                "SUCCESS RATE",
+                "UTILIZATION",
+                "PRINT TIME",
+                "FILAMENT",
+            ];
+            let rows = history
+                .printer_stats(since, now)?
+                .into_iter()
+                .map(|s| {
+                    let row = vec![
+                        s.printer.clone(),
+                        s.jobs.to_string(),
+                        s.success.to_string(),
+                        s.failed.to_string(),
+                        s.cancelled.to_string(),
+                        format!("{:.1}%", s.success_rate() * 100.0),
+                        format!("{:.1}%", s.utilization * 100.0),
+                        format_duration(s.busy),
+                        format!("{:.2} m", s.filament / 1000.0),
+                    ];
+                    (row, Style::new())
+                })
+                .collect();
+            print_table(&header, rows);
+
+            println!();
+            let rows = history
+                .weekly_filament(since)?
+                .into_iter()
+                .map(|w| {
+                    let row = vec![
+                        w.week,
+                        w.jobs.to_string(),
+                        format!("{:.2} m", w.filament / 1000.0),
+                    ];
+                    (row, Style::new())
+                })
+                .collect();
+            print_table(&["WEEK", "JOBS", "FILAMENT"], rows);
+        }
+        "export" => {
+            let filter = HistoryFilter {
+                printer: sub_match.get_one::<String>("printer").cloned(),
+                since,
+                limit: None,
+            };
+            let mut jobs = history.jobs(&filter)?;
+            jobs.reverse();
+            match sub_match.get_one::<String>("output") {
+                Some(path) => {
+                    let file = std::fs::File::create(path)
+                        .with_context(|| format!("Creating {}", path))?;
+                    write_csv(&jobs, std::io::BufWriter::new(file))?;
+                    println!("Exported {} jobs to {}", jobs.len(), path);
+                }
+                None => write_csv(&jobs, std::io::stdout().lock())?,
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(feature = "history")]
+async fn history_record(fleet: &Fleet, args: &ArgMatches) -> Result<()> {
+    let history = history_database(args)?;
+    let (_, sub_match) = args.subcommand().unwrap();
+    let interval = *sub_match.get_one::<Duration>("interval").unwrap();
+
+    log("Recording the jobs, press Ctrl-C to stop");
+    octoprint_client::octoprintclient::history::record(
+        fleet,
+        &history,
+        interval,
+        |job| {
+            log(&format!(
+                "{}: {} {} after {}",
+                job.printer,
+                job.file,
+                job.result,
+                format_duration(job.duration())
+            ))
+        },
+        async {
+            let _ = tokio::signal::ctrl_c().await;
+        },
+    )
+    .await
+    .with_context(|| "Recording the history")
+}
+
+#[cfg(feature = "mqtt")]
+fn mqtt_bridge_command() -> Command {
+    Command::new("mqtt-bridge")
+        .about("Publish the state of the printers to an MQTT broker and accept commands")
+        .arg(
+            Arg::new("broker")
+                .short('b')
+                .long("broker")
+                .help("Broker address, as host[:port]")
+                .default_value("localhost:1883"),
+        )
+        .arg(
+            Arg::new("client-id")
+                .long("client-id")
+                .default_value("octoprint-client"),
+        )
+        .arg(Arg::new("username").short('u').long("username"))
+        .arg(
+            Arg::new("password")
+                .short('p')
+                .long("password")
+                .requires("username"),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .help("First level of the topics")
+                .default_value("octoprint"),
+        )
+        .arg(
+            Arg::new("interval")
+                .long("interval")
+                .help("Time between two polls of the printers")
+                .value_parser(parse_interval)
+                .default_value("5s"),
+        )
+        .arg(
+            Arg::new("allow")
+                .short('a')
+                .long("allow")
+                .help("Accept this command, can be repeated (no command is accepted by default)")
+                .value_parser(["pause", "resume", "cancel", "temperature", "gcode"])
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("allow-gcode")
+                .long("allow-gcode")
+                .help("Only accept this G-code command (e.g. \"M84\"), can be repeated")
+                .action(ArgAction::Append),
+        )
+}
+
+#[cfg(feature = "mqtt")]
+async fn mqtt_bridge(fleet: Fleet, args: &ArgMatches) -> Result<()> {
+    let broker = args.get_one::<String>("broker").unwrap();
+    let (host, port) = match broker.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| anyhow!("Invalid port in \"{}\"", broker))?,
+        ),
+        None => (broker.as_str(), 1883),
+    };
+    let mut mqtt =
+        rumqttc::MqttOptions::new(args.get_one::<String>("client-id").unwrap(), host, port);
+    mqtt.set_keep_alive(Duration::from_secs(30));
+    if let Some(username) = args.get_one::<String>("username") {
+        let password = args
+            .get_one::<String>("password")
+            .cloned()
+            .unwrap_or_default();
+        mqtt.set_credentials(username, password);
+    }
+
+    let values = |name: &str| {
+        args.get_many::<String>(name)
+            .map(|v| v.cloned().collect())
+            .unwrap_or_default()
+    };
+    let options = BridgeOptions {
+        prefix: args.get_one::<String>("prefix").unwrap().clone(),
+        interval: *args.get_one::<Duration>("interval").unwrap(),
+        allow: AllowList {
+            commands: values("allow"),
+            gcode: args
+                .get_many::<String>("allow-gcode")
+                .map(|v| v.map(|g| g.to_uppercase()).collect())
+                .unwrap_or_default(),
+        },
+    };
+
+    log(&format!(
+        "Bridging {} printers to {}, press Ctrl-C to stop",
+        fleet.members().len(),
+        broker
+    ));
+    Bridge::new(fleet, options)
+        .run(
+            mqtt,
+            |event| match event {
+                BridgeEvent::Connected => log("Connected to the broker"),
+                BridgeEvent::ConnectionError(e) => log(&format!(
+                    "{}",
+                    Style::new()
+                        .red()
+                        .apply_to(format!("Broker connection failed: {}", e))
+                )),
+                BridgeEvent::Command {
+                    printer,
+                    command,
+                    result: Ok(()),
+                } => log(&format!("{}: {} done", printer, command)),
+                BridgeEvent::Command {
+                    printer,
+                    command,
+                    result: Err(e),
+                } => log(&format!(
+                    "{}",
+                    Style::new()
+                        .red()
+                        .apply_to(format!("{}: {} failed: {}", printer, command, e))
+                )),
+            },
+            async {
+                let _ = tokio::signal::ctrl_c().await;
+            },
+        )
+        .await
+        .with_context(|| "MQTT bridge")
+}
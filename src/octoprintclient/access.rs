@@ -0,0 +1,449 @@
+//! Access control of OctoPrint: users, groups and permissions.
+//!
+//! Managing users requires the "ADMIN" permission.
+
+use std::io::BufRead;
+
+use hyper::Method;
+
+use super::datamodel::{
+    CurrentUser, Group, GroupList, NewUser, Permission, PermissionList, User, UserList, UserUpdate,
+};
+use super::{decode, encode_path, OctoPrintClient, OctoPrintClientError};
+
+/// Result of the import of one user, see `OctoPrintClient::import_users()`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImportResult {
+    Created(String),
+    /// A user with the same name exists, it is left unchanged.
+    Exists(String),
+    Failed(String, String),
+}
+
+impl OctoPrintClient {
+    pub async fn get_users(&self) -> Result<Vec<User>, OctoPrintClientError> {
+        let list: UserList = self.get("access/users").await?;
+        Ok(list.users)
+    }
+
+    pub async fn get_user(&self, name: &str) -> Result<User, OctoPrintClientError> {
+        self.get(&user_endpoint(name)).await
+    }
+
+    pub async fn create_user(&self, user: &NewUser) -> Result<(), OctoPrintClientError> {
+        self.send(
+            Method::POST,
+            "access/users",
+            Some(serde_json::to_string(user)?),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Change the fields set in `update`.
+    pub async fn update_user(
+        &self,
+        name: &str,
+        update: &UserUpdate,
+    ) -> Result<(), OctoPrintClientError> {
+        self.send(
+            Method::PUT,
+            &user_endpoint(name),
+            Some(serde_json::to_string(update)?),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_user(&self, name: &str) -> Result<(), OctoPrintClientError> {
+        self.send(Method::DELETE, &user_endpoint(name), None)
+            .await?;
+        Ok(())
+    }
+
+    /// Change the password of a user, `current` is required to change one's own password
+    /// without the "ADMIN" permission.
+    pub async fn change_password(
+        &self,
+        name: &str,
+        password: &str,
+        current: Option<&str>,
+    ) -> Result<(), OctoPrintClientError> {
+        let mut body = serde_json::json!({ "password": password });
+        if let Some(current) = current {
+            body["current"] = current.into();
+        }
+        self.send(
+            Method::PUT,
+            &format!("{}/password", user_endpoint(name)),
+            Some(body.to_string()),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Generate a new API key for a user, replacing the previous one.
+    pub async fn generate_api_key(&self, name: &str) -> Result<String, OctoPrintClientError> {
+        let resp = self
+            .send(
+                Method::POST,
+                &format!("{}/apikey", user_endpoint(name)),
+                None,
+            )
+            .await?;
+        let body: serde_json::Value = decode(resp).await?;
+        body["apikey"]
+            .as_str()
+            .map(|k| k.to_string())
+            .ok_or(OctoPrintClientError::ServerError(
+                "No API key in response".to_string(),
+            ))
+    }
+
+    pub async fn delete_api_key(&self, name: &str) -> Result<(), OctoPrintClientError> {
+        self.send(
+            Method::DELETE,
+            &format!("{}/apikey", user_endpoint(name)),
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_groups(&self) -> Result<Vec<Group>, OctoPrintClientError> {
+        let list: GroupList = self.get("access/groups").await?;
+        Ok(list.groups)
+    }
+
+    pub async fn get_group(&self, key: &str) -> Result<Group, OctoPrintClientError> {
+        self.get(&format!("access/groups/{}", encode_path(key)))
+            .await
+    }
+
+    pub async fn create_group(&self, group: &Group) -> Result<(), OctoPrintClientError> {
+        self.send(
+            Method::POST,
+            "access/groups",
+            Some(serde_json::to_string(group)?),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Replace the description, permissions, subgroups and default flag of a group.
+    pub async fn update_group(&self, group: &Group) -> Result<(), OctoPrintClientError> {
+        self.send(
+            Method::PUT,
+            &format!("access/groups/{}", encode_path(&group.key)),
+            Some(serde_json::to_string(group)?),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_group(&self, key: &str) -> Result<(), OctoPrintClientError> {
+        self.send(
+            Method::DELETE,
+            &format!("access/groups/{}", encode_path(key)),
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_permissions(&self) -> Result<Vec<Permission>, OctoPrintClientError> {
+        let list: PermissionList = self.get("access/permissions").await?;
+        Ok(list.permissions)
+    }
+
+    /// The user behind the API key (or session) of the client.
+    pub async fn get_current_user(&self) -> Result<CurrentUser, OctoPrintClientError> {
+        self.get("currentuser").await
+    }
+
+    /// Create the users not existing yet, one after the other.
+    ///
+    /// Nothing is created if a user is in a group unknown by the server.
+    pub async fn import_users(
+        &self,
+        users: &[NewUser],
+    ) -> Result<Vec<ImportResult>, OctoPrintClientError> {
+        let groups = self.get_groups().await?;
+        let unknown: Vec<&str> = users
+            .iter()
+            .flat_map(|u| u.groups.iter().flatten())
+            .filter(|g| !groups.iter().any(|group| &group.key == *g))
+            .map(|g| g.as_str())
+            .collect();
+        if let Some(group) = unknown.first() {
+            return Err(OctoPrintClientError::ServerError(format!(
+                "Unknown group \"{}\"",
+                group
+            )));
+        }
+
+        let existing = self.get_users().await?;
+        let mut results = Vec::new();
+        for user in users {
+            if existing.iter().any(|u| u.name == user.name) {
+                results.push(ImportResult::Exists(user.name.clone()));
+                continue;
+            }
+            results.push(match self.create_user(user).await {
+                Ok(()) => ImportResult::Created(user.name.clone()),
+                Err(OctoPrintClientError::ServerError(e))
+                | Err(OctoPrintClientError::ConflictError(e)) => {
+                    ImportResult::Failed(user.name.clone(), e)
+                }
+                Err(e) => return Err(e),
+            });
+        }
+        Ok(results)
+    }
+}
+
+fn user_endpoint(name: &str) -> String {
+    format!("access/users/{}", encode_path(name))
+}
+
+/// Split a CSV line, fields may be quoted with `"`, quotes are doubled inside quoted fields.
+fn split_csv_line(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quote".to_string());
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+/// Read users from CSV, with a header line naming the columns.
+///
+/// The "name" and "password" columns are required. "groups" and "permissions" are lists of
+/// keys separated by `;`, the users get the default groups of the server if "groups" is absent
+/// or empty. "active" is "true" (default) or "false".
+pub fn parse_users_csv<R: BufRead>(reader: R) -> Result<Vec<NewUser>, String> {
+    let mut lines = reader.lines().enumerate();
+    let header = match lines.next() {
+        Some((_, line)) => split_csv_line(&line.map_err(|e| e.to_string())?)?,
+        None => return Err("Empty CSV file".to_string()),
+    };
+    let column = |name: &str| header.iter().position(|h| h.trim() == name);
+    let (Some(name_col), Some(password_col)) = (column("name"), column("password")) else {
+        return Err("The CSV header must have \"name\" and \"password\" columns".to_string());
+    };
+    if let Some(unknown) = header
+        .iter()
+        .find(|h| !["name", "password", "groups", "permissions", "active"].contains(&h.trim()))
+    {
+        return Err(format!("Unknown column \"{}\"", unknown));
+    }
+    let list = |field: &str| -> Vec<String> {
+        field
+            .split(';')
+            .map(|k| k.trim().to_string())
+            .filter(|k| !k.is_empty())
+            .collect()
+    };
+
+    let mut users = Vec::new();
+    for (idx, line) in lines {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = split_csv_line(&line).map_err(|e| format!("Line {}: {}", idx + 1, e))?;
+        if fields.len() != header.len() {
+            return Err(format!(
+                "Line {}: {} fields, expected {}",
+                idx + 1,
+                fields.len(),
+                header.len()
+            ));
+        }
+        let field = |col: Option<usize>| col.map(|c| fields[c].trim()).unwrap_or("");
+        let name = field(Some(name_col));
+        let password = &fields[password_col];
+        if name.is_empty() || password.is_empty() {
+            return Err(format!("Line {}: empty name or password", idx + 1));
+        }
+        let groups = list(field(column("groups")));
+        users.push(NewUser {
+            name: name.to_string(),
+            password: password.clone(),
+            active: match field(column("active")) {
+                "" | "true" => true,
+                "false" => false,
+                a => return Err(format!("Line {}: bad active value \"{}\"", idx + 1, a)),
+            },
+            groups: if groups.is_empty() {
+                None
+            } else {
+                Some(groups)
+            },
+            permissions: list(field(column("permissions"))),
+        });
+    }
+    Ok(users)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::testing::MockServer;
+
+    fn new_user(name: &str, groups: Option<&[&str]>) -> NewUser {
+        NewUser {
+            name: name.to_string(),
+            password: "secret".to_string(),
+            active: true,
+            groups: groups.map(|g| g.iter().map(|g| g.to_string()).collect()),
+            permissions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_users_csv() {
+        let csv = "name,password,groups,active\n\
+                   alice,\"pa,ss\",users;students,\n\
+                   \n\
+                   bob,\"say \"\"hi\"\"\",,false\n";
+        let users = parse_users_csv(csv.as_bytes()).unwrap();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].password, "pa,ss");
+        assert_eq!(
+            users[0].groups,
+            Some(vec!["users".to_string(), "students".to_string()])
+        );
+        assert!(users[0].active);
+        assert_eq!(users[1].password, "say \"hi\"");
+        assert_eq!(users[1].groups, None);
+        assert!(!users[1].active);
+
+        assert!(parse_users_csv("name,groups\nalice,users\n".as_bytes()).is_err());
+        assert!(parse_users_csv("name,password,email\n".as_bytes()).is_err());
+        let err = parse_users_csv("name,password\nalice\n".as_bytes()).unwrap_err();
+        assert!(err.starts_with("Line 2"), "{}", err);
+        assert!(parse_users_csv("name,password\n\"alice,x\n".as_bytes()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_users() {
+        let server = MockServer::start();
+        let c = server.client();
+
+        let me = c.get_current_user().await.unwrap();
+        assert_eq!(me.name.as_deref(), Some("_api"));
+        assert!(me.permissions.contains(&"ADMIN".to_string()));
+
+        c.create_user(&new_user("alice", None)).await.unwrap();
+        assert!(c.create_user(&new_user("alice", None)).await.is_err());
+        let alice = c.get_user("alice").await.unwrap();
+        assert!(alice.active);
+        assert_eq!(alice.groups, ["users"]);
+
+        let update = UserUpdate {
+            active: Some(false),
+            groups: Some(vec!["users".to_string(), "admins".to_string()]),
+            ..Default::default()
+        };
+        c.update_user("alice", &update).await.unwrap();
+        let request = server.requests().last().unwrap().clone();
+        assert_eq!(
+            request.body_json(),
+            serde_json::json!({"active": false, "groups": ["users", "admins"]})
+        );
+        let alice = c.get_user("alice").await.unwrap();
+        assert!(!alice.active);
+        assert!(alice.admin);
+
+        c.change_password("alice", "new secret", None)
+            .await
+            .unwrap();
+        assert_eq!(server.printer().users["alice"].password, "new secret");
+
+        let key = c.generate_api_key("alice").await.unwrap();
+        assert_eq!(c.get_user("alice").await.unwrap().apikey, Some(key));
+        c.delete_api_key("alice").await.unwrap();
+        assert_eq!(c.get_user("alice").await.unwrap().apikey, None);
+
+        assert_eq!(c.get_users().await.unwrap().len(), 2);
+        c.delete_user("alice").await.unwrap();
+        assert!(c.get_user("alice").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_groups() {
+        let server = MockServer::start();
+        let c = server.client();
+
+        let permissions = c.get_permissions().await.unwrap();
+        assert!(permissions.iter().any(|p| p.key == "PRINT"));
+        let groups = c.get_groups().await.unwrap();
+        assert!(groups.iter().any(|g| g.key == "admins" && !g.removable));
+
+        let mut students = Group {
+            key: "students".to_string(),
+            name: "Students".to_string(),
+            permissions: vec!["STATUS".to_string(), "FILES_LIST".to_string()],
+            ..Default::default()
+        };
+        c.create_group(&students).await.unwrap();
+        students.permissions.push("PRINT".to_string());
+        c.update_group(&students).await.unwrap();
+        let group = c.get_group("students").await.unwrap();
+        assert_eq!(group.permissions.len(), 3);
+        assert!(group.removable);
+
+        assert!(c.delete_group("admins").await.is_err());
+        c.delete_group("students").await.unwrap();
+        assert!(c.get_group("students").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_import_users() {
+        let server = MockServer::start();
+        let c = server.client();
+        c.create_user(&new_user("alice", None)).await.unwrap();
+
+        let users = [
+            new_user("alice", Some(&["users"])),
+            new_user("bob", Some(&["users", "admins"])),
+            new_user("carol", None),
+        ];
+        let results = c.import_users(&users).await.unwrap();
+        assert_eq!(
+            results,
+            [
+                ImportResult::Exists("alice".to_string()),
+                ImportResult::Created("bob".to_string()),
+                ImportResult::Created("carol".to_string()),
+            ]
+        );
+        assert_eq!(server.printer().users["bob"].groups, ["users", "admins"]);
+
+        let users = [
+            new_user("dave", None),
+            new_user("erin", Some(&["students"])),
+        ];
+        assert!(matches!(
+            c.import_users(&users).await,
+            Err(OctoPrintClientError::ServerError(e)) if e.contains("students")
+        ));
+        assert!(!server.printer().users.contains_key("dave"));
+    }
+}
@@ -0,0 +1,410 @@
+//! Watch a directory and upload the files written into it.
+//!
+//! A file is considered complete when the writer closed it (inotify close-write), or when its
+//! size did not change during the settle delay for writers keeping it open or platforms without
+//! close notifications.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use notify::event::{AccessKind, AccessMode, EventKind};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use super::sync::glob_match;
+use super::{OctoPrintClient, OctoPrintClientError, UploadOptions};
+use crate::gcode::metadata;
+
+/// What to do with a file once uploaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AfterUpload {
+    Nothing,
+    /// Select the file if the printer is idle.
+    Select,
+    /// Start printing the file if the printer is idle.
+    Print,
+}
+
+/// Options of `OctoPrintClient::auto_upload()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AutoUploadOptions {
+    /// Folder of the server to upload to, root of the storage if `None`.
+    pub remote_folder: Option<String>,
+    pub after_upload: AfterUpload,
+    /// Move the uploaded files there, they are left in place if `None`.
+    pub archive_dir: Option<PathBuf>,
+    /// Delay without size change after which a file still open is considered complete.
+    pub settle: Duration,
+    /// Only upload the files matching one of these patterns, all files if empty.
+    pub include: Vec<String>,
+}
+
+impl Default for AutoUploadOptions {
+    fn default() -> Self {
+        AutoUploadOptions {
+            remote_folder: None,
+            after_upload: AfterUpload::Nothing,
+            archive_dir: None,
+            settle: Duration::from_secs(2),
+            include: vec![
+                "*.gcode".to_string(),
+                "*.gco".to_string(),
+                "*.g".to_string(),
+            ],
+        }
+    }
+}
+
+impl AutoUploadOptions {
+    fn accepts(&self, path: &Path) -> bool {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return false,
+        };
+        !name.starts_with('.')
+            && (self.include.is_empty() || self.include.iter().any(|p| glob_match(p, &name)))
+    }
+}
+
+/// What happened to a file, reported to the caller of `auto_upload()`.
+#[derive(Debug)]
+pub enum AutoUploadEvent {
+    /// The file was uploaded to `remote_path`.
+    Uploaded {
+        path: PathBuf,
+        remote_path: String,
+    },
+    Selected(String),
+    Printing(String),
+    /// The printer is not ready (busy or not connected), the file was only uploaded.
+    PrinterNotReady(String),
+    Archived {
+        path: PathBuf,
+        archive: PathBuf,
+    },
+    Failed {
+        path: PathBuf,
+        error: OctoPrintClientError,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PendingFile {
+    size: Option<u64>,
+    changed: Instant,
+    closed: bool,
+}
+
+/// Files being written, waiting to be complete.
+#[derive(Debug, Default)]
+pub struct PendingFiles {
+    files: BTreeMap<PathBuf, PendingFile>,
+}
+
+impl PendingFiles {
+    /// Record the size of a file, `closed` if the writer closed it.
+    pub fn update(&mut self, path: &Path, size: Option<u64>, closed: bool, now: Instant) {
+        let entry = self.files.entry(path.to_path_buf()).or_insert(PendingFile {
+            size,
+            changed: now,
+            closed: false,
+        });
+        if entry.size != size {
+            entry.size = size;
+            entry.changed = now;
+        }
+        entry.closed |= closed;
+    }
+
+    /// Remove and return the files which are complete.
+    pub fn take_ready(&mut self, settle: Duration, now: Instant) -> Vec<PathBuf> {
+        let ready: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter(|(_, f)| f.size.is_some() && (f.closed || now - f.changed >= settle))
+            .map(|(p, _)| p.clone())
+            .collect();
+        for path in &ready {
+            self.files.remove(path);
+        }
+        ready
+    }
+
+    /// Forget the files which do not exist any more.
+    fn forget_missing(&mut self) {
+        self.files.retain(|p, _| p.exists());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+/// Move a file, copying it if the archive is on another file system.
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// Free path for `name` in `dir`, adding a number before the extension if needed.
+fn archive_path(dir: &Path, name: &str) -> PathBuf {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) => (stem, format!(".{}", extension)),
+        None => (name, String::new()),
+    };
+    let mut path = dir.join(name);
+    let mut n = 1;
+    while path.exists() {
+        path = dir.join(format!("{}-{}{}", stem, n, extension));
+        n += 1;
+    }
+    path
+}
+
+impl OctoPrintClient {
+    /// Upload a complete file as configured by `options`, then archive it.
+    pub async fn auto_upload_file<F: FnMut(AutoUploadEvent)>(
+        &self,
+        path: &Path,
+        options: &AutoUploadOptions,
+        log: &mut F,
+    ) -> Result<(), OctoPrintClientError> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let remote_path = match &options.remote_folder {
+            Some(folder) => format!("{}/{}", folder.trim_matches('/'), name),
+            None => name.clone(),
+        };
+
+        let idle = match options.after_upload {
+            AfterUpload::Nothing => false,
+            _ => self.get_current_job().await?.state.can_start(),
+        };
+        let metadata =
+            metadata::extract_metadata(std::io::BufReader::new(std::fs::File::open(path)?))?;
+        let upload_options = UploadOptions {
+            path: options.remote_folder.clone(),
+            select: idle,
+            print: idle && options.after_upload == AfterUpload::Print,
+            userdata: if metadata.is_empty() {
+                None
+            } else {
+                Some(serde_json::to_value(&metadata)?)
+            },
+        };
+        self.upload_with_options(std::fs::File::open(path)?, &name, &upload_options)
+            .await?;
+        log(AutoUploadEvent::Uploaded {
+            path: path.to_path_buf(),
+            remote_path: remote_path.clone(),
+        });
+        match (options.after_upload, idle) {
+            (AfterUpload::Nothing, _) => {}
+            (_, false) => log(AutoUploadEvent::PrinterNotReady(remote_path)),
+            (AfterUpload::Select, true) => log(AutoUploadEvent::Selected(remote_path)),
+            (AfterUpload::Print, true) => log(AutoUploadEvent::Printing(remote_path)),
+        }
+
+        if let Some(dir) = &options.archive_dir {
+            std::fs::create_dir_all(dir)?;
+            let archive = archive_path(dir, &name);
+            move_file(path, &archive)?;
+            log(AutoUploadEvent::Archived {
+                path: path.to_path_buf(),
+                archive,
+            });
+        }
+        Ok(())
+    }
+
+    /// Watch `dir` and upload the files written into it, until `shutdown` completes.
+    ///
+    /// The files already in `dir` are uploaded too if `existing` is set. Upload errors are
+    /// reported to `log` and do not stop the watch.
+    pub async fn auto_upload<F, S>(
+        &self,
+        dir: &Path,
+        options: &AutoUploadOptions,
+        existing: bool,
+        mut log: F,
+        shutdown: S,
+    ) -> Result<(), OctoPrintClientError>
+    where
+        F: FnMut(AutoUploadEvent),
+        S: Future<Output = ()>,
+    {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher = RecommendedWatcher::new(
+            move |event: notify::Result<notify::Event>| {
+                if let Ok(event) = event {
+                    let _ = tx.send(event);
+                }
+            },
+            notify::Config::default(),
+        )
+        .map_err(std::io::Error::other)?;
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(std::io::Error::other)?;
+
+        let mut pending = PendingFiles::default();
+        if existing {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_file() && options.accepts(&path) {
+                    let size = std::fs::metadata(&path).ok().map(|m| m.len());
+                    pending.update(&path, size, true, Instant::now());
+                }
+            }
+        }
+
+        let check_interval = (options.settle / 4).max(Duration::from_millis(50));
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => return Ok(()),
+                Some(event) = rx.recv() => {
+                    let closed = matches!(
+                        event.kind,
+                        EventKind::Access(AccessKind::Close(AccessMode::Write))
+                    );
+                    for path in event.paths.iter().filter(|p| options.accepts(p)) {
+                        let size = std::fs::metadata(path).ok().map(|m| m.len());
+                        pending.update(path, size, closed, Instant::now());
+                    }
+                }
+                _ = tokio::time::sleep(check_interval), if !pending.is_empty() => {}
+            }
+
+            pending.forget_missing();
+            for path in pending.take_ready(options.settle, Instant::now()) {
+                if let Err(error) = self.auto_upload_file(&path, options, &mut log).await {
+                    log(AutoUploadEvent::Failed { path, error });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::datamodel::State;
+    use crate::octoprintclient::testing::MockServer;
+
+    #[test]
+    fn test_pending_files() {
+        let start = Instant::now();
+        let settle = Duration::from_secs(2);
+        let mut pending = PendingFiles::default();
+        let a = Path::new("a.gcode");
+        let b = Path::new("b.gcode");
+
+        pending.update(a, Some(10), false, start);
+        pending.update(b, Some(10), false, start);
+        pending.update(a, Some(20), false, start + Duration::from_secs(1));
+        assert!(pending
+            .take_ready(settle, start + Duration::from_secs(1))
+            .is_empty());
+
+        // Size of b is stable for 2s, a is still growing
+        assert_eq!(
+            pending.take_ready(settle, start + Duration::from_secs(2)),
+            [b]
+        );
+
+        // Closed files are ready immediately
+        pending.update(a, Some(30), true, start + Duration::from_millis(2500));
+        assert_eq!(
+            pending.take_ready(settle, start + Duration::from_millis(2500)),
+            [a]
+        );
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn test_archive_path() {
+        let dir = std::env::temp_dir().join("octoprint-client-test-archive-path");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(archive_path(&dir, "a.gcode"), dir.join("a.gcode"));
+        std::fs::write(dir.join("a.gcode"), "").unwrap();
+        std::fs::write(dir.join("a-1.gcode"), "").unwrap();
+        assert_eq!(archive_path(&dir, "a.gcode"), dir.join("a-2.gcode"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_auto_upload() {
+        let base = std::env::temp_dir().join("octoprint-client-test-autoupload");
+        let _ = std::fs::remove_dir_all(&base);
+        let watched = base.join("watched");
+        std::fs::create_dir_all(&watched).unwrap();
+        std::fs::write(watched.join("existing.gcode"), "G28\n").unwrap();
+
+        let server = MockServer::start();
+        server.with_printer_mut(|p| {
+            p.connect_ticks = 0;
+            p.connect(None, None, None);
+        });
+        let c = server.client();
+        let options = AutoUploadOptions {
+            remote_folder: Some("incoming".to_string()),
+            after_upload: AfterUpload::Select,
+            archive_dir: Some(base.join("archive")),
+            settle: Duration::from_millis(200),
+            ..Default::default()
+        };
+
+        let (done_tx, done_rx) = tokio::sync::oneshot::channel::<()>();
+        let writer = {
+            let watched = watched.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                std::fs::write(watched.join("new.gcode"), "G28\nG1 X10\n").unwrap();
+                std::fs::write(watched.join("ignored.txt"), "text").unwrap();
+                tokio::time::sleep(Duration::from_millis(1000)).await;
+                let _ = done_tx.send(());
+            }
+        };
+        let mut events = Vec::new();
+        let watch = c.auto_upload(
+            &watched,
+            &options,
+            true,
+            |e| events.push(format!("{:?}", e)),
+            async {
+                let _ = done_rx.await;
+            },
+        );
+        let (result, _) = tokio::join!(watch, writer);
+        result.unwrap();
+
+        let printer = server.printer();
+        let paths: Vec<&str> = printer.files.keys().map(|p| p.as_str()).collect();
+        assert_eq!(paths, ["incoming/existing.gcode", "incoming/new.gcode"]);
+        // Both selected, the last one wins
+        assert_eq!(printer.job.unwrap().path, "incoming/new.gcode");
+        assert_eq!(printer.state, State::Operational);
+
+        assert!(base.join("archive/new.gcode").exists());
+        assert!(base.join("archive/existing.gcode").exists());
+        assert!(watched.join("ignored.txt").exists());
+        assert!(!watched.join("new.gcode").exists());
+        assert_eq!(
+            events.iter().filter(|e| e.starts_with("Uploaded")).count(),
+            2,
+            "{:?}",
+            events
+        );
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
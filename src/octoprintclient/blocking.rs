@@ -0,0 +1,460 @@
+//! Synchronous client, for programs without an async runtime.
+//!
+//! `BlockingOctoPrintClient` wraps an `OctoPrintClient` and runs its requests on a runtime it
+//! owns. Its methods must not be called from an async context, where blocking on the runtime
+//! panics: use `OctoPrintClient` there.
+//!
+//! ```no_run
+//! use octoprint_client::octoprintclient::blocking::BlockingOctoPrintClient;
+//! use octoprint_client::octoprintclient::Configuration;
+//!
+//! let client = BlockingOctoPrintClient::from_config(Configuration {
+//!     server_url: "http://octopi.local".to_string(),
+//!     api_key: "API_KEY".to_string(),
+//!     ..Default::default()
+//! })
+//! .unwrap();
+//! println!("{}", client.get_server_info().unwrap().version);
+//! ```
+
+use std::future::Future;
+use std::sync::Arc;
+
+use tokio::runtime::Runtime;
+
+use super::connection::{ConnectionBuilder, DetectionEvent, KnownPort, KnownPorts};
+use super::datamodel::*;
+use super::wait::WaitOptions;
+use super::{Configuration, OctoPrintClient, OctoPrintClientError, UploadOptions};
+
+/// Blocking counterpart of `OctoPrintClient`, its clones share the runtime and the session.
+#[derive(Clone, Debug)]
+pub struct BlockingOctoPrintClient {
+    client: OctoPrintClient,
+    runtime: Arc<Runtime>,
+}
+
+impl BlockingOctoPrintClient {
+    pub fn from_config(config: Configuration) -> Result<Self, OctoPrintClientError> {
+        Self::from_client(OctoPrintClient::from_config(config))
+    }
+
+    pub fn from_client(client: OctoPrintClient) -> Result<Self, OctoPrintClientError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(BlockingOctoPrintClient {
+            client,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// The async client, for the methods without blocking counterpart, see `block_on()`.
+    pub fn async_client(&self) -> &OctoPrintClient {
+        &self.client
+    }
+
+    /// Run a future on the runtime of the client, e.g. a call to `async_client()`.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    pub fn get_server_info(&self) -> Result<ServerInfo, OctoPrintClientError> {
+        self.block_on(self.client.get_server_info())
+    }
+
+    pub fn get_settings(&self) -> Result<Settings, OctoPrintClientError> {
+        self.block_on(self.client.get_settings())
+    }
+
+    pub fn get_current_job(&self) -> Result<JobInformation, OctoPrintClientError> {
+        self.block_on(self.client.get_current_job())
+    }
+
+    pub fn get_printer_state(&self) -> Result<PrinterInfo, OctoPrintClientError> {
+        self.block_on(self.client.get_printer_state())
+    }
+
+    pub fn get_printer_profiles(&self) -> Result<Vec<Profile>, OctoPrintClientError> {
+        self.block_on(self.client.get_printer_profiles())
+    }
+
+    pub fn get_printer_profile(&self, id: &str) -> Result<Profile, OctoPrintClientError> {
+        self.block_on(self.client.get_printer_profile(id))
+    }
+
+    pub fn get_current_printer_profile(&self) -> Result<Profile, OctoPrintClientError> {
+        self.block_on(self.client.get_current_printer_profile())
+    }
+
+    pub fn get_files(&self, recursive: bool) -> Result<FileList, OctoPrintClientError> {
+        self.block_on(self.client.get_files(recursive))
+    }
+
+    pub fn get_file(&self, path: &str) -> Result<FileEntry, OctoPrintClientError> {
+        self.block_on(self.client.get_file(path))
+    }
+
+    /// See `OctoPrintClient::download()`.
+    pub fn download(
+        &self,
+        path: &str,
+        max_bytes: Option<usize>,
+    ) -> Result<Vec<u8>, OctoPrintClientError> {
+        self.block_on(self.client.download(path, max_bytes))
+    }
+
+    pub fn delete_file(&self, path: &str) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.delete_file(path))
+    }
+
+    pub fn create_folder(&self, path: &str) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.create_folder(path))
+    }
+
+    /// See `OctoPrintClient::upload()`.
+    pub fn upload(&self, file: std::fs::File, file_name: &str) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.upload(file, file_name))
+    }
+
+    pub fn upload_with_options(
+        &self,
+        file: std::fs::File,
+        file_name: &str,
+        options: &UploadOptions,
+    ) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.upload_with_options(file, file_name, options))
+    }
+
+    pub fn send_gcode(&self, commands: &[&str]) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.send_gcode(commands))
+    }
+
+    pub fn select_file(&self, path: &str, print: bool) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.select_file(path, print))
+    }
+
+    pub fn cancel_job(&self) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.cancel_job())
+    }
+
+    pub fn pause_job(&self) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.pause_job())
+    }
+
+    pub fn resume_job(&self) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.resume_job())
+    }
+
+    pub fn set_tool_temperature(
+        &self,
+        tool: usize,
+        target: f32,
+    ) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.set_tool_temperature(tool, target))
+    }
+
+    pub fn set_bed_temperature(&self, target: f32) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.set_bed_temperature(target))
+    }
+
+    pub fn get_connection(&self) -> Result<PrinterConnection, OctoPrintClientError> {
+        self.block_on(self.client.get_connection())
+    }
+
+    pub fn connect(&self, cmd: &ConnectionCommand) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.connect(cmd))
+    }
+
+    pub fn connect_default(&self) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.connect_default())
+    }
+
+    /// See `OctoPrintClient::connect_with()`.
+    pub fn connect_with(
+        &self,
+        builder: &ConnectionBuilder,
+    ) -> Result<ConnectionCommand, OctoPrintClientError> {
+        self.block_on(self.client.connect_with(builder))
+    }
+
+    /// See `OctoPrintClient::detect_connection()`.
+    pub fn detect_connection<L>(
+        &self,
+        builder: &ConnectionBuilder,
+        known: &mut KnownPorts,
+        options: &WaitOptions,
+        log: L,
+    ) -> Result<KnownPort, OctoPrintClientError>
+    where
+        L: FnMut(DetectionEvent),
+    {
+        self.block_on(self.client.detect_connection(builder, known, options, log))
+    }
+
+    pub fn disconnect(&self) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.disconnect())
+    }
+
+    pub fn fake_ack(&self) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.fake_ack())
+    }
+
+    pub fn wait_for_printer<P>(
+        &self,
+        condition: P,
+        options: &WaitOptions,
+    ) -> Result<PrinterInfo, OctoPrintClientError>
+    where
+        P: FnMut(&PrinterInfo) -> bool,
+    {
+        self.block_on(self.client.wait_for_printer(condition, options))
+    }
+
+    pub fn wait_for_job<P>(
+        &self,
+        condition: P,
+        options: &WaitOptions,
+    ) -> Result<JobInformation, OctoPrintClientError>
+    where
+        P: FnMut(&JobInformation) -> bool,
+    {
+        self.block_on(self.client.wait_for_job(condition, options))
+    }
+
+    pub fn wait_for_connection<P>(
+        &self,
+        condition: P,
+        options: &WaitOptions,
+    ) -> Result<PrinterConnection, OctoPrintClientError>
+    where
+        P: FnMut(&PrinterConnection) -> bool,
+    {
+        self.block_on(self.client.wait_for_connection(condition, options))
+    }
+
+    pub fn wait_for_connection_state(
+        &self,
+        state: &State,
+        options: &WaitOptions,
+    ) -> Result<PrinterConnection, OctoPrintClientError> {
+        self.block_on(self.client.wait_for_connection_state(state, options))
+    }
+
+    pub fn wait_for_job_done(
+        &self,
+        options: &WaitOptions,
+    ) -> Result<JobInformation, OctoPrintClientError> {
+        self.block_on(self.client.wait_for_job_done(options))
+    }
+
+    /// See `OctoPrintClient::login()`.
+    pub fn login(
+        &self,
+        user: &str,
+        password: &str,
+        remember: bool,
+    ) -> Result<SessionUser, OctoPrintClientError> {
+        self.block_on(self.client.login(user, password, remember))
+    }
+
+    pub fn passive_login(&self) -> Result<SessionUser, OctoPrintClientError> {
+        self.block_on(self.client.passive_login())
+    }
+
+    pub fn logout(&self) -> Result<(), OctoPrintClientError> {
+        self.block_on(self.client.logout())
+    }
+
+    pub fn get_current_user(&self) -> Result<CurrentUser, OctoPrintClientError> {
+        self.block_on(self.client.get_current_user())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::testing::{MockServer, StoredFile, VirtualPrinter};
+    use hyper::{Method, StatusCode};
+    use std::time::Duration;
+
+    fn client(server: &MockServer) -> BlockingOctoPrintClient {
+        BlockingOctoPrintClient::from_client(server.client()).unwrap()
+    }
+
+    fn wait_options() -> WaitOptions {
+        WaitOptions {
+            timeout: Some(Duration::from_secs(5)),
+            poll_interval: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn test_get_server_info() {
+        let server = MockServer::start();
+        let c = client(&server);
+
+        assert_eq!(c.get_server_info().unwrap().version, "1.8.6");
+        assert_eq!(server.requests_to(Method::GET, "/api/server").len(), 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        let c = BlockingOctoPrintClient::from_config(Configuration {
+            server_url: "http://127.0.0.1:1".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(matches!(
+            c.get_server_info(),
+            Err(OctoPrintClientError::ClientError(_))
+        ));
+
+        let server = MockServer::start();
+        server.set_error(
+            Method::GET,
+            "/api/job",
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Something broke",
+        );
+        match client(&server).get_current_job() {
+            Err(OctoPrintClientError::ServerError(msg)) => assert_eq!(msg, "Something broke"),
+            r => panic!("Unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_printer() {
+        let server = MockServer::start();
+        let c = client(&server);
+
+        assert!(c.get_printer_state().is_err());
+        assert_eq!(c.get_current_job().unwrap().state, State::Closed);
+        assert!(c.get_current_printer_profile().unwrap().default);
+
+        c.connect_default().unwrap();
+        let printer = c.get_printer_state().unwrap();
+        assert!(printer.state.unwrap().flags.operational);
+
+        c.set_bed_temperature(60.0).unwrap();
+        let printer = c
+            .wait_for_printer(
+                |p| {
+                    p.temperature
+                        .as_ref()
+                        .and_then(|t| t.bed.as_ref())
+                        .and_then(|bed| bed.actual)
+                        .is_some_and(|actual| actual >= 60.0)
+                },
+                &wait_options(),
+            )
+            .unwrap();
+        assert_eq!(printer.temperature.unwrap().bed.unwrap().target, Some(60.0));
+    }
+
+    #[test]
+    fn test_upload_and_print() {
+        let server = MockServer::start();
+        server.with_printer_mut(|p| p.connect_ticks = 0);
+        let c = client(&server);
+
+        let path = std::env::temp_dir().join("octoprint-client-test-blocking-upload.gcode");
+        std::fs::write(&path, "G28\n").unwrap();
+        let options = UploadOptions {
+            path: Some("parts".to_string()),
+            select: false,
+            ..Default::default()
+        };
+        c.upload_with_options(std::fs::File::open(&path).unwrap(), "a.gcode", &options)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let paths: Vec<String> = c
+            .get_files(true)
+            .unwrap()
+            .all_files()
+            .iter()
+            .map(|f| f.path.clone())
+            .collect();
+        assert_eq!(paths, ["parts/a.gcode"]);
+        assert_eq!(c.download("parts/a.gcode", None).unwrap(), b"G28\n");
+
+        c.connect_default().unwrap();
+        c.select_file("parts/a.gcode", true).unwrap();
+        let job = c.wait_for_job_done(&wait_options()).unwrap();
+        assert_eq!(job.progress.completion, Some(100.0));
+        assert_eq!(job.job.file.path.as_deref(), Some("parts/a.gcode"));
+    }
+
+    #[test]
+    fn test_files() {
+        let server = MockServer::start();
+        server.with_printer_mut(|p| {
+            p.files.insert(
+                "a.gcode".to_string(),
+                StoredFile {
+                    content: b"G28\n".to_vec(),
+                    date: 0,
+                    userdata: None,
+                },
+            );
+        });
+        let c = client(&server);
+
+        c.create_folder("parts").unwrap();
+        assert_eq!(c.get_files(false).unwrap().files.len(), 2);
+        assert_eq!(c.get_file("a.gcode").unwrap().size, Some(4));
+        c.delete_file("a.gcode").unwrap();
+        assert!(c.get_file("a.gcode").is_err());
+    }
+
+    #[test]
+    fn test_connect_disconnect() {
+        let mut printer = VirtualPrinter::default();
+        printer.connect_ticks = 3;
+        let server = MockServer::with_printer(printer);
+        let c = client(&server);
+
+        let cmd = c
+            .connect_with(&ConnectionBuilder::new().port("VIRTUAL"))
+            .unwrap();
+        assert_eq!(cmd.baudrate, Some(115200));
+        let connection = c
+            .wait_for_connection_state(&State::Operational, &wait_options())
+            .unwrap();
+        assert_eq!(connection.current.port.as_deref(), Some("VIRTUAL"));
+
+        c.disconnect().unwrap();
+        let connection = c
+            .wait_for_connection_state(&State::Closed, &wait_options())
+            .unwrap();
+        assert_eq!(connection.current.port, None);
+
+        let options = WaitOptions {
+            timeout: Some(Duration::from_millis(100)),
+            poll_interval: Duration::from_millis(10),
+        };
+        assert!(matches!(
+            c.wait_for_connection(|_| false, &options),
+            Err(OctoPrintClientError::TimeoutError(_))
+        ));
+    }
+
+    #[test]
+    fn test_login_logout() {
+        let server = MockServer::start();
+        let c = BlockingOctoPrintClient::from_config(Configuration {
+            server_url: server.url(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert!(c.get_printer_state().is_err());
+        assert_eq!(c.login("admin", "admin", false).unwrap().user.name, "admin");
+        assert_eq!(c.get_current_user().unwrap().name.as_deref(), Some("admin"));
+        // The clones share the session
+        assert_eq!(c.clone().passive_login().unwrap().user.name, "admin");
+        c.logout().unwrap();
+        assert!(c.passive_login().is_err());
+    }
+}
@@ -0,0 +1,487 @@
+//! Serial connection between OctoPrint and the printer.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::datamodel::{ConnectionAction, ConnectionCommand, ConnectionOptions, State};
+use super::wait::WaitOptions;
+use super::{OctoPrintClient, OctoPrintClientError};
+
+/// Port letting the server detect the serial port of the printer.
+pub const AUTO_PORT: &str = "AUTO";
+/// Baudrate letting the server detect the baudrate of the printer.
+pub const AUTO_BAUDRATE: u32 = 0;
+
+/// Build a "connect" command, validated against the options of the server.
+///
+/// The values not set are the preferred ones of the server, or auto-detection for the port and
+/// baudrate without preference.
+///
+/// ```
+/// use octoprint_client::octoprintclient::connection::ConnectionBuilder;
+///
+/// let builder = ConnectionBuilder::new()
+///     .port("/dev/ttyACM0")
+///     .baudrate(115200)
+///     .save(true);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConnectionBuilder {
+    port: Option<String>,
+    baudrate: Option<u32>,
+    printer_profile: Option<String>,
+    save: bool,
+    autoconnect: Option<bool>,
+}
+
+impl ConnectionBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serial port, `AUTO_PORT` to detect it.
+    pub fn port(mut self, port: &str) -> Self {
+        self.port = Some(port.to_string());
+        self
+    }
+
+    /// Baudrate, `AUTO_BAUDRATE` to detect it.
+    pub fn baudrate(mut self, baudrate: u32) -> Self {
+        self.baudrate = Some(baudrate);
+        self
+    }
+
+    /// Identifier of the printer profile.
+    pub fn printer_profile(mut self, id: &str) -> Self {
+        self.printer_profile = Some(id.to_string());
+        self
+    }
+
+    /// Make the port, baudrate and profile the preferred ones of the server.
+    pub fn save(mut self, save: bool) -> Self {
+        self.save = save;
+        self
+    }
+
+    /// Connect when the server starts, unchanged if not set.
+    pub fn autoconnect(mut self, autoconnect: bool) -> Self {
+        self.autoconnect = Some(autoconnect);
+        self
+    }
+
+    /// The command, with the port, baudrate and profile existing on the server.
+    pub fn build(
+        &self,
+        options: &ConnectionOptions,
+    ) -> Result<ConnectionCommand, OctoPrintClientError> {
+        let invalid = |message: String| Err(OctoPrintClientError::ServerError(message));
+
+        let port = self
+            .port
+            .clone()
+            .or_else(|| options.port_preference.clone())
+            .unwrap_or(AUTO_PORT.to_string());
+        if port != AUTO_PORT && !options.ports.contains(&port) {
+            return invalid(format!(
+                "Unknown port {}, available: {}",
+                port,
+                options.ports.join(", ")
+            ));
+        }
+
+        let baudrate = self
+            .baudrate
+            .or(options.baudrate_preference)
+            .unwrap_or(AUTO_BAUDRATE);
+        if baudrate != AUTO_BAUDRATE && !options.baudrates.contains(&baudrate) {
+            return invalid(format!("Unsupported baudrate {}", baudrate));
+        }
+
+        let Some(profile) = self
+            .printer_profile
+            .clone()
+            .or_else(|| options.printer_profile_preference.clone())
+        else {
+            return invalid("No printer profile given nor preferred by the server".to_string());
+        };
+        if !options.printer_profiles.iter().any(|p| p.id == profile) {
+            return invalid(format!("Unknown printer profile {}", profile));
+        }
+
+        Ok(ConnectionCommand {
+            command: ConnectionAction::Connect,
+            port: Some(port),
+            baudrate: Some(baudrate),
+            printer_profile: Some(profile),
+            save: Some(self.save),
+            autoconnect: self.autoconnect,
+        })
+    }
+}
+
+impl OctoPrintClient {
+    /// Connect with the command built from the current options of the server, returns it.
+    pub async fn connect_with(
+        &self,
+        builder: &ConnectionBuilder,
+    ) -> Result<ConnectionCommand, OctoPrintClientError> {
+        let options = self.get_connection().await?.options;
+        let cmd = builder.build(&options)?;
+        self.connect(&cmd).await?;
+        Ok(cmd)
+    }
+
+    /// Send a fake acknowledgment to a printer waiting for one.
+    pub async fn fake_ack(&self) -> Result<(), OctoPrintClientError> {
+        self.connect(&ConnectionCommand::fake_ack()).await
+    }
+
+    /// Connect to the printer whichever port it is plugged on, as the ports may change when it
+    /// reboots.
+    ///
+    /// The port remembered in `known` for the printer profile is tried first, then `AUTO_PORT`,
+    /// then each port of the server. Each attempt lasts until the connection is operational or
+    /// fails, or until the timeout of `options`. The working combination is stored in `known`
+    /// and returned. The port set in `builder` and its `save` and `autoconnect` are ignored:
+    /// the preferences of the server are not changed.
+    pub async fn detect_connection<L>(
+        &self,
+        builder: &ConnectionBuilder,
+        known: &mut KnownPorts,
+        options: &WaitOptions,
+        mut log: L,
+    ) -> Result<KnownPort, OctoPrintClientError>
+    where
+        L: FnMut(DetectionEvent),
+    {
+        let server_options = self.get_connection().await?.options;
+        let attempt = ConnectionBuilder {
+            save: false,
+            autoconnect: None,
+            ..builder.clone()
+        };
+        // Validates the baudrate and profile before the first attempt
+        let profile = attempt
+            .build(&server_options)?
+            .printer_profile
+            .unwrap_or_default();
+        let server = self.config.server_url.clone();
+
+        let baudrate = builder.baudrate.unwrap_or(AUTO_BAUDRATE);
+        let mut candidates: Vec<KnownPort> = known
+            .get(&server, &profile)
+            .filter(|k| server_options.ports.contains(&k.port))
+            .cloned()
+            .into_iter()
+            .collect();
+        for port in
+            std::iter::once(AUTO_PORT).chain(server_options.ports.iter().map(|p| p.as_str()))
+        {
+            if !candidates.iter().any(|c| c.port == port) {
+                candidates.push(KnownPort {
+                    port: port.to_string(),
+                    baudrate,
+                });
+            }
+        }
+
+        for candidate in candidates {
+            log(DetectionEvent::Trying(candidate.clone()));
+            let cmd = attempt
+                .clone()
+                .port(&candidate.port)
+                .baudrate(candidate.baudrate)
+                .build(&server_options)?;
+            let result = match self.connect(&cmd).await {
+                Ok(()) => {
+                    self.wait_for_connection(
+                        |c| c.current.state == State::Operational || c.current.state.is_error(),
+                        options,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            let reason = match result {
+                Ok(c) if c.current.state == State::Operational => {
+                    let working = KnownPort {
+                        port: c.current.port.unwrap_or(candidate.port),
+                        baudrate: c.current.baudrate.unwrap_or(candidate.baudrate),
+                    };
+                    known.insert(&server, &profile, working.clone());
+                    log(DetectionEvent::Connected(working.clone()));
+                    return Ok(working);
+                }
+                Ok(c) => c.current.state.to_string(),
+                Err(OctoPrintClientError::ServerError(message))
+                | Err(OctoPrintClientError::ConflictError(message)) => message,
+                Err(e @ OctoPrintClientError::TimeoutError(_)) => e.to_string(),
+                Err(e) => return Err(e),
+            };
+            log(DetectionEvent::Failed(candidate, reason));
+            self.disconnect().await?;
+        }
+
+        Err(OctoPrintClientError::ServerError(format!(
+            "No working port found for printer profile {}",
+            profile
+        )))
+    }
+}
+
+/// Port and baudrate a printer was connected with.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct KnownPort {
+    pub port: String,
+    pub baudrate: u32,
+}
+
+/// Working port and baudrate of the printers, by server URL and printer profile, kept between
+/// runs by `OctoPrintClient::detect_connection()`.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct KnownPorts {
+    servers: BTreeMap<String, BTreeMap<String, KnownPort>>,
+}
+
+impl KnownPorts {
+    /// Load the ports saved at `path`, none if the file does not exist.
+    pub fn load(path: &Path) -> Result<KnownPorts, OctoPrintClientError> {
+        match std::fs::read(path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(KnownPorts::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Save the ports to `path`, the file is replaced atomically.
+    pub fn save(&self, path: &Path) -> Result<(), OctoPrintClientError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn get(&self, server_url: &str, profile: &str) -> Option<&KnownPort> {
+        self.servers.get(server_url)?.get(profile)
+    }
+
+    pub fn insert(&mut self, server_url: &str, profile: &str, port: KnownPort) {
+        self.servers
+            .entry(server_url.to_string())
+            .or_default()
+            .insert(profile.to_string(), port);
+    }
+}
+
+/// Progress of `OctoPrintClient::detect_connection()`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DetectionEvent {
+    Trying(KnownPort),
+    /// The attempt failed, with the reason.
+    Failed(KnownPort, String),
+    /// The printer is operational, with the actual port and baudrate when detected.
+    Connected(KnownPort),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::datamodel::{PrinterProfile, Profile};
+    use crate::octoprintclient::testing::{default_profile, MockServer, VirtualPrinter};
+    use hyper::Method;
+
+    fn options() -> ConnectionOptions {
+        ConnectionOptions {
+            baudrate_preference: Some(115200),
+            baudrates: vec![250000, 115200],
+            port_preference: None,
+            ports: vec!["/dev/ttyACM0".to_string(), "/dev/ttyUSB0".to_string()],
+            printer_profile_preference: Some("_default".to_string()),
+            printer_profiles: vec![
+                PrinterProfile {
+                    id: "_default".to_string(),
+                    name: "Default".to_string(),
+                },
+                PrinterProfile {
+                    id: "mk3".to_string(),
+                    name: "MK3".to_string(),
+                },
+            ],
+            autoconnect: Some(false),
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let cmd = ConnectionBuilder::new().build(&options()).unwrap();
+        assert_eq!(cmd.command, ConnectionAction::Connect);
+        assert_eq!(cmd.port.as_deref(), Some(AUTO_PORT));
+        assert_eq!(cmd.baudrate, Some(115200));
+        assert_eq!(cmd.printer_profile.as_deref(), Some("_default"));
+        assert_eq!(cmd.save, Some(false));
+        assert_eq!(cmd.autoconnect, None);
+
+        let cmd = ConnectionBuilder::new()
+            .port("/dev/ttyUSB0")
+            .baudrate(AUTO_BAUDRATE)
+            .printer_profile("mk3")
+            .save(true)
+            .autoconnect(true)
+            .build(&options())
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&cmd).unwrap(),
+            serde_json::json!({
+                "command": "connect",
+                "port": "/dev/ttyUSB0",
+                "baudrate": 0,
+                "printerProfile": "mk3",
+                "save": true,
+                "autoconnect": true,
+            })
+        );
+
+        for builder in [
+            ConnectionBuilder::new().port("/dev/ttyS0"),
+            ConnectionBuilder::new().baudrate(9600),
+            ConnectionBuilder::new().printer_profile("delta"),
+        ] {
+            assert!(builder.build(&options()).is_err(), "{:?}", builder);
+        }
+        let mut no_profile = options();
+        no_profile.printer_profile_preference = None;
+        assert!(ConnectionBuilder::new().build(&no_profile).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connect_with() {
+        let mut printer = VirtualPrinter::default();
+        printer.connect_ticks = 0;
+        printer.profiles.push(Profile {
+            id: "mk3".to_string(),
+            ..default_profile()
+        });
+        let server = MockServer::with_printer(printer);
+        let c = server.client();
+
+        let builder = ConnectionBuilder::new()
+            .printer_profile("mk3")
+            .save(true)
+            .autoconnect(true);
+        let cmd = c.connect_with(&builder).await.unwrap();
+        assert_eq!(cmd.port.as_deref(), Some("VIRTUAL"));
+        let printer = server.printer();
+        assert_eq!(printer.profile, "mk3");
+        assert_eq!(printer.profile_preference.as_deref(), Some("mk3"));
+        assert!(printer.autoconnect);
+
+        assert!(c
+            .connect_with(&ConnectionBuilder::new().port("/dev/ttyS0"))
+            .await
+            .is_err());
+
+        c.fake_ack().await.unwrap();
+        let request = server.requests_to(Method::POST, "/api/connection");
+        assert_eq!(
+            request.last().unwrap().body_json(),
+            serde_json::json!({"command": "fake_ack"})
+        );
+        assert_eq!(server.printer().fake_acks, 1);
+    }
+
+    #[tokio::test]
+    async fn test_detect_connection() {
+        let mut printer = VirtualPrinter::default();
+        printer.ports = vec!["/dev/ttyACM0".to_string(), "/dev/ttyACM1".to_string()];
+        printer.port_preference = Some("/dev/ttyACM0".to_string());
+        printer.printer_port = Some("/dev/ttyACM1".to_string());
+        printer.auto_detect = false;
+        let server = MockServer::with_printer(printer);
+        let c = server.client();
+        let options = WaitOptions {
+            timeout: Some(std::time::Duration::from_secs(5)),
+            poll_interval: std::time::Duration::from_millis(10),
+        };
+
+        let mut known = KnownPorts::default();
+        let mut events = Vec::new();
+        let port = c
+            .detect_connection(&ConnectionBuilder::new(), &mut known, &options, |e| {
+                events.push(e)
+            })
+            .await
+            .unwrap();
+        let working = KnownPort {
+            port: "/dev/ttyACM1".to_string(),
+            baudrate: 250000,
+        };
+        assert_eq!(port, working);
+        let tried: Vec<&str> = events
+            .iter()
+            .filter_map(|e| match e {
+                DetectionEvent::Trying(k) => Some(k.port.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(tried, [AUTO_PORT, "/dev/ttyACM0", "/dev/ttyACM1"]);
+        assert!(matches!(&events[1], DetectionEvent::Failed(k, _) if k.port == AUTO_PORT));
+        assert_eq!(
+            events.last(),
+            Some(&DetectionEvent::Connected(working.clone()))
+        );
+        assert_eq!(known.get(&server.url(), "_default"), Some(&working));
+        // The preferences are unchanged
+        assert_eq!(
+            server.printer().port_preference.as_deref(),
+            Some("/dev/ttyACM0")
+        );
+
+        // The remembered port is tried first
+        c.disconnect().await.unwrap();
+        let mut events = Vec::new();
+        c.detect_connection(&ConnectionBuilder::new(), &mut known, &options, |e| {
+            events.push(e)
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            events,
+            [
+                DetectionEvent::Trying(working.clone()),
+                DetectionEvent::Connected(working.clone())
+            ]
+        );
+
+        server.with_printer_mut(|p| p.printer_port = Some("/dev/ttyUSB0".to_string()));
+        assert!(c
+            .detect_connection(&ConnectionBuilder::new(), &mut known, &options, |_| {})
+            .await
+            .is_err());
+        assert_eq!(server.printer().state, State::Closed);
+    }
+
+    #[test]
+    fn test_known_ports() {
+        let dir = std::env::temp_dir().join(format!("known-ports-{}", std::process::id()));
+        let path = dir.join("ports.json");
+        assert_eq!(KnownPorts::load(&path).unwrap(), KnownPorts::default());
+
+        let mut known = KnownPorts::default();
+        let port = KnownPort {
+            port: "/dev/ttyUSB0".to_string(),
+            baudrate: 115200,
+        };
+        known.insert("http://mk3", "_default", port.clone());
+        known.save(&path).unwrap();
+        let loaded = KnownPorts::load(&path).unwrap();
+        assert_eq!(loaded.get("http://mk3", "_default"), Some(&port));
+        assert_eq!(loaded.get("http://mk3", "mk3"), None);
+        assert_eq!(loaded.get("http://other", "_default"), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,101 +1,535 @@
 #![allow(dead_code)]
 
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
 use serde_derive::{Deserialize, Serialize};
 
-#[derive(Deserialize, Debug)]
+/// Printer state as reported by OctoPrint in `JobInformation::state` and
+/// `CurrentConnection::state`.
+///
+/// States not known by this client are kept as `Unknown`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum State {
+    Offline,
+    Closed,
+    OfflineAfterError,
+    Error,
+    OpeningSerialConnection,
+    DetectingSerialConnection,
+    DetectingBaudrate,
+    Connecting,
+    Operational,
+    Starting,
+    StartingPrintFromSd,
+    Printing,
+    PrintingFromSd,
+    SendingFileToSd,
+    TransferringFileToSd,
+    Pausing,
+    Paused,
+    Resuming,
+    Cancelling,
+    Finishing,
+    Unknown(String),
+}
+
+impl State {
+    /// All the states known by this client.
+    pub const KNOWN: [State; 20] = [
+        State::Offline,
+        State::Closed,
+        State::OfflineAfterError,
+        State::Error,
+        State::OpeningSerialConnection,
+        State::DetectingSerialConnection,
+        State::DetectingBaudrate,
+        State::Connecting,
+        State::Operational,
+        State::Starting,
+        State::StartingPrintFromSd,
+        State::Printing,
+        State::PrintingFromSd,
+        State::SendingFileToSd,
+        State::TransferringFileToSd,
+        State::Pausing,
+        State::Paused,
+        State::Resuming,
+        State::Cancelling,
+        State::Finishing,
+    ];
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            State::Offline => "Offline",
+            State::Closed => "Closed",
+            State::OfflineAfterError => "Offline after error",
+            State::Error => "Error",
+            State::OpeningSerialConnection => "Opening serial connection",
+            State::DetectingSerialConnection => "Detecting serial connection",
+            State::DetectingBaudrate => "Detecting baudrate",
+            State::Connecting => "Connecting",
+            State::Operational => "Operational",
+            State::Starting => "Starting",
+            State::StartingPrintFromSd => "Starting print from SD",
+            State::Printing => "Printing",
+            State::PrintingFromSd => "Printing from SD",
+            State::SendingFileToSd => "Sending file to SD",
+            State::TransferringFileToSd => "Transferring file to SD",
+            State::Pausing => "Pausing",
+            State::Paused => "Paused",
+            State::Resuming => "Resuming",
+            State::Cancelling => "Cancelling",
+            State::Finishing => "Finishing",
+            State::Unknown(s) => s,
+        }
+    }
+
+    /// Serial connection is being established.
+    pub fn is_connecting(&self) -> bool {
+        matches!(
+            self,
+            State::OpeningSerialConnection
+                | State::DetectingSerialConnection
+                | State::DetectingBaudrate
+                | State::Connecting
+        )
+    }
+
+    /// Printer is connected, same as `PrinterFlags::operational`.
+    pub fn is_operational(&self) -> bool {
+        matches!(self, State::Operational) || self.is_busy()
+    }
+
+    /// Same as `PrinterFlags::printing`.
+    pub fn is_printing(&self) -> bool {
+        matches!(
+            self,
+            State::Starting
+                | State::StartingPrintFromSd
+                | State::Printing
+                | State::PrintingFromSd
+                | State::SendingFileToSd
+        )
+    }
+
+    /// Same as `PrinterFlags::paused`.
+    pub fn is_paused(&self) -> bool {
+        matches!(self, State::Paused)
+    }
+
+    /// Same as `PrinterFlags::error`.
+    pub fn is_error(&self) -> bool {
+        match self {
+            State::Error | State::OfflineAfterError => true,
+            State::Unknown(s) => s.to_lowercase().contains("error"),
+            _ => false,
+        }
+    }
+
+    /// Same as `PrinterFlags::closed_on_error`.
+    pub fn is_closed_or_error(&self) -> bool {
+        matches!(self, State::Offline | State::Closed) || self.is_error()
+    }
+
+    /// A job is running (or paused) or a file is transferred to the SD card.
+    pub fn is_busy(&self) -> bool {
+        self.is_printing()
+            || matches!(
+                self,
+                State::TransferringFileToSd
+                    | State::Pausing
+                    | State::Paused
+                    | State::Resuming
+                    | State::Cancelling
+                    | State::Finishing
+            )
+    }
+
+    /// A new job can be started, same as `PrinterFlags::ready`.
+    pub fn can_start(&self) -> bool {
+        matches!(self, State::Operational)
+    }
+}
+
+impl From<&str> for State {
+    fn from(s: &str) -> Self {
+        match s {
+            "Offline" => State::Offline,
+            "Closed" => State::Closed,
+            "Offline after error" => State::OfflineAfterError,
+            "Error" => State::Error,
+            "Opening serial connection" => State::OpeningSerialConnection,
+            "Detecting serial connection" => State::DetectingSerialConnection,
+            "Detecting baudrate" => State::DetectingBaudrate,
+            "Connecting" => State::Connecting,
+            "Operational" => State::Operational,
+            "Starting" => State::Starting,
+            "Starting print from SD" => State::StartingPrintFromSd,
+            "Printing" => State::Printing,
+            "Printing from SD" => State::PrintingFromSd,
+            "Sending file to SD" => State::SendingFileToSd,
+            "Transferring file to SD" => State::TransferringFileToSd,
+            "Pausing" => State::Pausing,
+            "Paused" => State::Paused,
+            "Resuming" => State::Resuming,
+            "Cancelling" => State::Cancelling,
+            "Finishing" => State::Finishing,
+            other => State::Unknown(other.to_string()),
+        }
+    }
+}
+
+impl From<String> for State {
+    fn from(s: String) -> Self {
+        State::from(s.as_str())
+    }
+}
+
+impl From<State> for String {
+    fn from(state: State) -> Self {
+        state.as_str().to_string()
+    }
+}
+
+impl std::str::FromStr for State {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(State::from(s))
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// (De)serialize an optional duration expressed in seconds.
+pub(crate) mod optional_seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(value: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(d) => s.serialize_some(&d.as_secs_f64()),
+            None => s.serialize_none(),
+        }
+    }
+
+    /// Negative values are read as zero, values too large for a duration as `None`.
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        let seconds: Option<f64> = Option::deserialize(d)?;
+        Ok(seconds.and_then(|s| Duration::try_from_secs_f64(s.max(0.0)).ok()))
+    }
+}
+
+/// (De)serialize an optional UNIX timestamp expressed in seconds.
+mod optional_timestamp {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    pub fn serialize<S: Serializer>(value: &Option<SystemTime>, s: S) -> Result<S::Ok, S::Error> {
+        match value.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+            Some(d) => s.serialize_some(&d.as_secs()),
+            None => s.serialize_none(),
+        }
+    }
+
+    /// Negative values are read as the epoch, values out of the range of `SystemTime` as `None`.
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<SystemTime>, D::Error> {
+        let seconds: Option<f64> = Option::deserialize(d)?;
+        Ok(seconds
+            .and_then(|s| Duration::try_from_secs_f64(s.max(0.0)).ok())
+            .and_then(|d| UNIX_EPOCH.checked_add(d)))
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct File {
-    pub date: Option<u64>,
+    #[serde(default, with = "optional_timestamp")]
+    pub date: Option<SystemTime>,
     pub display: Option<String>,
     pub name: Option<String>,
     pub origin: Option<String>,
     pub path: Option<String>,
-    pub size: Option<u32>,
+    pub size: Option<u64>,
 }
 
-#[derive(Deserialize, Debug)]
+impl File {
+    /// Upload date as UTC date-time.
+    #[cfg(feature = "chrono")]
+    pub fn date_time(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.date.map(chrono::DateTime::from)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct Tool {
-    pub length: f32,
-    pub volume: f32,
+    #[serde(default)]
+    pub length: f64,
+    #[serde(default)]
+    pub volume: f64,
 }
 
-#[derive(Deserialize, Debug)]
+/// Filament usage per tool, keyed by tool name ("tool0", "tool1", ...).
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct Filament {
-    pub tool0: Option<Tool>,
+    #[serde(flatten)]
+    pub tools: BTreeMap<String, Tool>,
+}
+
+impl Filament {
+    pub fn tool(&self, index: usize) -> Option<&Tool> {
+        self.tools.get(&format!("tool{}", index))
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct Job {
     pub file: File,
-    #[serde(rename = "estimatedPrintTime")]
-    pub estimated_print_time: Option<f32>,
-    #[serde(rename = "lastPrintTime")]
-    pub last_print_time: Option<f32>,
+    #[serde(rename = "estimatedPrintTime", default, with = "optional_seconds")]
+    pub estimated_print_time: Option<Duration>,
+    #[serde(rename = "averagePrintTime", default, with = "optional_seconds")]
+    pub average_print_time: Option<Duration>,
+    #[serde(rename = "lastPrintTime", default, with = "optional_seconds")]
+    pub last_print_time: Option<Duration>,
     pub filament: Option<Filament>,
+    pub user: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct Progress {
-    pub completion: Option<f32>,
-    pub filepos: Option<u32>,
-    #[serde(rename = "printTime")]
-    pub print_time: Option<u32>,
-    #[serde(rename = "printTimeLeft")]
-    pub print_time_left: Option<i64>,
+    pub completion: Option<f64>,
+    pub filepos: Option<u64>,
+    #[serde(rename = "printTime", default, with = "optional_seconds")]
+    pub print_time: Option<Duration>,
+    #[serde(rename = "printTimeLeft", default, with = "optional_seconds")]
+    pub print_time_left: Option<Duration>,
     #[serde(rename = "printTimeLeftOrigin")]
     pub print_time_left_origin: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct JobInformation {
     pub job: Job,
     pub progress: Progress,
-    pub state: String,
+    pub state: State,
     pub error: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+impl JobInformation {
+    /// Wall-clock time at which the job should end, computed from `now`.
+    pub fn estimated_completion_from(&self, now: SystemTime) -> Option<SystemTime> {
+        self.progress.print_time_left.map(|left| now + left)
+    }
+
+    /// Wall-clock time at which the job should end.
+    pub fn estimated_completion(&self) -> Option<SystemTime> {
+        self.estimated_completion_from(SystemTime::now())
+    }
+
+    /// Same as `estimated_completion()`, in local time.
+    #[cfg(feature = "chrono")]
+    pub fn estimated_completion_local(&self) -> Option<chrono::DateTime<chrono::Local>> {
+        self.estimated_completion().map(chrono::DateTime::from)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct FileRefs {
+    pub resource: String,
+    pub download: Option<String>,
+    pub model: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Dimensions {
+    pub width: f64,
+    pub depth: f64,
+    pub height: f64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct PrintingArea {
+    #[serde(rename = "minX")]
+    pub min_x: f64,
+    #[serde(rename = "maxX")]
+    pub max_x: f64,
+    #[serde(rename = "minY")]
+    pub min_y: f64,
+    #[serde(rename = "maxY")]
+    pub max_y: f64,
+    #[serde(rename = "minZ")]
+    pub min_z: f64,
+    #[serde(rename = "maxZ")]
+    pub max_z: f64,
+}
+
+/// Server side analysis of a G-code file.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct GcodeAnalysis {
+    #[serde(rename = "estimatedPrintTime", default, with = "optional_seconds")]
+    pub estimated_print_time: Option<Duration>,
+    pub filament: Option<Filament>,
+    pub dimensions: Option<Dimensions>,
+    #[serde(rename = "printingArea")]
+    pub printing_area: Option<PrintingArea>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct LastPrint {
+    #[serde(default, with = "optional_timestamp")]
+    pub date: Option<SystemTime>,
+    pub success: bool,
+    #[serde(rename = "printTime", default, with = "optional_seconds")]
+    pub print_time: Option<Duration>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct PrintStatistics {
+    pub success: u32,
+    pub failure: u32,
+    pub last: Option<LastPrint>,
+}
+
+/// File or folder of the Files API (`/api/files`).
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct FileEntry {
+    pub name: String,
+    pub display: Option<String>,
+    pub path: String,
+    /// "machinecode", "model" or "folder".
+    #[serde(rename = "type")]
+    pub file_type: String,
+    #[serde(rename = "typePath", default)]
+    pub type_path: Vec<String>,
+    pub origin: Option<String>,
+    /// SHA1 of the file content.
+    pub hash: Option<String>,
+    pub size: Option<u64>,
+    #[serde(default, with = "optional_timestamp")]
+    pub date: Option<SystemTime>,
+    pub refs: Option<FileRefs>,
+    #[serde(rename = "gcodeAnalysis")]
+    pub gcode_analysis: Option<GcodeAnalysis>,
+    pub prints: Option<PrintStatistics>,
+    /// Content of a folder, when listed recursively.
+    pub children: Option<Vec<FileEntry>>,
+    /// JSON document given on upload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub userdata: Option<serde_json::Value>,
+}
+
+impl FileEntry {
+    pub fn is_folder(&self) -> bool {
+        self.file_type == "folder"
+    }
+
+    /// This entry and all entries below it.
+    pub fn walk(&self) -> Vec<&FileEntry> {
+        let mut entries = vec![self];
+        for child in self.children.iter().flatten() {
+            entries.extend(child.walk());
+        }
+        entries
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct FileList {
+    pub files: Vec<FileEntry>,
+    /// Free space on the storage, in bytes.
+    pub free: Option<u64>,
+    /// Size of the storage, in bytes.
+    pub total: Option<u64>,
+}
+
+impl FileList {
+    /// All files (not folders), including the ones in sub-folders.
+    pub fn all_files(&self) -> Vec<&FileEntry> {
+        self.files
+            .iter()
+            .flat_map(|f| f.walk())
+            .filter(|f| !f.is_folder())
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct ServerInfo {
     pub version: String,
     pub safemode: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct ErrorMsg {
     pub error: String,
 }
 
-#[derive(Deserialize, Debug)]
+/// Temperatures of all heaters, tools are keyed by name ("tool0", "tool1", ...).
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct TemperatureState {
-    pub tool0: Option<TemperatureData>,
     pub bed: Option<TemperatureData>,
+    pub chamber: Option<TemperatureData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history: Option<Vec<TemperatureHistory>>,
+    #[serde(flatten)]
+    pub tools: BTreeMap<String, TemperatureData>,
+}
+
+impl TemperatureState {
+    pub fn tool(&self, index: usize) -> Option<&TemperatureData> {
+        self.tools.get(&format!("tool{}", index))
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct TemperatureData {
-    pub actual: f32,
-    pub target: f32,
+    pub actual: Option<f32>,
+    pub target: Option<f32>,
     pub offset: Option<f32>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TemperatureHistory {
+    pub time: u64,
+    #[serde(flatten)]
+    pub heaters: BTreeMap<String, TemperatureData>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct SDState {
     pub ready: bool,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct PrinterState {
-    text: String,
-    error: Option<String>,
-    flags: PrinterFlags,
+    pub text: String,
+    pub error: Option<String>,
+    pub flags: PrinterFlags,
+}
+
+impl PrinterState {
+    pub fn state(&self) -> State {
+        State::from(self.text.as_str())
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct PrinterFlags {
     pub operational: bool,
     pub paused: bool,
     pub printing: bool,
     pub pausing: bool,
     pub cancelling: bool,
+    #[serde(default)]
+    pub finishing: bool,
+    #[serde(default)]
+    pub resuming: bool,
     #[serde(rename = "sdReady")]
     pub sd_ready: bool,
     pub error: bool,
@@ -103,29 +537,128 @@ pub struct PrinterFlags {
     #[serde(rename = "closedOrError")]
     pub closed_on_error: bool,
 }
-#[derive(Deserialize, Debug)]
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct PrinterInfo {
     pub temperature: Option<TemperatureState>,
     pub sd: Option<SDState>,
     pub state: Option<PrinterState>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct CurrentConnection {
     pub baudrate: Option<u32>,
     pub port: Option<String>,
     #[serde(rename = "printerProfile")]
     pub printer_profile: String,
-    pub state: String,
+    pub state: State,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct PrinterProfile {
     pub id: String,
     pub name: String,
 }
 
-#[derive(Deserialize, Debug)]
+/// Printable area limits, when the printer cannot reach its whole volume.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct CustomBox {
+    pub x_min: f64,
+    pub x_max: f64,
+    pub y_min: f64,
+    pub y_max: f64,
+    pub z_min: f64,
+    pub z_max: f64,
+}
+
+/// `custom_box` is either `false` or a `CustomBox`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum CustomBoxSetting {
+    Enabled(CustomBox),
+    Disabled(bool),
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ProfileVolume {
+    /// "rectangular" or "circular".
+    #[serde(rename = "formFactor")]
+    pub form_factor: String,
+    /// "lowerleft" or "center".
+    pub origin: String,
+    pub width: f64,
+    pub depth: f64,
+    pub height: f64,
+    pub custom_box: Option<CustomBoxSetting>,
+}
+
+impl ProfileVolume {
+    pub fn is_circular(&self) -> bool {
+        self.form_factor == "circular"
+    }
+
+    /// Limits of the printable area, taking origin and custom box into account.
+    pub fn bounds(&self) -> CustomBox {
+        if let Some(CustomBoxSetting::Enabled(custom_box)) = &self.custom_box {
+            return custom_box.clone();
+        }
+        if self.origin == "center" {
+            CustomBox {
+                x_min: -self.width / 2.0,
+                x_max: self.width / 2.0,
+                y_min: -self.depth / 2.0,
+                y_max: self.depth / 2.0,
+                z_min: 0.0,
+                z_max: self.height,
+            }
+        } else {
+            CustomBox {
+                x_min: 0.0,
+                x_max: self.width,
+                y_min: 0.0,
+                y_max: self.depth,
+                z_min: 0.0,
+                z_max: self.height,
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ProfileExtruder {
+    pub count: u32,
+    #[serde(rename = "nozzleDiameter")]
+    pub nozzle_diameter: f64,
+    #[serde(default)]
+    pub offsets: Vec<(f64, f64)>,
+    #[serde(rename = "sharedNozzle", default)]
+    pub shared_nozzle: bool,
+}
+
+/// Complete printer profile, from `/api/printerprofiles`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub id: String,
+    pub name: String,
+    pub model: Option<String>,
+    #[serde(default)]
+    pub default: bool,
+    #[serde(default)]
+    pub current: bool,
+    pub volume: ProfileVolume,
+    #[serde(rename = "heatedBed")]
+    pub heated_bed: bool,
+    #[serde(rename = "heatedChamber", default)]
+    pub heated_chamber: bool,
+    pub extruder: ProfileExtruder,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ProfileList {
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct ConnectionOptions {
     #[serde(rename = "baudratePreference")]
     pub baudrate_preference: Option<u32>,
@@ -137,35 +670,699 @@ pub struct ConnectionOptions {
     pub printer_profile_preference: Option<String>,
     #[serde(rename = "printerProfiles")]
     pub printer_profiles: Vec<PrinterProfile>,
+    pub autoconnect: Option<bool>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct PrinterConnection {
     pub current: CurrentConnection,
     pub options: ConnectionOptions,
 }
 
-#[derive(Serialize, Debug)]
+/// Command of `POST /api/connection`.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionAction {
+    Connect,
+    Disconnect,
+    /// Acknowledge a command the printer did not answer, to unblock the communication.
+    FakeAck,
+}
 
+/// Body of `POST /api/connection`, see `connection::ConnectionBuilder` to build a valid
+/// "connect" command.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct ConnectionCommand {
-    pub command: String,
+    pub command: ConnectionAction,
+    /// Serial port, or "AUTO" to let the server detect it.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub port: Option<String>,
+    /// 0 to let the server detect it.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub baudrate: Option<u32>,
-    #[serde(rename = "printerProfile")]
+    #[serde(rename = "printerProfile", skip_serializing_if = "Option::is_none")]
     pub printer_profile: Option<String>,
+    /// Make the port, baudrate and profile the preferred ones.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub save: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub autoconnect: Option<bool>,
 }
 
-#[derive(Serialize, Debug)]
+impl ConnectionCommand {
+    pub fn fake_ack() -> Self {
+        ConnectionCommand {
+            command: ConnectionAction::FakeAck,
+            port: None,
+            baudrate: None,
+            printer_profile: None,
+            save: None,
+            autoconnect: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct DisconnectCommand {
     pub command: String,
 }
 
-impl DisconnectCommand {
-    pub fn default() -> Self {
+impl Default for DisconnectCommand {
+    fn default() -> Self {
         DisconnectCommand {
             command: "disconnect".to_string(),
         }
     }
 }
+
+/// Body of `POST /api/printer/command`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct GcodeCommand {
+    pub commands: Vec<String>,
+}
+
+impl GcodeCommand {
+    pub fn new(commands: &[&str]) -> Self {
+        GcodeCommand {
+            commands: commands.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+}
+
+/// Body of `POST /api/files/local/<path>` selecting a file.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct SelectCommand {
+    pub command: String,
+    pub print: bool,
+}
+
+/// Body of `POST /api/job`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct JobCommand {
+    pub command: String,
+    /// For the "pause" command: "pause", "resume" or "toggle".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+}
+
+impl JobCommand {
+    pub fn new(command: &str) -> Self {
+        JobCommand {
+            command: command.to_string(),
+            action: None,
+        }
+    }
+}
+
+/// Body of `POST /api/printer/tool` and `POST /api/printer/bed` setting a target temperature.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TargetCommand {
+    pub command: String,
+    /// Targets of the tools, keyed by tool name ("tool0", "tool1", ...).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub targets: Option<BTreeMap<String, f32>>,
+    /// Target of the bed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<f32>,
+}
+
+impl TargetCommand {
+    pub fn tool(index: usize, target: f32) -> Self {
+        TargetCommand {
+            command: "target".to_string(),
+            targets: Some(BTreeMap::from([(format!("tool{}", index), target)])),
+            target: None,
+        }
+    }
+
+    pub fn bed(target: f32) -> Self {
+        TargetCommand {
+            command: "target".to_string(),
+            targets: None,
+            target: Some(target),
+        }
+    }
+}
+
+/// Settings of the server (`/api/settings`), only the sections used by this client.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    #[serde(default)]
+    pub webcam: WebcamSettings,
+}
+
+/// Webcam section of the settings.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct WebcamSettings {
+    #[serde(rename = "webcamEnabled", default)]
+    pub webcam_enabled: bool,
+    #[serde(rename = "timelapseEnabled", default)]
+    pub timelapse_enabled: bool,
+    /// MJPEG stream, may be relative to the server.
+    #[serde(rename = "streamUrl")]
+    pub stream_url: Option<String>,
+    /// JPEG snapshot, may be relative to the server.
+    #[serde(rename = "snapshotUrl")]
+    pub snapshot_url: Option<String>,
+    #[serde(rename = "flipH", default)]
+    pub flip_h: bool,
+    #[serde(rename = "flipV", default)]
+    pub flip_v: bool,
+    /// Rotate the image by 90° counter-clockwise.
+    #[serde(rename = "rotate90", default)]
+    pub rotate_90: bool,
+    /// "16:9" or "4:3".
+    #[serde(rename = "streamRatio")]
+    pub stream_ratio: Option<String>,
+}
+
+/// When timelapse frames are captured.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimelapseType {
+    #[default]
+    Off,
+    /// On each layer change.
+    Zchange,
+    /// Every `interval` seconds.
+    Timed,
+}
+
+impl TimelapseType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimelapseType::Off => "off",
+            TimelapseType::Zchange => "zchange",
+            TimelapseType::Timed => "timed",
+        }
+    }
+}
+
+impl std::str::FromStr for TimelapseType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(TimelapseType::Off),
+            "zchange" => Ok(TimelapseType::Zchange),
+            "timed" => Ok(TimelapseType::Timed),
+            _ => Err(format!("Unknown timelapse type \"{}\"", s)),
+        }
+    }
+}
+
+impl fmt::Display for TimelapseType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Timelapse configuration, from `/api/timelapse`.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct TimelapseConfig {
+    #[serde(rename = "type")]
+    pub timelapse_type: TimelapseType,
+    /// Frames per second of the rendered video.
+    #[serde(default)]
+    pub fps: u32,
+    /// Time the last frame is repeated at the end of the video, in seconds.
+    #[serde(rename = "postRoll", default)]
+    pub post_roll: u32,
+    /// Time between two frames of a "timed" timelapse, in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u32>,
+    /// Keep capturing frames during the post roll of a "timed" timelapse.
+    #[serde(rename = "capturePostRoll", skip_serializing_if = "Option::is_none")]
+    pub capture_post_roll: Option<bool>,
+    /// Z-hop of the retractions, ignored by "zchange" timelapses, in mm.
+    #[serde(rename = "retractionZHop", skip_serializing_if = "Option::is_none")]
+    pub retraction_z_hop: Option<f64>,
+    /// Minimum time between two frames of a "zchange" timelapse, in seconds.
+    #[serde(rename = "minDelay", skip_serializing_if = "Option::is_none")]
+    pub min_delay: Option<f64>,
+}
+
+/// Body of `POST /api/timelapse`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TimelapseConfigCommand {
+    #[serde(flatten)]
+    pub config: TimelapseConfig,
+    /// Make the configuration the default one.
+    pub save: bool,
+}
+
+/// A rendered timelapse.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TimelapseFile {
+    pub name: String,
+    /// Human readable size, e.g. "1.2MB".
+    pub size: String,
+    pub bytes: u64,
+    /// Human readable date, e.g. "2023-01-15 10:22".
+    pub date: String,
+    pub url: Option<String>,
+}
+
+/// Frames of a timelapse not rendered yet.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct UnrenderedTimelapse {
+    pub name: String,
+    pub size: String,
+    pub bytes: u64,
+    pub date: String,
+    /// Frames are still being captured.
+    #[serde(default)]
+    pub recording: bool,
+    #[serde(default)]
+    pub rendering: bool,
+    #[serde(default)]
+    pub processing: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TimelapseList {
+    pub config: TimelapseConfig,
+    #[serde(default)]
+    pub enabled: bool,
+    pub files: Vec<TimelapseFile>,
+    /// Only listed when requested.
+    #[serde(default)]
+    pub unrendered: Vec<UnrenderedTimelapse>,
+}
+
+/// A slicer of `/api/slicing`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Slicer {
+    pub key: String,
+    #[serde(rename = "displayName")]
+    pub display_name: String,
+    #[serde(default)]
+    pub default: bool,
+    /// The slicer can be used, e.g. the path of its executable is set.
+    #[serde(default)]
+    pub configured: bool,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, SlicingProfile>,
+}
+
+/// Slicing profile, only listed fields are sent when updating it.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct SlicingProfile {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub key: String,
+    #[serde(rename = "displayName", skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<bool>,
+    #[serde(skip_serializing)]
+    pub resource: Option<String>,
+    /// Settings of the slicer, only sent with a single profile.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+/// Options of the "slice" command of `/api/files/local/<path>`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SliceCommand {
+    pub slicer: String,
+    /// Path of the G-code file to create, the model path with a ".gcode" extension if `None`.
+    pub gcode: Option<String>,
+    /// Slicing profile, the default one of the slicer if `None`.
+    pub profile: Option<String>,
+    /// Settings overriding the ones of the profile.
+    pub overrides: BTreeMap<String, serde_json::Value>,
+    pub printer_profile: Option<String>,
+    /// Position of the center of the model on the bed.
+    pub position: Option<(f64, f64)>,
+    pub select: bool,
+    pub print: bool,
+}
+
+impl SliceCommand {
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut body = serde_json::json!({
+            "command": "slice",
+            "slicer": self.slicer,
+            "select": self.select,
+            "print": self.print,
+        });
+        let object = body.as_object_mut().unwrap();
+        if let Some(gcode) = &self.gcode {
+            object.insert("gcode".to_string(), gcode.clone().into());
+        }
+        if let Some(profile) = &self.profile {
+            object.insert("profile".to_string(), profile.clone().into());
+        }
+        for (key, value) in &self.overrides {
+            object.insert(format!("profile.{}", key), value.clone());
+        }
+        if let Some(printer_profile) = &self.printer_profile {
+            object.insert("printerProfile".to_string(), printer_profile.clone().into());
+        }
+        if let Some((x, y)) = self.position {
+            object.insert("position".to_string(), serde_json::json!({"x": x, "y": y}));
+        }
+        body
+    }
+}
+
+/// Progress of a slicing job, from the push socket.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct SlicingProgress {
+    pub slicer: String,
+    pub source_location: String,
+    pub source_path: String,
+    pub dest_location: String,
+    pub dest_path: String,
+    /// In %.
+    pub progress: f64,
+}
+
+/// Event sent on the push socket.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct PushEvent {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    #[serde(default)]
+    pub payload: serde_json::Value,
+}
+
+/// A user account of `/api/access/users`.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct User {
+    pub name: String,
+    pub active: bool,
+    /// Deprecated by the groups, true for members of "admins".
+    #[serde(default)]
+    pub admin: bool,
+    /// API key of the user, if one was generated.
+    pub apikey: Option<String>,
+    /// Keys of the groups of the user.
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Keys of the permissions given to the user, on top of the ones of its groups.
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct UserList {
+    pub users: Vec<User>,
+}
+
+/// Body of `POST /api/access/users`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct NewUser {
+    pub name: String,
+    pub password: String,
+    pub active: bool,
+    /// Groups of the user, the default groups of the server if `None`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permissions: Vec<String>,
+}
+
+/// Body of `PUT /api/access/users/<name>`, only the fields set are changed.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct UserUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<Vec<String>>,
+}
+
+/// A group of `/api/access/groups`, users get the permissions of their groups.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Group {
+    pub key: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    /// Keys of the groups whose permissions are included.
+    #[serde(default)]
+    pub subgroups: Vec<String>,
+    /// New users are added to the default groups.
+    #[serde(default)]
+    pub default: bool,
+    /// Built-in groups can not be removed, ignored when creating or updating a group.
+    #[serde(default)]
+    pub removable: bool,
+    #[serde(default)]
+    pub changeable: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct GroupList {
+    pub groups: Vec<Group>,
+}
+
+/// A permission of `/api/access/permissions`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Permission {
+    pub key: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// The permission gives control over the server or the printer.
+    #[serde(default)]
+    pub dangerous: bool,
+    /// Groups having this permission on a new installation.
+    #[serde(default)]
+    pub default_groups: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct PermissionList {
+    pub permissions: Vec<Permission>,
+}
+
+/// Response of `/api/login`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct SessionUser {
+    #[serde(flatten)]
+    pub user: User,
+    /// Identifier of the session, used to authenticate the push socket.
+    pub session: Option<String>,
+}
+
+/// User of the current session or API key, from `/api/currentuser`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct CurrentUser {
+    /// `None` for anonymous access.
+    pub name: Option<String>,
+    /// Keys of all the permissions of the user, including the ones of its groups.
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_state_from_string() {
+        assert_eq!(State::from("Operational"), State::Operational);
+        assert_eq!(State::from("Printing from SD"), State::PrintingFromSd);
+        assert_eq!(
+            State::from("Some plugin state"),
+            State::Unknown("Some plugin state".to_string())
+        );
+        assert_eq!(State::Finishing.to_string(), "Finishing");
+    }
+
+    #[test]
+    fn test_state_helpers() {
+        assert!(State::Operational.can_start());
+        assert!(State::Operational.is_operational());
+        assert!(!State::Operational.is_busy());
+        assert!(State::Paused.is_busy());
+        assert!(State::Paused.is_operational());
+        assert!(!State::Paused.can_start());
+        assert!(State::Closed.is_closed_or_error());
+        assert!(State::OfflineAfterError.is_error());
+        assert!(State::Unknown("Error: Too many consecutive timeouts".to_string()).is_error());
+        assert!(State::Connecting.is_connecting());
+    }
+
+    #[test]
+    fn test_invalid_times() {
+        let progress: Progress = serde_json::from_str(
+            r#"{"completion": null, "filepos": null, "printTime": -5, "printTimeLeft": 1e300}"#,
+        )
+        .unwrap();
+        assert_eq!(progress.print_time, Some(Duration::ZERO));
+        assert_eq!(progress.print_time_left, None);
+
+        let file: File = serde_json::from_str(r#"{"date": 1e300}"#).unwrap();
+        assert_eq!(file.date, None);
+        let file: File = serde_json::from_str(r#"{"date": 1e15}"#).unwrap();
+        assert!(file.date.is_some());
+    }
+
+    #[test]
+    fn test_server_info() {
+        let info: ServerInfo =
+            serde_json::from_str(include_str!("../../tests/responses/server.json")).unwrap();
+
+        assert_eq!(info.version, "1.8.6");
+        assert_eq!(info.safemode, None);
+    }
+
+    #[test]
+    fn test_job_idle() {
+        let job: JobInformation =
+            serde_json::from_str(include_str!("../../tests/responses/job_idle.json")).unwrap();
+
+        assert_eq!(job.state, State::Operational);
+        assert_eq!(job.job.file.path, None);
+        assert_eq!(job.job.filament, None);
+        assert_eq!(job.progress.completion, None);
+        assert_eq!(job.progress.print_time_left, None);
+        assert_eq!(job.estimated_completion(), None);
+    }
+
+    #[test]
+    fn test_job_printing() {
+        let job: JobInformation =
+            serde_json::from_str(include_str!("../../tests/responses/job_printing.json")).unwrap();
+
+        assert_eq!(job.state, State::Printing);
+        assert_eq!(job.job.file.size, Some(5_368_709_120));
+        assert_eq!(job.job.user.as_deref(), Some("rust"));
+        assert_eq!(job.progress.filepos, Some(4_294_967_396));
+        assert_eq!(job.progress.print_time, Some(Duration::from_secs(276)));
+        assert_eq!(job.progress.print_time_left, Some(Duration::from_secs(912)));
+        assert_eq!(
+            job.job.file.date,
+            Some(UNIX_EPOCH + Duration::from_secs(1668090815))
+        );
+        assert_eq!(job.job.estimated_print_time.unwrap().as_millis(), 1188043);
+
+        let now = UNIX_EPOCH + Duration::from_secs(1668091000);
+        assert_eq!(
+            job.estimated_completion_from(now),
+            Some(UNIX_EPOCH + Duration::from_secs(1668091912))
+        );
+
+        let filament = job.job.filament.as_ref().unwrap();
+        assert_eq!(filament.tools.len(), 2);
+        assert_eq!(filament.tool(1).unwrap().length, 120.5);
+
+        // Round trip through serialization
+        let json = serde_json::to_string(&job).unwrap();
+        assert_eq!(serde_json::from_str::<JobInformation>(&json).unwrap(), job);
+    }
+
+    #[test]
+    fn test_printer() {
+        let printer: PrinterInfo =
+            serde_json::from_str(include_str!("../../tests/responses/printer.json")).unwrap();
+
+        let state = printer.state.unwrap();
+        assert_eq!(state.state(), State::Printing);
+        assert!(state.flags.printing);
+        assert!(!state.flags.finishing);
+        assert!(!state.flags.resuming);
+
+        let temperature = printer.temperature.unwrap();
+        assert_eq!(temperature.tools.len(), 1);
+        assert_eq!(temperature.tool(0).unwrap().target, Some(210.0));
+        assert_eq!(temperature.bed.unwrap().actual, Some(60.0));
+        assert_eq!(temperature.chamber.unwrap().actual, None);
+        assert_eq!(temperature.history.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_connection() {
+        let conn: PrinterConnection =
+            serde_json::from_str(include_str!("../../tests/responses/connection.json")).unwrap();
+
+        assert_eq!(conn.current.state, State::Operational);
+        assert_eq!(conn.current.port.as_deref(), Some("VIRTUAL"));
+        assert_eq!(conn.options.baudrates.len(), 7);
+        assert_eq!(conn.options.printer_profiles[0].id, "_default");
+        assert_eq!(conn.options.autoconnect, Some(false));
+    }
+
+    #[test]
+    fn test_printer_profiles() {
+        let list: ProfileList =
+            serde_json::from_str(include_str!("../../tests/responses/printerprofiles.json"))
+                .unwrap();
+
+        assert_eq!(list.profiles.len(), 2);
+
+        let default = &list.profiles["_default"];
+        assert!(default.default);
+        assert!(default.heated_bed);
+        assert_eq!(default.extruder.nozzle_diameter, 0.4);
+        assert_eq!(
+            default.volume.custom_box,
+            Some(CustomBoxSetting::Disabled(false))
+        );
+        assert_eq!(default.volume.bounds().x_max, 200.0);
+
+        let delta = &list.profiles["delta"];
+        assert!(delta.volume.is_circular());
+        assert_eq!(delta.volume.bounds().x_min, -85.0);
+        assert_eq!(delta.extruder.count, 2);
+        match &delta.volume.custom_box {
+            Some(CustomBoxSetting::Enabled(b)) => assert_eq!(b.z_max, 300.0),
+            b => panic!("Unexpected custom box {:?}", b),
+        }
+    }
+
+    #[test]
+    fn test_files() {
+        let list: FileList =
+            serde_json::from_str(include_str!("../../tests/responses/files.json")).unwrap();
+
+        assert_eq!(list.files.len(), 2);
+        assert_eq!(list.free, Some(47342469120));
+
+        let files = list.all_files();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, "test_upload/test.gcode");
+        assert_eq!(files[1].name, "whistle_v2.gcode");
+
+        let whistle = files[1];
+        assert_eq!(
+            whistle.hash.as_deref(),
+            Some("6e4a6b2c5f5a1e4c3f0bb1a4c8b5d9f1e0a2b3c4")
+        );
+        let analysis = whistle.gcode_analysis.as_ref().unwrap();
+        assert_eq!(
+            analysis.estimated_print_time,
+            Some(Duration::from_secs(1188))
+        );
+        assert_eq!(analysis.dimensions.as_ref().unwrap().height, 12.0);
+        let prints = whistle.prints.as_ref().unwrap();
+        assert_eq!(prints.success, 4);
+        assert!(!prints.last.as_ref().unwrap().success);
+
+        let folder = &list.files[0];
+        assert!(folder.is_folder());
+        assert_eq!(folder.children.as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_connection_closed() {
+        let conn: PrinterConnection =
+            serde_json::from_str(include_str!("../../tests/responses/connection_closed.json"))
+                .unwrap();
+
+        assert_eq!(conn.current.state, State::Closed);
+        assert_eq!(conn.current.port, None);
+        assert_eq!(conn.current.baudrate, None);
+    }
+}
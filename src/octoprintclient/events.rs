@@ -0,0 +1,163 @@
+//! Printer events, detected from the successive states of a printer.
+
+use super::datamodel::{JobInformation, State};
+
+/// Completion (in %) from which a job which stopped is reported as done.
+const COMPLETE: f64 = 99.9;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PrinterEvent {
+    Connected,
+    Disconnected,
+    /// The printer went in error state.
+    Error,
+    PrintStarted,
+    PrintPaused,
+    PrintResumed,
+    PrintDone,
+    /// The job stopped before the end while the printer stayed operational.
+    PrintCancelled,
+    /// The job stopped on a printer error or disconnection.
+    PrintFailed,
+}
+
+impl PrinterEvent {
+    pub const ALL: [PrinterEvent; 9] = [
+        PrinterEvent::Connected,
+        PrinterEvent::Disconnected,
+        PrinterEvent::Error,
+        PrinterEvent::PrintStarted,
+        PrinterEvent::PrintPaused,
+        PrinterEvent::PrintResumed,
+        PrinterEvent::PrintDone,
+        PrinterEvent::PrintCancelled,
+        PrinterEvent::PrintFailed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PrinterEvent::Connected => "Connected",
+            PrinterEvent::Disconnected => "Disconnected",
+            PrinterEvent::Error => "Error",
+            PrinterEvent::PrintStarted => "PrintStarted",
+            PrinterEvent::PrintPaused => "PrintPaused",
+            PrinterEvent::PrintResumed => "PrintResumed",
+            PrinterEvent::PrintDone => "PrintDone",
+            PrinterEvent::PrintCancelled => "PrintCancelled",
+            PrinterEvent::PrintFailed => "PrintFailed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PrinterEvent> {
+        PrinterEvent::ALL.into_iter().find(|e| e.as_str() == name)
+    }
+}
+
+impl std::fmt::Display for PrinterEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Events of a printer going from `previous` to `current` state.
+///
+/// `completion` is the last progress seen, to tell a finished job from a cancelled one.
+pub fn state_events(previous: &State, completion: f64, current: &State) -> Vec<PrinterEvent> {
+    let mut events = Vec::new();
+    if !previous.is_operational() && current.is_operational() {
+        events.push(PrinterEvent::Connected);
+    }
+    if !previous.is_error() && current.is_error() {
+        events.push(PrinterEvent::Error);
+    }
+    if !previous.is_busy() && current.is_printing() {
+        events.push(PrinterEvent::PrintStarted);
+    }
+    if !previous.is_paused() && current.is_paused() {
+        events.push(PrinterEvent::PrintPaused);
+    }
+    if previous.is_paused() && !current.is_paused() && current.is_busy() {
+        events.push(PrinterEvent::PrintResumed);
+    }
+    if previous.is_busy() && !current.is_busy() {
+        events.push(if completion >= COMPLETE || *previous == State::Finishing {
+            PrinterEvent::PrintDone
+        } else if current.is_closed_or_error() {
+            PrinterEvent::PrintFailed
+        } else {
+            PrinterEvent::PrintCancelled
+        });
+    }
+    if previous.is_operational() && !current.is_operational() && !current.is_error() {
+        events.push(PrinterEvent::Disconnected);
+    }
+    events
+}
+
+/// Follows the job of one printer to detect its events.
+#[derive(Clone, Debug, Default)]
+pub struct EventTracker {
+    state: Option<State>,
+    completion: f64,
+}
+
+impl EventTracker {
+    /// Update with the current job, returns the events since the previous update.
+    ///
+    /// The first update only records the state.
+    pub fn update(&mut self, job: &JobInformation) -> Vec<PrinterEvent> {
+        let events = match &self.state {
+            Some(previous) => state_events(previous, self.completion, &job.state),
+            None => Vec::new(),
+        };
+        self.state = Some(job.state.clone());
+        self.completion = job.progress.completion.unwrap_or(0.0);
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_events() {
+        use PrinterEvent::*;
+        assert_eq!(
+            state_events(&State::Closed, 0.0, &State::Operational),
+            [Connected]
+        );
+        assert_eq!(
+            state_events(&State::Operational, 0.0, &State::Printing),
+            [PrintStarted]
+        );
+        assert_eq!(
+            state_events(&State::Printing, 50.0, &State::Paused),
+            [PrintPaused]
+        );
+        assert_eq!(
+            state_events(&State::Paused, 50.0, &State::Printing),
+            [PrintResumed]
+        );
+        assert_eq!(
+            state_events(&State::Printing, 100.0, &State::Operational),
+            [PrintDone]
+        );
+        assert_eq!(
+            state_events(&State::Cancelling, 50.0, &State::Operational),
+            [PrintCancelled]
+        );
+        assert_eq!(
+            state_events(&State::Printing, 50.0, &State::Error),
+            [Error, PrintFailed]
+        );
+        assert_eq!(
+            state_events(&State::Operational, 0.0, &State::Closed),
+            [Disconnected]
+        );
+        assert!(state_events(&State::Printing, 50.0, &State::Printing).is_empty());
+
+        assert_eq!(PrinterEvent::from_name("PrintDone"), Some(PrintDone));
+        assert_eq!(PrinterEvent::from_name("printdone"), None);
+    }
+}
@@ -0,0 +1,360 @@
+//! Prometheus exporter for the printers of a fleet.
+//!
+//! Each scrape of `/metrics` queries all the printers (see `Fleet::status()`) and renders the
+//! result in the Prometheus text format. A printer which does not answer has `octoprint_up`
+//! set to 0 and no other metric.
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+
+use super::datamodel::State;
+use super::fleet::{Fleet, PrinterStatus};
+use super::OctoPrintClientError;
+
+/// Status of one printer, with the time it took to get it.
+#[derive(Debug)]
+pub struct Scrape {
+    pub name: String,
+    pub result: Result<PrinterStatus, OctoPrintClientError>,
+    pub duration: Duration,
+}
+
+/// Query the status of all the printers.
+pub async fn scrape(fleet: &Fleet) -> Vec<Scrape> {
+    fleet
+        .broadcast(|client| async move {
+            let start = Instant::now();
+            let result = client.get_status().await;
+            Ok((result, start.elapsed()))
+        })
+        .await
+        .into_iter()
+        .map(|r| match r.result {
+            Ok((result, duration)) => Scrape {
+                name: r.name,
+                result,
+                duration,
+            },
+            // Only the timeout can fail
+            Err(error) => Scrape {
+                name: r.name,
+                result: Err(error),
+                duration: fleet.timeout,
+            },
+        })
+        .collect()
+}
+
+/// Samples of one metric.
+struct Family {
+    name: &'static str,
+    help: &'static str,
+    samples: Vec<String>,
+}
+
+/// Metrics in the Prometheus text format, grouped by name.
+#[derive(Default)]
+struct Metrics {
+    families: Vec<Family>,
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Metrics {
+    fn add(&mut self, name: &'static str, help: &'static str, labels: &[(&str, &str)], value: f64) {
+        let labels = labels
+            .iter()
+            .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let sample = format!("{}{{{}}} {}", name, labels, value);
+        match self.families.iter_mut().find(|f| f.name == name) {
+            Some(family) => family.samples.push(sample),
+            None => self.families.push(Family {
+                name,
+                help,
+                samples: vec![sample],
+            }),
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for family in &self.families {
+            out.push_str(&format!("# HELP {} {}\n", family.name, family.help));
+            out.push_str(&format!("# TYPE {} gauge\n", family.name));
+            for sample in &family.samples {
+                out.push_str(sample);
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+fn bool_value(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Render the scrapes in the Prometheus text format.
+pub fn render(scrapes: &[Scrape]) -> String {
+    let mut m = Metrics::default();
+    for scrape in scrapes {
+        let printer = scrape.name.as_str();
+        let labels = [("printer", printer)];
+        m.add(
+            "octoprint_up",
+            "1 if the OctoPrint server answered",
+            &labels,
+            bool_value(scrape.result.is_ok()),
+        );
+        m.add(
+            "octoprint_scrape_duration_seconds",
+            "Time taken to query the OctoPrint server",
+            &labels,
+            scrape.duration.as_secs_f64(),
+        );
+        let Ok(status) = &scrape.result else {
+            continue;
+        };
+
+        m.add(
+            "octoprint_printer_connected",
+            "1 if the printer is connected to OctoPrint",
+            &labels,
+            bool_value(status.connection.current.state.is_operational()),
+        );
+        let state = &status.job.state;
+        for known in State::KNOWN.iter() {
+            m.add(
+                "octoprint_printer_state",
+                "1 for the current state of the printer",
+                &[("printer", printer), ("state", known.as_str())],
+                bool_value(known == state),
+            );
+        }
+        if let State::Unknown(name) = state {
+            m.add(
+                "octoprint_printer_state",
+                "1 for the current state of the printer",
+                &[("printer", printer), ("state", name)],
+                1.0,
+            );
+        }
+
+        if let Some(temperature) = status.printer.as_ref().and_then(|p| p.temperature.as_ref()) {
+            let heaters = temperature
+                .tools
+                .iter()
+                .map(|(name, data)| (name.as_str(), data))
+                .chain(temperature.bed.iter().map(|data| ("bed", data)))
+                .chain(temperature.chamber.iter().map(|data| ("chamber", data)));
+            for (heater, data) in heaters {
+                let labels = [("printer", printer), ("heater", heater)];
+                if let Some(actual) = data.actual {
+                    m.add(
+                        "octoprint_temperature_celsius",
+                        "Actual temperature of the heater",
+                        &labels,
+                        actual as f64,
+                    );
+                }
+                if let Some(target) = data.target {
+                    m.add(
+                        "octoprint_temperature_target_celsius",
+                        "Target temperature of the heater",
+                        &labels,
+                        target as f64,
+                    );
+                }
+            }
+        }
+
+        let progress = &status.job.progress;
+        if let Some(completion) = progress.completion {
+            m.add(
+                "octoprint_job_progress_ratio",
+                "Progress of the current job, from 0 to 1",
+                &labels,
+                completion / 100.0,
+            );
+        }
+        if let Some(print_time) = progress.print_time {
+            m.add(
+                "octoprint_job_print_time_seconds",
+                "Time elapsed since the start of the current job",
+                &labels,
+                print_time.as_secs_f64(),
+            );
+        }
+        if let Some(left) = progress.print_time_left {
+            m.add(
+                "octoprint_job_print_time_left_seconds",
+                "Estimated time left to complete the current job",
+                &labels,
+                left.as_secs_f64(),
+            );
+        }
+    }
+    m.render()
+}
+
+async fn handle(fleet: Fleet, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(Body::from(render(&scrape(&fleet).await))),
+        (&Method::GET, "/") => Response::builder()
+            .header("Content-Type", "text/html")
+            .body(Body::from(
+                "<html><body><a href=\"/metrics\">Metrics</a></body></html>",
+            )),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(response.unwrap())
+}
+
+/// Serve `/metrics` on `listener` until `shutdown` completes.
+pub async fn serve<S>(
+    fleet: Fleet,
+    listener: std::net::TcpListener,
+    shutdown: S,
+) -> Result<(), OctoPrintClientError>
+where
+    S: Future<Output = ()>,
+{
+    let make_svc = make_service_fn(move |_| {
+        let fleet = fleet.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(fleet.clone(), req))) }
+    });
+    listener.set_nonblocking(true)?;
+    Server::from_tcp(listener)?
+        .serve(make_svc)
+        .with_graceful_shutdown(shutdown)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::testing::{MockServer, MOCK_API_KEY};
+    use crate::octoprintclient::PrinterConfiguration;
+
+    #[tokio::test]
+    async fn test_render() {
+        let server = MockServer::start();
+        server.with_printer_mut(|p| {
+            p.connect_ticks = 0;
+            p.connect(None, None, None);
+            p.tools[0].target = 215.0;
+        });
+        let unreachable = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let fleet = Fleet::from_configs(&[
+            PrinterConfiguration {
+                name: "mk3".to_string(),
+                server_url: server.url(),
+                api_key: MOCK_API_KEY.to_string(),
+                group: None,
+            },
+            PrinterConfiguration {
+                name: "down".to_string(),
+                server_url: format!("http://127.0.0.1:{}", unreachable),
+                api_key: MOCK_API_KEY.to_string(),
+                group: None,
+            },
+        ]);
+
+        let text = render(&scrape(&fleet).await);
+        let lines: Vec<&str> = text.lines().collect();
+        for expected in [
+            "# TYPE octoprint_up gauge",
+            "octoprint_up{printer=\"mk3\"} 1",
+            "octoprint_up{printer=\"down\"} 0",
+            "octoprint_printer_connected{printer=\"mk3\"} 1",
+            "octoprint_printer_state{printer=\"mk3\",state=\"Operational\"} 1",
+            "octoprint_printer_state{printer=\"mk3\",state=\"Printing\"} 0",
+            "octoprint_temperature_target_celsius{printer=\"mk3\",heater=\"tool0\"} 215",
+            "octoprint_temperature_target_celsius{printer=\"mk3\",heater=\"bed\"} 0",
+        ] {
+            assert!(
+                lines.contains(&expected),
+                "{} missing in\n{}",
+                expected,
+                text
+            );
+        }
+        // One HELP line per metric
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|l| l.starts_with("# HELP octoprint_printer_state "))
+                .count(),
+            1
+        );
+        assert!(!text.contains("octoprint_printer_connected{printer=\"down\"}"));
+        assert!(!text.contains("octoprint_job_progress_ratio"));
+    }
+
+    #[test]
+    fn test_escape() {
+        let mut m = Metrics::default();
+        m.add("a", "help", &[("printer", "\"big\"\\one\n")], 0.5);
+        assert_eq!(
+            m.render(),
+            "# HELP a help\n# TYPE a gauge\na{printer=\"\\\"big\\\"\\\\one\\n\"} 0.5\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let server = MockServer::start();
+        let fleet = Fleet::single("mock", server.client());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let exporter = tokio::spawn(serve(fleet, listener, async {
+            stopped.await.ok();
+        }));
+
+        let client = hyper::Client::new();
+        let response = client
+            .get(format!("http://{}/metrics", addr).parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("octoprint_up{printer=\"mock\"} 1"));
+        assert!(body.contains("octoprint_printer_state{printer=\"mock\",state=\"Closed\"} 1"));
+
+        let response = client
+            .get(format!("http://{}/other", addr).parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        stop.send(()).unwrap();
+        exporter.await.unwrap().unwrap();
+    }
+}
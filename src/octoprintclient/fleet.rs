@@ -0,0 +1,285 @@
+//! Several OctoPrint servers queried and commanded together.
+
+use std::future::Future;
+use std::time::Duration;
+
+use futures::future::join_all;
+
+use super::datamodel::{JobInformation, PrinterConnection, PrinterInfo, State};
+use super::{OctoPrintClient, OctoPrintClientError, PrinterConfiguration};
+
+/// State of one printer of the fleet.
+#[derive(Clone, Debug)]
+pub struct PrinterStatus {
+    pub connection: PrinterConnection,
+    pub job: JobInformation,
+    /// `None` when the printer is not connected.
+    pub printer: Option<PrinterInfo>,
+}
+
+/// What a command did on one printer of the fleet.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Done,
+    /// Not sent, because of the state of the job of the printer.
+    Skipped(State),
+}
+
+/// Result of a request to one printer of the fleet.
+#[derive(Debug)]
+pub struct FleetResult<T> {
+    pub name: String,
+    pub result: Result<T, OctoPrintClientError>,
+}
+
+/// A printer of the fleet.
+#[derive(Clone, Debug)]
+pub struct FleetMember {
+    pub name: String,
+    pub group: Option<String>,
+    pub client: OctoPrintClient,
+}
+
+#[derive(Clone, Debug)]
+pub struct Fleet {
+    members: Vec<FleetMember>,
+    /// Time given to each server to answer.
+    pub timeout: Duration,
+}
+
+impl Fleet {
+    pub fn from_configs(printers: &[PrinterConfiguration]) -> Self {
+        Fleet {
+            members: printers
+                .iter()
+                .map(|p| FleetMember {
+                    name: p.name.clone(),
+                    group: p.group.clone(),
+                    client: OctoPrintClient::from_config(p.configuration()),
+                })
+                .collect(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// A fleet of one printer.
+    pub fn single(name: &str, client: OctoPrintClient) -> Self {
+        Fleet {
+            members: vec![FleetMember {
+                name: name.to_string(),
+                group: None,
+                client,
+            }],
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    pub fn members(&self) -> &[FleetMember] {
+        &self.members
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.members.iter().map(|m| m.name.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Run `request` on all the printers concurrently, results are in configuration order.
+    ///
+    /// Each request is given `timeout` to complete, one unreachable server does not delay the
+    /// others.
+    pub async fn broadcast<'a, T, F, Fut>(&'a self, request: F) -> Vec<FleetResult<T>>
+    where
+        F: Fn(&'a OctoPrintClient) -> Fut,
+        Fut: Future<Output = Result<T, OctoPrintClientError>>,
+    {
+        join_all(self.members.iter().map(|member| {
+            let request = request(&member.client);
+            async move {
+                let result = match tokio::time::timeout(self.timeout, request).await {
+                    Ok(result) => result,
+                    Err(_) => Err(OctoPrintClientError::TimeoutError(self.timeout)),
+                };
+                FleetResult {
+                    name: member.name.clone(),
+                    result,
+                }
+            }
+        }))
+        .await
+    }
+
+    /// Connection, job and temperatures of all the printers.
+    pub async fn status(&self) -> Vec<FleetResult<PrinterStatus>> {
+        self.broadcast(|client| client.get_status()).await
+    }
+
+    /// Send G-code commands to all the printers.
+    ///
+    /// Unless `force` is set, the printers with a job in progress are skipped.
+    pub async fn send_gcode(&self, commands: &[&str], force: bool) -> Vec<FleetResult<Outcome>> {
+        self.broadcast_unless(
+            |state| !force && state.is_busy(),
+            |client| client.send_gcode(commands),
+        )
+        .await
+    }
+
+    /// Disconnect all the printers.
+    ///
+    /// Unless `force` is set, the printers with a job in progress are skipped, disconnecting
+    /// them would stop the print.
+    pub async fn disconnect(&self, force: bool) -> Vec<FleetResult<Outcome>> {
+        self.broadcast_unless(
+            |state| !force && state.is_busy(),
+            |client| client.disconnect(),
+        )
+        .await
+    }
+
+    /// Cancel the jobs of all the printers, the printers without a job in progress are skipped.
+    pub async fn cancel(&self) -> Vec<FleetResult<Outcome>> {
+        self.broadcast_unless(|state| !state.is_busy(), |client| client.cancel_job())
+            .await
+    }
+
+    /// Run `request` on the printers whose job state does not match `skip`.
+    async fn broadcast_unless<'a, S, F, Fut>(
+        &'a self,
+        skip: S,
+        request: F,
+    ) -> Vec<FleetResult<Outcome>>
+    where
+        S: Fn(&State) -> bool,
+        F: Fn(&'a OctoPrintClient) -> Fut,
+        Fut: Future<Output = Result<(), OctoPrintClientError>>,
+    {
+        let (skip, request) = (&skip, &request);
+        self.broadcast(|client| async move {
+            let job = client.get_current_job().await?;
+            if skip(&job.state) {
+                return Ok(Outcome::Skipped(job.state));
+            }
+            request(client).await?;
+            Ok(Outcome::Done)
+        })
+        .await
+    }
+}
+
+impl OctoPrintClient {
+    /// Connection, job and temperatures of the printer.
+    pub async fn get_status(&self) -> Result<PrinterStatus, OctoPrintClientError> {
+        let connection = self.get_connection().await?;
+        let job = self.get_current_job().await?;
+        let printer = if connection.current.state.is_operational() {
+            Some(self.get_printer_state().await?)
+        } else {
+            None
+        };
+        Ok(PrinterStatus {
+            connection,
+            job,
+            printer,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::datamodel::State;
+    use crate::octoprintclient::testing::{MockServer, StoredFile, MOCK_API_KEY};
+
+    fn printer_config(name: &str, server_url: String) -> PrinterConfiguration {
+        PrinterConfiguration {
+            name: name.to_string(),
+            server_url,
+            api_key: MOCK_API_KEY.to_string(),
+            group: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fleet() {
+        let idle = MockServer::start();
+        let printing = MockServer::start();
+        printing.with_printer_mut(|p| {
+            p.connect_ticks = 0;
+            p.progress_step = 0.0;
+            p.connect(None, None, None);
+            p.files.insert(
+                "a.gcode".to_string(),
+                StoredFile {
+                    content: b"G28\n".to_vec(),
+                    date: 0,
+                    userdata: None,
+                },
+            );
+            p.select("a.gcode");
+            p.start();
+        });
+        let unreachable = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let fleet = Fleet::from_configs(&[
+            printer_config("idle", idle.url()),
+            printer_config("printing", printing.url()),
+            printer_config("down", format!("http://127.0.0.1:{}", unreachable)),
+        ]);
+        assert_eq!(
+            fleet.names().collect::<Vec<_>>(),
+            ["idle", "printing", "down"]
+        );
+
+        let status = fleet.status().await;
+        assert_eq!(status.len(), 3);
+        let idle_status = status[0].result.as_ref().unwrap();
+        assert_eq!(idle_status.connection.current.state, State::Closed);
+        assert!(idle_status.printer.is_none());
+        let printing_status = status[1].result.as_ref().unwrap();
+        assert_eq!(printing_status.job.state, State::Printing);
+        assert!(printing_status.printer.is_some());
+        assert!(matches!(
+            status[2].result,
+            Err(OctoPrintClientError::ClientError(_))
+        ));
+
+        let results = fleet.send_gcode(&["M84"], false).await;
+        assert!(matches!(
+            results[0].result,
+            Err(OctoPrintClientError::ConflictError(_))
+        ));
+        assert_eq!(
+            results[1].result.as_ref().unwrap(),
+            &Outcome::Skipped(State::Printing)
+        );
+        assert!(results[2].result.is_err());
+        assert!(printing.printer().commands.is_empty());
+
+        let results = fleet.send_gcode(&["M84"], true).await;
+        assert_eq!(results[1].result.as_ref().unwrap(), &Outcome::Done);
+        assert_eq!(printing.printer().commands, ["M84"]);
+
+        let results = fleet.disconnect(false).await;
+        assert_eq!(results[0].result.as_ref().unwrap(), &Outcome::Done);
+        assert_eq!(
+            results[1].result.as_ref().unwrap(),
+            &Outcome::Skipped(State::Printing)
+        );
+        assert!(printing.printer().state.is_busy());
+
+        let results = fleet.cancel().await;
+        assert_eq!(
+            results[0].result.as_ref().unwrap(),
+            &Outcome::Skipped(State::Closed)
+        );
+        assert_eq!(results[1].result.as_ref().unwrap(), &Outcome::Done);
+        assert_eq!(printing.printer().state, State::Cancelling);
+    }
+}
@@ -0,0 +1,682 @@
+//! Local print history, stored in a SQLite database.
+//!
+//! OctoPrint only counts the successes and failures of each file. The recorder polls the
+//! printers of a fleet, follows their jobs with a `JobTracker` and saves one `JobRecord` per
+//! finished job, used for the statistics reports.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::datamodel::{JobInformation, State, TemperatureState};
+use super::events::{state_events, PrinterEvent};
+use super::fleet::Fleet;
+use super::OctoPrintClientError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobResult {
+    Success,
+    /// Printer error or disconnection during the print.
+    Failed,
+    /// Stopped before the end while the printer stayed operational.
+    Cancelled,
+}
+
+impl JobResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobResult::Success => "success",
+            JobResult::Failed => "failed",
+            JobResult::Cancelled => "cancelled",
+        }
+    }
+
+    /// Result of the job ended by `event`, `None` if the event is not the end of a job.
+    pub fn from_event(event: PrinterEvent) -> Option<JobResult> {
+        match event {
+            PrinterEvent::PrintDone => Some(JobResult::Success),
+            PrinterEvent::PrintFailed => Some(JobResult::Failed),
+            PrinterEvent::PrintCancelled => Some(JobResult::Cancelled),
+            _ => None,
+        }
+    }
+
+    fn from_str(s: &str) -> JobResult {
+        match s {
+            "success" => JobResult::Success,
+            "cancelled" => JobResult::Cancelled,
+            _ => JobResult::Failed,
+        }
+    }
+}
+
+impl std::fmt::Display for JobResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One job, as saved in the history.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JobRecord {
+    pub printer: String,
+    /// Path of the file on the server.
+    pub file: String,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub result: JobResult,
+    /// Filament used by all the tools, in mm.
+    pub filament: Option<f64>,
+    /// Highest temperature of the tools during the job, in °C.
+    pub peak_tool: Option<f32>,
+    pub peak_bed: Option<f32>,
+}
+
+impl JobRecord {
+    pub fn duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap_or_default()
+    }
+}
+
+/// Job currently followed by a tracker.
+#[derive(Clone, Debug)]
+struct RunningJob {
+    file: String,
+    start: SystemTime,
+    last_state: State,
+    completion: f64,
+    /// Filament needed by the whole file, in mm.
+    filament: Option<f64>,
+    peak_tool: Option<f32>,
+    peak_bed: Option<f32>,
+}
+
+/// Follows the job of one printer across successive observations.
+///
+/// A job is seen when the printer becomes busy, and ends when it is not busy anymore or when
+/// another file is printed. Its result is given by the end event of `events::state_events`.
+#[derive(Clone, Debug, Default)]
+pub struct JobTracker {
+    current: Option<RunningJob>,
+}
+
+impl JobTracker {
+    /// Update with the state of the printer at `now`, returns the job which just ended.
+    ///
+    /// `temperatures` is `None` when the printer is not connected.
+    pub fn update(
+        &mut self,
+        printer: &str,
+        job: &JobInformation,
+        temperatures: Option<&TemperatureState>,
+        now: SystemTime,
+    ) -> Option<JobRecord> {
+        if !job.state.is_busy() {
+            return self.end_job(printer, &job.state, now);
+        }
+
+        let file = job.job.file.path.clone().unwrap_or_default();
+        // The job may have started before the first observation
+        let start = job
+            .progress
+            .print_time
+            .and_then(|t| now.checked_sub(t))
+            .unwrap_or(now);
+        // The previous job ended between two observations, and another one started
+        let ended = if self.current.as_ref().is_some_and(|r| r.file != file) {
+            self.end_job(printer, &State::Operational, start)
+        } else {
+            None
+        };
+        let running = self.current.get_or_insert_with(|| RunningJob {
+            file: file.clone(),
+            start,
+            last_state: job.state.clone(),
+            completion: 0.0,
+            filament: None,
+            peak_tool: None,
+            peak_bed: None,
+        });
+        running.last_state = job.state.clone();
+        if let Some(completion) = job.progress.completion {
+            running.completion = completion;
+        }
+        if let Some(filament) = &job.job.filament {
+            running.filament = Some(filament.tools.values().map(|t| t.length).sum());
+        }
+        if let Some(temperatures) = temperatures {
+            let tools = temperatures.tools.values().filter_map(|t| t.actual);
+            running.peak_tool = max(running.peak_tool, tools.fold(None, |m, t| max(m, Some(t))));
+            running.peak_bed = max(
+                running.peak_bed,
+                temperatures.bed.as_ref().and_then(|b| b.actual),
+            );
+        }
+        ended
+    }
+
+    /// End the current job at `end`, the printer being then in `state`.
+    fn end_job(&mut self, printer: &str, state: &State, end: SystemTime) -> Option<JobRecord> {
+        let running = self.current.take()?;
+        // The job was busy and the printer is not anymore, there is always an end event
+        let result = state_events(&running.last_state, running.completion, state)
+            .into_iter()
+            .find_map(JobResult::from_event)
+            .unwrap_or(JobResult::Cancelled);
+        let done = if result == JobResult::Success {
+            1.0
+        } else {
+            running.completion / 100.0
+        };
+        Some(JobRecord {
+            printer: printer.to_string(),
+            file: running.file,
+            start: running.start,
+            end,
+            result,
+            filament: running.filament.map(|f| f * done),
+            peak_tool: running.peak_tool,
+            peak_bed: running.peak_bed,
+        })
+    }
+}
+
+fn max(a: Option<f32>, b: Option<f32>) -> Option<f32> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Selection of the jobs of the history.
+#[derive(Clone, Debug, Default)]
+pub struct HistoryFilter {
+    pub printer: Option<String>,
+    /// Only the jobs started after this time.
+    pub since: Option<SystemTime>,
+    /// Only the most recent jobs.
+    pub limit: Option<usize>,
+}
+
+/// Statistics of one printer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrinterStats {
+    pub printer: String,
+    pub jobs: u64,
+    pub success: u64,
+    pub failed: u64,
+    pub cancelled: u64,
+    /// Time spent printing.
+    pub busy: Duration,
+    /// Part of the period spent printing, between 0 and 1.
+    pub utilization: f64,
+    /// Filament used, in mm.
+    pub filament: f64,
+}
+
+impl PrinterStats {
+    /// Part of the jobs which succeeded, between 0 and 1.
+    pub fn success_rate(&self) -> f64 {
+        if self.jobs == 0 {
+            0.0
+        } else {
+            self.success as f64 / self.jobs as f64
+        }
+    }
+}
+
+/// Filament used by all the printers during one week.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeeklyFilament {
+    /// Monday of the week, as "YYYY-MM-DD" (UTC).
+    pub week: String,
+    /// Filament used, in mm.
+    pub filament: f64,
+    pub jobs: u64,
+}
+
+/// The history database.
+#[derive(Debug)]
+pub struct History {
+    connection: Connection,
+}
+
+fn timestamp(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+fn from_timestamp(seconds: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(seconds.max(0) as u64)
+}
+
+impl History {
+    /// Open the database at `path`, it is created if needed.
+    pub fn open(path: &Path) -> Result<History, OctoPrintClientError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        History::init(Connection::open(path)?)
+    }
+
+    /// A database only kept in memory, for tests.
+    pub fn open_in_memory() -> Result<History, OctoPrintClientError> {
+        History::init(Connection::open_in_memory()?)
+    }
+
+    fn init(connection: Connection) -> Result<History, OctoPrintClientError> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS jobs (
+                id INTEGER PRIMARY KEY,
+                printer TEXT NOT NULL,
+                file TEXT NOT NULL,
+                start INTEGER NOT NULL,
+                end INTEGER NOT NULL,
+                result TEXT NOT NULL,
+                filament REAL,
+                peak_tool REAL,
+                peak_bed REAL
+            );
+            CREATE INDEX IF NOT EXISTS jobs_start ON jobs (start);",
+        )?;
+        Ok(History { connection })
+    }
+
+    pub fn insert(&self, record: &JobRecord) -> Result<(), OctoPrintClientError> {
+        self.connection.execute(
+            "INSERT INTO jobs (printer, file, start, end, result, filament, peak_tool, peak_bed)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                record.printer,
+                record.file,
+                timestamp(record.start),
+                timestamp(record.end),
+                record.result.as_str(),
+                record.filament,
+                record.peak_tool,
+                record.peak_bed,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Jobs matching the filter, most recent first.
+    pub fn jobs(&self, filter: &HistoryFilter) -> Result<Vec<JobRecord>, OctoPrintClientError> {
+        let mut statement = self.connection.prepare(
+            "SELECT printer, file, start, end, result, filament, peak_tool, peak_bed FROM jobs
+             WHERE (?1 IS NULL OR printer = ?1) AND start >= ?2
+             ORDER BY start DESC, id DESC LIMIT ?3",
+        )?;
+        let rows = statement.query_map(
+            params![
+                filter.printer,
+                filter.since.map_or(0, timestamp),
+                filter.limit.map_or(-1, |l| l as i64),
+            ],
+            |row| {
+                Ok(JobRecord {
+                    printer: row.get(0)?,
+                    file: row.get(1)?,
+                    start: from_timestamp(row.get(2)?),
+                    end: from_timestamp(row.get(3)?),
+                    result: JobResult::from_str(&row.get::<_, String>(4)?),
+                    filament: row.get(5)?,
+                    peak_tool: row.get(6)?,
+                    peak_bed: row.get(7)?,
+                })
+            },
+        )?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Statistics per printer of the jobs started since `since` (all of them if `None`).
+    ///
+    /// The utilization is computed over the period from `since` (or the first job) to `now`.
+    pub fn printer_stats(
+        &self,
+        since: Option<SystemTime>,
+        now: SystemTime,
+    ) -> Result<Vec<PrinterStats>, OctoPrintClientError> {
+        let since = since.map(timestamp);
+        let first: Option<i64> = match since {
+            Some(since) => Some(since),
+            None => self
+                .connection
+                .query_row("SELECT MIN(start) FROM jobs", [], |row| row.get(0))
+                .optional()?
+                .flatten(),
+        };
+        let period = (timestamp(now) - first.unwrap_or(0)).max(1) as f64;
+
+        let mut statement = self.connection.prepare(
+            "SELECT printer, COUNT(*),
+                SUM(result = 'success'), SUM(result = 'failed'), SUM(result = 'cancelled'),
+                SUM(end - start), TOTAL(filament)
+             FROM jobs WHERE start >= ?1 GROUP BY printer ORDER BY printer",
+        )?;
+        let rows = statement.query_map(params![since.unwrap_or(0)], |row| {
+            let busy: i64 = row.get(5)?;
+            Ok(PrinterStats {
+                printer: row.get(0)?,
+                jobs: row.get(1)?,
+                success: row.get(2)?,
+                failed: row.get(3)?,
+                cancelled: row.get(4)?,
+                busy: Duration::from_secs(busy.max(0) as u64),
+                utilization: (busy as f64 / period).min(1.0),
+                filament: row.get(6)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Filament used per week (starting on monday), oldest first.
+    pub fn weekly_filament(
+        &self,
+        since: Option<SystemTime>,
+    ) -> Result<Vec<WeeklyFilament>, OctoPrintClientError> {
+        let mut statement = self.connection.prepare(
+            "SELECT date(start, 'unixepoch', 'weekday 0', '-6 days') AS week,
+                TOTAL(filament), COUNT(*)
+             FROM jobs WHERE start >= ?1 GROUP BY week ORDER BY week",
+        )?;
+        let rows = statement.query_map(params![since.map_or(0, timestamp)], |row| {
+            Ok(WeeklyFilament {
+                week: row.get(0)?,
+                filament: row.get(1)?,
+                jobs: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+/// Quote a CSV field if needed.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Write the records as CSV, with a header line. Times are in UTC (RFC 3339).
+pub fn write_csv<W: Write>(records: &[JobRecord], mut w: W) -> std::io::Result<()> {
+    let optional = |v: Option<String>| v.unwrap_or_default();
+    let time = |t: SystemTime| {
+        chrono::DateTime::<chrono::Utc>::from(t).to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    };
+    writeln!(
+        w,
+        "printer,file,start,end,duration_s,result,filament_mm,peak_tool_c,peak_bed_c"
+    )?;
+    for r in records {
+        writeln!(
+            w,
+            "{},{},{},{},{},{},{},{},{}",
+            csv_field(&r.printer),
+            csv_field(&r.file),
+            time(r.start),
+            time(r.end),
+            r.duration().as_secs(),
+            r.result,
+            optional(r.filament.map(|f| format!("{:.1}", f))),
+            optional(r.peak_tool.map(|t| format!("{:.1}", t))),
+            optional(r.peak_bed.map(|t| format!("{:.1}", t))),
+        )?;
+    }
+    Ok(())
+}
+
+/// Poll the printers every `interval` and save their jobs in `history` until `shutdown`
+/// completes.
+///
+/// `log` is called for each job saved. Unreachable printers are skipped, their jobs are
+/// still followed when they come back.
+pub async fn record<L, S>(
+    fleet: &Fleet,
+    history: &History,
+    interval: Duration,
+    mut log: L,
+    shutdown: S,
+) -> Result<(), OctoPrintClientError>
+where
+    L: FnMut(&JobRecord),
+    S: Future<Output = ()>,
+{
+    let mut trackers: BTreeMap<String, JobTracker> = BTreeMap::new();
+    tokio::pin!(shutdown);
+    loop {
+        for status in fleet.status().await {
+            let Ok(printer) = status.result else {
+                continue;
+            };
+            let temperatures = printer
+                .printer
+                .as_ref()
+                .and_then(|p| p.temperature.as_ref());
+            let tracker = trackers.entry(status.name.clone()).or_default();
+            if let Some(record) =
+                tracker.update(&status.name, &printer.job, temperatures, SystemTime::now())
+            {
+                history.insert(&record)?;
+                log(&record);
+            }
+        }
+        tokio::select! {
+            _ = &mut shutdown => return Ok(()),
+            _ = tokio::time::sleep(interval) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::testing::{MockServer, StoredFile};
+    use serde_json::json;
+
+    fn job(state: &str, completion: Option<f64>, print_time: Option<u64>) -> JobInformation {
+        serde_json::from_value(json!({
+            "job": {
+                "file": {"name": "a.gcode", "path": "a.gcode", "origin": "local"},
+                "filament": {"tool0": {"length": 1000.0, "volume": 2.4}},
+            },
+            "progress": {"completion": completion, "printTime": print_time},
+            "state": state,
+        }))
+        .unwrap()
+    }
+
+    fn temperatures(tool: f32, bed: f32) -> TemperatureState {
+        serde_json::from_value(json!({
+            "tool0": {"actual": tool, "target": 215.0},
+            "bed": {"actual": bed, "target": 60.0},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_tracker() {
+        let t0 = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let at = |s: u64| t0 + Duration::from_secs(s);
+        let mut tracker = JobTracker::default();
+        let idle = job("Operational", None, None);
+
+        // First seen in the middle of the print
+        assert!(tracker.update("mk3", &idle, None, at(0)).is_none());
+        let printing = job("Printing", Some(50.0), Some(600));
+        let t = temperatures(212.0, 60.5);
+        assert!(tracker.update("mk3", &printing, Some(&t), at(0)).is_none());
+        let t = temperatures(215.5, 59.0);
+        let finishing = job("Finishing", Some(99.0), Some(1200));
+        assert!(tracker
+            .update("mk3", &finishing, Some(&t), at(600))
+            .is_none());
+        let record = tracker.update("mk3", &idle, None, at(660)).unwrap();
+        assert_eq!(record.result, JobResult::Success);
+        assert_eq!(record.start, t0 - Duration::from_secs(600));
+        assert_eq!(record.duration(), Duration::from_secs(1260));
+        assert_eq!(record.filament, Some(1000.0));
+        assert_eq!(record.peak_tool, Some(215.5));
+        assert_eq!(record.peak_bed, Some(60.5));
+        assert!(tracker.update("mk3", &idle, None, at(700)).is_none());
+
+        // Stopped at 25%
+        tracker.update("mk3", &job("Printing", Some(25.0), Some(0)), None, at(800));
+        let record = tracker.update("mk3", &idle, None, at(900)).unwrap();
+        assert_eq!(record.result, JobResult::Cancelled);
+        assert_eq!(record.filament, Some(250.0));
+
+        // Printer error
+        tracker.update("mk3", &job("Paused", Some(10.0), Some(0)), None, at(1000));
+        let error = job("Offline after error", None, None);
+        let record = tracker.update("mk3", &error, None, at(1100)).unwrap();
+        assert_eq!(record.result, JobResult::Failed);
+        assert_eq!(record.peak_tool, None);
+
+        // Another file started between two polls, without an idle state
+        tracker.update(
+            "mk3",
+            &job("Printing", Some(99.95), Some(0)),
+            None,
+            at(1200),
+        );
+        let mut next = job("Printing", Some(5.0), Some(60));
+        next.job.file.path = Some("b.gcode".to_string());
+        let record = tracker.update("mk3", &next, None, at(1400)).unwrap();
+        assert_eq!(record.file, "a.gcode");
+        assert_eq!(record.result, JobResult::Success);
+        assert_eq!(record.end, at(1340));
+        let record = tracker.update("mk3", &idle, None, at(1500)).unwrap();
+        assert_eq!(record.file, "b.gcode");
+        assert_eq!(record.start, at(1340));
+        assert_eq!(record.result, JobResult::Cancelled);
+    }
+
+    #[test]
+    fn test_history() {
+        let history = History::open_in_memory().unwrap();
+        // Monday 2023-11-13 00:00 UTC
+        let monday = UNIX_EPOCH + Duration::from_secs(1_699_833_600);
+        let day = Duration::from_secs(86400);
+        let hour = Duration::from_secs(3600);
+        let record = |printer: &str, start: SystemTime, result, filament| JobRecord {
+            printer: printer.to_string(),
+            file: "folder/part, v2.gcode".to_string(),
+            start,
+            end: start + hour,
+            result,
+            filament,
+            peak_tool: Some(215.0),
+            peak_bed: None,
+        };
+        for r in [
+            record("mk3", monday, JobResult::Success, Some(1000.0)),
+            record("mk3", monday + day * 6, JobResult::Failed, Some(50.0)),
+            record("mini", monday + day * 7, JobResult::Success, Some(500.0)),
+            record("mk3", monday + day * 8, JobResult::Cancelled, None),
+        ] {
+            history.insert(&r).unwrap();
+        }
+
+        let all = history.jobs(&HistoryFilter::default()).unwrap();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[0].result, JobResult::Cancelled);
+        assert_eq!(
+            all[3],
+            record("mk3", monday, JobResult::Success, Some(1000.0))
+        );
+        let filter = HistoryFilter {
+            printer: Some("mk3".to_string()),
+            since: Some(monday + day),
+            limit: Some(1),
+        };
+        let mk3 = history.jobs(&filter).unwrap();
+        assert_eq!(mk3.len(), 1);
+        assert_eq!(mk3[0].start, monday + day * 8);
+
+        let stats = history.printer_stats(None, monday + day * 10).unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].printer, "mini");
+        let mk3 = &stats[1];
+        assert_eq!(
+            (mk3.jobs, mk3.success, mk3.failed, mk3.cancelled),
+            (3, 1, 1, 1)
+        );
+        assert_eq!(mk3.busy, hour * 3);
+        assert!((mk3.utilization - 3.0 / 240.0).abs() < 1e-9);
+        assert!((mk3.success_rate() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(mk3.filament, 1050.0);
+
+        let weeks = history.weekly_filament(None).unwrap();
+        assert_eq!(
+            weeks,
+            [
+                WeeklyFilament {
+                    week: "2023-11-13".to_string(),
+                    filament: 1050.0,
+                    jobs: 2
+                },
+                WeeklyFilament {
+                    week: "2023-11-20".to_string(),
+                    filament: 500.0,
+                    jobs: 2
+                },
+            ]
+        );
+
+        let mut csv = Vec::new();
+        write_csv(&all[3..], &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "printer,file,start,end,duration_s,result,filament_mm,peak_tool_c,peak_bed_c\n\
+             mk3,\"folder/part, v2.gcode\",2023-11-13T00:00:00Z,2023-11-13T01:00:00Z,3600,success,1000.0,215.0,\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record() {
+        let server = MockServer::start();
+        server.with_printer_mut(|p| {
+            p.connect_ticks = 0;
+            p.progress_step = 0.2;
+            p.connect(None, None, None);
+            p.files.insert(
+                "a.gcode".to_string(),
+                StoredFile {
+                    content: b"G28\n".to_vec(),
+                    date: 0,
+                    userdata: None,
+                },
+            );
+            p.select("a.gcode");
+            p.start();
+        });
+        let fleet = Fleet::single("mock", server.client());
+        let history = History::open_in_memory().unwrap();
+        let mut logged = 0;
+        record(
+            &fleet,
+            &history,
+            Duration::from_millis(1),
+            |_| logged += 1,
+            tokio::time::sleep(Duration::from_millis(200)),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(logged, 1);
+        let jobs = history.jobs(&HistoryFilter::default()).unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].printer, "mock");
+        assert_eq!(jobs[0].file, "a.gcode");
+        assert_eq!(jobs[0].result, JobResult::Success);
+        assert!(jobs[0].peak_tool.is_some());
+    }
+}
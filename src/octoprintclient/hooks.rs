@@ -0,0 +1,769 @@
+//! Hooks: run a command or call a webhook when a printer event happens.
+//!
+//! Hooks are listed in the configuration file, e.g.:
+//!
+//! ```toml
+//! [[hooks]]
+//! events = ["PrintDone", "PrintFailed"]
+//! command = "notify-send \"$OCTOPRINT_PRINTER\" \"$MESSAGE\""
+//! env = { MESSAGE = "{file}: {event}" }
+//!
+//! [[hooks]]
+//! events = ["TemperatureAbove"]
+//! heater = "tool0"
+//! threshold = 270
+//! url = "http://alerts.local/printers"
+//! payload = '{"text": "{printer} {heater} is at {temperature}°C"}'
+//! rate_limit = 300
+//! ```
+//!
+//! The events are those of `events::PrinterEvent`, and "TemperatureAbove" when a heater goes
+//! over `threshold`. Templates replace `{printer}`, `{event}`, `{state}`, `{file}`,
+//! `{progress}`, `{heater}`, `{temperature}`, `{error}` and `{timestamp}` by their value.
+//! Commands also get them in the `OCTOPRINT_<NAME>` environment variables.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hyper::{Body, Method, Request};
+use serde_derive::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+use super::events::{EventTracker, PrinterEvent};
+use super::fleet::{Fleet, PrinterStatus};
+use super::{https_client, HttpsClient, OctoPrintClientError};
+
+/// Name of the event of a heater going over the threshold of a hook.
+pub const TEMPERATURE_ABOVE: &str = "TemperatureAbove";
+
+/// A heater must cool this much below the threshold before it can trigger again, in °C.
+const HYSTERESIS: f32 = 2.0;
+
+/// Time given to a command or a webhook to complete.
+const ACTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Delay before the first retry, doubled for each following one.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct HookConfiguration {
+    /// Name used in the logs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub events: Vec<String>,
+    /// Only the events of these printers, all if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub printers: Vec<String>,
+    /// Shell command to run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Templated environment variables of the command.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// URL to POST to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Templated JSON body of the webhook, all the values by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// Heater watched by "TemperatureAbove" ("tool0", "bed", ...), all if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heater: Option<String>,
+    /// Temperature triggering "TemperatureAbove", in °C.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f32>,
+    /// Minimum time between two runs for the same printer, in seconds.
+    #[serde(default)]
+    pub rate_limit: u64,
+    /// Number of retries when the action fails.
+    #[serde(default)]
+    pub retries: u32,
+}
+
+impl HookConfiguration {
+    /// Check that the hook can be used, returns the problem otherwise.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.command.is_some() == self.url.is_some() {
+            return Err("Either \"command\" or \"url\" must be set".to_string());
+        }
+        if self.events.is_empty() {
+            return Err("No event".to_string());
+        }
+        for event in &self.events {
+            if event == TEMPERATURE_ABOVE {
+                if self.threshold.is_none() {
+                    return Err(format!("\"{}\" needs a \"threshold\"", TEMPERATURE_ABOVE));
+                }
+            } else if PrinterEvent::from_name(event).is_none() {
+                return Err(format!("Unknown event \"{}\"", event));
+            }
+        }
+        if let Some(url) = &self.url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err("Only http:// and https:// URLs are supported".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn accepts(&self, printer: &str, event: &str) -> bool {
+        self.events.iter().any(|e| e == event)
+            && (self.printers.is_empty() || self.printers.iter().any(|p| p == printer))
+    }
+}
+
+/// An event which triggered a hook, with the values of the templates.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HookEvent {
+    pub printer: String,
+    pub event: String,
+    pub state: String,
+    pub file: Option<String>,
+    /// Progress of the job, in %.
+    pub progress: Option<f64>,
+    pub heater: Option<String>,
+    pub temperature: Option<f32>,
+    pub error: Option<String>,
+    pub time: Option<SystemTime>,
+}
+
+impl HookEvent {
+    fn new(printer: &str, event: &str, status: &PrinterStatus) -> Self {
+        let job = &status.job;
+        HookEvent {
+            printer: printer.to_string(),
+            event: event.to_string(),
+            state: job.state.to_string(),
+            file: job.job.file.path.clone(),
+            progress: job.progress.completion,
+            error: job.error.clone().filter(|e| !e.is_empty()),
+            time: Some(SystemTime::now()),
+            ..Default::default()
+        }
+    }
+
+    /// Values of the templates, by name.
+    pub fn variables(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("printer", self.printer.clone()),
+            ("event", self.event.clone()),
+            ("state", self.state.clone()),
+            ("file", self.file.clone().unwrap_or_default()),
+            (
+                "progress",
+                self.progress
+                    .map(|p| format!("{:.1}", p))
+                    .unwrap_or_default(),
+            ),
+            ("heater", self.heater.clone().unwrap_or_default()),
+            (
+                "temperature",
+                self.temperature
+                    .map(|t| format!("{:.1}", t))
+                    .unwrap_or_default(),
+            ),
+            ("error", self.error.clone().unwrap_or_default()),
+            (
+                "timestamp",
+                self.time
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs().to_string())
+                    .unwrap_or_default(),
+            ),
+        ]
+    }
+}
+
+/// Replace the `{name}` placeholders of `template`, unknown names are kept.
+///
+/// With `json`, the values are escaped to be used inside a JSON string.
+pub fn render_template(template: &str, event: &HookEvent, json: bool) -> String {
+    let variables = event.variables();
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let (_, value) = variables.iter().find(|(name, _)| *name == &rest[1..end])?;
+            Some((end, value))
+        });
+        match value {
+            Some((end, value)) => {
+                if json {
+                    let quoted = serde_json::to_string(value).unwrap_or_default();
+                    out.push_str(&quoted[1..quoted.len() - 1]);
+                } else {
+                    out.push_str(value);
+                }
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// What the hooks did, reported to the caller.
+#[derive(Debug)]
+pub enum HookLog {
+    Done {
+        hook: String,
+        event: HookEvent,
+    },
+    /// Skipped because the hook ran less than `rate_limit` seconds ago.
+    RateLimited {
+        hook: String,
+        event: HookEvent,
+    },
+    /// The action failed, after all the retries.
+    Failed {
+        hook: String,
+        event: HookEvent,
+        error: String,
+    },
+}
+
+/// State of a printer, to detect its events.
+#[derive(Debug, Default)]
+struct PrinterWatch {
+    events: EventTracker,
+    /// Hooks (by index) and heaters currently above the threshold.
+    above: BTreeSet<(usize, String)>,
+}
+
+/// Detects the events of the printers and runs the hooks.
+#[derive(Debug)]
+pub struct HookRunner {
+    hooks: Vec<HookConfiguration>,
+    printers: BTreeMap<String, PrinterWatch>,
+    /// Last run of each hook (by index) for each printer.
+    last_run: BTreeMap<(usize, String), Instant>,
+    /// Client of the webhooks, for http:// and https:// URLs.
+    client: HttpsClient,
+}
+
+impl HookRunner {
+    pub fn new(hooks: Vec<HookConfiguration>) -> Self {
+        HookRunner {
+            hooks,
+            printers: BTreeMap::new(),
+            last_run: BTreeMap::new(),
+            client: https_client(),
+        }
+    }
+
+    fn hook_name(&self, index: usize) -> String {
+        self.hooks[index]
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("hook #{}", index + 1))
+    }
+
+    /// Update with the status of a printer, returns the hooks (by index) to run.
+    pub fn triggered(&mut self, printer: &str, status: &PrinterStatus) -> Vec<(usize, HookEvent)> {
+        let watch = self.printers.entry(printer.to_string()).or_default();
+        let mut triggered = Vec::new();
+        for event in watch.events.update(&status.job) {
+            for (index, hook) in self.hooks.iter().enumerate() {
+                if hook.accepts(printer, event.as_str()) {
+                    triggered.push((index, HookEvent::new(printer, event.as_str(), status)));
+                }
+            }
+        }
+
+        let temperature = status.printer.as_ref().and_then(|p| p.temperature.as_ref());
+        let heaters: Vec<(String, f32)> = temperature
+            .map(|t| {
+                t.tools
+                    .iter()
+                    .map(|(name, data)| (name.clone(), data))
+                    .chain(t.bed.iter().map(|data| ("bed".to_string(), data)))
+                    .chain(t.chamber.iter().map(|data| ("chamber".to_string(), data)))
+                    .filter_map(|(name, data)| data.actual.map(|a| (name, a)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        for (index, hook) in self.hooks.iter().enumerate() {
+            let Some(threshold) = hook.threshold else {
+                continue;
+            };
+            if !hook.accepts(printer, TEMPERATURE_ABOVE) {
+                continue;
+            }
+            for (heater, actual) in &heaters {
+                if hook.heater.as_ref().is_some_and(|h| h != heater) {
+                    continue;
+                }
+                let key = (index, heater.clone());
+                if *actual > threshold {
+                    if watch.above.insert(key) {
+                        let mut event = HookEvent::new(printer, TEMPERATURE_ABOVE, status);
+                        event.heater = Some(heater.clone());
+                        event.temperature = Some(*actual);
+                        triggered.push((index, event));
+                    }
+                } else if *actual < threshold - HYSTERESIS {
+                    watch.above.remove(&key);
+                }
+            }
+        }
+        triggered
+    }
+
+    /// Record a run of the hook, false if it ran less than `rate_limit` before `now`.
+    pub fn rate_limit(&mut self, index: usize, printer: &str, now: Instant) -> bool {
+        let limit = Duration::from_secs(self.hooks[index].rate_limit);
+        let key = (index, printer.to_string());
+        match self.last_run.get(&key) {
+            Some(last) if now.duration_since(*last) < limit => false,
+            _ => {
+                self.last_run.insert(key, now);
+                true
+            }
+        }
+    }
+
+    /// Run the action of a hook, with its retries.
+    pub async fn execute(&self, index: usize, event: &HookEvent) -> Result<(), String> {
+        Self::execute_hook(&self.client, &self.hooks[index], event).await
+    }
+
+    async fn execute_hook(
+        client: &HttpsClient,
+        hook: &HookConfiguration,
+        event: &HookEvent,
+    ) -> Result<(), String> {
+        let mut delay = RETRY_DELAY;
+        let mut attempt = 0;
+        loop {
+            let action = Self::action(client, hook, event);
+            let result = match tokio::time::timeout(ACTION_TIMEOUT, action).await {
+                Ok(result) => result,
+                Err(_) => Err("Timeout".to_string()),
+            };
+            if result.is_ok() || attempt >= hook.retries {
+                return result;
+            }
+            attempt += 1;
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    async fn action(
+        client: &HttpsClient,
+        hook: &HookConfiguration,
+        event: &HookEvent,
+    ) -> Result<(), String> {
+        if let Some(command) = &hook.command {
+            let mut process = if cfg!(windows) {
+                let mut p = tokio::process::Command::new("cmd");
+                p.arg("/C");
+                p
+            } else {
+                let mut p = tokio::process::Command::new("sh");
+                p.arg("-c");
+                p
+            };
+            process.arg(command).kill_on_drop(true);
+            for (name, value) in event.variables() {
+                process.env(format!("OCTOPRINT_{}", name.to_uppercase()), value);
+            }
+            for (name, template) in &hook.env {
+                process.env(name, render_template(template, event, false));
+            }
+            let status = process.status().await.map_err(|e| e.to_string())?;
+            if status.success() {
+                Ok(())
+            } else {
+                Err(format!("Command failed ({})", status))
+            }
+        } else if let Some(url) = &hook.url {
+            let body = match &hook.payload {
+                Some(template) => render_template(template, event, true),
+                None => serde_json::Value::Object(
+                    event
+                        .variables()
+                        .into_iter()
+                        .map(|(name, value)| (name.to_string(), value.into()))
+                        .collect(),
+                )
+                .to_string(),
+            };
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri(url)
+                .header("Content-Type", "application/json");
+            for (name, value) in &hook.headers {
+                request = request.header(name, value);
+            }
+            let request = request.body(Body::from(body)).map_err(|e| e.to_string())?;
+            let response = client.request(request).await.map_err(|e| e.to_string())?;
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                Err(format!("Webhook failed ({})", response.status()))
+            }
+        } else {
+            Err("No action".to_string())
+        }
+    }
+
+    /// Poll the printers every `interval` and run the hooks until `shutdown` completes.
+    ///
+    /// The hooks run in their own tasks, a slow or retrying action does not delay the next polls.
+    /// The actions still running are aborted on shutdown.
+    pub async fn run<L, S>(
+        &mut self,
+        fleet: &Fleet,
+        interval: Duration,
+        mut log: L,
+        shutdown: S,
+    ) -> Result<(), OctoPrintClientError>
+    where
+        L: FnMut(HookLog),
+        S: Future<Output = ()>,
+    {
+        tokio::pin!(shutdown);
+        let mut running = JoinSet::new();
+        loop {
+            for status in fleet.status().await {
+                let Ok(printer) = &status.result else {
+                    continue;
+                };
+                for (index, event) in self.triggered(&status.name, printer) {
+                    if self.rate_limit(index, &status.name, Instant::now()) {
+                        let client = self.client.clone();
+                        let hook = self.hooks[index].clone();
+                        running.spawn(async move {
+                            let result = Self::execute_hook(&client, &hook, &event).await;
+                            (index, event, result)
+                        });
+                    } else {
+                        log(HookLog::RateLimited {
+                            hook: self.hook_name(index),
+                            event,
+                        });
+                    }
+                }
+            }
+
+            let next_poll = tokio::time::sleep(interval);
+            tokio::pin!(next_poll);
+            loop {
+                tokio::select! {
+                    _ = &mut shutdown => return Ok(()),
+                    _ = &mut next_poll => break,
+                    Some(Ok((index, event, result))) = running.join_next() => {
+                        let hook = self.hook_name(index);
+                        log(match result {
+                            Ok(()) => HookLog::Done { hook, event },
+                            Err(error) => HookLog::Failed { hook, event, error },
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::testing::{MockServer, StoredFile};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Response, Server, StatusCode};
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+
+    fn event() -> HookEvent {
+        HookEvent {
+            printer: "mk3".to_string(),
+            event: "PrintDone".to_string(),
+            state: "Operational".to_string(),
+            file: Some("say \"hi\" {event}.gcode".to_string()),
+            progress: Some(100.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_template() {
+        let e = event();
+        assert_eq!(
+            render_template("{printer}: {file} {event} {unknown}", &e, false),
+            "mk3: say \"hi\" {event}.gcode PrintDone {unknown}"
+        );
+        let json = render_template(r#"{"text": "{file} at {progress}%"}"#, &e, true);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["text"], "say \"hi\" {event}.gcode at 100.0%");
+    }
+
+    #[test]
+    fn test_validate() {
+        let hook = |s: &str| toml_hook(s).validate();
+        assert!(hook("events = ['PrintDone']\ncommand = 'true'").is_ok());
+        assert!(hook("events = ['PrintDone']").is_err());
+        assert!(hook("events = ['PrintDone']\ncommand = 'true'\nurl = 'http://a'").is_err());
+        assert!(hook("events = ['Done']\ncommand = 'true'").is_err());
+        assert!(hook("events = ['TemperatureAbove']\ncommand = 'true'").is_err());
+        assert!(hook("events = ['TemperatureAbove']\nthreshold = 100\ncommand = 'true'").is_ok());
+        assert!(hook("events = ['Error']\nurl = 'https://a'").is_ok());
+        assert!(hook("events = ['Error']\nurl = 'ftp://a'").is_err());
+    }
+
+    fn toml_hook(s: &str) -> HookConfiguration {
+        let v: serde_json::Value = s
+            .lines()
+            .map(|l| l.split_once(" = ").unwrap())
+            .map(|(k, v)| {
+                let v = v.replace('\'', "\"");
+                (k.to_string(), serde_json::from_str(&v).unwrap())
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into();
+        serde_json::from_value(v).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_triggered() {
+        let server = MockServer::start();
+        server.with_printer_mut(|p| {
+            p.connect_ticks = 0;
+            p.progress_step = 0.0;
+            p.heat_rate = 0.0;
+            p.connect(None, None, None);
+            p.files.insert(
+                "a.gcode".to_string(),
+                StoredFile {
+                    content: b"G28\n".to_vec(),
+                    date: 0,
+                    userdata: None,
+                },
+            );
+            p.select("a.gcode");
+        });
+        let client = server.client();
+        let mut runner = HookRunner::new(vec![
+            HookConfiguration {
+                events: vec!["PrintStarted".to_string(), "PrintCancelled".to_string()],
+                command: Some("true".to_string()),
+                rate_limit: 60,
+                ..Default::default()
+            },
+            HookConfiguration {
+                events: vec![TEMPERATURE_ABOVE.to_string()],
+                heater: Some("tool0".to_string()),
+                threshold: Some(250.0),
+                command: Some("true".to_string()),
+                ..Default::default()
+            },
+            HookConfiguration {
+                events: vec!["PrintStarted".to_string()],
+                printers: vec!["other".to_string()],
+                command: Some("true".to_string()),
+                ..Default::default()
+            },
+        ]);
+
+        assert!(runner
+            .triggered("mk3", &client.get_status().await.unwrap())
+            .is_empty());
+        server.with_printer_mut(|p| p.start());
+        let triggered = runner.triggered("mk3", &client.get_status().await.unwrap());
+        assert_eq!(triggered.len(), 1);
+        assert_eq!(triggered[0].0, 0);
+        assert_eq!(triggered[0].1.event, "PrintStarted");
+        assert_eq!(triggered[0].1.file.as_deref(), Some("a.gcode"));
+
+        // Temperature crosses the threshold once
+        server.with_printer_mut(|p| p.tools[0].actual = 255.0);
+        let triggered = runner.triggered("mk3", &client.get_status().await.unwrap());
+        assert_eq!(triggered.len(), 1);
+        assert_eq!(triggered[0].1.heater.as_deref(), Some("tool0"));
+        assert_eq!(triggered[0].1.temperature, Some(255.0));
+        server.with_printer_mut(|p| p.tools[0].actual = 249.0);
+        assert!(runner
+            .triggered("mk3", &client.get_status().await.unwrap())
+            .is_empty());
+        server.with_printer_mut(|p| p.tools[0].actual = 251.0);
+        assert!(runner
+            .triggered("mk3", &client.get_status().await.unwrap())
+            .is_empty());
+
+        client.cancel_job().await.unwrap();
+        server.with_printer_mut(|p| p.tick());
+        let triggered = runner.triggered("mk3", &client.get_status().await.unwrap());
+        assert_eq!(triggered[0].1.event, "PrintCancelled");
+
+        let now = Instant::now();
+        assert!(runner.rate_limit(0, "mk3", now));
+        assert!(!runner.rate_limit(0, "mk3", now + Duration::from_secs(30)));
+        assert!(runner.rate_limit(0, "mini", now));
+        assert!(runner.rate_limit(0, "mk3", now + Duration::from_secs(60)));
+        assert!(runner.rate_limit(1, "mk3", now));
+        assert!(runner.rate_limit(1, "mk3", now));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command() {
+        let output = std::env::temp_dir().join("octoprint-client-test-hook.txt");
+        let _ = std::fs::remove_file(&output);
+        let runner = HookRunner::new(vec![HookConfiguration {
+            events: vec!["PrintDone".to_string()],
+            command: Some(format!(
+                "echo \"$OCTOPRINT_PRINTER $MESSAGE\" > {}",
+                output.display()
+            )),
+            env: BTreeMap::from([("MESSAGE".to_string(), "{file} {event}".to_string())]),
+            ..Default::default()
+        }]);
+        runner.execute(0, &event()).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "mk3 say \"hi\" {event}.gcode PrintDone\n"
+        );
+        std::fs::remove_file(&output).unwrap();
+
+        let runner = HookRunner::new(vec![HookConfiguration {
+            command: Some("exit 3".to_string()),
+            ..Default::default()
+        }]);
+        assert!(runner.execute(0, &event()).await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_slow_hook() {
+        let server = MockServer::start();
+        server.with_printer_mut(|p| {
+            p.connect_ticks = 0;
+            p.progress_step = 0.0;
+            p.connect(None, None, None);
+            p.files.insert(
+                "a.gcode".to_string(),
+                StoredFile {
+                    content: b"G28\n".to_vec(),
+                    date: 0,
+                    userdata: None,
+                },
+            );
+            p.select("a.gcode");
+        });
+        let fleet = Fleet::single("mk3", server.client());
+        let mut runner = HookRunner::new(vec![
+            HookConfiguration {
+                events: vec!["PrintStarted".to_string()],
+                command: Some("sleep 30".to_string()),
+                ..Default::default()
+            },
+            HookConfiguration {
+                events: vec!["PrintCancelled".to_string()],
+                command: Some("true".to_string()),
+                ..Default::default()
+            },
+        ]);
+
+        // Starts then cancels the print while the first hook is still running
+        let client = server.client();
+        let done = tokio::sync::Notify::new();
+        let printer = async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            server.with_printer_mut(|p| p.start());
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            client.cancel_job().await.unwrap();
+            done.notified().await;
+        };
+        let mut logs = Vec::new();
+        let run = runner.run(
+            &fleet,
+            Duration::from_millis(50),
+            |entry| {
+                if let HookLog::Done { event, .. } = &entry {
+                    if event.event == "PrintCancelled" {
+                        done.notify_one();
+                    }
+                }
+                logs.push(entry);
+            },
+            async {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            },
+        );
+        let finished = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::select! {
+                result = run => panic!("Hooks stopped: {:?}", result),
+                _ = printer => {}
+            }
+        })
+        .await;
+        assert!(finished.is_ok(), "The slow hook blocked the polls");
+        assert_eq!(logs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_webhook() {
+        // Fails the first request, accepts the next ones
+        // Authorization header and body of each request
+        type Received = Vec<(Option<String>, Vec<u8>)>;
+        let received: Arc<Mutex<Received>> = Arc::default();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_received = received.clone();
+        let make_svc = make_service_fn(move |_| {
+            let received = server_received.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let received = received.clone();
+                    async move {
+                        let token = req
+                            .headers()
+                            .get("Authorization")
+                            .map(|v| v.to_str().unwrap().to_string());
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        let mut received = received.lock().unwrap();
+                        received.push((token, body.to_vec()));
+                        let status = if received.len() == 1 {
+                            StatusCode::INTERNAL_SERVER_ERROR
+                        } else {
+                            StatusCode::OK
+                        };
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .status(status)
+                                .body(Body::empty())
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+        tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_svc));
+
+        let runner = HookRunner::new(vec![HookConfiguration {
+            events: vec!["PrintDone".to_string()],
+            url: Some(format!("http://{}/hook", addr)),
+            payload: Some(r#"{"text": "{printer}: {file}"}"#.to_string()),
+            headers: BTreeMap::from([("Authorization".to_string(), "Bearer x".to_string())]),
+            retries: 1,
+            ..Default::default()
+        }]);
+        runner.execute(0, &event()).await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[1].0.as_deref(), Some("Bearer x"));
+        let body: serde_json::Value = serde_json::from_slice(&received[1].1).unwrap();
+        assert_eq!(body["text"], "mk3: say \"hi\" {event}.gcode");
+    }
+}
@@ -1,25 +1,56 @@
+use serde::de::DeserializeOwned;
 use serde_derive::{Deserialize, Serialize};
 use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
 
-use hyper;
-use hyper::body::Buf;
+use hyper::body::{Buf, HttpBody};
 use hyper::{Body, Client, Method, Request, Response, StatusCode};
 
+pub mod access;
+pub mod autoupload;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod connection;
 pub mod datamodel;
-
+pub mod events;
+pub mod exporter;
+pub mod fleet;
+#[cfg(feature = "history")]
+pub mod history;
+pub mod hooks;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod push;
+pub mod queue;
+pub mod session;
+pub mod slicing;
+pub mod sync;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod timelapse;
+pub mod wait;
+pub mod webcam;
+
+use self::connection::ConnectionBuilder;
 use self::datamodel::*;
+use self::session::CookieJar;
 
 // type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 use thiserror::Error;
 
 // TODO: Use something geretated randomly for each request.
-const BONDARY: &'static str = "----WebKitFormBoundaryNhILabgMzjj9z3Io";
+const BONDARY: &str = "----WebKitFormBoundaryNhILabgMzjj9z3Io";
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum OctoPrintClientError {
     #[error("Server Error")]
     ServerError(String),
+    /// The request is refused in the current state of the printer (HTTP 409), e.g. the state
+    /// of the printer is asked while it is not connected.
+    #[error("Conflict")]
+    ConflictError(String),
     #[error("Client Error")]
     ClientError(#[from] hyper::Error),
     #[error("HTTP Error")]
@@ -28,68 +59,473 @@ pub enum OctoPrintClientError {
     JSONDecodeError(#[from] serde_json::Error),
     #[error("IO Error")]
     IOError(#[from] std::io::Error),
+    #[error("Timeout")]
+    TimeoutError(std::time::Duration),
+    #[error("Image Error")]
+    ImageError(#[from] image::ImageError),
+    #[error("WebSocket Error")]
+    WebSocketError(Box<tokio_tungstenite::tungstenite::Error>),
+    /// The webcam is not configured, or its stream is not valid MJPEG.
+    #[error("Webcam Error: {0}")]
+    WebcamError(String),
+    #[cfg(feature = "history")]
+    #[error("Database Error")]
+    DatabaseError(#[from] rusqlite::Error),
+    #[cfg(feature = "mqtt")]
+    #[error("MQTT Error")]
+    MqttError(#[from] rumqttc::ClientError),
+}
+
+// Boxed, the error of tungstenite is much larger than the others
+impl From<tokio_tungstenite::tungstenite::Error> for OctoPrintClientError {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        OctoPrintClientError::WebSocketError(Box::new(e))
+    }
+}
+
+/// Percent-encode a file path for use in an URL, `/` are kept.
+pub fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// Client for the URLs which are not on the OctoPrint server (webhooks, webcams), which may use
+/// https.
+type HttpsClient = Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>;
+
+fn https_client() -> HttpsClient {
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    Client::builder().build(connector)
+}
+
+/// Error reported by the server in an unexpected response.
+async fn error_from_response(mut resp: Response<Body>) -> OctoPrintClientError {
+    let status = resp.status();
+    let body = hyper::body::to_bytes(resp.body_mut())
+        .await
+        .unwrap_or_default();
+    let message = match serde_json::from_slice::<ErrorMsg>(&body) {
+        Ok(error_msg) => error_msg.error,
+        Err(_) => status.to_string(),
+    };
+    if status == StatusCode::CONFLICT {
+        OctoPrintClientError::ConflictError(message)
+    } else {
+        OctoPrintClientError::ServerError(message)
+    }
+}
+
+/// Decode the JSON body of a response.
+async fn decode<T: DeserializeOwned>(mut resp: Response<Body>) -> Result<T, OctoPrintClientError> {
+    let json_doc = hyper::body::aggregate(resp.body_mut()).await?;
+    Ok(serde_json::from_reader(json_doc.reader())?)
+}
+
+/// Content type of an uploaded file, from its extension.
+pub fn content_type(file_name: &str) -> &'static str {
+    let extension = file_name
+        .rsplit_once('.')
+        .map(|(_, e)| e.to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "gcode" | "gco" | "g" => "text/x.gcode",
+        "stl" => "model/stl",
+        _ => "application/octet-stream",
+    }
 }
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct Configuration {
+    pub server_url: String,
+    /// Empty when the API is used with a session, see `session`.
+    #[serde(default)]
+    pub api_key: String,
+    /// User to log in with when there is no API key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Password of `username`, asked if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Printers of the fleet, see `fleet::Fleet`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub printers: Vec<PrinterConfiguration>,
+    /// Commands or webhooks run on printer events, see `hooks`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<hooks::HookConfiguration>,
+}
+
+/// A printer of the fleet.
+#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PrinterConfiguration {
+    pub name: String,
     pub server_url: String,
     pub api_key: String,
+    /// Group of printers, used to target print queue jobs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+impl PrinterConfiguration {
+    pub fn configuration(&self) -> Configuration {
+        Configuration {
+            server_url: self.server_url.clone(),
+            api_key: self.api_key.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Options of `OctoPrintClient::upload_with_options()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UploadOptions {
+    /// Folder to upload to, created if needed. Root of the storage if `None`.
+    pub path: Option<String>,
+    /// Select the file after upload.
+    pub select: bool,
+    /// Start printing the file after upload.
+    pub print: bool,
+    /// JSON document saved with the file as metadata.
+    pub userdata: Option<serde_json::Value>,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        UploadOptions {
+            path: None,
+            select: true,
+            print: false,
+            userdata: None,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct OctoPrintClient {
     config: Configuration,
+    /// Session cookies, see `session`.
+    cookies: Arc<Mutex<CookieJar>>,
 }
 
 impl OctoPrintClient {
     pub fn from_config(config: Configuration) -> Self {
-        OctoPrintClient { config }
+        OctoPrintClient {
+            config,
+            cookies: Arc::new(Mutex::new(CookieJar::default())),
+        }
+    }
+
+    /// Request with the credentials of the client: the API key if set, and the session cookies
+    /// with the CSRF token after a login.
+    fn request(&self, method: Method, uri: impl AsRef<str>) -> hyper::http::request::Builder {
+        let mut req = Request::builder().uri(uri.as_ref());
+        if !self.config.api_key.is_empty() {
+            req = req.header("X-Api-Key", &self.config.api_key);
+        }
+        let cookies = self.cookies.lock().unwrap();
+        if let Some(cookie) = cookies.header() {
+            req = req.header("Cookie", cookie);
+            if let (Some(token), false) = (cookies.csrf_token(), method == Method::GET) {
+                req = req.header("X-CSRF-Token", token);
+            }
+        }
+        req.method(method)
     }
 
     async fn fetch_url(&self, endpoint: &str) -> Result<Response<Body>, OctoPrintClientError> {
         let full_uri = self.config.server_url.clone() + "/api/" + endpoint;
-        let req = Request::builder()
-            .method(Method::GET)
-            .uri(&full_uri)
-            .header("X-Api-Key", &self.config.api_key)
+        let req = self.request(Method::GET, &full_uri).body(Body::empty())?;
+
+        let client = Client::new();
+        let resp = client.request(req).await?;
+        if resp.status() != StatusCode::OK {
+            return Err(error_from_response(resp).await);
+        }
+
+        Ok(resp)
+    }
+
+    /// GET `endpoint` and decode the JSON response.
+    async fn get<T: DeserializeOwned>(&self, endpoint: &str) -> Result<T, OctoPrintClientError> {
+        decode(self.fetch_url(endpoint).await?).await
+    }
+
+    pub async fn get_current_job(&self) -> Result<JobInformation, OctoPrintClientError> {
+        self.get("job").await
+    }
+
+    pub async fn get_server_info(&self) -> Result<ServerInfo, OctoPrintClientError> {
+        self.get("server").await
+    }
+
+    pub async fn get_printer_state(&self) -> Result<PrinterInfo, OctoPrintClientError> {
+        self.get("printer").await
+    }
+
+    /// Settings of the server.
+    pub async fn get_settings(&self) -> Result<Settings, OctoPrintClientError> {
+        self.get("settings").await
+    }
+
+    pub async fn get_printer_profiles(&self) -> Result<Vec<Profile>, OctoPrintClientError> {
+        let list: ProfileList = self.get("printerprofiles").await?;
+        Ok(list.profiles.into_values().collect())
+    }
+
+    pub async fn get_printer_profile(&self, id: &str) -> Result<Profile, OctoPrintClientError> {
+        self.get(&format!("printerprofiles/{}", id)).await
+    }
+
+    /// Profile of the connected printer, or the default profile if not connected.
+    pub async fn get_current_printer_profile(&self) -> Result<Profile, OctoPrintClientError> {
+        let profiles = self.get_printer_profiles().await?;
+        profiles
+            .iter()
+            .find(|p| p.current)
+            .or_else(|| profiles.iter().find(|p| p.default))
+            .cloned()
+            .ok_or(OctoPrintClientError::ServerError(
+                "No current printer profile".to_string(),
+            ))
+    }
+
+    /// Files and folders of the local storage, with the content of the folders if `recursive`.
+    pub async fn get_files(&self, recursive: bool) -> Result<FileList, OctoPrintClientError> {
+        self.get(&format!("files/local?recursive={}", recursive))
+            .await
+    }
+
+    /// Information about a file of the local storage.
+    pub async fn get_file(&self, path: &str) -> Result<FileEntry, OctoPrintClientError> {
+        self.get(&format!("files/local/{}", encode_path(path)))
+            .await
+    }
+
+    /// Download a file of the local storage, stopping after `max_bytes` if given.
+    pub async fn download(
+        &self,
+        path: &str,
+        max_bytes: Option<usize>,
+    ) -> Result<Vec<u8>, OctoPrintClientError> {
+        let req = self
+            .request(
+                Method::GET,
+                format!(
+                    "{}/downloads/files/local/{}",
+                    self.config.server_url,
+                    encode_path(path)
+                ),
+            )
             .body(Body::empty())?;
 
         let client = Client::new();
         let mut resp = client.request(req).await?;
         if resp.status() != StatusCode::OK {
-            let error_msg: ErrorMsg =
-                serde_json::from_reader(hyper::body::aggregate(resp.body_mut()).await?.reader())?;
-            return Err(OctoPrintClientError::ServerError(error_msg.error));
+            return Err(OctoPrintClientError::ServerError(format!(
+                "Download of {} failed: {}",
+                path,
+                resp.status()
+            )));
+        }
+
+        let max_bytes = max_bytes.unwrap_or(usize::MAX);
+        let mut content = Vec::new();
+        while let Some(chunk) = resp.body_mut().data().await {
+            content.extend_from_slice(&chunk?);
+            if content.len() >= max_bytes {
+                content.truncate(max_bytes);
+                break;
+            }
         }
+        Ok(content)
+    }
 
+    /// Send a request to `endpoint` with an optional JSON body, any success status is accepted.
+    async fn send(
+        &self,
+        method: Method,
+        endpoint: &str,
+        body: Option<String>,
+    ) -> Result<Response<Body>, OctoPrintClientError> {
+        let req = self.request(method, self.config.server_url.clone() + "/api/" + endpoint);
+        let req = match body {
+            Some(body) => req
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))?,
+            None => req.body(Body::empty())?,
+        };
+
+        let client = Client::new();
+        let resp = client.request(req).await?;
+        if !resp.status().is_success() {
+            return Err(error_from_response(resp).await);
+        }
         Ok(resp)
     }
 
-    pub async fn get_current_job(&self) -> Result<JobInformation, OctoPrintClientError> {
-        let mut resp = self.fetch_url("job").await?;
-        let json_doc = hyper::body::aggregate(resp.body_mut()).await?;
+    /// POST a JSON command to `endpoint`, the server answers "204 No Content".
+    async fn post_command<T: serde::Serialize>(
+        &self,
+        endpoint: &str,
+        command: &T,
+    ) -> Result<(), OctoPrintClientError> {
+        let req = self
+            .request(
+                Method::POST,
+                self.config.server_url.clone() + "/api/" + endpoint,
+            )
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(command)?))?;
 
-        Ok(serde_json::from_reader(json_doc.reader())?)
+        let client = Client::new();
+        let resp = client.request(req).await?;
+        if resp.status() != StatusCode::NO_CONTENT {
+            return Err(error_from_response(resp).await);
+        }
+        Ok(())
     }
 
-    pub async fn get_server_info(&self) -> Result<ServerInfo, OctoPrintClientError> {
-        let mut resp = self.fetch_url("server").await?;
-        let json_doc = hyper::body::aggregate(resp.body_mut()).await?;
+    /// Send G-code commands to the printer, it must be operational.
+    pub async fn send_gcode(&self, commands: &[&str]) -> Result<(), OctoPrintClientError> {
+        self.post_command("printer/command", &GcodeCommand::new(commands))
+            .await
+    }
 
-        Ok(serde_json::from_reader(json_doc.reader())?)
+    /// Select a file of the local storage for printing, and start printing it if `print`.
+    pub async fn select_file(&self, path: &str, print: bool) -> Result<(), OctoPrintClientError> {
+        self.post_command(
+            &format!("files/local/{}", encode_path(path)),
+            &SelectCommand {
+                command: "select".to_string(),
+                print,
+            },
+        )
+        .await
     }
 
-    pub async fn get_printer_state(&self) -> Result<PrinterInfo, OctoPrintClientError> {
-        let mut resp = self.fetch_url("printer").await?;
-        let json_doc = hyper::body::aggregate(resp.body_mut()).await?;
+    /// Cancel the current print job.
+    pub async fn cancel_job(&self) -> Result<(), OctoPrintClientError> {
+        self.post_command("job", &JobCommand::new("cancel")).await
+    }
+
+    /// Pause the current print job.
+    pub async fn pause_job(&self) -> Result<(), OctoPrintClientError> {
+        let cmd = JobCommand {
+            action: Some("pause".to_string()),
+            ..JobCommand::new("pause")
+        };
+        self.post_command("job", &cmd).await
+    }
+
+    /// Resume the paused print job.
+    pub async fn resume_job(&self) -> Result<(), OctoPrintClientError> {
+        let cmd = JobCommand {
+            action: Some("resume".to_string()),
+            ..JobCommand::new("pause")
+        };
+        self.post_command("job", &cmd).await
+    }
+
+    /// Set the target temperature of a tool, 0 turns the heater off.
+    pub async fn set_tool_temperature(
+        &self,
+        tool: usize,
+        target: f32,
+    ) -> Result<(), OctoPrintClientError> {
+        self.post_command("printer/tool", &TargetCommand::tool(tool, target))
+            .await
+    }
 
-        Ok(serde_json::from_reader(json_doc.reader())?)
+    /// Set the target temperature of the bed, 0 turns the heater off.
+    pub async fn set_bed_temperature(&self, target: f32) -> Result<(), OctoPrintClientError> {
+        self.post_command("printer/bed", &TargetCommand::bed(target))
+            .await
     }
 
+    /// Delete a file, or a folder with all its content.
+    pub async fn delete_file(&self, path: &str) -> Result<(), OctoPrintClientError> {
+        let req = self
+            .request(
+                Method::DELETE,
+                format!(
+                    "{}/api/files/local/{}",
+                    self.config.server_url,
+                    encode_path(path)
+                ),
+            )
+            .body(Body::empty())?;
+
+        let client = Client::new();
+        let resp = client.request(req).await?;
+        if resp.status() != StatusCode::NO_CONTENT {
+            return Err(error_from_response(resp).await);
+        }
+        Ok(())
+    }
+
+    /// Create a folder in the local storage, `path` is the full path of the new folder.
+    pub async fn create_folder(&self, path: &str) -> Result<(), OctoPrintClientError> {
+        let path = path.trim_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+
+        let mut payload = Vec::new();
+        for (field, value) in [("foldername", name), ("path", parent)] {
+            write!(payload, "--{}\r\n", BONDARY)?;
+            write!(
+                payload,
+                "Content-Disposition: form-data; name=\"{}\"\r\n",
+                field
+            )?;
+            write!(payload, "\r\n")?;
+            write!(payload, "{}\r\n", value)?;
+        }
+        write!(payload, "--{}--\r\n", BONDARY)?;
+
+        let req = self
+            .request(
+                Method::POST,
+                self.config.server_url.clone() + "/api/files/local",
+            )
+            .header(
+                "Content-Type",
+                format!("multipart/form-data; boundary={}", BONDARY),
+            )
+            .body(Body::from(payload))?;
+
+        let client = Client::new();
+        let resp = client.request(req).await?;
+        if resp.status() != StatusCode::CREATED {
+            return Err(error_from_response(resp).await);
+        }
+        Ok(())
+    }
+
+    /// Upload a file (G-code or model) to the root of the local storage and select it.
     pub async fn upload(
+        &self,
+        file: std::fs::File,
+        file_name: &str,
+    ) -> Result<(), OctoPrintClientError> {
+        self.upload_with_options(file, file_name, &UploadOptions::default())
+            .await
+    }
+
+    pub async fn upload_with_options(
         &self,
         mut file: std::fs::File,
         file_name: &str,
+        options: &UploadOptions,
     ) -> Result<(), OctoPrintClientError> {
         let mut payload = Vec::new();
 
@@ -99,32 +535,40 @@ impl OctoPrintClient {
             "Content-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n",
             file_name
         )?;
-        write!(payload, "Content-Type: text/x.gcode\r\n")?;
+        write!(payload, "Content-Type: {}\r\n", content_type(file_name))?;
         write!(payload, "\r\n")?;
         file.read_to_end(&mut payload)?;
         write!(payload, "\r\n")?;
-        write!(payload, "--{}\r\n", BONDARY)?;
-        write!(
-            payload,
-            "Content-Disposition: form-data; name=\"select\"\r\n"
-        )?;
-        write!(payload, "\r\n")?;
-        write!(payload, "true\r\n")?;
-        write!(payload, "--{}\r\n", BONDARY)?;
-        write!(
-            payload,
-            "Content-Disposition: form-data; name=\"print\"\r\n"
-        )?;
-        write!(payload, "\r\n")?;
-        write!(payload, "false\r\n")?;
+
+        let mut fields = vec![
+            ("select", options.select.to_string()),
+            ("print", options.print.to_string()),
+        ];
+        if let Some(path) = &options.path {
+            fields.push(("path", path.clone()));
+        }
+        if let Some(userdata) = &options.userdata {
+            fields.push(("userdata", userdata.to_string()));
+        }
+        for (name, value) in fields {
+            write!(payload, "--{}\r\n", BONDARY)?;
+            write!(
+                payload,
+                "Content-Disposition: form-data; name=\"{}\"\r\n",
+                name
+            )?;
+            write!(payload, "\r\n")?;
+            write!(payload, "{}\r\n", value)?;
+        }
         write!(payload, "--{}--\r\n", BONDARY)?;
 
         let length = payload.len();
 
-        let req = Request::builder()
-            .method(Method::POST)
-            .uri(self.config.server_url.clone() + "/api/files/local")
-            .header("X-Api-Key", &self.config.api_key)
+        let req = self
+            .request(
+                Method::POST,
+                self.config.server_url.clone() + "/api/files/local",
+            )
             .header(
                 "Content-Type",
                 format!("multipart/form-data; boundary={}", BONDARY),
@@ -146,17 +590,15 @@ impl OctoPrintClient {
     }
 
     pub async fn get_connection(&self) -> Result<PrinterConnection, OctoPrintClientError> {
-        let mut resp = self.fetch_url("connection").await?;
-        let json_doc = hyper::body::aggregate(resp.body_mut()).await?;
-
-        Ok(serde_json::from_reader(json_doc.reader())?)
+        self.get("connection").await
     }
 
     pub async fn connect(&self, cmd: &ConnectionCommand) -> Result<(), OctoPrintClientError> {
-        let req = Request::builder()
-            .method(Method::POST)
-            .uri(self.config.server_url.clone() + "/api/connection")
-            .header("X-Api-Key", &self.config.api_key)
+        let req = self
+            .request(
+                Method::POST,
+                self.config.server_url.clone() + "/api/connection",
+            )
             .header("Content-Type", "application/json")
             .body(Body::from(serde_json::to_string(cmd)?))?;
 
@@ -172,59 +614,23 @@ impl OctoPrintClient {
         Ok(())
     }
 
+    /// Connect with the preferred port, baudrate and profile of the server, see
+    /// `ConnectionBuilder`.
     pub async fn connect_default(&self) -> Result<(), OctoPrintClientError> {
-        let conn_state = self.get_connection().await?;
-        let br =
-            conn_state
-                .options
-                .baudrate_preference
-                .ok_or(OctoPrintClientError::ServerError(
-                    "No default baudrate stored in server".to_string(),
-                ))?;
-        let port = conn_state
-            .options
-            .port_preference
-            .ok_or(OctoPrintClientError::ServerError(
-                "No default port stored in server".to_string(),
-            ))?;
-
-        let profile = conn_state.options.printer_profile_preference.ok_or(
-            OctoPrintClientError::ServerError("No default port stored in server".to_string()),
-        )?;
-
-        let connect_cmd = ConnectionCommand {
-            command: "connect".to_string(),
-            port: Some(port),
-            baudrate: Some(br),
-            printer_profile: Some(profile), //Some("_default".to_string()),
-            save: Some(true),
-            autoconnect: Some(false),
-        };
-
-        let req = Request::builder()
-            .method(Method::POST)
-            .uri(self.config.server_url.clone() + "/api/connection")
-            .header("X-Api-Key", &self.config.api_key)
-            .header("Content-Type", "application/json")
-            .body(Body::from(serde_json::to_string(&connect_cmd)?))?;
-
-        let client = Client::new();
-        let mut resp = client.request(req).await?;
-        if resp.status() != StatusCode::NO_CONTENT {
-            eprintln!(
-                "{}",
-                hyper::body::aggregate(resp.body_mut()).await?.remaining()
-            );
-            return Err(OctoPrintClientError::ServerError("Server error".into()));
-        }
-        Ok(())
+        let options = self.get_connection().await?.options;
+        let cmd = ConnectionBuilder::new()
+            .save(true)
+            .autoconnect(false)
+            .build(&options)?;
+        self.connect(&cmd).await
     }
 
     pub async fn disconnect(&self) -> Result<(), OctoPrintClientError> {
-        let req = Request::builder()
-            .method(Method::POST)
-            .uri(self.config.server_url.clone() + "/api/connection")
-            .header("X-Api-Key", &self.config.api_key)
+        let req = self
+            .request(
+                Method::POST,
+                self.config.server_url.clone() + "/api/connection",
+            )
             .header("Content-Type", "application/json")
             .body(Body::from(serde_json::to_string(
                 &DisconnectCommand::default(),
@@ -245,47 +651,31 @@ impl OctoPrintClient {
 
 #[cfg(test)]
 mod tests {
+    use super::testing::{MockServer, StoredFile, VirtualPrinter};
+    use super::wait::WaitOptions;
     use super::*;
-    use std::process::Command;
-
-    fn get_apikey() -> String {
-        let buffer = Command::new("./tests/get-apikey.sh")
-            .output()
-            .unwrap()
-            .stdout;
-
-        let raw_api_key = String::from_utf8(buffer).unwrap();
-        let clean_api_key = raw_api_key
-            .strip_suffix("\n")
-            .unwrap_or(raw_api_key.as_str());
-
-        clean_api_key.into()
-    }
-
-    fn get_client() -> OctoPrintClient {
-        let c = Configuration {
-            api_key: get_apikey(),
-            server_url: "http://localhost".to_string(),
-        };
-        println!("Config: {:?}", c);
-
-        //OctoPrintClient::from_config(confy::load("octoprint-client").unwrap())
-        OctoPrintClient::from_config(c)
-    }
 
     fn get_client_with_wrong_url() -> OctoPrintClient {
+        // Bind then release a port, so that nothing listens on it
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
         let c = Configuration {
             api_key: "38863B6406FC4C1299E1974FAC6842B4".to_string(),
-            server_url: "http://idontexist.org".to_string(),
+            server_url: format!("http://127.0.0.1:{}", port),
+            ..Default::default()
         };
 
         OctoPrintClient::from_config(c)
     }
 
-    fn get_client_with_wrong_api_key() -> OctoPrintClient {
+    fn get_client_with_wrong_api_key(server: &MockServer) -> OctoPrintClient {
         let c = Configuration {
             api_key: "abdcasdfasfdasf".to_string(),
-            server_url: "http://localhost".to_string(),
+            server_url: server.url(),
+            ..Default::default()
         };
 
         OctoPrintClient::from_config(c)
@@ -293,14 +683,20 @@ mod tests {
 
     #[tokio::test]
     pub async fn test_get_server_info() {
-        let c = get_client();
-
-        //println!("{:?}", c);
+        let server = MockServer::start();
+        let c = server.client();
 
         let info = c.get_server_info().await.unwrap();
 
         assert_eq!(info.version, "1.8.6");
         assert_eq!(info.safemode, None);
+
+        let requests = server.requests_to(Method::GET, "/api/server");
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].headers.get("X-Api-Key").unwrap(),
+            testing::MOCK_API_KEY
+        );
     }
 
     #[tokio::test]
@@ -309,65 +705,390 @@ mod tests {
         let info_result = c.get_server_info().await;
         println!("{:?}", info_result);
 
-        assert!(info_result.is_err());
+        assert!(matches!(
+            info_result,
+            Err(OctoPrintClientError::ClientError(_))
+        ));
     }
 
     #[tokio::test]
     pub async fn test_false_apikey() {
-        let c = get_client_with_wrong_api_key();
+        let server = MockServer::start();
+        let c = get_client_with_wrong_api_key(&server);
         let info_result = c.get_server_info().await;
         println!("{:?}", info_result);
 
-        assert!(info_result.is_err());
+        assert!(matches!(
+            info_result,
+            Err(OctoPrintClientError::ServerError(_))
+        ));
+    }
+
+    #[tokio::test]
+    pub async fn test_server_error() {
+        let server = MockServer::start();
+        server.set_error(
+            Method::GET,
+            "/api/job",
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Something broke",
+        );
+        let c = server.client();
+
+        match c.get_current_job().await {
+            Err(OctoPrintClientError::ServerError(msg)) => assert_eq!(msg, "Something broke"),
+            r => panic!("Unexpected result {:?}", r),
+        }
     }
 
     #[tokio::test]
     pub async fn test_get_printer_state() {
-        let c = get_client();
+        let server = MockServer::start();
+        let c = server.client();
+
+        // Not connected yet
+        assert!(c.get_printer_state().await.is_err());
 
         c.connect_default().await.unwrap();
 
-        c.get_printer_state().await.unwrap();
+        let printer = c.get_printer_state().await.unwrap();
+        assert!(printer.state.unwrap().flags.operational);
+    }
+
+    #[tokio::test]
+    pub async fn test_get_printer_profiles() {
+        let server = MockServer::start();
+        let c = server.client();
+
+        let profiles = c.get_printer_profiles().await.unwrap();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].id, "_default");
+
+        let profile = c.get_printer_profile("_default").await.unwrap();
+        assert_eq!(profile.volume.width, 200.0);
+        assert!(c.get_printer_profile("unknown").await.is_err());
+
+        let profile = c.get_current_printer_profile().await.unwrap();
+        assert!(profile.default);
     }
 
     #[tokio::test]
     pub async fn test_get_current_job() {
-        let c = get_client();
+        let server = MockServer::start();
+        let c = server.client();
+
+        let job = c.get_current_job().await.unwrap();
+
+        assert_eq!(job.state, State::Closed);
+        assert_eq!(job.job.file.path, None);
+    }
 
-        c.get_current_job().await.unwrap();
+    #[test]
+    fn test_content_type() {
+        assert_eq!(content_type("benchy.gcode"), "text/x.gcode");
+        assert_eq!(content_type("dir/part.GCO"), "text/x.gcode");
+        assert_eq!(content_type("gear.stl"), "model/stl");
+        assert_eq!(content_type("README"), "application/octet-stream");
+    }
+
+    #[tokio::test]
+    pub async fn test_upload() {
+        let server = MockServer::start();
+        let c = server.client();
+
+        let mut path = std::env::temp_dir();
+        path.push("octoprint-client-test-upload.gcode");
+        std::fs::write(&path, "G28\nG1 X10 Y10\n").unwrap();
+
+        c.upload(std::fs::File::open(&path).unwrap(), "test.gcode")
+            .await
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let printer = server.printer();
+        assert_eq!(
+            printer.files.get("test.gcode").unwrap().content,
+            b"G28\nG1 X10 Y10\n"
+        );
+        // File is selected, but not printed
+        assert_eq!(printer.job.unwrap().path, "test.gcode");
+        assert_eq!(printer.state, State::Closed);
+
+        let requests = server.requests_to(Method::POST, "/api/files/local");
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0]
+            .body_text()
+            .contains("Content-Type: text/x.gcode"));
+    }
+
+    #[tokio::test]
+    pub async fn test_upload_with_options() {
+        let server = MockServer::start();
+        let c = server.client();
+
+        let mut path = std::env::temp_dir();
+        path.push("octoprint-client-test-upload-options.gcode");
+        std::fs::write(&path, ";TIME:60\nG28\n").unwrap();
+
+        let options = UploadOptions {
+            path: Some("parts".to_string()),
+            select: false,
+            print: false,
+            userdata: Some(serde_json::json!({"slicer": "Cura"})),
+        };
+        c.upload_with_options(std::fs::File::open(&path).unwrap(), "a.gcode", &options)
+            .await
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let printer = server.printer();
+        assert_eq!(printer.job, None);
+        let file = c.get_file("parts/a.gcode").await.unwrap();
+        assert_eq!(file.userdata.unwrap()["slicer"], "Cura");
+    }
+
+    #[tokio::test]
+    pub async fn test_files() {
+        let server = MockServer::start();
+        server.with_printer_mut(|p| {
+            for path in ["a.gcode", "parts/b c.gcode", "parts/sub/d.gcode"] {
+                p.files.insert(
+                    path.to_string(),
+                    StoredFile {
+                        content: path.as_bytes().to_vec(),
+                        date: 1668090815,
+                        userdata: None,
+                    },
+                );
+            }
+        });
+        let c = server.client();
+
+        let list = c.get_files(false).await.unwrap();
+        assert_eq!(list.files.len(), 2);
+        assert!(list.files[1].is_folder());
+        assert_eq!(list.files[1].children, None);
+
+        let list = c.get_files(true).await.unwrap();
+        let paths: Vec<&str> = list.all_files().iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["a.gcode", "parts/b c.gcode", "parts/sub/d.gcode"]);
+
+        let file = c.get_file("parts/b c.gcode").await.unwrap();
+        assert_eq!(file.size, Some(15));
+        assert!(matches!(
+            c.get_file("missing.gcode").await,
+            Err(OctoPrintClientError::ServerError(_))
+        ));
+
+        assert_eq!(
+            c.download("parts/b c.gcode", None).await.unwrap(),
+            b"parts/b c.gcode"
+        );
+        assert_eq!(
+            c.download("parts/b c.gcode", Some(5)).await.unwrap(),
+            b"parts"
+        );
+        assert_eq!(
+            server.requests().last().unwrap().path,
+            "/downloads/files/local/parts/b%20c.gcode"
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_wait_timeout() {
+        let server = MockServer::start();
+        let c = server.client();
+
+        let options = WaitOptions {
+            timeout: Some(std::time::Duration::from_millis(300)),
+            poll_interval: std::time::Duration::from_millis(100),
+        };
+        let result = c.wait_for_connection(|_| false, &options).await;
+
+        assert!(matches!(result, Err(OctoPrintClientError::TimeoutError(_))));
+    }
+
+    #[tokio::test]
+    pub async fn test_wait_errors() {
+        let server = MockServer::start();
+        let c = server.client();
+        let options = WaitOptions {
+            timeout: None,
+            poll_interval: std::time::Duration::from_millis(10),
+        };
+
+        // Not operational yet (409): polled until it is
+        server.with_printer_mut(|p| {
+            p.connect_ticks = 3;
+            p.connect(None, None, None)
+        });
+        assert!(matches!(
+            c.get_printer_state().await,
+            Err(OctoPrintClientError::ConflictError(_))
+        ));
+        c.wait_for_printer(|_| true, &options).await.unwrap();
+
+        // Other errors are returned, even without timeout
+        server.set_error(
+            Method::GET,
+            "/api/printer",
+            StatusCode::FORBIDDEN,
+            "Forbidden",
+        );
+        assert!(matches!(
+            c.wait_for_printer(|_| true, &options).await,
+            Err(OctoPrintClientError::ServerError(msg)) if msg == "Forbidden"
+        ));
+    }
+
+    #[tokio::test]
+    pub async fn test_wait_job_done() {
+        let server = MockServer::start();
+        let c = server.client();
+        server.with_printer_mut(|p| {
+            p.connect_ticks = 0;
+            p.connect(None, None, None);
+            p.files.insert(
+                "benchy.gcode".to_string(),
+                testing::StoredFile {
+                    content: b"G28\n".to_vec(),
+                    date: 0,
+                    userdata: None,
+                },
+            );
+            assert!(p.select("benchy.gcode"));
+            assert!(p.start());
+        });
+
+        let job = c.get_current_job().await.unwrap();
+        assert_eq!(job.state, State::Printing);
+        assert_eq!(job.job.file.path.as_deref(), Some("benchy.gcode"));
+
+        let options = WaitOptions {
+            timeout: Some(std::time::Duration::from_secs(5)),
+            poll_interval: std::time::Duration::from_millis(10),
+        };
+        let job = c.wait_for_job_done(&options).await.unwrap();
+
+        assert_eq!(job.state, State::Operational);
+        assert_eq!(job.progress.completion, Some(100.0));
+    }
+
+    #[tokio::test]
+    pub async fn test_wait_job_end() {
+        use super::events::PrinterEvent;
+
+        let server = MockServer::start();
+        let c = server.client();
+        server.with_printer_mut(|p| {
+            p.connect_ticks = 0;
+            p.connect(None, None, None);
+            p.files.insert(
+                "benchy.gcode".to_string(),
+                StoredFile {
+                    content: b"G28\n".to_vec(),
+                    date: 0,
+                    userdata: None,
+                },
+            );
+            p.select("benchy.gcode");
+        });
+        let options = WaitOptions {
+            timeout: Some(std::time::Duration::from_secs(5)),
+            poll_interval: std::time::Duration::from_millis(10),
+        };
+
+        // No job running
+        let (_, end) = c.wait_for_job_end(false, &options).await.unwrap();
+        assert_eq!(end, None);
+
+        server.with_printer_mut(|p| p.start());
+        let (job, end) = c.wait_for_job_end(false, &options).await.unwrap();
+        assert_eq!(end, Some(PrinterEvent::PrintDone));
+        assert_eq!(job.state, State::Operational);
+
+        // Started while waiting, then cancelled
+        server.with_printer_mut(|p| p.progress_step = 0.0);
+        let started = async {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            server.with_printer_mut(|p| p.start());
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            server.with_printer_mut(|p| p.cancel());
+        };
+        let ((_, end), _) = tokio::join!(
+            async { c.wait_for_job_end(true, &options).await.unwrap() },
+            started
+        );
+        assert_eq!(end, Some(PrinterEvent::PrintCancelled));
+    }
+
+    #[tokio::test]
+    pub async fn test_wait_server_not_answering() {
+        // Accepts the connections but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let c = OctoPrintClient::from_config(Configuration {
+            api_key: "38863B6406FC4C1299E1974FAC6842B4".to_string(),
+            server_url: format!("http://{}", listener.local_addr().unwrap()),
+            ..Default::default()
+        });
+        let options = WaitOptions {
+            timeout: Some(std::time::Duration::from_millis(300)),
+            poll_interval: std::time::Duration::from_millis(100),
+        };
+        let result = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            c.wait_for_job_done(&options),
+        )
+        .await
+        .expect("The timeout did not stop the request");
+        assert!(matches!(result, Err(OctoPrintClientError::TimeoutError(_))));
     }
 
     #[tokio::test]
     pub async fn test_connect_disconnect() {
-        let c = get_client();
+        let mut printer = VirtualPrinter::default();
+        printer.connect_ticks = 3;
+        let server = MockServer::with_printer(printer);
+        let c = server.client();
 
         let connection_info = c.get_connection().await.unwrap();
 
         let connect_cmd = ConnectionCommand {
-            command: "connect".to_string(),
+            command: ConnectionAction::Connect,
             port: Some("VIRTUAL".to_string()),
             baudrate: Some(115200),
-            printer_profile: Some(connection_info.options.printer_profile_preference).unwrap(), //Some("_default".to_string()),
+            printer_profile: connection_info.options.printer_profile_preference,
             save: Some(true),
             autoconnect: Some(false),
         };
 
         c.connect(&connect_cmd).await.unwrap();
 
-        std::thread::sleep(std::time::Duration::from_secs(2));
+        let wait_options = WaitOptions {
+            timeout: Some(std::time::Duration::from_secs(10)),
+            poll_interval: std::time::Duration::from_millis(10),
+        };
+        let connection_info = c
+            .wait_for_connection_state(&State::Operational, &wait_options)
+            .await
+            .unwrap();
 
-        let connection_info = c.get_connection().await.unwrap();
+        assert_eq!(connection_info.current.state, State::Operational);
+        assert_eq!(connection_info.current.port.as_deref(), Some("VIRTUAL"));
 
-        assert_eq!(connection_info.current.state, "Operational");
+        let request = &server.requests_to(Method::POST, "/api/connection")[0];
+        assert_eq!(request.body_json()["command"], "connect");
+        assert_eq!(request.body_json()["baudrate"], 115200);
 
         c.disconnect().await.unwrap();
 
-        std::thread::sleep(std::time::Duration::from_secs(2));
-
-        let connection_info = c.get_connection().await.unwrap();
+        let connection_info = c
+            .wait_for_connection_state(&State::Closed, &wait_options)
+            .await
+            .unwrap();
 
         println!("connection info : {:?}", connection_info);
 
-        assert_eq!(connection_info.current.state, "Closed");
+        assert_eq!(connection_info.current.state, State::Closed);
     }
 }
@@ -0,0 +1,742 @@
+//! Bridge between the printers of a fleet and an MQTT broker.
+//!
+//! For each printer, the bridge publishes JSON payloads on the topics below `<prefix>/<printer>`:
+//!
+//!  - `state`: state of the printer (retained),
+//!  - `job`: file and progress of the current job (retained),
+//!  - `temperature`: actual and target temperatures of the heaters (retained),
+//!  - `event`: state changes ("PrintStarted", "PrintDone", ...) and command results.
+//!
+//! The retained topics are only published when their payload changes. Commands are received on
+//! `<prefix>/<printer>/command`, e.g. `{"command": "temperature", "heater": "bed", "target": 60}`,
+//! and only executed if they are in the allow-list. `<prefix>/bridge` is "online" while the bridge
+//! is connected, and "offline" otherwise (last will).
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+
+use super::events::EventTracker;
+use super::fleet::{Fleet, PrinterStatus};
+use super::{OctoPrintClient, OctoPrintClientError};
+
+/// Delay before connecting again to the broker after an error.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Highest heater target accepted from MQTT, in °C.
+pub const MAX_TARGET_TEMPERATURE: f32 = 300.0;
+
+/// Command received on a command topic.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum BridgeCommand {
+    Pause,
+    Resume,
+    Cancel,
+    /// Set the target of a heater: "tool0", "tool1", ... or "bed".
+    Temperature {
+        heater: String,
+        target: f32,
+    },
+    Gcode {
+        gcode: Vec<String>,
+    },
+}
+
+impl BridgeCommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BridgeCommand::Pause => "pause",
+            BridgeCommand::Resume => "resume",
+            BridgeCommand::Cancel => "cancel",
+            BridgeCommand::Temperature { .. } => "temperature",
+            BridgeCommand::Gcode { .. } => "gcode",
+        }
+    }
+
+    async fn execute(&self, client: &OctoPrintClient) -> Result<(), OctoPrintClientError> {
+        match self {
+            BridgeCommand::Pause => client.pause_job().await,
+            BridgeCommand::Resume => client.resume_job().await,
+            BridgeCommand::Cancel => client.cancel_job().await,
+            BridgeCommand::Temperature { heater, target } => match heater.as_str() {
+                "bed" => client.set_bed_temperature(*target).await,
+                tool => match tool.strip_prefix("tool").and_then(|i| i.parse().ok()) {
+                    Some(index) => client.set_tool_temperature(index, *target).await,
+                    None => Err(OctoPrintClientError::ServerError(format!(
+                        "Unknown heater {}",
+                        heater
+                    ))),
+                },
+            },
+            BridgeCommand::Gcode { gcode } => {
+                let commands: Vec<&str> = gcode.iter().map(|c| c.as_str()).collect();
+                client.send_gcode(&commands).await
+            }
+        }
+    }
+}
+
+/// Commands accepted from MQTT, nothing is accepted by default.
+#[derive(Clone, Debug, Default)]
+pub struct AllowList {
+    /// Names of the accepted commands ("pause", "resume", "cancel", "temperature", "gcode").
+    pub commands: BTreeSet<String>,
+    /// G-code commands accepted by "gcode" (e.g. "M84"), all if empty.
+    pub gcode: BTreeSet<String>,
+}
+
+impl AllowList {
+    /// Check that the command is allowed, returns the reason otherwise.
+    pub fn check(&self, command: &BridgeCommand) -> Result<(), String> {
+        if !self.commands.contains(command.name()) {
+            return Err(format!("Command \"{}\" is not allowed", command.name()));
+        }
+        match command {
+            BridgeCommand::Gcode { gcode } if !self.gcode.is_empty() => {
+                // An entry may hold several commands, separated by line breaks
+                for line in gcode.iter().flat_map(|l| l.split(['\n', '\r'])) {
+                    let Some(word) = line.split_whitespace().next() else {
+                        continue;
+                    };
+                    if !self.gcode.contains(&word.to_uppercase()) {
+                        return Err(format!("G-code \"{}\" is not allowed", word));
+                    }
+                }
+            }
+            BridgeCommand::Temperature { target, .. }
+                if !(0.0..=MAX_TARGET_TEMPERATURE).contains(target) =>
+            {
+                return Err(format!(
+                    "Target {} is not between 0 and {}",
+                    target, MAX_TARGET_TEMPERATURE
+                ));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BridgeOptions {
+    /// First level of all the topics.
+    pub prefix: String,
+    /// Time between two polls of the printers.
+    pub interval: Duration,
+    pub allow: AllowList,
+}
+
+impl BridgeOptions {
+    /// Check that the options can be used, returns the problem otherwise.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interval.is_zero() {
+            return Err("The interval must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for BridgeOptions {
+    fn default() -> Self {
+        BridgeOptions {
+            prefix: "octoprint".to_string(),
+            interval: Duration::from_secs(5),
+            allow: AllowList::default(),
+        }
+    }
+}
+
+/// Message to publish.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    /// JSON payload.
+    pub payload: String,
+    pub retain: bool,
+}
+
+/// What the bridge did, reported to the caller.
+#[derive(Debug)]
+pub enum BridgeEvent {
+    Connected,
+    /// The connection to the broker failed, it is retried.
+    ConnectionError(String),
+    Command {
+        printer: String,
+        command: String,
+        result: Result<(), String>,
+    },
+}
+
+/// What was last seen and published for a printer.
+#[derive(Debug, Default)]
+struct PrinterTrack {
+    events: EventTracker,
+    /// Last payload of the retained topics.
+    published: BTreeMap<String, String>,
+}
+
+fn seconds(d: Option<Duration>) -> Value {
+    d.map_or(Value::Null, |d| json!(d.as_secs()))
+}
+
+fn describe(error: &OctoPrintClientError) -> String {
+    match error {
+        OctoPrintClientError::ServerError(message)
+        | OctoPrintClientError::ConflictError(message) => message.clone(),
+        e => format!("{}: {:?}", e, e),
+    }
+}
+
+/// Publishes the state of the printers of a fleet, and executes the commands received.
+#[derive(Debug)]
+pub struct Bridge {
+    fleet: Fleet,
+    options: BridgeOptions,
+    printers: BTreeMap<String, PrinterTrack>,
+}
+
+impl Bridge {
+    pub fn new(fleet: Fleet, options: BridgeOptions) -> Self {
+        Bridge {
+            fleet,
+            options,
+            printers: BTreeMap::new(),
+        }
+    }
+
+    /// Topic `name` of a printer.
+    pub fn topic(&self, printer: &str, name: &str) -> String {
+        format!("{}/{}/{}", self.options.prefix, printer, name)
+    }
+
+    /// "online" while the bridge is connected, "offline" otherwise.
+    pub fn availability_topic(&self) -> String {
+        format!("{}/bridge", self.options.prefix)
+    }
+
+    pub fn command_topics(&self) -> Vec<String> {
+        self.fleet
+            .names()
+            .map(|name| self.topic(name, "command"))
+            .collect()
+    }
+
+    /// Query the printers, returns the messages to publish.
+    pub async fn poll(&mut self) -> Vec<Message> {
+        let mut messages = Vec::new();
+        for status in self.fleet.status().await {
+            let name = status.name;
+            let payloads = match &status.result {
+                Ok(s) => Self::payloads(s),
+                Err(e) => vec![(
+                    "state",
+                    json!({"state": null, "reachable": false, "connected": false,
+                           "error": describe(e)}),
+                )],
+            };
+            let topics: Vec<(String, Value)> = payloads
+                .into_iter()
+                .map(|(topic, payload)| (self.topic(&name, topic), payload))
+                .collect();
+            let event_topic = self.topic(&name, "event");
+            let track = self.printers.entry(name).or_default();
+
+            for (topic, payload) in topics {
+                let payload = payload.to_string();
+                if track.published.get(&topic) != Some(&payload) {
+                    track.published.insert(topic.clone(), payload.clone());
+                    messages.push(Message {
+                        topic,
+                        payload,
+                        retain: true,
+                    });
+                }
+            }
+
+            // An unreachable server does not tell anything about the printer
+            let Ok(s) = &status.result else {
+                continue;
+            };
+            for event in track.events.update(&s.job) {
+                messages.push(Message {
+                    topic: event_topic.clone(),
+                    payload: json!({
+                        "event": event.as_str(),
+                        "state": s.job.state.as_str(),
+                        "file": s.job.job.file.path,
+                    })
+                    .to_string(),
+                    retain: false,
+                });
+            }
+        }
+        messages
+    }
+
+    /// Payloads of the retained topics, by topic name.
+    fn payloads(status: &PrinterStatus) -> Vec<(&'static str, Value)> {
+        let job = &status.job;
+        let mut payloads = vec![
+            (
+                "state",
+                json!({
+                    "state": job.state.as_str(),
+                    "reachable": true,
+                    "connected": status.connection.current.state.is_operational(),
+                    "error": job.error,
+                }),
+            ),
+            (
+                "job",
+                json!({
+                    "file": job.job.file.path,
+                    "progress": job.progress.completion,
+                    "print_time": seconds(job.progress.print_time),
+                    "print_time_left": seconds(job.progress.print_time_left),
+                    "estimated_print_time": seconds(job.job.estimated_print_time),
+                }),
+            ),
+        ];
+        if let Some(temperature) = status.printer.as_ref().and_then(|p| p.temperature.as_ref()) {
+            let heaters: serde_json::Map<String, Value> = temperature
+                .tools
+                .iter()
+                .map(|(name, data)| (name.clone(), data))
+                .chain(temperature.bed.iter().map(|data| ("bed".to_string(), data)))
+                .chain(
+                    temperature
+                        .chamber
+                        .iter()
+                        .map(|data| ("chamber".to_string(), data)),
+                )
+                .map(|(name, data)| (name, json!({"actual": data.actual, "target": data.target})))
+                .collect();
+            payloads.push(("temperature", Value::Object(heaters)));
+        }
+        payloads
+    }
+
+    /// Execute a command received on `topic`, `None` if it is not a command topic.
+    pub async fn command(&self, topic: &str, payload: &[u8]) -> Option<BridgeEvent> {
+        let member = self
+            .fleet
+            .members()
+            .iter()
+            .find(|m| self.topic(&m.name, "command") == topic)?;
+        let (command, result) = match serde_json::from_slice::<BridgeCommand>(payload) {
+            Ok(command) => {
+                let result = match self.options.allow.check(&command) {
+                    Ok(()) => command
+                        .execute(&member.client)
+                        .await
+                        .map_err(|e| describe(&e)),
+                    Err(reason) => Err(reason),
+                };
+                (command.name().to_string(), result)
+            }
+            Err(e) => (String::new(), Err(format!("Invalid command: {}", e))),
+        };
+        Some(BridgeEvent::Command {
+            printer: member.name.clone(),
+            command,
+            result,
+        })
+    }
+
+    /// Event message reporting the result of a command.
+    fn command_result(&self, printer: &str, command: &str, result: &Result<(), String>) -> Message {
+        let payload = match result {
+            Ok(()) => json!({"event": "CommandDone", "command": command}),
+            Err(error) => json!({"event": "CommandFailed", "command": command, "error": error}),
+        };
+        Message {
+            topic: self.topic(printer, "event"),
+            payload: payload.to_string(),
+            retain: false,
+        }
+    }
+
+    /// Connect to the broker and run the bridge until `shutdown` completes.
+    ///
+    /// The connection is retried when it fails, the state is published again after each
+    /// reconnection. Fails with an `InvalidInput` error if the options are invalid.
+    pub async fn run<L, S>(
+        mut self,
+        mut mqtt: MqttOptions,
+        mut log: L,
+        shutdown: S,
+    ) -> Result<(), OctoPrintClientError>
+    where
+        L: FnMut(BridgeEvent),
+        S: Future<Output = ()>,
+    {
+        self.options
+            .validate()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+        enum Incoming {
+            Connected,
+            Publish(String, Vec<u8>),
+            Error(String),
+        }
+
+        let availability = self.availability_topic();
+        mqtt.set_last_will(LastWill::new(
+            &availability,
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+        let (client, mut eventloop) = AsyncClient::new(mqtt, 64);
+
+        // The event loop must be polled while publishing, to send the messages
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let poller = tokio::spawn(async move {
+            loop {
+                let incoming = match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => Incoming::Connected,
+                    Ok(Event::Incoming(Packet::Publish(p))) => {
+                        Incoming::Publish(p.topic, p.payload.to_vec())
+                    }
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+                    Ok(_) => continue,
+                    Err(e) => {
+                        let sent = tx.send(Incoming::Error(e.to_string()));
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                        if sent.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                if tx.send(incoming).is_err() {
+                    break;
+                }
+            }
+        });
+
+        tokio::pin!(shutdown);
+        let mut interval = tokio::time::interval(self.options.interval);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                Some(incoming) = rx.recv() => match incoming {
+                    Incoming::Connected => {
+                        log(BridgeEvent::Connected);
+                        for topic in self.command_topics() {
+                            client.subscribe(topic, QoS::AtLeastOnce).await?;
+                        }
+                        client
+                            .publish(&availability, QoS::AtLeastOnce, true, "online")
+                            .await?;
+                        // The broker may have lost the retained messages
+                        self.printers
+                            .values_mut()
+                            .for_each(|track| track.published.clear());
+                        interval.reset_immediately();
+                    }
+                    Incoming::Publish(topic, payload) => {
+                        if let Some(event) = self.command(&topic, &payload).await {
+                            if let BridgeEvent::Command { printer, command, result } = &event {
+                                let m = self.command_result(printer, command, result);
+                                client
+                                    .publish(m.topic, QoS::AtLeastOnce, m.retain, m.payload)
+                                    .await?;
+                            }
+                            log(event);
+                        }
+                    }
+                    Incoming::Error(e) => log(BridgeEvent::ConnectionError(e)),
+                },
+                _ = interval.tick() => {
+                    for m in self.poll().await {
+                        client
+                            .publish(m.topic, QoS::AtLeastOnce, m.retain, m.payload)
+                            .await?;
+                    }
+                }
+            }
+        }
+
+        client
+            .publish(&availability, QoS::AtLeastOnce, true, "offline")
+            .await?;
+        client.disconnect().await?;
+        let _ = tokio::time::timeout(Duration::from_secs(1), poller).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::testing::{MockServer, StoredFile};
+
+    fn allow(commands: &[&str], gcode: &[&str]) -> AllowList {
+        AllowList {
+            commands: commands.iter().map(|c| c.to_string()).collect(),
+            gcode: gcode.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let parse = |s: &str| serde_json::from_str::<BridgeCommand>(s).unwrap();
+        let pause = parse(r#"{"command": "pause"}"#);
+        let temperature = parse(r#"{"command": "temperature", "heater": "bed", "target": 60}"#);
+        assert_eq!(
+            temperature,
+            BridgeCommand::Temperature {
+                heater: "bed".to_string(),
+                target: 60.0
+            }
+        );
+        let gcode = parse(r#"{"command": "gcode", "gcode": ["m84", "G28 X"]}"#);
+
+        let list = allow(&["pause", "gcode"], &[]);
+        assert!(list.check(&pause).is_ok());
+        assert!(list.check(&temperature).is_err());
+        assert!(list.check(&gcode).is_ok());
+        assert!(AllowList::default().check(&pause).is_err());
+
+        let list = allow(&["gcode"], &["M84"]);
+        assert_eq!(
+            list.check(&gcode),
+            Err("G-code \"G28\" is not allowed".to_string())
+        );
+        assert!(allow(&["gcode"], &["M84", "G28"]).check(&gcode).is_ok());
+
+        // Every line of an entry is checked
+        let list = allow(&["gcode"], &["M117"]);
+        for payload in [
+            r#"{"command": "gcode", "gcode": ["M117 hi\nM502\nM500"]}"#,
+            r#"{"command": "gcode", "gcode": ["M117 hi\rM502"]}"#,
+        ] {
+            assert_eq!(
+                list.check(&parse(payload)),
+                Err("G-code \"M502\" is not allowed".to_string())
+            );
+        }
+        assert!(list
+            .check(&parse(
+                r#"{"command": "gcode", "gcode": ["M117 a\n\nM117 b\n"]}"#
+            ))
+            .is_ok());
+
+        let list = allow(&["temperature"], &[]);
+        assert!(list.check(&temperature).is_ok());
+        for target in ["-10", "1000", "1e30"] {
+            let command = parse(&format!(
+                r#"{{"command": "temperature", "heater": "tool0", "target": {}}}"#,
+                target
+            ));
+            assert!(list.check(&command).is_err(), "{}", target);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_poll() {
+        let server = MockServer::start();
+        server.with_printer_mut(|p| {
+            p.connect_ticks = 0;
+            p.progress_step = 0.0;
+            p.heat_rate = 0.0;
+            p.connect(None, None, None);
+            p.files.insert(
+                "a.gcode".to_string(),
+                StoredFile {
+                    content: b"G28\n".to_vec(),
+                    date: 0,
+                    userdata: None,
+                },
+            );
+            p.select("a.gcode");
+        });
+        let mut bridge = Bridge::new(
+            Fleet::single("mk3", server.client()),
+            BridgeOptions::default(),
+        );
+        assert_eq!(bridge.command_topics(), ["octoprint/mk3/command"]);
+
+        let messages = bridge.poll().await;
+        let topics: Vec<&str> = messages.iter().map(|m| m.topic.as_str()).collect();
+        assert_eq!(
+            topics,
+            [
+                "octoprint/mk3/state",
+                "octoprint/mk3/job",
+                "octoprint/mk3/temperature"
+            ]
+        );
+        assert!(messages.iter().all(|m| m.retain));
+        let state: Value = serde_json::from_str(&messages[0].payload).unwrap();
+        assert_eq!(state["state"], "Operational");
+        assert_eq!(state["connected"], true);
+        let temperature: Value = serde_json::from_str(&messages[2].payload).unwrap();
+        assert_eq!(temperature["bed"]["actual"], 21.0);
+
+        // Nothing changed
+        assert!(bridge.poll().await.is_empty());
+
+        server.with_printer_mut(|p| p.start());
+        let messages = bridge.poll().await;
+        let event = messages
+            .iter()
+            .find(|m| m.topic == "octoprint/mk3/event")
+            .unwrap();
+        assert!(!event.retain);
+        let event: Value = serde_json::from_str(&event.payload).unwrap();
+        assert_eq!(event["event"], "PrintStarted");
+        assert_eq!(event["file"], "a.gcode");
+    }
+
+    #[tokio::test]
+    async fn test_command() {
+        let server = MockServer::start();
+        server.with_printer_mut(|p| {
+            p.connect_ticks = 0;
+            p.connect(None, None, None);
+        });
+        let bridge = Bridge::new(
+            Fleet::single("mk3", server.client()),
+            BridgeOptions {
+                allow: allow(&["temperature", "gcode"], &["M84"]),
+                ..Default::default()
+            },
+        );
+        let result = |event: Option<BridgeEvent>| match event {
+            Some(BridgeEvent::Command { result, .. }) => result,
+            e => panic!("Unexpected {:?}", e),
+        };
+
+        assert!(bridge
+            .command("octoprint/other/command", b"{\"command\": \"pause\"}")
+            .await
+            .is_none());
+        let r = bridge
+            .command("octoprint/mk3/command", b"{\"command\": \"pause\"}")
+            .await;
+        assert_eq!(
+            result(r),
+            Err("Command \"pause\" is not allowed".to_string())
+        );
+        assert!(result(bridge.command("octoprint/mk3/command", b"pause").await).is_err());
+
+        let payload = br#"{"command": "temperature", "heater": "tool0", "target": 215}"#;
+        assert_eq!(
+            result(bridge.command("octoprint/mk3/command", payload).await),
+            Ok(())
+        );
+        let payload = br#"{"command": "temperature", "heater": "bed", "target": 60}"#;
+        assert_eq!(
+            result(bridge.command("octoprint/mk3/command", payload).await),
+            Ok(())
+        );
+        let payload = br#"{"command": "gcode", "gcode": ["M84"]}"#;
+        assert_eq!(
+            result(bridge.command("octoprint/mk3/command", payload).await),
+            Ok(())
+        );
+
+        let printer = server.printer();
+        assert_eq!(printer.tools[0].target, 215.0);
+        assert_eq!(printer.bed.target, 60.0);
+        assert_eq!(printer.commands, ["M84"]);
+    }
+
+    #[tokio::test]
+    async fn test_zero_interval() {
+        let options = BridgeOptions {
+            interval: Duration::ZERO,
+            ..Default::default()
+        };
+        assert!(options.validate().is_err());
+        assert!(BridgeOptions::default().validate().is_ok());
+
+        let server = MockServer::start();
+        let bridge = Bridge::new(Fleet::single("mk3", server.client()), options);
+        let result = bridge
+            .run(
+                MqttOptions::new("octoprint-test", "localhost", 1),
+                |_| {},
+                async {},
+            )
+            .await;
+        assert!(matches!(result, Err(OctoPrintClientError::IOError(_))));
+    }
+
+    /// Needs a broker, e.g. `mosquitto -p 1883`, set `MQTT_BROKER` to use another port.
+    #[tokio::test]
+    #[ignore]
+    async fn test_broker() {
+        let broker = std::env::var("MQTT_BROKER").unwrap_or("localhost:1883".to_string());
+        let (host, port) = broker.split_once(':').unwrap();
+        let port: u16 = port.parse().unwrap();
+
+        let server = MockServer::start();
+        server.with_printer_mut(|p| {
+            p.connect_ticks = 0;
+            p.connect(None, None, None);
+        });
+        let prefix = format!("octoprint-test-{}", std::process::id());
+        let bridge = Bridge::new(
+            Fleet::single("mk3", server.client()),
+            BridgeOptions {
+                prefix: prefix.clone(),
+                interval: Duration::from_millis(100),
+                allow: allow(&["temperature"], &[]),
+            },
+        );
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let running = tokio::spawn(bridge.run(
+            MqttOptions::new("octoprint-bridge-test", host, port),
+            |_| {},
+            async {
+                stopped.await.ok();
+            },
+        ));
+
+        let (client, mut eventloop) =
+            AsyncClient::new(MqttOptions::new("octoprint-test-client", host, port), 16);
+        client
+            .subscribe(format!("{}/#", prefix), QoS::AtLeastOnce)
+            .await
+            .unwrap();
+        let mut received = BTreeMap::new();
+        let mut sent = false;
+        let result = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Event::Incoming(Packet::Publish(p)) = eventloop.poll().await.unwrap() {
+                    received.insert(p.topic.clone(), p.payload.to_vec());
+                }
+                if received.contains_key(&format!("{}/mk3/state", prefix)) && !sent {
+                    sent = true;
+                    client
+                        .publish(
+                            format!("{}/mk3/command", prefix),
+                            QoS::AtLeastOnce,
+                            false,
+                            r#"{"command": "temperature", "heater": "bed", "target": 55}"#,
+                        )
+                        .await
+                        .unwrap();
+                }
+                if received.contains_key(&format!("{}/mk3/event", prefix)) {
+                    break;
+                }
+            }
+        })
+        .await;
+        assert!(result.is_ok(), "Received {:?}", received.keys());
+        assert_eq!(received[&format!("{}/bridge", prefix)], b"online");
+        assert_eq!(server.printer().bed.target, 55.0);
+
+        stop.send(()).unwrap();
+        running.await.unwrap().unwrap();
+    }
+}
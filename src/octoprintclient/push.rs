@@ -0,0 +1,161 @@
+//! Push socket of OctoPrint (`/sockjs/websocket`), on which the server sends its state and
+//! events as they happen.
+//!
+//! Most messages are only sent to authenticated sockets, see `PushSocket::auth()`.
+
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use super::datamodel::{PushEvent, SlicingProgress};
+use super::{OctoPrintClient, OctoPrintClientError};
+
+/// A message of the push socket.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PushMessage {
+    /// First message after connection, with the version of the server.
+    Connected(Value),
+    /// The session of the socket expired and `auth` must be sent again.
+    ReauthRequired(Value),
+    /// Current state of the printer, sent periodically.
+    Current(Value),
+    /// State history, sent after authentication.
+    History(Value),
+    Event(PushEvent),
+    SlicingProgress(SlicingProgress),
+    /// Messages not handled by this client ("plugin", "timelapse", ...).
+    Other(String, Value),
+}
+
+impl PushMessage {
+    /// Decode a message, which is a JSON object with one member.
+    pub fn parse(text: &str) -> Result<PushMessage, OctoPrintClientError> {
+        let object: serde_json::Map<String, Value> = serde_json::from_str(text)?;
+        let Some((kind, value)) = object.into_iter().next() else {
+            return Err(OctoPrintClientError::ServerError(
+                "Empty push message".to_string(),
+            ));
+        };
+        Ok(match kind.as_str() {
+            "connected" => PushMessage::Connected(value),
+            "reauthRequired" => PushMessage::ReauthRequired(value),
+            "current" => PushMessage::Current(value),
+            "history" => PushMessage::History(value),
+            "event" => PushMessage::Event(serde_json::from_value(value)?),
+            "slicingProgress" => PushMessage::SlicingProgress(serde_json::from_value(value)?),
+            _ => PushMessage::Other(kind, value),
+        })
+    }
+}
+
+/// Connection to the push socket, see `OctoPrintClient::push_socket()`.
+pub struct PushSocket {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl PushSocket {
+    /// Connect to the push socket of the server at `server_url`.
+    pub async fn connect(server_url: &str) -> Result<PushSocket, OctoPrintClientError> {
+        let url = format!(
+            "{}/sockjs/websocket",
+            server_url.trim_end_matches('/').replacen("http", "ws", 1)
+        );
+        let (ws, _) = tokio_tungstenite::connect_async(url).await?;
+        Ok(PushSocket { ws })
+    }
+
+    async fn send(&mut self, message: Value) -> Result<(), OctoPrintClientError> {
+        self.ws.send(Message::text(message.to_string())).await?;
+        Ok(())
+    }
+
+    /// Authenticate the socket with the session of a logged in user, see `session`.
+    pub async fn auth(&mut self, user: &str, session: &str) -> Result<(), OctoPrintClientError> {
+        self.send(serde_json::json!({ "auth": format!("{}:{}", user, session) }))
+            .await
+    }
+
+    /// Only receive one "current" message out of `factor`, the default is every 500ms.
+    pub async fn throttle(&mut self, factor: u32) -> Result<(), OctoPrintClientError> {
+        self.send(serde_json::json!({ "throttle": factor })).await
+    }
+
+    /// Next message, `None` when the server closed the socket.
+    pub async fn next_message(&mut self) -> Result<Option<PushMessage>, OctoPrintClientError> {
+        while let Some(message) = self.ws.next().await {
+            match message? {
+                Message::Text(text) => return PushMessage::parse(&text).map(Some),
+                Message::Close(_) => return Ok(None),
+                // Pings are answered by the library
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    pub async fn close(mut self) -> Result<(), OctoPrintClientError> {
+        self.ws.close(None).await?;
+        Ok(())
+    }
+}
+
+impl OctoPrintClient {
+    /// Connect to the push socket of the server.
+    pub async fn push_socket(&self) -> Result<PushSocket, OctoPrintClientError> {
+        PushSocket::connect(&self.config.server_url).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::testing::MockServer;
+
+    #[test]
+    fn test_parse() {
+        assert!(matches!(
+            PushMessage::parse(r#"{"connected": {"version": "1.8.6"}}"#).unwrap(),
+            PushMessage::Connected(v) if v["version"] == "1.8.6"
+        ));
+        assert_eq!(
+            PushMessage::parse(r#"{"event": {"type": "PrintDone", "payload": {"time": 3.5}}}"#)
+                .unwrap(),
+            PushMessage::Event(PushEvent {
+                event_type: "PrintDone".to_string(),
+                payload: serde_json::json!({"time": 3.5}),
+            })
+        );
+        assert!(matches!(
+            PushMessage::parse(r#"{"plugin": {}}"#).unwrap(),
+            PushMessage::Other(kind, _) if kind == "plugin"
+        ));
+        assert!(PushMessage::parse("{}").is_err());
+        assert!(PushMessage::parse(r#"{"event": 1}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_push_socket() {
+        let server = MockServer::start();
+        let mut socket = server.client().push_socket().await.unwrap();
+        assert!(matches!(
+            socket.next_message().await.unwrap(),
+            Some(PushMessage::Connected(_))
+        ));
+
+        socket.auth("admin", "0123456789").await.unwrap();
+        assert!(matches!(
+            socket.next_message().await.unwrap(),
+            Some(PushMessage::History(_))
+        ));
+        assert_eq!(server.push_auth(), ["admin:0123456789"]);
+
+        server.push(serde_json::json!({"event": {"type": "Connected", "payload": {}}}));
+        assert!(matches!(
+            socket.next_message().await.unwrap(),
+            Some(PushMessage::Event(e)) if e.event_type == "Connected"
+        ));
+        socket.close().await.unwrap();
+    }
+}
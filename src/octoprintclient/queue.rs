@@ -0,0 +1,575 @@
+//! Local print queue, dispatching jobs to the printers of a fleet.
+//!
+//! The queue is a JSON file, saved after each change so that it survives restarts. Changes
+//! are made under a lock on the file (see `PrintQueue::update()`), the queue can be edited
+//! while the scheduler runs. The scheduler starts the next matching job on each idle printer,
+//! after a human confirmed that the printer is ready (i.e. the bed was cleared).
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::datamodel::Profile;
+use super::fleet::{Fleet, FleetMember};
+use super::{OctoPrintClientError, UploadOptions};
+
+/// File printed by a queue item.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueFile {
+    /// Path in the local storage of the server.
+    Remote(String),
+    /// Local file, uploaded to the printer when the job starts.
+    Local(PathBuf),
+}
+
+impl std::fmt::Display for QueueFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueueFile::Remote(path) => write!(f, "{}", path),
+            QueueFile::Local(path) => write!(f, "{} (local)", path.display()),
+        }
+    }
+}
+
+/// Printers allowed to print a queue item, all printers match if no field is set.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Constraints {
+    /// Name of the printer, as in the configuration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub printer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Printer profile id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Nozzle diameter, in mm.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nozzle: Option<f64>,
+}
+
+impl Constraints {
+    pub fn matches(&self, member: &FleetMember, profile: &Profile) -> bool {
+        self.printer.as_ref().is_none_or(|p| *p == member.name)
+            && self
+                .group
+                .as_ref()
+                .is_none_or(|g| member.group.as_ref() == Some(g))
+            && self.profile.as_ref().is_none_or(|p| *p == profile.id)
+            && self
+                .nozzle
+                .is_none_or(|n| (n - profile.extruder.nozzle_diameter).abs() < 0.001)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Constraints::default()
+    }
+}
+
+impl std::fmt::Display for Constraints {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(printer) = &self.printer {
+            parts.push(format!("printer={}", printer));
+        }
+        if let Some(group) = &self.group {
+            parts.push(format!("group={}", group));
+        }
+        if let Some(profile) = &self.profile {
+            parts.push(format!("profile={}", profile));
+        }
+        if let Some(nozzle) = self.nozzle {
+            parts.push(format!("nozzle={}", nozzle));
+        }
+        f.write_str(&parts.join(" "))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueueItem {
+    pub id: u64,
+    pub file: QueueFile,
+    /// Number of copies to print.
+    pub copies: u32,
+    /// Number of copies already started.
+    #[serde(default)]
+    pub started: u32,
+    #[serde(default)]
+    pub constraints: Constraints,
+}
+
+impl QueueItem {
+    /// File name on the server.
+    fn remote_path(&self) -> String {
+        match &self.file {
+            QueueFile::Remote(path) => path.clone(),
+            QueueFile::Local(path) => path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PrintQueue {
+    next_id: u64,
+    pub items: Vec<QueueItem>,
+}
+
+impl PrintQueue {
+    /// Load the queue from `path`, an empty queue if the file does not exist.
+    pub fn load(path: &Path) -> Result<PrintQueue, OctoPrintClientError> {
+        match std::fs::read(path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(PrintQueue::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Save the queue to `path`, the file is replaced atomically.
+    pub fn save(&self, path: &Path) -> Result<(), OctoPrintClientError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Load the queue saved at `path`, change it with `change` and save it, while holding an
+    /// exclusive lock so that concurrent changes are not lost. Returns the result of `change`.
+    pub fn update<T, F>(path: &Path, change: F) -> Result<T, OctoPrintClientError>
+    where
+        F: FnOnce(&mut PrintQueue) -> T,
+    {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let lock = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path.with_extension("lock"))?;
+        lock.lock()?;
+        let mut queue = PrintQueue::load(path)?;
+        let result = change(&mut queue);
+        queue.save(path)?;
+        Ok(result)
+    }
+
+    /// Add an item at the end of the queue, returns its id.
+    pub fn add(&mut self, file: QueueFile, copies: u32, constraints: Constraints) -> u64 {
+        self.next_id += 1;
+        self.items.push(QueueItem {
+            id: self.next_id,
+            file,
+            copies,
+            started: 0,
+            constraints,
+        });
+        self.next_id
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<QueueItem> {
+        let index = self.items.iter().position(|i| i.id == id)?;
+        Some(self.items.remove(index))
+    }
+
+    /// Move an item to `position` (0 is the head of the queue).
+    pub fn reorder(&mut self, id: u64, position: usize) -> bool {
+        match self.remove(id) {
+            Some(item) => {
+                let position = position.min(self.items.len());
+                self.items.insert(position, item);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// First item which can be printed by the printer.
+    pub fn next_for(&self, member: &FleetMember, profile: &Profile) -> Option<&QueueItem> {
+        self.items
+            .iter()
+            .find(|i| i.constraints.matches(member, profile))
+    }
+
+    /// Count one more copy started, the item is removed after the last one.
+    pub fn mark_started(&mut self, id: u64) {
+        if let Some(item) = self.items.iter_mut().find(|i| i.id == id) {
+            item.started += 1;
+            if item.started >= item.copies {
+                self.remove(id);
+            }
+        }
+    }
+}
+
+/// What the scheduler did, reported to the caller.
+#[derive(Debug)]
+pub enum SchedulerEvent {
+    /// A copy of the item was started on the printer.
+    Started { printer: String, item: QueueItem },
+    /// The human did not confirm the printer is ready, it is skipped until its state changes
+    /// or for `Scheduler::ask_again`.
+    Declined { printer: String },
+    Failed {
+        printer: String,
+        error: OctoPrintClientError,
+    },
+}
+
+/// Starts the queued jobs on the idle printers.
+#[derive(Debug)]
+pub struct Scheduler {
+    /// The queue as of the last step, it is reloaded before each step.
+    pub queue: PrintQueue,
+    /// Delay before asking again for a printer the human declined to start a job on.
+    pub ask_again: Duration,
+    path: PathBuf,
+    /// Printers the human declined to start a job on, with the time of the answer.
+    declined: BTreeMap<String, Instant>,
+}
+
+impl Scheduler {
+    /// Scheduler for the queue saved at `path`.
+    pub fn load(path: &Path) -> Result<Scheduler, OctoPrintClientError> {
+        Ok(Scheduler {
+            queue: PrintQueue::load(path)?,
+            ask_again: Duration::from_secs(600),
+            path: path.to_path_buf(),
+            declined: BTreeMap::new(),
+        })
+    }
+
+    /// Reload the queue and start the next job on each idle printer.
+    ///
+    /// `confirm` is asked before each start, it must only return true once the bed was cleared.
+    /// Fails if the queue file cannot be read or written.
+    pub async fn step<C, L>(
+        &mut self,
+        fleet: &Fleet,
+        mut confirm: C,
+        mut log: L,
+    ) -> Result<(), OctoPrintClientError>
+    where
+        C: FnMut(&str, &QueueItem) -> bool,
+        L: FnMut(SchedulerEvent),
+    {
+        self.queue = PrintQueue::load(&self.path)?;
+        for member in fleet.members() {
+            let printer = member.name.clone();
+            let state = match member.client.get_current_job().await {
+                Ok(job) => job.state,
+                Err(error) => {
+                    log(SchedulerEvent::Failed { printer, error });
+                    continue;
+                }
+            };
+            if !state.can_start() {
+                self.declined.remove(&printer);
+                continue;
+            }
+            if self
+                .declined
+                .get(&printer)
+                .is_some_and(|at| at.elapsed() < self.ask_again)
+            {
+                continue;
+            }
+
+            let profile = match member.client.get_current_printer_profile().await {
+                Ok(profile) => profile,
+                Err(error) => {
+                    log(SchedulerEvent::Failed { printer, error });
+                    continue;
+                }
+            };
+            let item = match self.queue.next_for(member, &profile) {
+                Some(item) => item.clone(),
+                None => continue,
+            };
+            if !confirm(&printer, &item) {
+                self.declined.insert(printer.clone(), Instant::now());
+                log(SchedulerEvent::Declined { printer });
+                continue;
+            }
+
+            match start(member, &item).await {
+                Ok(()) => {
+                    self.declined.remove(&printer);
+                    let id = item.id;
+                    log(SchedulerEvent::Started { printer, item });
+                    self.queue = PrintQueue::update(&self.path, |queue| {
+                        queue.mark_started(id);
+                        queue.clone()
+                    })?;
+                }
+                Err(error) => log(SchedulerEvent::Failed { printer, error }),
+            }
+        }
+        Ok(())
+    }
+
+    /// Run `step()` every `interval` until the queue is empty or `shutdown` completes.
+    pub async fn run<C, L, S>(
+        &mut self,
+        fleet: &Fleet,
+        interval: Duration,
+        mut confirm: C,
+        mut log: L,
+        shutdown: S,
+    ) -> Result<(), OctoPrintClientError>
+    where
+        C: FnMut(&str, &QueueItem) -> bool,
+        L: FnMut(SchedulerEvent),
+        S: Future<Output = ()>,
+    {
+        tokio::pin!(shutdown);
+        loop {
+            self.step(fleet, &mut confirm, &mut log).await?;
+            if self.queue.items.is_empty() {
+                return Ok(());
+            }
+            tokio::select! {
+                _ = &mut shutdown => return Ok(()),
+                _ = tokio::time::sleep(interval) => {}
+            }
+        }
+    }
+}
+
+/// Start printing one copy of the item.
+async fn start(member: &FleetMember, item: &QueueItem) -> Result<(), OctoPrintClientError> {
+    match &item.file {
+        QueueFile::Remote(path) => member.client.select_file(path, true).await,
+        QueueFile::Local(path) => {
+            let options = UploadOptions {
+                print: true,
+                ..Default::default()
+            };
+            member
+                .client
+                .upload_with_options(std::fs::File::open(path)?, &item.remote_path(), &options)
+                .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::datamodel::State;
+    use crate::octoprintclient::testing::{MockServer, StoredFile, MOCK_API_KEY};
+    use crate::octoprintclient::PrinterConfiguration;
+
+    fn queue_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_queue() {
+        let path = queue_path("octoprint-client-test-queue.json");
+        let mut queue = PrintQueue::default();
+        let a = queue.add(
+            QueueFile::Remote("a.gcode".into()),
+            2,
+            Constraints::default(),
+        );
+        let b = queue.add(
+            QueueFile::Local("/tmp/b.gcode".into()),
+            1,
+            Constraints {
+                nozzle: Some(0.6),
+                ..Default::default()
+            },
+        );
+        let c = queue.add(
+            QueueFile::Remote("c.gcode".into()),
+            1,
+            Constraints::default(),
+        );
+        assert!(queue.reorder(c, 0));
+        assert!(!queue.reorder(42, 0));
+        let ids: Vec<u64> = queue.items.iter().map(|i| i.id).collect();
+        assert_eq!(ids, [c, a, b]);
+
+        queue.save(&path).unwrap();
+        let mut queue = PrintQueue::load(&path).unwrap();
+        assert_eq!(queue.items.len(), 3);
+        assert!(queue.remove(c).is_some());
+        queue.mark_started(a);
+        assert_eq!(queue.items[0].started, 1);
+        queue.mark_started(a);
+        assert_eq!(queue.items.len(), 1);
+        // Ids are not reused
+        assert_eq!(
+            queue.add(
+                QueueFile::Remote("d.gcode".into()),
+                1,
+                Constraints::default()
+            ),
+            4
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(PrintQueue::load(&path).unwrap().items.is_empty());
+    }
+
+    #[test]
+    fn test_constraints() {
+        let profile = crate::octoprintclient::testing::default_profile();
+        let member = FleetMember {
+            name: "mk3".to_string(),
+            group: Some("prusa".to_string()),
+            client: MockServer::start().client(),
+        };
+        let matches = |c: Constraints| c.matches(&member, &profile);
+        assert!(matches(Constraints::default()));
+        assert!(matches(Constraints {
+            printer: Some("mk3".into()),
+            group: Some("prusa".into()),
+            profile: Some("_default".into()),
+            nozzle: Some(0.4),
+        }));
+        assert!(!matches(Constraints {
+            printer: Some("mini".into()),
+            ..Default::default()
+        }));
+        assert!(!matches(Constraints {
+            group: Some("voron".into()),
+            ..Default::default()
+        }));
+        assert!(!matches(Constraints {
+            nozzle: Some(0.6),
+            ..Default::default()
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_scheduler() {
+        let servers = [MockServer::start(), MockServer::start()];
+        for server in &servers {
+            server.with_printer_mut(|p| {
+                p.connect_ticks = 0;
+                p.progress_step = 0.0;
+                p.connect(None, None, None);
+                p.files.insert(
+                    "a.gcode".to_string(),
+                    StoredFile {
+                        content: b"G28\n".to_vec(),
+                        date: 0,
+                        userdata: None,
+                    },
+                );
+            });
+        }
+        let fleet = Fleet::from_configs(&[
+            PrinterConfiguration {
+                name: "one".to_string(),
+                server_url: servers[0].url(),
+                api_key: MOCK_API_KEY.to_string(),
+                group: None,
+            },
+            PrinterConfiguration {
+                name: "two".to_string(),
+                server_url: servers[1].url(),
+                api_key: MOCK_API_KEY.to_string(),
+                group: Some("big".to_string()),
+            },
+        ]);
+
+        let path = queue_path("octoprint-client-test-scheduler.json");
+        let mut queue = PrintQueue::default();
+        let big = queue.add(
+            QueueFile::Remote("a.gcode".into()),
+            1,
+            Constraints {
+                group: Some("big".into()),
+                ..Default::default()
+            },
+        );
+        let any = queue.add(
+            QueueFile::Remote("a.gcode".into()),
+            3,
+            Constraints::default(),
+        );
+        queue.save(&path).unwrap();
+
+        let mut scheduler = Scheduler::load(&path).unwrap();
+        let mut events = Vec::new();
+
+        // Printer "one" is declined, "two" gets the job of its group
+        scheduler
+            .step(&fleet, |printer, _| printer == "two", |e| events.push(e))
+            .await
+            .unwrap();
+        assert!(matches!(&events[0], SchedulerEvent::Declined { printer } if printer == "one"));
+        assert!(
+            matches!(&events[1], SchedulerEvent::Started { printer, item } if printer == "two" && item.id == big)
+        );
+        assert_eq!(servers[1].printer().state, State::Printing);
+        assert_eq!(servers[0].printer().state, State::Operational);
+
+        // Not asked again until the state of "one" changes
+        let asked = std::cell::Cell::new(0);
+        let count = |_: &str, _: &QueueItem| {
+            asked.set(asked.get() + 1);
+            false
+        };
+        scheduler.step(&fleet, count, |_| {}).await.unwrap();
+        assert_eq!(asked.get(), 0);
+        // or until the delay passed
+        scheduler.ask_again = Duration::ZERO;
+        scheduler.step(&fleet, count, |_| {}).await.unwrap();
+        assert_eq!(asked.get(), 1);
+        scheduler.ask_again = Duration::from_secs(600);
+
+        // Items added while the scheduler runs are kept and scheduled
+        let added = PrintQueue::update(&path, |q| {
+            q.add(
+                QueueFile::Remote("a.gcode".into()),
+                1,
+                Constraints {
+                    printer: Some("one".into()),
+                    ..Default::default()
+                },
+            )
+        })
+        .unwrap();
+        PrintQueue::update(&path, |q| q.reorder(added, 0)).unwrap();
+        servers[0].with_printer_mut(|p| p.state = State::Printing);
+        scheduler.step(&fleet, |_, _| true, |_| {}).await.unwrap();
+        servers[0].with_printer_mut(|p| p.state = State::Operational);
+        let mut started = Vec::new();
+        scheduler
+            .step(
+                &fleet,
+                |_, _| true,
+                |e| {
+                    if let SchedulerEvent::Started { item, .. } = e {
+                        started.push(item.id)
+                    }
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(started, [added]);
+        assert_eq!(servers[0].printer().state, State::Printing);
+
+        let saved = PrintQueue::load(&path).unwrap();
+        assert_eq!(saved, scheduler.queue);
+        assert_eq!(saved.items.len(), 1);
+        assert_eq!(saved.items[0].id, any);
+        assert_eq!(saved.items[0].started, 0);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("lock")).unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+//! Session authentication: login with a user name and password, as an alternative to the API
+//! key when it is disabled, and passive login to find the user behind an API key.
+//!
+//! The session is kept in cookies shared by all the clones of an `OctoPrintClient`. Requests
+//! authenticated by the session cookie must send the CSRF token set by the server, this is done
+//! by `OctoPrintClient::request()`.
+
+use std::collections::BTreeMap;
+
+use hyper::header::SET_COOKIE;
+use hyper::{HeaderMap, Method, StatusCode};
+
+use super::datamodel::SessionUser;
+use super::push::PushSocket;
+use super::{decode, OctoPrintClient, OctoPrintClientError};
+
+/// Cookies set by the server, by name.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CookieJar {
+    cookies: BTreeMap<String, String>,
+}
+
+impl CookieJar {
+    /// Store the cookies of the `Set-Cookie` headers, removing the expired ones.
+    pub fn store(&mut self, headers: &HeaderMap) {
+        for header in headers.get_all(SET_COOKIE) {
+            let Ok(header) = header.to_str() else {
+                continue;
+            };
+            let mut attributes = header.split(';').map(|a| a.trim());
+            let Some((name, value)) = attributes.next().and_then(|c| c.split_once('=')) else {
+                continue;
+            };
+            let expired = attributes
+                .any(|a| a.eq_ignore_ascii_case("max-age=0") || a.to_lowercase().contains("1970"));
+            if expired || value.is_empty() {
+                self.cookies.remove(name);
+            } else {
+                self.cookies.insert(name.to_string(), value.to_string());
+            }
+        }
+    }
+
+    /// Value of the `Cookie` header, `None` without cookies.
+    pub fn header(&self) -> Option<String> {
+        if self.cookies.is_empty() {
+            return None;
+        }
+        let cookies: Vec<String> = self
+            .cookies
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        Some(cookies.join("; "))
+    }
+
+    /// The CSRF token, its cookie name ends with the port of the server ("csrf_token_P80").
+    pub fn csrf_token(&self) -> Option<&str> {
+        self.cookies
+            .iter()
+            .find(|(name, _)| name.starts_with("csrf_token"))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+    }
+}
+
+impl OctoPrintClient {
+    async fn post_login(
+        &self,
+        body: serde_json::Value,
+    ) -> Result<SessionUser, OctoPrintClientError> {
+        let resp = self
+            .send(Method::POST, "login", Some(body.to_string()))
+            .await?;
+        self.cookies.lock().unwrap().store(resp.headers());
+        if resp.status() == StatusCode::NO_CONTENT {
+            return Err(OctoPrintClientError::ServerError(
+                "Not logged in".to_string(),
+            ));
+        }
+        decode(resp).await
+    }
+
+    /// Log in with a user name and password, the next requests are authenticated by the
+    /// session. The session lasts longer if `remember`.
+    pub async fn login(
+        &self,
+        user: &str,
+        password: &str,
+        remember: bool,
+    ) -> Result<SessionUser, OctoPrintClientError> {
+        self.post_login(serde_json::json!({
+            "user": user,
+            "pass": password,
+            "remember": remember,
+        }))
+        .await
+    }
+
+    /// The user authenticated by the API key or the current session, with a session usable
+    /// e.g. to authenticate the push socket.
+    pub async fn passive_login(&self) -> Result<SessionUser, OctoPrintClientError> {
+        self.post_login(serde_json::json!({ "passive": true }))
+            .await
+    }
+
+    /// End the session, the cookies are removed.
+    pub async fn logout(&self) -> Result<(), OctoPrintClientError> {
+        let resp = self.send(Method::POST, "logout", None).await;
+        self.cookies.lock().unwrap().clear();
+        resp?;
+        Ok(())
+    }
+
+    /// Connect to the push socket and authenticate it with a passive login, so that the server
+    /// sends its state and events.
+    pub async fn authenticated_push_socket(&self) -> Result<PushSocket, OctoPrintClientError> {
+        let user = self.passive_login().await?;
+        let session = user.session.ok_or(OctoPrintClientError::ServerError(
+            "No session in login response".to_string(),
+        ))?;
+        let mut socket = self.push_socket().await?;
+        socket.auth(&user.user.name, &session).await?;
+        Ok(socket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::push::PushMessage;
+    use crate::octoprintclient::testing::MockServer;
+    use crate::octoprintclient::Configuration;
+    use hyper::header::HeaderValue;
+
+    #[test]
+    fn test_cookie_jar() {
+        let mut jar = CookieJar::default();
+        assert_eq!(jar.header(), None);
+
+        let mut headers = HeaderMap::new();
+        for cookie in [
+            "session_P80=abc; Path=/; HttpOnly",
+            "csrf_token_P80=xyz; Path=/",
+            "remember_token_P80=r; Expires=Thu, 01 Jan 2037 00:00:00 GMT",
+        ] {
+            headers.append(SET_COOKIE, HeaderValue::from_static(cookie));
+        }
+        jar.store(&headers);
+        assert_eq!(
+            jar.header().as_deref(),
+            Some("csrf_token_P80=xyz; remember_token_P80=r; session_P80=abc")
+        );
+        assert_eq!(jar.csrf_token(), Some("xyz"));
+
+        let mut headers = HeaderMap::new();
+        headers.append(
+            SET_COOKIE,
+            HeaderValue::from_static("session_P80=; Expires=Thu, 01 Jan 1970 00:00:00 GMT"),
+        );
+        headers.append(
+            SET_COOKIE,
+            HeaderValue::from_static("remember_token_P80=r; Max-Age=0"),
+        );
+        jar.store(&headers);
+        assert_eq!(jar.header().as_deref(), Some("csrf_token_P80=xyz"));
+    }
+
+    #[tokio::test]
+    async fn test_login_logout() {
+        let server = MockServer::start();
+        let c = OctoPrintClient::from_config(Configuration {
+            server_url: server.url(),
+            ..Default::default()
+        });
+        assert!(c.get_printer_state().await.is_err());
+        assert!(c.login("admin", "wrong", false).await.is_err());
+
+        let user = c.login("admin", "admin", true).await.unwrap();
+        assert_eq!(user.user.name, "admin");
+        assert!(user.session.is_some());
+        let request = server.requests().last().unwrap().clone();
+        assert_eq!(
+            request.body_json(),
+            serde_json::json!({"user": "admin", "pass": "admin", "remember": true})
+        );
+
+        let current = c.get_current_user().await.unwrap();
+        assert_eq!(current.name.as_deref(), Some("admin"));
+        // Requests changing the state send the CSRF token
+        c.create_folder("parts").await.unwrap();
+        let request = server.requests().last().unwrap().clone();
+        assert!(request.headers.get("X-CSRF-Token").is_some());
+        assert!(request.headers.get("X-Api-Key").is_none());
+        // The session is shared by the clones
+        assert_eq!(c.clone().passive_login().await.unwrap().user.name, "admin");
+
+        c.logout().await.unwrap();
+        assert!(c.get_printer_state().await.is_err());
+        assert!(c.passive_login().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_passive_login() {
+        let server = MockServer::start();
+        let c = server.client();
+        let user = c.passive_login().await.unwrap();
+        assert_eq!(user.user.name, "_api");
+
+        let mut socket = c.authenticated_push_socket().await.unwrap();
+        assert!(matches!(
+            socket.next_message().await.unwrap(),
+            Some(PushMessage::Connected(_))
+        ));
+        assert!(matches!(
+            socket.next_message().await.unwrap(),
+            Some(PushMessage::History(_))
+        ));
+        let session = server.push_auth()[0].clone();
+        assert!(session.starts_with("_api:"));
+        assert_ne!(session, "_api:");
+
+        // API key of a user
+        server.with_printer_mut(|p| {
+            p.users.get_mut("admin").unwrap().apikey = Some("ADMINKEY".to_string())
+        });
+        let admin = OctoPrintClient::from_config(Configuration {
+            server_url: server.url(),
+            api_key: "ADMINKEY".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(admin.passive_login().await.unwrap().user.name, "admin");
+    }
+}
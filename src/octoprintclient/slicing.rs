@@ -0,0 +1,279 @@
+//! Server-side slicing of models (STL files) with the slicers configured in OctoPrint.
+
+use std::collections::BTreeMap;
+
+use hyper::Method;
+
+use super::datamodel::{SliceCommand, Slicer, SlicingProfile};
+use super::push::{PushMessage, PushSocket};
+use super::{decode, encode_path, OctoPrintClient, OctoPrintClientError};
+
+impl OctoPrintClient {
+    /// Slicers of the server, by key, with their profiles.
+    pub async fn get_slicers(&self) -> Result<BTreeMap<String, Slicer>, OctoPrintClientError> {
+        self.get("slicing").await
+    }
+
+    /// The default slicer, if it is configured.
+    pub async fn get_default_slicer(&self) -> Result<Slicer, OctoPrintClientError> {
+        self.get_slicers()
+            .await?
+            .into_values()
+            .find(|s| s.default && s.configured)
+            .ok_or(OctoPrintClientError::ServerError(
+                "No default slicer configured".to_string(),
+            ))
+    }
+
+    pub async fn get_slicing_profiles(
+        &self,
+        slicer: &str,
+    ) -> Result<BTreeMap<String, SlicingProfile>, OctoPrintClientError> {
+        self.get(&format!("slicing/{}/profiles", encode_path(slicer)))
+            .await
+    }
+
+    /// A slicing profile, with its settings.
+    pub async fn get_slicing_profile(
+        &self,
+        slicer: &str,
+        key: &str,
+    ) -> Result<SlicingProfile, OctoPrintClientError> {
+        self.get(&profile_endpoint(slicer, key)).await
+    }
+
+    /// Create a slicing profile, or replace it if it exists.
+    pub async fn create_slicing_profile(
+        &self,
+        slicer: &str,
+        key: &str,
+        profile: &SlicingProfile,
+    ) -> Result<SlicingProfile, OctoPrintClientError> {
+        let resp = self
+            .send(
+                Method::PUT,
+                &profile_endpoint(slicer, key),
+                Some(serde_json::to_string(profile)?),
+            )
+            .await?;
+        decode(resp).await
+    }
+
+    /// Update the fields set in `profile`, the settings are merged with the current ones.
+    pub async fn update_slicing_profile(
+        &self,
+        slicer: &str,
+        key: &str,
+        profile: &SlicingProfile,
+    ) -> Result<SlicingProfile, OctoPrintClientError> {
+        let resp = self
+            .send(
+                Method::PATCH,
+                &profile_endpoint(slicer, key),
+                Some(serde_json::to_string(profile)?),
+            )
+            .await?;
+        decode(resp).await
+    }
+
+    pub async fn delete_slicing_profile(
+        &self,
+        slicer: &str,
+        key: &str,
+    ) -> Result<(), OctoPrintClientError> {
+        self.send(Method::DELETE, &profile_endpoint(slicer, key), None)
+            .await?;
+        Ok(())
+    }
+
+    /// Start slicing a model of the local storage, see `PushSocket::wait_for_slicing()`.
+    pub async fn slice(
+        &self,
+        path: &str,
+        command: &SliceCommand,
+    ) -> Result<(), OctoPrintClientError> {
+        self.send(
+            Method::POST,
+            &format!("files/local/{}", encode_path(path)),
+            Some(command.to_json().to_string()),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+fn profile_endpoint(slicer: &str, key: &str) -> String {
+    format!(
+        "slicing/{}/profiles/{}",
+        encode_path(slicer),
+        encode_path(key)
+    )
+}
+
+impl PushSocket {
+    /// Wait until the slicing of the model at `path` is done, returns the path of the G-code.
+    ///
+    /// `progress` is called with the progress in %. The socket must be authenticated, see
+    /// `OctoPrintClient::authenticated_push_socket()`.
+    pub async fn wait_for_slicing<P>(
+        &mut self,
+        path: &str,
+        mut progress: P,
+    ) -> Result<String, OctoPrintClientError>
+    where
+        P: FnMut(f64),
+    {
+        while let Some(message) = self.next_message().await? {
+            match message {
+                PushMessage::SlicingProgress(p) if p.source_path == path => progress(p.progress),
+                PushMessage::Event(event) if event.payload["stl"] == path => {
+                    match event.event_type.as_str() {
+                        "SlicingDone" => {
+                            return Ok(event.payload["gcode"].as_str().unwrap_or("").to_string())
+                        }
+                        "SlicingFailed" => {
+                            return Err(OctoPrintClientError::ServerError(format!(
+                                "Slicing failed: {}",
+                                event.payload["reason"].as_str().unwrap_or("unknown reason")
+                            )))
+                        }
+                        "SlicingCancelled" => {
+                            return Err(OctoPrintClientError::ServerError(
+                                "Slicing cancelled".to_string(),
+                            ))
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(OctoPrintClientError::ServerError(
+            "Push socket closed while slicing".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::testing::{MockServer, StoredFile};
+
+    #[test]
+    fn test_slice_command() {
+        let command = SliceCommand {
+            slicer: "curalegacy".to_string(),
+            profile: Some("fast".to_string()),
+            overrides: BTreeMap::from([("layer_height".to_string(), 0.3.into())]),
+            position: Some((100.0, 50.0)),
+            print: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            command.to_json(),
+            serde_json::json!({
+                "command": "slice",
+                "slicer": "curalegacy",
+                "profile": "fast",
+                "profile.layer_height": 0.3,
+                "position": {"x": 100.0, "y": 50.0},
+                "select": false,
+                "print": true,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slicing_profiles() {
+        let server = MockServer::start();
+        let c = server.client();
+
+        let slicer = c.get_default_slicer().await.unwrap();
+        assert_eq!(slicer.key, "curalegacy");
+        assert!(slicer.profiles.contains_key("default"));
+
+        let mut data = serde_json::Map::new();
+        data.insert("layer_height".to_string(), 0.3.into());
+        let profile = SlicingProfile {
+            display_name: Some("Fast".to_string()),
+            data: Some(data),
+            ..Default::default()
+        };
+        let created = c
+            .create_slicing_profile("curalegacy", "fast", &profile)
+            .await
+            .unwrap();
+        assert_eq!(created.key, "fast");
+        assert_eq!(c.get_slicing_profiles("curalegacy").await.unwrap().len(), 2);
+
+        let update = SlicingProfile {
+            description: Some("For drafts".to_string()),
+            ..Default::default()
+        };
+        c.update_slicing_profile("curalegacy", "fast", &update)
+            .await
+            .unwrap();
+        let request = server.requests().last().unwrap().clone();
+        assert_eq!(request.method, Method::PATCH);
+        assert_eq!(
+            request.body_json(),
+            serde_json::json!({"description": "For drafts"})
+        );
+        let fast = c.get_slicing_profile("curalegacy", "fast").await.unwrap();
+        assert_eq!(fast.display_name.as_deref(), Some("Fast"));
+        assert_eq!(fast.description.as_deref(), Some("For drafts"));
+        assert_eq!(fast.data.unwrap()["layer_height"], 0.3);
+
+        c.delete_slicing_profile("curalegacy", "fast")
+            .await
+            .unwrap();
+        assert!(c.get_slicing_profile("curalegacy", "fast").await.is_err());
+        assert!(c.get_slicing_profiles("unknown").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_slice() {
+        let server = MockServer::start();
+        server.with_printer_mut(|p| {
+            p.files.insert(
+                "parts/gear.stl".to_string(),
+                StoredFile {
+                    content: b"solid gear\nendsolid gear\n".to_vec(),
+                    date: 0,
+                    userdata: None,
+                },
+            );
+        });
+        let c = server.client();
+        let mut socket = c.push_socket().await.unwrap();
+        socket.auth("admin", "session").await.unwrap();
+
+        let command = SliceCommand {
+            slicer: "curalegacy".to_string(),
+            select: true,
+            ..Default::default()
+        };
+        c.slice("parts/gear.stl", &command).await.unwrap();
+        let mut steps = Vec::new();
+        let gcode = socket
+            .wait_for_slicing("parts/gear.stl", |p| steps.push(p))
+            .await
+            .unwrap();
+        assert_eq!(gcode, "parts/gear.gcode");
+        assert_eq!(steps, [0.0, 50.0, 100.0]);
+        assert!(server.printer().files.contains_key("parts/gear.gcode"));
+        assert_eq!(server.printer().job.unwrap().path, "parts/gear.gcode");
+
+        let command = SliceCommand {
+            slicer: "curalegacy".to_string(),
+            profile: Some("unknown".to_string()),
+            ..Default::default()
+        };
+        c.slice("parts/gear.stl", &command).await.unwrap();
+        assert!(matches!(
+            socket.wait_for_slicing("parts/gear.stl", |_| {}).await,
+            Err(OctoPrintClientError::ServerError(e)) if e.contains("unknown")
+        ));
+        assert!(c.slice("missing.stl", &command).await.is_err());
+    }
+}
@@ -0,0 +1,497 @@
+//! Synchronize a local directory with a folder of the OctoPrint local storage.
+//!
+//! The plan is computed from the current state of both sides each time, so an interrupted
+//! synchronization is resumed by running it again: files already uploaded are found unchanged.
+//! Folders are created first, then files are uploaded, and files are deleted last so that an
+//! interruption never leaves the server with less than before.
+
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use sha1::{Digest, Sha1};
+
+use super::datamodel::FileList;
+use super::{OctoPrintClient, OctoPrintClientError, UploadOptions};
+use crate::gcode::metadata;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Upload new and changed files, never delete anything on the server.
+    Push,
+    /// Like `Push`, and delete the files not present locally.
+    Mirror,
+}
+
+/// Options of `OctoPrintClient::sync_plan()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyncOptions {
+    pub mode: SyncMode,
+    /// Only synchronize the files matching one of these patterns, all files if empty.
+    pub include: Vec<String>,
+    /// Ignore the files matching one of these patterns.
+    pub exclude: Vec<String>,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions {
+            mode: SyncMode::Push,
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+impl SyncOptions {
+    /// Check the include and exclude patterns against a relative path.
+    ///
+    /// Patterns without `/` are matched against the file name, others against the path.
+    pub fn accepts(&self, path: &str) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let matches = |pattern: &String| {
+            if pattern.contains('/') {
+                glob_match(pattern, path)
+            } else {
+                glob_match(pattern, name)
+            }
+        };
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Match `text` against a pattern where `*` matches any characters and `?` one character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` in the pattern and of the text it matched up to
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Let the last `*` match one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// SHA1 of the data as lower case hexadecimal, as reported in the `hash` of the Files API.
+pub fn sha1_hex(data: &[u8]) -> String {
+    to_hex(&Sha1::digest(data))
+}
+
+fn file_sha1(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha1::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A file of the local directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalFile {
+    /// Path relative to the synchronized directory, with `/` separators.
+    pub path: String,
+    pub full_path: PathBuf,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// List the files of `dir` and its sub-directories accepted by the options.
+pub fn scan_local(dir: &Path, options: &SyncOptions) -> std::io::Result<Vec<LocalFile>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![(dir.to_path_buf(), String::new())];
+    while let Some((dir, prefix)) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') {
+                continue;
+            }
+            let path = format!("{}{}", prefix, name);
+            let meta = entry.metadata()?;
+            if meta.is_dir() {
+                dirs.push((entry.path(), format!("{}/", path)));
+            } else if meta.is_file() && options.accepts(&path) {
+                files.push(LocalFile {
+                    path,
+                    full_path: entry.path(),
+                    size: meta.len(),
+                    modified: meta.modified().ok(),
+                });
+            }
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// Why a file present on both sides is uploaded again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeReason {
+    Size,
+    Hash,
+    /// The server does not report a hash and the local file is newer.
+    Modified,
+}
+
+impl fmt::Display for ChangeReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChangeReason::Size => "size changed",
+            ChangeReason::Hash => "content changed",
+            ChangeReason::Modified => "newer",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyncAction {
+    /// Create a folder, by remote path.
+    CreateFolder(String),
+    /// Upload a local file, `reason` is `None` for new files.
+    Upload {
+        file: LocalFile,
+        remote_path: String,
+        reason: Option<ChangeReason>,
+    },
+    /// Delete a file, by remote path.
+    Delete(String),
+}
+
+impl fmt::Display for SyncAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncAction::CreateFolder(path) => write!(f, "+ {}/", path),
+            SyncAction::Upload {
+                remote_path,
+                reason: None,
+                ..
+            } => write!(f, "+ {}", remote_path),
+            SyncAction::Upload {
+                remote_path,
+                reason: Some(reason),
+                ..
+            } => write!(f, "~ {} ({})", remote_path, reason),
+            SyncAction::Delete(path) => write!(f, "- {}", path),
+        }
+    }
+}
+
+/// Actions needed to synchronize, in the order they must be applied.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SyncPlan {
+    pub actions: Vec<SyncAction>,
+    /// Remote paths of the files already up to date.
+    pub unchanged: Vec<String>,
+}
+
+impl SyncPlan {
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
+
+fn join(folder: &str, path: &str) -> String {
+    if folder.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}/{}", folder, path)
+    }
+}
+
+/// Compute the plan to synchronize the local files to `remote_folder`.
+///
+/// Local files are hashed only when their size matches the remote one.
+pub fn plan(
+    local: &[LocalFile],
+    remote: &FileList,
+    remote_folder: &str,
+    options: &SyncOptions,
+) -> std::io::Result<SyncPlan> {
+    let remote_folder = remote_folder.trim_matches('/');
+    let entries: Vec<_> = remote.files.iter().flat_map(|f| f.walk()).collect();
+    let mut folders: Vec<&str> = entries
+        .iter()
+        .filter(|e| e.is_folder())
+        .map(|e| e.path.as_str())
+        .collect();
+
+    let mut result = SyncPlan::default();
+    let mut new_folders = Vec::new();
+    let mut uploads = Vec::new();
+    for file in local {
+        let remote_path = join(remote_folder, &file.path);
+        let existing = entries
+            .iter()
+            .find(|e| !e.is_folder() && e.path == remote_path);
+
+        let reason = match existing {
+            None => None,
+            Some(e) if e.size != Some(file.size) => Some(ChangeReason::Size),
+            Some(e) => match &e.hash {
+                Some(hash) if *hash != file_sha1(&file.full_path)? => Some(ChangeReason::Hash),
+                Some(_) => {
+                    result.unchanged.push(remote_path);
+                    continue;
+                }
+                None if file.modified > e.date => Some(ChangeReason::Modified),
+                None => {
+                    result.unchanged.push(remote_path);
+                    continue;
+                }
+            },
+        };
+
+        // Create the missing parent folders, outermost first
+        let parents: Vec<&str> = remote_path
+            .match_indices('/')
+            .map(|(i, _)| &remote_path[..i])
+            .collect();
+        for parent in parents {
+            if !folders.contains(&parent) && !new_folders.contains(&parent.to_string()) {
+                new_folders.push(parent.to_string());
+            }
+        }
+
+        uploads.push(SyncAction::Upload {
+            file: file.clone(),
+            remote_path,
+            reason,
+        });
+    }
+    folders.extend(new_folders.iter().map(|f| f.as_str()));
+
+    let mut deletes = Vec::new();
+    if options.mode == SyncMode::Mirror {
+        let prefix = if remote_folder.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", remote_folder)
+        };
+        for entry in entries.iter().filter(|e| !e.is_folder()) {
+            let relative = match entry.path.strip_prefix(&prefix) {
+                Some(relative) => relative,
+                None => continue,
+            };
+            if options.accepts(relative) && !local.iter().any(|f| f.path == relative) {
+                deletes.push(SyncAction::Delete(entry.path.clone()));
+            }
+        }
+    }
+
+    result.actions = new_folders
+        .into_iter()
+        .map(SyncAction::CreateFolder)
+        .chain(uploads)
+        .chain(deletes)
+        .collect();
+    Ok(result)
+}
+
+impl OctoPrintClient {
+    /// Compare `local_dir` with `remote_folder` of the local storage.
+    pub async fn sync_plan(
+        &self,
+        local_dir: &Path,
+        remote_folder: &str,
+        options: &SyncOptions,
+    ) -> Result<SyncPlan, OctoPrintClientError> {
+        let local = scan_local(local_dir, options)?;
+        let remote = self.get_files(true).await?;
+        Ok(plan(&local, &remote, remote_folder, options)?)
+    }
+
+    /// Apply the actions of the plan in order, `progress` is called before each action.
+    ///
+    /// Stops at the first error, run `sync_plan()` again to resume.
+    pub async fn apply_sync<F: FnMut(&SyncAction)>(
+        &self,
+        plan: &SyncPlan,
+        mut progress: F,
+    ) -> Result<(), OctoPrintClientError> {
+        for action in &plan.actions {
+            progress(action);
+            match action {
+                SyncAction::CreateFolder(path) => self.create_folder(path).await?,
+                SyncAction::Upload {
+                    file, remote_path, ..
+                } => {
+                    let (folder, name) = remote_path.rsplit_once('/').unwrap_or(("", remote_path));
+                    let metadata = metadata::extract_metadata(std::io::BufReader::new(
+                        std::fs::File::open(&file.full_path)?,
+                    ))?;
+                    let options = UploadOptions {
+                        path: Some(folder.to_string()).filter(|f| !f.is_empty()),
+                        select: false,
+                        print: false,
+                        userdata: if metadata.is_empty() {
+                            None
+                        } else {
+                            Some(serde_json::to_value(&metadata)?)
+                        },
+                    };
+                    self.upload_with_options(std::fs::File::open(&file.full_path)?, name, &options)
+                        .await?;
+                }
+                SyncAction::Delete(path) => self.delete_file(path).await?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::testing::{MockServer, StoredFile};
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.gcode", "benchy.gcode"));
+        assert!(!glob_match("*.gcode", "benchy.stl"));
+        assert!(glob_match("test_?.g*", "test_1.gco"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+
+        let options = SyncOptions {
+            include: vec!["*.gcode".to_string()],
+            exclude: vec!["draft/*".to_string(), "*_old.gcode".to_string()],
+            ..Default::default()
+        };
+        assert!(options.accepts("parts/a.gcode"));
+        assert!(!options.accepts("parts/a.stl"));
+        assert!(!options.accepts("draft/a.gcode"));
+        assert!(!options.accepts("a_old.gcode"));
+    }
+
+    #[test]
+    fn test_sha1_hex() {
+        assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    fn local_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, content) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn stored(content: &str) -> StoredFile {
+        StoredFile {
+            content: content.as_bytes().to_vec(),
+            date: 0,
+            userdata: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync() {
+        let dir = local_dir(
+            "octoprint-client-test-sync",
+            &[
+                ("a.gcode", "G28\n"),
+                ("same.gcode", "G1 X1\n"),
+                ("hash.gcode", "G1 X2\n"),
+                ("size.gcode", "G1 X100\n"),
+                ("parts/sub/b.gcode", "G1 Y1\n"),
+                ("notes.txt", "not G-code"),
+            ],
+        );
+        let server = MockServer::start();
+        server.with_printer_mut(|p| {
+            p.files
+                .insert("out/same.gcode".to_string(), stored("G1 X1\n"));
+            p.files
+                .insert("out/hash.gcode".to_string(), stored("G1 X3\n"));
+            p.files
+                .insert("out/size.gcode".to_string(), stored("G1 X1\n"));
+            p.files.insert("out/old.gcode".to_string(), stored("G28\n"));
+            p.files.insert("other.gcode".to_string(), stored("G28\n"));
+        });
+        let c = server.client();
+        let options = SyncOptions {
+            mode: SyncMode::Mirror,
+            include: vec!["*.gcode".to_string()],
+            ..Default::default()
+        };
+
+        let plan = c.sync_plan(&dir, "out", &options).await.unwrap();
+        let actions: Vec<String> = plan.actions.iter().map(|a| a.to_string()).collect();
+        assert_eq!(
+            actions,
+            [
+                "+ out/parts/",
+                "+ out/parts/sub/",
+                "+ out/a.gcode",
+                "~ out/hash.gcode (content changed)",
+                "+ out/parts/sub/b.gcode",
+                "~ out/size.gcode (size changed)",
+                "- out/old.gcode",
+            ]
+        );
+        assert_eq!(plan.unchanged, ["out/same.gcode"]);
+
+        // Interrupted after the first upload
+        let mut partial = plan.clone();
+        partial.actions.truncate(3);
+        c.apply_sync(&partial, |_| {}).await.unwrap();
+        let plan = c.sync_plan(&dir, "out", &options).await.unwrap();
+        assert_eq!(plan.actions.len(), 4);
+
+        let mut applied = Vec::new();
+        c.apply_sync(&plan, |a| applied.push(a.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(applied.len(), 4);
+
+        let printer = server.printer();
+        let paths: Vec<&str> = printer.files.keys().map(|p| p.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "other.gcode",
+                "out/a.gcode",
+                "out/hash.gcode",
+                "out/parts/sub/b.gcode",
+                "out/same.gcode",
+                "out/size.gcode",
+            ]
+        );
+        assert_eq!(printer.files["out/size.gcode"].content, b"G1 X100\n");
+        assert!(printer.job.is_none());
+
+        assert!(c.sync_plan(&dir, "out", &options).await.unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,2157 @@
+//! In-process mock of an OctoPrint server, for offline tests.
+//!
+//! The mock listens on a random local port and runs in its own thread, so it can be used from
+//! both synchronous and asynchronous tests. It answers every endpoint supported by
+//! `OctoPrintClient` from a simulated printer (`VirtualPrinter`), records all received requests
+//! and can be scripted to return arbitrary responses or errors.
+//!
+//! ```no_run
+//! use octoprint_client::octoprintclient::testing::MockServer;
+//!
+//! # async fn example() {
+//! let server = MockServer::start();
+//! let client = server.client();
+//! let info = client.get_server_info().await.unwrap();
+//! assert_eq!(info.version, "1.8.6");
+//! # }
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use hyper::header::{HeaderValue, SET_COOKIE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, oneshot};
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use super::datamodel::*;
+use super::{Configuration, OctoPrintClient};
+
+/// API key accepted by the mock server.
+pub const MOCK_API_KEY: &str = "MOCKAPIKEY0123456789ABCDEF012345";
+
+/// Cookies of the session, OctoPrint suffixes them with the port of the server.
+const SESSION_COOKIE: &str = "session_P80";
+const CSRF_COOKIE: &str = "csrf_token_P80";
+const REMEMBER_COOKIE: &str = "remember_token_P80";
+
+/// Version reported by `/api/server`.
+pub const MOCK_VERSION: &str = "1.8.6";
+
+/// A request received by the mock server.
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    pub method: Method,
+    /// Path, including the query string if any.
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl RecordedRequest {
+    pub fn body_json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap_or(Value::Null)
+    }
+
+    pub fn body_text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+}
+
+/// A canned response, returned instead of the simulated one.
+#[derive(Clone, Debug)]
+struct ScriptedResponse {
+    method: Method,
+    path: String,
+    status: StatusCode,
+    body: String,
+    /// Number of times this response is served, `None` for ever.
+    remaining: Option<usize>,
+}
+
+/// Temperature of a simulated heater.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Heater {
+    pub actual: f32,
+    pub target: f32,
+}
+
+impl Heater {
+    fn room_temperature() -> Self {
+        Heater {
+            actual: 21.0,
+            target: 0.0,
+        }
+    }
+
+    fn step(&mut self, rate: f32) {
+        let goal = if self.target > 0.0 { self.target } else { 21.0 };
+        if (goal - self.actual).abs() <= rate {
+            self.actual = goal;
+        } else if goal > self.actual {
+            self.actual += rate;
+        } else {
+            self.actual -= rate;
+        }
+    }
+
+    fn data(&self) -> TemperatureData {
+        TemperatureData {
+            actual: Some(self.actual),
+            target: Some(self.target),
+            offset: Some(0.0),
+        }
+    }
+}
+
+/// Job running on the simulated printer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VirtualJob {
+    pub path: String,
+    pub size: u64,
+    /// Progress, from 0.0 to 1.0.
+    pub progress: f64,
+    /// Estimated print time.
+    pub total_time: Duration,
+    pub user: Option<String>,
+}
+
+/// A file stored on the mock server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredFile {
+    pub content: Vec<u8>,
+    /// Upload date, as UNIX timestamp.
+    pub date: u64,
+    /// `userdata` field of the upload.
+    pub userdata: Option<Value>,
+}
+
+/// A user account of the mock server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredUser {
+    pub password: String,
+    pub active: bool,
+    pub groups: Vec<String>,
+    pub permissions: Vec<String>,
+    pub apikey: Option<String>,
+}
+
+/// The permissions of the mock server, a subset of the ones of OctoPrint.
+pub fn mock_permissions() -> Vec<Permission> {
+    let permission = |key: &str, name: &str, dangerous: bool, groups: &[&str]| Permission {
+        key: key.to_string(),
+        name: name.to_string(),
+        description: String::new(),
+        dangerous,
+        default_groups: groups.iter().map(|g| g.to_string()).collect(),
+    };
+    vec![
+        permission("ADMIN", "Admin", true, &["admins"]),
+        permission("STATUS", "Status", false, &["users", "guests"]),
+        permission("CONNECTION", "Connection", false, &["users"]),
+        permission("WEBCAM", "Webcam", false, &["users", "guests"]),
+        permission("FILES_LIST", "File List", false, &["users", "guests"]),
+        permission("FILES_UPLOAD", "File Upload", false, &["users"]),
+        permission("FILES_DELETE", "File Delete", false, &["users"]),
+        permission("FILES_SELECT", "File Select", false, &["users"]),
+        permission("PRINT", "Print", false, &["users"]),
+        permission("CONTROL", "Control", true, &["users"]),
+        permission("SLICE", "Slice", false, &["users"]),
+        permission(
+            "TIMELAPSE_LIST",
+            "Timelapse List",
+            false,
+            &["users", "guests"],
+        ),
+    ]
+}
+
+/// The built-in groups of OctoPrint, with the default permissions.
+fn default_groups() -> BTreeMap<String, Group> {
+    let permissions = mock_permissions();
+    ["admins", "users", "guests"]
+        .iter()
+        .map(|key| {
+            let mut name = key.to_string();
+            name[..1].make_ascii_uppercase();
+            let group = Group {
+                key: key.to_string(),
+                name,
+                description: String::new(),
+                permissions: permissions
+                    .iter()
+                    .filter(|p| p.default_groups.iter().any(|g| g == key))
+                    .map(|p| p.key.clone())
+                    .collect(),
+                subgroups: Vec::new(),
+                default: *key == "users",
+                removable: false,
+                changeable: *key != "admins",
+            };
+            (key.to_string(), group)
+        })
+        .collect()
+}
+
+/// The default profile of OctoPrint: 200mm cube, heated bed, one 0.4mm extruder.
+pub fn default_profile() -> Profile {
+    Profile {
+        id: "_default".to_string(),
+        name: "Default".to_string(),
+        model: Some("Generic RepRap Printer".to_string()),
+        default: true,
+        current: false,
+        volume: ProfileVolume {
+            form_factor: "rectangular".to_string(),
+            origin: "lowerleft".to_string(),
+            width: 200.0,
+            depth: 200.0,
+            height: 200.0,
+            custom_box: Some(CustomBoxSetting::Disabled(false)),
+        },
+        heated_bed: true,
+        heated_chamber: false,
+        extruder: ProfileExtruder {
+            count: 1,
+            nozzle_diameter: 0.4,
+            offsets: vec![(0.0, 0.0)],
+            shared_nozzle: false,
+        },
+    }
+}
+
+/// A JPEG image, red on the left half and blue on the right half.
+pub fn test_jpeg(width: u32, height: u32) -> Vec<u8> {
+    let image = image::RgbImage::from_fn(width, height, |x, _| {
+        if x < width / 2 {
+            image::Rgb([255, 0, 0])
+        } else {
+            image::Rgb([0, 0, 255])
+        }
+    });
+    let mut jpeg = Vec::new();
+    image::DynamicImage::ImageRgb8(image)
+        .write_to(
+            &mut std::io::Cursor::new(&mut jpeg),
+            image::ImageOutputFormat::Jpeg(90),
+        )
+        .unwrap();
+    jpeg
+}
+
+/// The simulated printer.
+///
+/// The simulation advances one step ("tick") for each request received by the server, which
+/// keeps tests deterministic: a connection becomes operational after `connect_ticks` requests,
+/// heaters move by `heat_rate` degrees and print jobs by `progress_step` on each request.
+#[derive(Clone, Debug)]
+pub struct VirtualPrinter {
+    pub state: State,
+    pub error: Option<String>,
+    pub port: Option<String>,
+    pub baudrate: Option<u32>,
+    pub profile: String,
+    pub ports: Vec<String>,
+    pub baudrates: Vec<u32>,
+    pub profiles: Vec<Profile>,
+    pub port_preference: Option<String>,
+    pub baudrate_preference: Option<u32>,
+    pub profile_preference: Option<String>,
+    pub autoconnect: bool,
+    /// Number of "fake_ack" commands received.
+    pub fake_acks: u32,
+    /// Port the printer is plugged on, connecting to another one fails. Any port works if `None`.
+    pub printer_port: Option<String>,
+    /// The "AUTO" port finds the printer, else connecting to it fails.
+    pub auto_detect: bool,
+    pub tools: Vec<Heater>,
+    pub bed: Heater,
+    pub job: Option<VirtualJob>,
+    /// Files in the "local" storage, by path.
+    pub files: BTreeMap<String, StoredFile>,
+    /// Folders in the "local" storage, the folders containing files are implicit.
+    pub folders: BTreeSet<String>,
+    /// G-code commands received by the printer.
+    pub commands: Vec<String>,
+    /// Number of ticks spent connecting.
+    pub connect_ticks: u32,
+    /// Temperature change per tick, in °C.
+    pub heat_rate: f32,
+    /// Job progress per tick.
+    pub progress_step: f64,
+    /// Webcam section of `/api/settings`.
+    pub webcam: WebcamSettings,
+    /// JPEG frames of the webcam stream, the first one is the snapshot.
+    pub webcam_frames: Vec<Vec<u8>>,
+    pub timelapse_config: TimelapseConfig,
+    /// Rendered timelapses, by name.
+    pub timelapses: BTreeMap<String, Vec<u8>>,
+    /// Names of the unrendered timelapses, rendered instantly.
+    pub unrendered_timelapses: BTreeSet<String>,
+    /// Slicers, by key, with their profiles and the settings of the profiles.
+    pub slicers: BTreeMap<String, Slicer>,
+    /// User accounts, by name.
+    pub users: BTreeMap<String, StoredUser>,
+    pub groups: BTreeMap<String, Group>,
+    ticks_left: u32,
+    keys_generated: u32,
+}
+
+impl Default for VirtualPrinter {
+    fn default() -> Self {
+        VirtualPrinter {
+            state: State::Closed,
+            error: None,
+            port: None,
+            baudrate: None,
+            profile: "_default".to_string(),
+            ports: vec!["VIRTUAL".to_string()],
+            baudrates: vec![250000, 230400, 115200, 57600, 38400, 19200, 9600],
+            profiles: vec![default_profile()],
+            port_preference: Some("VIRTUAL".to_string()),
+            baudrate_preference: Some(115200),
+            profile_preference: Some("_default".to_string()),
+            autoconnect: false,
+            fake_acks: 0,
+            printer_port: None,
+            auto_detect: true,
+            tools: vec![Heater::room_temperature()],
+            bed: Heater::room_temperature(),
+            job: None,
+            files: BTreeMap::new(),
+            folders: BTreeSet::new(),
+            commands: Vec::new(),
+            connect_ticks: 1,
+            heat_rate: 20.0,
+            progress_step: 0.25,
+            webcam: WebcamSettings {
+                webcam_enabled: true,
+                stream_url: Some("/webcam/?action=stream".to_string()),
+                snapshot_url: Some("/webcam/?action=snapshot".to_string()),
+                ..Default::default()
+            },
+            webcam_frames: Vec::new(),
+            timelapse_config: TimelapseConfig {
+                fps: 25,
+                ..Default::default()
+            },
+            timelapses: BTreeMap::new(),
+            unrendered_timelapses: BTreeSet::new(),
+            slicers: BTreeMap::from([(
+                "curalegacy".to_string(),
+                Slicer {
+                    key: "curalegacy".to_string(),
+                    display_name: "Cura Legacy".to_string(),
+                    default: true,
+                    configured: true,
+                    profiles: BTreeMap::from([(
+                        "default".to_string(),
+                        SlicingProfile {
+                            key: "default".to_string(),
+                            display_name: Some("Default".to_string()),
+                            description: None,
+                            default: Some(true),
+                            resource: None,
+                            data: json!({"layer_height": 0.1}).as_object().cloned(),
+                        },
+                    )]),
+                },
+            )]),
+            users: BTreeMap::from([(
+                "admin".to_string(),
+                StoredUser {
+                    password: "admin".to_string(),
+                    active: true,
+                    groups: vec!["admins".to_string(), "users".to_string()],
+                    permissions: Vec::new(),
+                    apikey: None,
+                },
+            )]),
+            groups: default_groups(),
+            ticks_left: 0,
+            keys_generated: 0,
+        }
+    }
+}
+
+impl VirtualPrinter {
+    /// A user as listed by `/api/access/users`.
+    pub fn user(&self, name: &str) -> Option<User> {
+        self.users.get(name).map(|u| User {
+            name: name.to_string(),
+            active: u.active,
+            admin: u.groups.iter().any(|g| g == "admins"),
+            apikey: u.apikey.clone(),
+            groups: u.groups.clone(),
+            permissions: u.permissions.clone(),
+        })
+    }
+
+    fn user_list(&self) -> UserList {
+        UserList {
+            users: self.users.keys().filter_map(|n| self.user(n)).collect(),
+        }
+    }
+
+    fn group_list(&self) -> GroupList {
+        GroupList {
+            groups: self.groups.values().cloned().collect(),
+        }
+    }
+
+    /// All the permissions of a user, "ADMIN" gives all of them.
+    pub fn user_permissions(&self, groups: &[String], permissions: &[String]) -> Vec<String> {
+        let mut all: BTreeSet<String> = permissions.iter().cloned().collect();
+        for group in groups.iter().filter_map(|g| self.groups.get(g)) {
+            all.extend(group.permissions.iter().cloned());
+        }
+        if all.contains("ADMIN") {
+            return mock_permissions().into_iter().map(|p| p.key).collect();
+        }
+        all.into_iter().collect()
+    }
+
+    /// Response of `/api/timelapse`.
+    pub fn timelapse_list(&self, unrendered: bool) -> TimelapseList {
+        let size = |bytes: usize| format!("{:.1}KB", bytes as f64 / 1024.0);
+        let date = "2023-01-15 10:22".to_string();
+        TimelapseList {
+            config: self.timelapse_config.clone(),
+            enabled: self.timelapse_config.timelapse_type != TimelapseType::Off,
+            files: self
+                .timelapses
+                .iter()
+                .map(|(name, content)| TimelapseFile {
+                    name: name.clone(),
+                    size: size(content.len()),
+                    bytes: content.len() as u64,
+                    date: date.clone(),
+                    url: Some(format!("/downloads/timelapse/{}", name)),
+                })
+                .collect(),
+            unrendered: if unrendered {
+                self.unrendered_timelapses
+                    .iter()
+                    .map(|name| UnrenderedTimelapse {
+                        name: name.clone(),
+                        size: size(0),
+                        bytes: 0,
+                        date: date.clone(),
+                        recording: false,
+                        rendering: false,
+                        processing: false,
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    /// Advance the simulation by one step.
+    pub fn tick(&mut self) {
+        match self.state {
+            State::Connecting => {
+                if self.ticks_left == 0 {
+                    self.connected();
+                } else {
+                    self.ticks_left -= 1;
+                }
+            }
+            State::Printing => {
+                if let Some(job) = &mut self.job {
+                    job.progress = (job.progress + self.progress_step).min(1.0);
+                    if job.progress >= 1.0 {
+                        self.state = State::Finishing;
+                    }
+                }
+            }
+            State::Pausing => self.state = State::Paused,
+            State::Resuming => self.state = State::Printing,
+            State::Cancelling | State::Finishing => self.state = State::Operational,
+            _ => {}
+        }
+
+        let rate = self.heat_rate;
+        self.tools.iter_mut().for_each(|t| t.step(rate));
+        self.bed.step(rate);
+    }
+
+    /// Open the serial connection, it becomes operational after `connect_ticks` ticks.
+    pub fn connect(
+        &mut self,
+        port: Option<String>,
+        baudrate: Option<u32>,
+        profile: Option<String>,
+    ) {
+        let mut port = port.or_else(|| self.port_preference.clone());
+        if port.as_deref() == Some("AUTO") && self.auto_detect {
+            port = self
+                .printer_port
+                .clone()
+                .or_else(|| self.ports.first().cloned());
+        }
+        self.port = port;
+        self.baudrate = match baudrate.or(self.baudrate_preference) {
+            Some(0) => self.baudrates.first().copied(),
+            baudrate => baudrate,
+        };
+        if let Some(profile) = profile.or_else(|| self.profile_preference.clone()) {
+            self.profile = profile;
+        }
+        self.error = None;
+        if self.connect_ticks == 0 {
+            self.connected();
+        } else {
+            self.state = State::Connecting;
+            self.ticks_left = self.connect_ticks - 1;
+        }
+    }
+
+    /// End of the connection, which fails if the printer is not on the port.
+    fn connected(&mut self) {
+        match self.port.as_deref() {
+            Some("AUTO") => self.fail("Failed to autodetect serial port"),
+            Some(port) if self.printer_port.as_deref().is_some_and(|p| p != port) => {
+                self.fail("No response from the printer")
+            }
+            _ => self.state = State::Operational,
+        }
+    }
+
+    pub fn disconnect(&mut self) {
+        self.port = None;
+        self.baudrate = None;
+        self.job = None;
+        self.state = State::Closed;
+    }
+
+    /// Put the printer in error state, as when the firmware reports an error.
+    pub fn fail(&mut self, message: &str) {
+        self.error = Some(message.to_string());
+        self.state = State::Error;
+    }
+
+    /// Select a stored file, returns false if it does not exist.
+    pub fn select(&mut self, path: &str) -> bool {
+        match self.files.get(path) {
+            Some(file) => {
+                self.job = Some(VirtualJob {
+                    path: path.to_string(),
+                    size: file.content.len() as u64,
+                    progress: 0.0,
+                    total_time: Duration::from_secs(600),
+                    user: Some("mock".to_string()),
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Start printing the selected file.
+    pub fn start(&mut self) -> bool {
+        if self.state.can_start() && self.job.is_some() {
+            if let Some(job) = &mut self.job {
+                job.progress = 0.0;
+            }
+            self.state = State::Printing;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn pause(&mut self) -> bool {
+        if matches!(self.state, State::Printing) {
+            self.state = State::Pausing;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn resume(&mut self) -> bool {
+        if matches!(self.state, State::Paused) {
+            self.state = State::Resuming;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn cancel(&mut self) -> bool {
+        if self.state.is_busy() {
+            self.state = State::Cancelling;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Handle a G-code command sent to the printer, heater commands change the targets.
+    pub fn gcode(&mut self, command: &str) {
+        self.commands.push(command.to_string());
+
+        let mut words = command.split_whitespace();
+        let code = words.next().unwrap_or("").to_uppercase();
+        let mut tool = 0;
+        let mut target = None;
+        for word in words {
+            let (letter, value) = word.split_at(1);
+            match letter.to_uppercase().as_str() {
+                "S" | "R" => target = value.parse::<f32>().ok(),
+                "T" => tool = value.parse::<usize>().unwrap_or(0),
+                _ => {}
+            }
+        }
+        match (code.as_str(), target) {
+            ("M104" | "M109", Some(t)) => {
+                if let Some(heater) = self.tools.get_mut(tool) {
+                    heater.target = t;
+                }
+            }
+            ("M140" | "M190", Some(t)) => self.bed.target = t,
+            _ => {}
+        }
+    }
+
+    fn connection(&self) -> PrinterConnection {
+        PrinterConnection {
+            current: CurrentConnection {
+                baudrate: self.baudrate,
+                port: self.port.clone(),
+                printer_profile: self.profile.clone(),
+                state: self.state.clone(),
+            },
+            options: ConnectionOptions {
+                baudrate_preference: self.baudrate_preference,
+                baudrates: self.baudrates.clone(),
+                port_preference: self.port_preference.clone(),
+                ports: self.ports.clone(),
+                printer_profile_preference: self.profile_preference.clone(),
+                printer_profiles: self
+                    .profiles
+                    .iter()
+                    .map(|p| PrinterProfile {
+                        id: p.id.clone(),
+                        name: p.name.clone(),
+                    })
+                    .collect(),
+                autoconnect: Some(self.autoconnect),
+            },
+        }
+    }
+
+    fn profile(&self, id: &str) -> Option<Profile> {
+        let connected = !self.state.is_closed_or_error();
+        self.profiles.iter().find(|p| p.id == id).map(|p| Profile {
+            current: connected && p.id == self.profile,
+            ..p.clone()
+        })
+    }
+
+    /// Entry of the Files API for a stored file or a folder.
+    fn file_entry(&self, path: &str) -> Option<FileEntry> {
+        let path = path.trim_matches('/');
+        let name = path.rsplit('/').next().unwrap_or("").to_string();
+        let refs = |prefix: &str| format!("{}/files/local/{}", prefix, super::encode_path(path));
+        if let Some(file) = self.files.get(path) {
+            return Some(FileEntry {
+                display: Some(name.clone()),
+                name,
+                path: path.to_string(),
+                file_type: if path.ends_with(".stl") {
+                    "model"
+                } else {
+                    "machinecode"
+                }
+                .to_string(),
+                type_path: if path.ends_with(".stl") {
+                    vec!["model".to_string(), "stl".to_string()]
+                } else {
+                    vec!["machinecode".to_string(), "gcode".to_string()]
+                },
+                origin: Some("local".to_string()),
+                hash: Some(super::sync::sha1_hex(&file.content)),
+                size: Some(file.content.len() as u64),
+                date: Some(std::time::UNIX_EPOCH + Duration::from_secs(file.date)),
+                refs: Some(FileRefs {
+                    resource: refs("/api"),
+                    download: Some(refs("/downloads")),
+                    model: None,
+                }),
+                gcode_analysis: None,
+                prints: None,
+                children: None,
+                userdata: file.userdata.clone(),
+            });
+        }
+
+        if !self.is_folder(path) {
+            return None;
+        }
+        let children = self.file_entries(path, true);
+        Some(FileEntry {
+            display: Some(name.clone()),
+            name,
+            path: path.to_string(),
+            file_type: "folder".to_string(),
+            type_path: vec!["folder".to_string()],
+            origin: Some("local".to_string()),
+            hash: None,
+            size: Some(children.iter().filter_map(|c| c.size).sum()),
+            date: None,
+            refs: Some(FileRefs {
+                resource: refs("/api"),
+                download: None,
+                model: None,
+            }),
+            gcode_analysis: None,
+            prints: None,
+            children: Some(children),
+            userdata: None,
+        })
+    }
+
+    fn is_folder(&self, path: &str) -> bool {
+        let prefix = format!("{}/", path);
+        self.folders.contains(path)
+            || self
+                .files
+                .keys()
+                .chain(self.folders.iter())
+                .any(|p| p.starts_with(&prefix))
+    }
+
+    /// Delete a file or a folder with its content, returns false if not found.
+    pub fn delete(&mut self, path: &str) -> bool {
+        let path = path.trim_matches('/');
+        if self.files.remove(path).is_some() {
+            return true;
+        }
+        if !self.is_folder(path) {
+            return false;
+        }
+        let prefix = format!("{}/", path);
+        self.files.retain(|p, _| !p.starts_with(&prefix));
+        self.folders
+            .retain(|p| p != path && !p.starts_with(&prefix));
+        true
+    }
+
+    /// Files and folders directly in `folder`, folders include their content if `recursive`.
+    fn file_entries(&self, folder: &str, recursive: bool) -> Vec<FileEntry> {
+        let prefix = if folder.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", folder)
+        };
+        let names: BTreeSet<String> = self
+            .files
+            .keys()
+            .chain(self.folders.iter())
+            .filter_map(|path| path.strip_prefix(&prefix))
+            .map(|rest| rest.split('/').next().unwrap_or("").to_string())
+            .filter(|name| !name.is_empty())
+            .collect();
+
+        names
+            .iter()
+            .filter_map(|name| self.file_entry(&format!("{}{}", prefix, name)))
+            .map(|mut entry| {
+                if !recursive && entry.is_folder() {
+                    entry.children = None;
+                }
+                entry
+            })
+            .collect()
+    }
+
+    fn job_information(&self) -> JobInformation {
+        let job = self.job.as_ref();
+        let name = job.map(|j| j.path.rsplit('/').next().unwrap_or("").to_string());
+        let print_time = job.map(|j| j.total_time.mul_f64(j.progress));
+        JobInformation {
+            job: Job {
+                file: File {
+                    date: job
+                        .and_then(|j| self.files.get(&j.path))
+                        .map(|f| std::time::UNIX_EPOCH + Duration::from_secs(f.date)),
+                    display: name.clone(),
+                    name,
+                    origin: job.map(|_| "local".to_string()),
+                    path: job.map(|j| j.path.clone()),
+                    size: job.map(|j| j.size),
+                },
+                estimated_print_time: job.map(|j| j.total_time),
+                average_print_time: None,
+                last_print_time: None,
+                filament: None,
+                user: job.and_then(|j| j.user.clone()),
+            },
+            progress: Progress {
+                completion: job.map(|j| j.progress * 100.0),
+                filepos: job.map(|j| (j.size as f64 * j.progress) as u64),
+                print_time,
+                print_time_left: job.map(|j| j.total_time - j.total_time.mul_f64(j.progress)),
+                print_time_left_origin: job.map(|_| "estimate".to_string()),
+            },
+            state: self.state.clone(),
+            error: self.error.clone(),
+        }
+    }
+
+    fn printer_info(&self) -> PrinterInfo {
+        let state = &self.state;
+        PrinterInfo {
+            temperature: Some(TemperatureState {
+                bed: Some(self.bed.data()),
+                chamber: None,
+                history: None,
+                tools: self
+                    .tools
+                    .iter()
+                    .enumerate()
+                    .map(|(i, t)| (format!("tool{}", i), t.data()))
+                    .collect(),
+            }),
+            sd: Some(SDState { ready: false }),
+            state: Some(PrinterState {
+                text: state.to_string(),
+                error: Some(self.error.clone().unwrap_or_default()),
+                flags: PrinterFlags {
+                    operational: state.is_operational(),
+                    paused: state.is_paused(),
+                    printing: state.is_printing(),
+                    pausing: matches!(state, State::Pausing),
+                    cancelling: matches!(state, State::Cancelling),
+                    finishing: matches!(state, State::Finishing),
+                    resuming: matches!(state, State::Resuming),
+                    sd_ready: false,
+                    error: state.is_error(),
+                    ready: state.can_start(),
+                    closed_on_error: state.is_closed_or_error(),
+                },
+            }),
+        }
+    }
+}
+
+struct MockState {
+    api_key: String,
+    printer: VirtualPrinter,
+    requests: Vec<RecordedRequest>,
+    responses: Vec<ScriptedResponse>,
+    /// Messages sent to the push sockets.
+    push: broadcast::Sender<String>,
+    /// "auth" messages received on the push sockets.
+    push_auth: Vec<String>,
+    /// Sessions of `/api/login`, by identifier.
+    sessions: BTreeMap<String, MockSession>,
+    sessions_created: u32,
+}
+
+#[derive(Clone, Debug)]
+struct MockSession {
+    user: String,
+    csrf_token: String,
+}
+
+/// A running mock server, stopped when dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Start a server with a disconnected virtual printer.
+    pub fn start() -> MockServer {
+        Self::with_printer(VirtualPrinter::default())
+    }
+
+    /// Start a server simulating the given printer.
+    pub fn with_printer(printer: VirtualPrinter) -> MockServer {
+        let state = Arc::new(Mutex::new(MockState {
+            api_key: MOCK_API_KEY.to_string(),
+            printer,
+            requests: Vec::new(),
+            responses: Vec::new(),
+            push: broadcast::channel(64).0,
+            push_auth: Vec::new(),
+            sessions: BTreeMap::new(),
+            sessions_created: 0,
+        }));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Bind mock server");
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server_state = state.clone();
+        let thread = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                let make_svc = make_service_fn(move |_| {
+                    let state = server_state.clone();
+                    async move {
+                        Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req)))
+                    }
+                });
+                Server::from_tcp(listener)
+                    .unwrap()
+                    .serve(make_svc)
+                    .with_graceful_shutdown(async {
+                        shutdown_rx.await.ok();
+                    })
+                    .await
+                    .unwrap();
+            });
+        });
+
+        MockServer {
+            addr,
+            state,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        }
+    }
+
+    /// Base URL of the server, e.g. "http://127.0.0.1:12345".
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn configuration(&self) -> Configuration {
+        Configuration {
+            server_url: self.url(),
+            api_key: MOCK_API_KEY.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// A client configured for this server.
+    pub fn client(&self) -> OctoPrintClient {
+        OctoPrintClient::from_config(self.configuration())
+    }
+
+    /// Access the simulated printer.
+    pub fn with_printer_mut<R>(&self, f: impl FnOnce(&mut VirtualPrinter) -> R) -> R {
+        f(&mut self.state.lock().unwrap().printer)
+    }
+
+    /// A copy of the simulated printer.
+    pub fn printer(&self) -> VirtualPrinter {
+        self.state.lock().unwrap().printer.clone()
+    }
+
+    /// All requests received so far.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Received requests matching the method and path (without query string).
+    pub fn requests_to(&self, method: Method, path: &str) -> Vec<RecordedRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|r| r.method == method && r.path.split('?').next() == Some(path))
+            .collect()
+    }
+
+    /// Always answer requests to `method` `path` with the given status and body.
+    pub fn set_response(&self, method: Method, path: &str, status: StatusCode, body: &str) {
+        self.add_response(method, path, status, body, None);
+    }
+
+    /// Answer the next `count` requests to `method` `path` with the given status and body.
+    pub fn set_response_times(
+        &self,
+        method: Method,
+        path: &str,
+        status: StatusCode,
+        body: &str,
+        count: usize,
+    ) {
+        self.add_response(method, path, status, body, Some(count));
+    }
+
+    /// Answer requests to `method` `path` with an OctoPrint error message.
+    pub fn set_error(&self, method: Method, path: &str, status: StatusCode, message: &str) {
+        let body = json!({ "error": message }).to_string();
+        self.set_response(method, path, status, &body);
+    }
+
+    /// Send a message to the connected push sockets.
+    pub fn push(&self, message: Value) {
+        self.state
+            .lock()
+            .unwrap()
+            .push
+            .send(message.to_string())
+            .ok();
+    }
+
+    /// "auth" messages received on the push sockets ("user:session").
+    pub fn push_auth(&self) -> Vec<String> {
+        self.state.lock().unwrap().push_auth.clone()
+    }
+
+    /// Remove all scripted responses.
+    pub fn clear_responses(&self) {
+        self.state.lock().unwrap().responses.clear();
+    }
+
+    fn add_response(
+        &self,
+        method: Method,
+        path: &str,
+        status: StatusCode,
+        body: &str,
+        remaining: Option<usize>,
+    ) {
+        self.state.lock().unwrap().responses.push(ScriptedResponse {
+            method,
+            path: path.to_string(),
+            status,
+            body: body.to_string(),
+            remaining,
+        });
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+fn json_response<T: serde::Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    let mut resp = Response::new(Body::from(serde_json::to_string(value).unwrap()));
+    *resp.status_mut() = status;
+    resp.headers_mut()
+        .insert("Content-Type", HeaderValue::from_static("application/json"));
+    resp
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, &json!({ "error": message }))
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = status;
+    resp
+}
+
+/// A part of a "multipart/form-data" body.
+#[derive(Debug)]
+struct FormPart {
+    name: String,
+    filename: Option<String>,
+    data: Vec<u8>,
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn header_param(headers: &str, param: &str) -> Option<String> {
+    let pattern = format!("{}=\"", param);
+    let start = headers.find(&pattern)? + pattern.len();
+    let end = headers[start..].find('"')? + start;
+    Some(headers[start..end].to_string())
+}
+
+fn parse_multipart(headers: &HeaderMap, body: &[u8]) -> Vec<FormPart> {
+    let boundary = headers
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split("boundary=").nth(1))
+        .map(|b| format!("--{}", b.trim_matches('"')));
+    let boundary = match boundary {
+        Some(b) => b,
+        None => return Vec::new(),
+    };
+
+    let mut parts = Vec::new();
+    let mut rest = body;
+    while let Some(start) = find(rest, boundary.as_bytes()) {
+        rest = &rest[start + boundary.len()..];
+        if rest.starts_with(b"--") {
+            break;
+        }
+        let header_end = match find(rest, b"\r\n\r\n") {
+            Some(i) => i,
+            None => break,
+        };
+        let part_headers = String::from_utf8_lossy(&rest[..header_end]).to_string();
+        let data_start = header_end + 4;
+        let data_end = find(&rest[data_start..], boundary.as_bytes())
+            .map(|i| data_start + i)
+            .unwrap_or(rest.len());
+        let mut data = &rest[data_start..data_end];
+        if data.ends_with(b"\r\n") {
+            data = &data[..data.len() - 2];
+        }
+        parts.push(FormPart {
+            name: header_param(&part_headers, "name").unwrap_or_default(),
+            filename: header_param(&part_headers, "filename"),
+            data: data.to_vec(),
+        });
+        rest = &rest[data_end..];
+    }
+    parts
+}
+
+/// Decode a percent-encoded path.
+fn decode_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Accept a WebSocket connection on `/sockjs/websocket`.
+fn push_socket(state: Arc<Mutex<MockState>>, mut req: Request<Body>) -> Response<Body> {
+    let Some(key) = req.headers().get("Sec-WebSocket-Key").cloned() else {
+        return error_response(StatusCode::BAD_REQUEST, "Not a WebSocket request");
+    };
+    let mut messages = state.lock().unwrap().push.subscribe();
+    let upgrade = hyper::upgrade::on(&mut req);
+    tokio::spawn(async move {
+        let Ok(upgraded) = upgrade.await else {
+            return;
+        };
+        let mut ws = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+        let connected = json!({"connected": {"version": MOCK_VERSION, "safe_mode": false}});
+        if ws.send(Message::text(connected.to_string())).await.is_err() {
+            return;
+        }
+        loop {
+            tokio::select! {
+                message = ws.next() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        let auth = serde_json::from_str::<Value>(&text)
+                            .ok()
+                            .and_then(|v| v["auth"].as_str().map(|a| a.to_string()));
+                        if let Some(auth) = auth {
+                            state.lock().unwrap().push_auth.push(auth);
+                            let history = json!({"history": {"logs": [], "temps": []}});
+                            if ws.send(Message::text(history.to_string())).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    _ => {}
+                },
+                message = messages.recv() => match message {
+                    Ok(text) => {
+                        if ws.send(Message::text(text)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(_) => break,
+                },
+            }
+        }
+    });
+
+    let mut resp = empty_response(StatusCode::SWITCHING_PROTOCOLS);
+    let headers = resp.headers_mut();
+    headers.insert("Connection", HeaderValue::from_static("Upgrade"));
+    headers.insert("Upgrade", HeaderValue::from_static("websocket"));
+    headers.insert(
+        "Sec-WebSocket-Accept",
+        HeaderValue::from_str(&derive_accept_key(key.as_bytes())).unwrap(),
+    );
+    resp
+}
+
+async fn handle(
+    state: Arc<Mutex<MockState>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    if req.uri().path() == "/sockjs/websocket" {
+        return Ok(push_socket(state, req));
+    }
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body)
+        .await
+        .unwrap_or_default()
+        .to_vec();
+    let path = parts
+        .uri
+        .path_and_query()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_default();
+
+    let mut state = state.lock().unwrap();
+    let request = RecordedRequest {
+        method: parts.method,
+        path,
+        headers: parts.headers,
+        body,
+    };
+    state.requests.push(request.clone());
+    state.printer.tick();
+
+    Ok(respond(&mut state, &request))
+}
+
+fn respond(state: &mut MockState, request: &RecordedRequest) -> Response<Body> {
+    let path = request.path.split('?').next().unwrap_or("").to_string();
+
+    // Scripted responses have precedence over the simulation
+    if let Some(idx) = state
+        .responses
+        .iter()
+        .position(|r| r.method == request.method && r.path == path)
+    {
+        let scripted = state.responses[idx].clone();
+        match scripted.remaining {
+            Some(n) if n <= 1 => {
+                state.responses.remove(idx);
+            }
+            Some(n) => state.responses[idx].remaining = Some(n - 1),
+            None => {}
+        }
+        let mut resp = Response::new(Body::from(scripted.body));
+        *resp.status_mut() = scripted.status;
+        return resp;
+    }
+
+    // The webcam streamer does not check the API key
+    if request.method == Method::GET && path == "/webcam/" {
+        return webcam(&state.printer, request);
+    }
+
+    let api_key = request
+        .headers
+        .get("X-Api-Key")
+        .and_then(|v| v.to_str().ok());
+    // The global API key (`None`), the key of a user or the session cookie
+    let session_id = cookie(request, SESSION_COOKIE).filter(|id| state.sessions.contains_key(id));
+    let user = match (api_key, &session_id) {
+        (Some(key), _) if key == state.api_key => Some(None),
+        (Some(key), _) => state
+            .printer
+            .users
+            .iter()
+            .find(|(_, u)| u.active && u.apikey.as_deref() == Some(key))
+            .map(|(name, _)| Some(name.clone())),
+        (None, Some(id)) => {
+            let session = &state.sessions[id];
+            let token = request
+                .headers
+                .get("X-CSRF-Token")
+                .and_then(|v| v.to_str().ok());
+            if request.method != Method::GET
+                && (token != Some(session.csrf_token.as_str())
+                    || cookie(request, CSRF_COOKIE).as_ref() != Some(&session.csrf_token))
+            {
+                return error_response(StatusCode::BAD_REQUEST, "CSRF validation failed");
+            }
+            Some(Some(session.user.clone()))
+        }
+        (None, None) => None,
+    };
+    match (&request.method, path.as_str()) {
+        (&Method::POST, "/api/login") => return login(state, request, user, session_id),
+        (&Method::POST, "/api/logout") => {
+            if let Some(id) = session_id {
+                state.sessions.remove(&id);
+            }
+            let mut resp = empty_response(StatusCode::NO_CONTENT);
+            for name in [SESSION_COOKIE, REMEMBER_COOKIE] {
+                let expired = format!("{}=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Path=/", name);
+                resp.headers_mut()
+                    .append(SET_COOKIE, HeaderValue::from_str(&expired).unwrap());
+            }
+            return resp;
+        }
+        _ => {}
+    }
+    let Some(user) = user else {
+        return error_response(StatusCode::FORBIDDEN, "Forbidden");
+    };
+
+    let push = &state.push;
+    let printer = &mut state.printer;
+    match (&request.method, path.as_str()) {
+        (&Method::GET, "/api/server") => json_response(
+            StatusCode::OK,
+            &ServerInfo {
+                version: MOCK_VERSION.to_string(),
+                safemode: None,
+            },
+        ),
+        (&Method::GET, "/api/settings") => {
+            json_response(StatusCode::OK, &json!({ "webcam": printer.webcam }))
+        }
+        (&Method::GET, "/api/timelapse") => {
+            let unrendered = request.path.contains("unrendered=true");
+            json_response(StatusCode::OK, &printer.timelapse_list(unrendered))
+        }
+        (&Method::POST, "/api/timelapse") => {
+            match serde_json::from_slice::<TimelapseConfig>(&request.body) {
+                Ok(config) => {
+                    printer.timelapse_config = config;
+                    json_response(StatusCode::OK, &printer.timelapse_list(false))
+                }
+                Err(_) => error_response(StatusCode::BAD_REQUEST, "Invalid configuration"),
+            }
+        }
+        (&Method::POST, p) if p.starts_with("/api/timelapse/unrendered/") => {
+            let name = decode_path(&p["/api/timelapse/unrendered/".len()..]);
+            if request.body_json()["command"] != "render" {
+                error_response(StatusCode::BAD_REQUEST, "Unknown command")
+            } else if printer.unrendered_timelapses.remove(&name) {
+                printer
+                    .timelapses
+                    .insert(format!("{}.mp4", name), b"mp4".to_vec());
+                empty_response(StatusCode::NO_CONTENT)
+            } else {
+                error_response(StatusCode::NOT_FOUND, "Unknown timelapse")
+            }
+        }
+        (&Method::DELETE, p) if p.starts_with("/api/timelapse/unrendered/") => {
+            let name = decode_path(&p["/api/timelapse/unrendered/".len()..]);
+            if printer.unrendered_timelapses.remove(&name) {
+                empty_response(StatusCode::NO_CONTENT)
+            } else {
+                error_response(StatusCode::NOT_FOUND, "Unknown timelapse")
+            }
+        }
+        (&Method::DELETE, p) if p.starts_with("/api/timelapse/") => {
+            let name = decode_path(&p["/api/timelapse/".len()..]);
+            if printer.timelapses.remove(&name).is_some() {
+                json_response(StatusCode::OK, &printer.timelapse_list(false))
+            } else {
+                error_response(StatusCode::NOT_FOUND, "Unknown timelapse")
+            }
+        }
+        (&Method::GET, p) if p.starts_with("/downloads/timelapse/") => {
+            match printer
+                .timelapses
+                .get(&decode_path(&p["/downloads/timelapse/".len()..]))
+            {
+                Some(content) => Response::new(Body::from(content.clone())),
+                None => error_response(StatusCode::NOT_FOUND, "File not found"),
+            }
+        }
+        (&Method::GET, "/api/currentuser") => {
+            let (name, groups, permissions) =
+                match user.and_then(|n| printer.users.get(&n).map(|u| (n, u))) {
+                    Some((name, u)) => (name, u.groups.clone(), u.permissions.clone()),
+                    None => ("_api".to_string(), vec!["admins".to_string()], Vec::new()),
+                };
+            let current = CurrentUser {
+                name: Some(name),
+                permissions: printer.user_permissions(&groups, &permissions),
+                groups,
+            };
+            json_response(StatusCode::OK, &current)
+        }
+        (method, p) if p.starts_with("/api/access/") => {
+            access(printer, method, &p["/api/access/".len()..], request)
+        }
+        (&Method::GET, "/api/slicing") => {
+            let slicers: BTreeMap<_, _> = printer
+                .slicers
+                .iter()
+                .map(|(key, slicer)| {
+                    let mut slicer = slicer.clone();
+                    slicer.profiles.values_mut().for_each(|p| p.data = None);
+                    (key.clone(), slicer)
+                })
+                .collect();
+            json_response(StatusCode::OK, &slicers)
+        }
+        (method, p) if p.starts_with("/api/slicing/") => {
+            slicing_profile(printer, method, &p["/api/slicing/".len()..], request)
+        }
+        (&Method::GET, "/api/job") => json_response(StatusCode::OK, &printer.job_information()),
+        (&Method::POST, "/api/job") => job_command(printer, &request.body_json()),
+        (&Method::GET, "/api/printer") => {
+            if printer.state.is_operational() {
+                json_response(StatusCode::OK, &printer.printer_info())
+            } else {
+                error_response(StatusCode::CONFLICT, "Printer is not operational")
+            }
+        }
+        (&Method::POST, "/api/printer/command") => {
+            let body = request.body_json();
+            let commands = match (body.get("command"), body.get("commands")) {
+                (Some(Value::String(c)), _) => vec![c.clone()],
+                (_, Some(Value::Array(cs))) => cs
+                    .iter()
+                    .filter_map(|c| c.as_str().map(|s| s.to_string()))
+                    .collect(),
+                _ => return error_response(StatusCode::BAD_REQUEST, "No command"),
+            };
+            if !printer.state.is_operational() {
+                return error_response(StatusCode::CONFLICT, "Printer is not operational");
+            }
+            commands.iter().for_each(|c| printer.gcode(c));
+            empty_response(StatusCode::NO_CONTENT)
+        }
+        (&Method::POST, "/api/printer/tool") => {
+            if !printer.state.is_operational() {
+                return error_response(StatusCode::CONFLICT, "Printer is not operational");
+            }
+            let body = request.body_json();
+            let Some(targets) = body.get("targets").and_then(|t| t.as_object()) else {
+                return error_response(StatusCode::BAD_REQUEST, "No targets");
+            };
+            for (name, target) in targets {
+                let tool = name
+                    .strip_prefix("tool")
+                    .and_then(|i| i.parse::<usize>().ok())
+                    .and_then(|i| printer.tools.get_mut(i));
+                match (tool, target.as_f64()) {
+                    (Some(tool), Some(target)) => tool.target = target as f32,
+                    _ => return error_response(StatusCode::BAD_REQUEST, "Invalid target"),
+                }
+            }
+            empty_response(StatusCode::NO_CONTENT)
+        }
+        (&Method::POST, "/api/printer/bed") => {
+            if !printer.state.is_operational() {
+                return error_response(StatusCode::CONFLICT, "Printer is not operational");
+            }
+            match request.body_json().get("target").and_then(|t| t.as_f64()) {
+                Some(target) => {
+                    printer.bed.target = target as f32;
+                    empty_response(StatusCode::NO_CONTENT)
+                }
+                None => error_response(StatusCode::BAD_REQUEST, "Invalid target"),
+            }
+        }
+        (&Method::GET, "/api/connection") => json_response(StatusCode::OK, &printer.connection()),
+        (&Method::POST, "/api/connection") => connection_command(printer, &request.body_json()),
+        (&Method::POST, "/api/files/local") => upload(printer, request),
+        (&Method::GET, "/api/files") | (&Method::GET, "/api/files/local") => {
+            let recursive = request.path.contains("recursive=true");
+            json_response(
+                StatusCode::OK,
+                &FileList {
+                    files: printer.file_entries("", recursive),
+                    free: Some(1 << 30),
+                    total: Some(1 << 32),
+                },
+            )
+        }
+        (&Method::GET, p) if p.starts_with("/api/files/local/") => {
+            match printer.file_entry(&decode_path(&p["/api/files/local/".len()..])) {
+                Some(entry) => json_response(StatusCode::OK, &entry),
+                None => error_response(StatusCode::NOT_FOUND, "File not found"),
+            }
+        }
+        (&Method::POST, p) if p.starts_with("/api/files/local/") => {
+            let path = decode_path(&p["/api/files/local/".len()..]);
+            let body = request.body_json();
+            if body.get("command").and_then(|c| c.as_str()) == Some("slice") {
+                return slice_command(printer, push, &path, &body);
+            }
+            if body.get("command").and_then(|c| c.as_str()) != Some("select") {
+                return error_response(StatusCode::BAD_REQUEST, "Unknown command");
+            }
+            if printer.state.is_busy() {
+                return error_response(StatusCode::CONFLICT, "Printer is already printing");
+            }
+            if !printer.select(&path) {
+                return error_response(StatusCode::NOT_FOUND, "File not found");
+            }
+            if body.get("print").and_then(|p| p.as_bool()) == Some(true) && !printer.start() {
+                return error_response(StatusCode::CONFLICT, "Printer is not operational");
+            }
+            empty_response(StatusCode::NO_CONTENT)
+        }
+        (&Method::DELETE, p) if p.starts_with("/api/files/local/") => {
+            let path = decode_path(&p["/api/files/local/".len()..]);
+            let busy = printer.state.is_busy()
+                && printer
+                    .job
+                    .as_ref()
+                    .is_some_and(|j| j.path == path || j.path.starts_with(&format!("{}/", path)));
+            if busy {
+                error_response(
+                    StatusCode::CONFLICT,
+                    "Trying to delete a file that is currently in use",
+                )
+            } else if printer.delete(&path) {
+                empty_response(StatusCode::NO_CONTENT)
+            } else {
+                error_response(StatusCode::NOT_FOUND, "File not found")
+            }
+        }
+        (&Method::GET, p) if p.starts_with("/downloads/files/local/") => {
+            match printer
+                .files
+                .get(&decode_path(&p["/downloads/files/local/".len()..]))
+            {
+                Some(file) => Response::new(Body::from(file.content.clone())),
+                None => error_response(StatusCode::NOT_FOUND, "File not found"),
+            }
+        }
+        (&Method::GET, "/api/printerprofiles") => {
+            let profiles = printer
+                .profiles
+                .iter()
+                .filter_map(|p| printer.profile(&p.id))
+                .map(|p| (p.id.clone(), p))
+                .collect();
+            json_response(StatusCode::OK, &ProfileList { profiles })
+        }
+        (&Method::GET, p) if p.starts_with("/api/printerprofiles/") => {
+            match printer.profile(&p["/api/printerprofiles/".len()..]) {
+                Some(profile) => json_response(StatusCode::OK, &profile),
+                None => error_response(StatusCode::NOT_FOUND, "Unknown profile"),
+            }
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "Not found"),
+    }
+}
+
+/// Mock of mjpg-streamer: `?action=snapshot` or `?action=stream`.
+fn webcam(printer: &VirtualPrinter, request: &RecordedRequest) -> Response<Body> {
+    let Some(snapshot) = printer.webcam_frames.first() else {
+        return empty_response(StatusCode::SERVICE_UNAVAILABLE);
+    };
+    if request.path.ends_with("action=snapshot") {
+        let mut resp = Response::new(Body::from(snapshot.clone()));
+        resp.headers_mut()
+            .insert("Content-Type", HeaderValue::from_static("image/jpeg"));
+        resp
+    } else if request.path.ends_with("action=stream") {
+        let mut body = Vec::new();
+        for frame in &printer.webcam_frames {
+            body.extend_from_slice(
+                format!(
+                    "--boundarydonotcross\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                    frame.len()
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(frame);
+            body.extend_from_slice(b"\r\n");
+        }
+        let mut resp = Response::new(Body::from(body));
+        resp.headers_mut().insert(
+            "Content-Type",
+            HeaderValue::from_static("multipart/x-mixed-replace;boundary=boundarydonotcross"),
+        );
+        resp
+    } else {
+        error_response(StatusCode::NOT_FOUND, "Not found")
+    }
+}
+
+/// Value of a cookie of the request.
+fn cookie(request: &RecordedRequest, name: &str) -> Option<String> {
+    let cookies = request.headers.get("Cookie")?.to_str().ok()?;
+    cookies
+        .split(';')
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(n, _)| *n == name)
+        .map(|(_, value)| value.to_string())
+}
+
+/// `/api/login`, with a user name and password or passive.
+fn login(
+    state: &mut MockState,
+    request: &RecordedRequest,
+    user: Option<Option<String>>,
+    session_id: Option<String>,
+) -> Response<Body> {
+    let body = request.body_json();
+    let (name, session_id) = if body["passive"] == true {
+        match user {
+            Some(name) => (name.unwrap_or("_api".to_string()), session_id),
+            None => return empty_response(StatusCode::NO_CONTENT),
+        }
+    } else {
+        let (Some(name), Some(password)) = (body["user"].as_str(), body["pass"].as_str()) else {
+            return error_response(StatusCode::BAD_REQUEST, "Missing user or password");
+        };
+        match state.printer.users.get(name) {
+            Some(u) if u.active && u.password == password => (name.to_string(), None),
+            _ => {
+                return error_response(StatusCode::FORBIDDEN, "User unknown or password incorrect")
+            }
+        }
+    };
+    let session_id = session_id.unwrap_or_else(|| {
+        state.sessions_created += 1;
+        let id = format!("SESSION{:04}", state.sessions_created);
+        let session = MockSession {
+            user: name.clone(),
+            csrf_token: format!("CSRF{:04}", state.sessions_created),
+        };
+        state.sessions.insert(id.clone(), session);
+        id
+    });
+
+    let user = match state.printer.user(&name) {
+        Some(user) => user,
+        None => User {
+            name: name.clone(),
+            active: true,
+            admin: true,
+            groups: vec!["admins".to_string()],
+            ..Default::default()
+        },
+    };
+    let mut resp = json_response(
+        StatusCode::OK,
+        &SessionUser {
+            user,
+            session: Some(session_id.clone()),
+        },
+    );
+    let mut cookies = vec![
+        format!("{}={}; Path=/; HttpOnly", SESSION_COOKIE, session_id),
+        format!(
+            "{}={}; Path=/",
+            CSRF_COOKIE, state.sessions[&session_id].csrf_token
+        ),
+    ];
+    if body["remember"] == true {
+        cookies.push(format!("{}={}; Path=/; HttpOnly", REMEMBER_COOKIE, name));
+    }
+    for cookie in cookies {
+        resp.headers_mut()
+            .append(SET_COOKIE, HeaderValue::from_str(&cookie).unwrap());
+    }
+    resp
+}
+
+/// Requests to `/api/access/{users,groups,permissions}`.
+fn access(
+    printer: &mut VirtualPrinter,
+    method: &Method,
+    path: &str,
+    request: &RecordedRequest,
+) -> Response<Body> {
+    let parts: Vec<String> = path.split('/').map(decode_path).collect();
+    let parts: Vec<&str> = parts.iter().map(|p| p.as_str()).collect();
+    let unknown_group = |printer: &VirtualPrinter, groups: &[String]| {
+        groups.iter().any(|g| !printer.groups.contains_key(g))
+    };
+    match (method, parts.as_slice()) {
+        (&Method::GET, ["permissions"]) => json_response(
+            StatusCode::OK,
+            &PermissionList {
+                permissions: mock_permissions(),
+            },
+        ),
+        (&Method::GET, ["users"]) => json_response(StatusCode::OK, &printer.user_list()),
+        (&Method::POST, ["users"]) => {
+            let Ok(new) = serde_json::from_slice::<NewUser>(&request.body) else {
+                return error_response(StatusCode::BAD_REQUEST, "Invalid user");
+            };
+            if printer.users.contains_key(&new.name) {
+                return error_response(StatusCode::CONFLICT, "User already exists");
+            }
+            let groups = new.groups.unwrap_or_else(|| {
+                printer
+                    .groups
+                    .values()
+                    .filter(|g| g.default)
+                    .map(|g| g.key.clone())
+                    .collect()
+            });
+            if unknown_group(printer, &groups) {
+                return error_response(StatusCode::BAD_REQUEST, "Unknown group");
+            }
+            printer.users.insert(
+                new.name,
+                StoredUser {
+                    password: new.password,
+                    active: new.active,
+                    groups,
+                    permissions: new.permissions,
+                    apikey: None,
+                },
+            );
+            json_response(StatusCode::OK, &printer.user_list())
+        }
+        (&Method::GET, ["users", name]) => match printer.user(name) {
+            Some(user) => json_response(StatusCode::OK, &user),
+            None => error_response(StatusCode::NOT_FOUND, "Unknown user"),
+        },
+        (&Method::PUT, ["users", name]) => {
+            let Ok(update) = serde_json::from_slice::<UserUpdate>(&request.body) else {
+                return error_response(StatusCode::BAD_REQUEST, "Invalid user");
+            };
+            if update
+                .groups
+                .as_ref()
+                .is_some_and(|g| unknown_group(printer, g))
+            {
+                return error_response(StatusCode::BAD_REQUEST, "Unknown group");
+            }
+            let Some(user) = printer.users.get_mut(*name) else {
+                return error_response(StatusCode::NOT_FOUND, "Unknown user");
+            };
+            if let Some(active) = update.active {
+                user.active = active;
+            }
+            if let Some(groups) = update.groups {
+                user.groups = groups;
+            }
+            if let Some(permissions) = update.permissions {
+                user.permissions = permissions;
+            }
+            json_response(StatusCode::OK, &printer.user_list())
+        }
+        (&Method::DELETE, ["users", name]) => match printer.users.remove(*name) {
+            Some(_) => json_response(StatusCode::OK, &printer.user_list()),
+            None => error_response(StatusCode::NOT_FOUND, "Unknown user"),
+        },
+        (&Method::PUT, ["users", name, "password"]) => {
+            let password = request.body_json()["password"]
+                .as_str()
+                .map(|p| p.to_string());
+            match (printer.users.get_mut(*name), password) {
+                (Some(user), Some(password)) => {
+                    user.password = password;
+                    json_response(StatusCode::OK, &json!({}))
+                }
+                (None, _) => error_response(StatusCode::NOT_FOUND, "Unknown user"),
+                (_, None) => error_response(StatusCode::BAD_REQUEST, "No password"),
+            }
+        }
+        (&Method::POST, ["users", name, "apikey"]) => {
+            printer.keys_generated += 1;
+            let key = format!("USERKEY{:025}", printer.keys_generated);
+            match printer.users.get_mut(*name) {
+                Some(user) => {
+                    user.apikey = Some(key.clone());
+                    json_response(StatusCode::OK, &json!({ "apikey": key }))
+                }
+                None => error_response(StatusCode::NOT_FOUND, "Unknown user"),
+            }
+        }
+        (&Method::DELETE, ["users", name, "apikey"]) => match printer.users.get_mut(*name) {
+            Some(user) => {
+                user.apikey = None;
+                json_response(StatusCode::OK, &json!({}))
+            }
+            None => error_response(StatusCode::NOT_FOUND, "Unknown user"),
+        },
+        (&Method::GET, ["groups"]) => json_response(StatusCode::OK, &printer.group_list()),
+        (&Method::POST, ["groups"]) => {
+            let Ok(group) = serde_json::from_slice::<Group>(&request.body) else {
+                return error_response(StatusCode::BAD_REQUEST, "Invalid group");
+            };
+            if printer.groups.contains_key(&group.key) {
+                return error_response(StatusCode::CONFLICT, "Group already exists");
+            }
+            let group = Group {
+                removable: true,
+                changeable: true,
+                ..group
+            };
+            printer.groups.insert(group.key.clone(), group);
+            json_response(StatusCode::OK, &printer.group_list())
+        }
+        (&Method::GET, ["groups", key]) => match printer.groups.get(*key) {
+            Some(group) => json_response(StatusCode::OK, group),
+            None => error_response(StatusCode::NOT_FOUND, "Unknown group"),
+        },
+        (&Method::PUT, ["groups", key]) => {
+            let Ok(update) = serde_json::from_slice::<Group>(&request.body) else {
+                return error_response(StatusCode::BAD_REQUEST, "Invalid group");
+            };
+            match printer.groups.get_mut(*key) {
+                Some(group) if group.changeable => {
+                    group.description = update.description;
+                    group.permissions = update.permissions;
+                    group.subgroups = update.subgroups;
+                    group.default = update.default;
+                    json_response(StatusCode::OK, &printer.group_list())
+                }
+                Some(_) => error_response(StatusCode::BAD_REQUEST, "Group can not be changed"),
+                None => error_response(StatusCode::NOT_FOUND, "Unknown group"),
+            }
+        }
+        (&Method::DELETE, ["groups", key]) => match printer.groups.get(*key) {
+            Some(group) if group.removable => {
+                printer.groups.remove(*key);
+                json_response(StatusCode::OK, &printer.group_list())
+            }
+            Some(_) => error_response(StatusCode::BAD_REQUEST, "Group can not be removed"),
+            None => error_response(StatusCode::NOT_FOUND, "Unknown group"),
+        },
+        _ => error_response(StatusCode::NOT_FOUND, "Not found"),
+    }
+}
+
+/// Requests to `/api/slicing/<slicer>/profiles[/<key>]`.
+fn slicing_profile(
+    printer: &mut VirtualPrinter,
+    method: &Method,
+    path: &str,
+    request: &RecordedRequest,
+) -> Response<Body> {
+    let parts: Vec<String> = path.split('/').map(decode_path).collect();
+    let Some(slicer) = printer.slicers.get_mut(&parts[0]) else {
+        return error_response(StatusCode::NOT_FOUND, "Unknown slicer");
+    };
+    let resource = |key: &str| Some(format!("/api/slicing/{}/profiles/{}", parts[0], key));
+    match (method, parts.get(1).map(|p| p.as_str()), parts.get(2)) {
+        (&Method::GET, Some("profiles"), None) => {
+            let profiles: BTreeMap<_, _> = slicer
+                .profiles
+                .iter()
+                .map(|(key, profile)| {
+                    let profile = SlicingProfile {
+                        data: None,
+                        resource: resource(key),
+                        ..profile.clone()
+                    };
+                    (key.clone(), profile)
+                })
+                .collect();
+            json_response(StatusCode::OK, &profiles)
+        }
+        (&Method::GET, Some("profiles"), Some(key)) => match slicer.profiles.get(key) {
+            Some(profile) => json_response(
+                StatusCode::OK,
+                &SlicingProfile {
+                    resource: resource(key),
+                    ..profile.clone()
+                },
+            ),
+            None => error_response(StatusCode::NOT_FOUND, "Unknown profile"),
+        },
+        (&Method::PUT, Some("profiles"), Some(key)) => {
+            match serde_json::from_slice::<SlicingProfile>(&request.body) {
+                Ok(profile) => {
+                    let profile = SlicingProfile {
+                        key: key.clone(),
+                        default: Some(profile.default.unwrap_or(false)),
+                        ..profile
+                    };
+                    slicer.profiles.insert(key.clone(), profile.clone());
+                    json_response(StatusCode::CREATED, &profile)
+                }
+                Err(_) => error_response(StatusCode::BAD_REQUEST, "Invalid profile"),
+            }
+        }
+        (&Method::PATCH, Some("profiles"), Some(key)) => {
+            let (Some(profile), Ok(update)) = (
+                slicer.profiles.get_mut(key),
+                serde_json::from_slice::<SlicingProfile>(&request.body),
+            ) else {
+                return error_response(StatusCode::NOT_FOUND, "Unknown profile");
+            };
+            if update.display_name.is_some() {
+                profile.display_name = update.display_name;
+            }
+            if update.description.is_some() {
+                profile.description = update.description;
+            }
+            if update.default.is_some() {
+                profile.default = update.default;
+            }
+            if let Some(data) = update.data {
+                profile
+                    .data
+                    .get_or_insert_with(Default::default)
+                    .extend(data);
+            }
+            json_response(StatusCode::OK, &profile.clone())
+        }
+        (&Method::DELETE, Some("profiles"), Some(key)) => match slicer.profiles.remove(key) {
+            Some(_) => empty_response(StatusCode::NO_CONTENT),
+            None => error_response(StatusCode::NOT_FOUND, "Unknown profile"),
+        },
+        _ => error_response(StatusCode::NOT_FOUND, "Not found"),
+    }
+}
+
+/// "slice" command of a model: slicing is instant, its progress and result are pushed.
+fn slice_command(
+    printer: &mut VirtualPrinter,
+    push: &broadcast::Sender<String>,
+    path: &str,
+    body: &Value,
+) -> Response<Body> {
+    if !printer.files.contains_key(path) {
+        return error_response(StatusCode::NOT_FOUND, "File not found");
+    }
+    if !path.ends_with(".stl") {
+        return error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Not a model");
+    }
+    let slicer_key = body["slicer"].as_str().unwrap_or("");
+    let Some(slicer) = printer.slicers.get(slicer_key).filter(|s| s.configured) else {
+        return error_response(StatusCode::BAD_REQUEST, "Unknown slicer");
+    };
+    let folder = path.rsplit_once('/').map(|(f, _)| format!("{}/", f));
+    let gcode = match body["gcode"].as_str() {
+        Some(name) if name.contains('/') => name.to_string(),
+        Some(name) => format!("{}{}", folder.unwrap_or_default(), name),
+        None => format!("{}.gcode", path.trim_end_matches(".stl")),
+    };
+    let profile = body["profile"]
+        .as_str()
+        .map(|p| p.to_string())
+        .or_else(|| {
+            slicer
+                .profiles
+                .values()
+                .find(|p| p.default == Some(true))
+                .map(|p| p.key.clone())
+        })
+        .unwrap_or_default();
+
+    let send = |message: Value| {
+        push.send(message.to_string()).ok();
+    };
+    let progress = |progress: f64| {
+        json!({"slicingProgress": {
+            "slicer": slicer_key,
+            "source_location": "local",
+            "source_path": path,
+            "dest_location": "local",
+            "dest_path": gcode,
+            "progress": progress,
+        }})
+    };
+    let mut payload = json!({
+        "stl": path,
+        "stl_location": "local",
+        "gcode": gcode,
+        "gcode_location": "local",
+    });
+    send(progress(0.0));
+    if !slicer.profiles.contains_key(&profile) {
+        payload["reason"] = format!("Profile {} doesn't exist", profile).into();
+        send(json!({"event": {"type": "SlicingFailed", "payload": payload}}));
+    } else {
+        send(progress(50.0));
+        send(progress(100.0));
+        printer.files.insert(
+            gcode.clone(),
+            StoredFile {
+                content: format!("; Sliced by {} with {}\nG28\n", slicer_key, profile).into_bytes(),
+                date: unix_now(),
+                userdata: None,
+            },
+        );
+        let select = body["select"].as_bool() == Some(true);
+        let print = body["print"].as_bool() == Some(true);
+        if select || print {
+            printer.select(&gcode);
+        }
+        if print {
+            printer.start();
+        }
+        payload["time"] = 0.1.into();
+        send(json!({"event": {"type": "SlicingDone", "payload": payload}}));
+    }
+    json_response(
+        StatusCode::ACCEPTED,
+        &json!({"origin": "local", "path": gcode, "name": gcode.rsplit('/').next()}),
+    )
+}
+
+fn connection_command(printer: &mut VirtualPrinter, body: &Value) -> Response<Body> {
+    let text = |key: &str| {
+        body.get(key)
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+    };
+    match body.get("command").and_then(|c| c.as_str()) {
+        Some("connect") => {
+            let port = text("port");
+            if let Some(port) = &port {
+                if !printer.ports.contains(port) && port != "AUTO" {
+                    return error_response(StatusCode::BAD_REQUEST, "Invalid port");
+                }
+            }
+            let baudrate = body
+                .get("baudrate")
+                .and_then(|v| v.as_u64())
+                .map(|b| b as u32);
+            if baudrate.is_some_and(|b| b != 0 && !printer.baudrates.contains(&b)) {
+                return error_response(StatusCode::BAD_REQUEST, "Invalid baudrate");
+            }
+            let profile = text("printerProfile");
+            if let Some(profile) = &profile {
+                if !printer.profiles.iter().any(|p| &p.id == profile) {
+                    return error_response(StatusCode::BAD_REQUEST, "Invalid printer profile");
+                }
+            }
+            if body["save"] == true {
+                printer.port_preference = port.clone().or(printer.port_preference.take());
+                printer.baudrate_preference = baudrate.or(printer.baudrate_preference);
+                printer.profile_preference = profile.clone().or(printer.profile_preference.take());
+            }
+            if let Some(autoconnect) = body["autoconnect"].as_bool() {
+                printer.autoconnect = autoconnect;
+            }
+            printer.connect(port, baudrate, profile);
+            empty_response(StatusCode::NO_CONTENT)
+        }
+        Some("disconnect") => {
+            printer.disconnect();
+            empty_response(StatusCode::NO_CONTENT)
+        }
+        Some("fake_ack") => {
+            printer.fake_acks += 1;
+            empty_response(StatusCode::NO_CONTENT)
+        }
+        _ => error_response(StatusCode::BAD_REQUEST, "Unknown command"),
+    }
+}
+
+fn job_command(printer: &mut VirtualPrinter, body: &Value) -> Response<Body> {
+    let done = match body.get("command").and_then(|c| c.as_str()) {
+        Some("start") => printer.start(),
+        Some("restart") => printer.state.is_paused() && printer.start(),
+        Some("cancel") => printer.cancel(),
+        Some("pause") => match body.get("action").and_then(|a| a.as_str()) {
+            Some("resume") => printer.resume(),
+            Some("toggle") => printer.pause() || printer.resume(),
+            _ => printer.pause(),
+        },
+        _ => return error_response(StatusCode::BAD_REQUEST, "Unknown command"),
+    };
+    if done {
+        empty_response(StatusCode::NO_CONTENT)
+    } else {
+        error_response(
+            StatusCode::CONFLICT,
+            "Printer is not in the right state for this command",
+        )
+    }
+}
+
+fn upload(printer: &mut VirtualPrinter, request: &RecordedRequest) -> Response<Body> {
+    let parts = parse_multipart(&request.headers, &request.body);
+    let field = |name: &str| {
+        parts
+            .iter()
+            .find(|p| p.name == name)
+            .map(|p| String::from_utf8_lossy(&p.data).to_string())
+    };
+
+    let folder = field("path").unwrap_or_default();
+    let folder = folder.trim_matches('/');
+
+    if let Some(name) = field("foldername") {
+        let path = if folder.is_empty() {
+            name.clone()
+        } else {
+            format!("{}/{}", folder, name)
+        };
+        if printer.files.contains_key(&path) || printer.is_folder(&path) {
+            return error_response(StatusCode::CONFLICT, "Folder already exists");
+        }
+        printer.folders.insert(path.clone());
+        return json_response(
+            StatusCode::CREATED,
+            &json!({
+                "done": true,
+                "folder": {"name": name, "origin": "local", "path": path}
+            }),
+        );
+    }
+
+    let file = match parts.iter().find(|p| p.name == "file") {
+        Some(file) => file,
+        None => return error_response(StatusCode::BAD_REQUEST, "No file included"),
+    };
+    let name = file
+        .filename
+        .as_deref()
+        .unwrap_or("")
+        .rsplit('/')
+        .next()
+        .unwrap_or("")
+        .to_string();
+    let path = if folder.is_empty() {
+        name.clone()
+    } else {
+        format!("{}/{}", folder, name)
+    };
+    printer.files.insert(
+        path.clone(),
+        StoredFile {
+            content: file.data.clone(),
+            date: unix_now(),
+            userdata: field("userdata").and_then(|u| serde_json::from_str(&u).ok()),
+        },
+    );
+
+    let select = field("select").as_deref() == Some("true");
+    let print = field("print").as_deref() == Some("true");
+    if select || print {
+        printer.select(&path);
+    }
+    if print {
+        printer.start();
+    }
+
+    json_response(
+        StatusCode::CREATED,
+        &json!({
+            "done": true,
+            "files": {
+                "local": {
+                    "name": name,
+                    "origin": "local",
+                    "path": path,
+                }
+            }
+        }),
+    )
+}
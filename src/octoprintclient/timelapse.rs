@@ -0,0 +1,204 @@
+//! Timelapses recorded by OctoPrint: rendered videos, unrendered frames and configuration.
+
+use std::io::Write;
+
+use hyper::body::HttpBody;
+use hyper::{Body, Client, Method, StatusCode};
+
+use super::datamodel::{TimelapseConfig, TimelapseConfigCommand, TimelapseList};
+use super::{encode_path, OctoPrintClient, OctoPrintClientError};
+
+impl OctoPrintClient {
+    /// Rendered timelapses and configuration, with the unrendered ones if `unrendered`.
+    pub async fn get_timelapses(
+        &self,
+        unrendered: bool,
+    ) -> Result<TimelapseList, OctoPrintClientError> {
+        self.get(&format!("timelapse?unrendered={}", unrendered))
+            .await
+    }
+
+    /// Current timelapse configuration.
+    pub async fn get_timelapse_config(&self) -> Result<TimelapseConfig, OctoPrintClientError> {
+        Ok(self.get_timelapses(false).await?.config)
+    }
+
+    /// Change the timelapse configuration, for the next prints too if `save`.
+    pub async fn set_timelapse_config(
+        &self,
+        config: &TimelapseConfig,
+        save: bool,
+    ) -> Result<(), OctoPrintClientError> {
+        let command = TimelapseConfigCommand {
+            config: config.clone(),
+            save,
+        };
+        self.send(
+            Method::POST,
+            "timelapse",
+            Some(serde_json::to_string(&command)?),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Delete a rendered timelapse.
+    pub async fn delete_timelapse(&self, name: &str) -> Result<(), OctoPrintClientError> {
+        self.send(
+            Method::DELETE,
+            &format!("timelapse/{}", encode_path(name)),
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Delete the frames of an unrendered timelapse.
+    pub async fn delete_unrendered_timelapse(
+        &self,
+        name: &str,
+    ) -> Result<(), OctoPrintClientError> {
+        self.send(
+            Method::DELETE,
+            &format!("timelapse/unrendered/{}", encode_path(name)),
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Start rendering an unrendered timelapse, the video appears in the list once done.
+    pub async fn render_timelapse(&self, name: &str) -> Result<(), OctoPrintClientError> {
+        self.post_command(
+            &format!("timelapse/unrendered/{}", encode_path(name)),
+            &serde_json::json!({"command": "render"}),
+        )
+        .await
+    }
+
+    /// Download a rendered timelapse to `out`, returns the number of bytes written.
+    ///
+    /// `progress` is called after each chunk with the bytes received so far and the total size
+    /// if known.
+    pub async fn download_timelapse<W, P>(
+        &self,
+        name: &str,
+        out: &mut W,
+        mut progress: P,
+    ) -> Result<u64, OctoPrintClientError>
+    where
+        W: Write,
+        P: FnMut(u64, Option<u64>),
+    {
+        let req = self
+            .request(
+                Method::GET,
+                format!(
+                    "{}/downloads/timelapse/{}",
+                    self.config.server_url,
+                    encode_path(name)
+                ),
+            )
+            .body(Body::empty())?;
+
+        let client = Client::new();
+        let mut resp = client.request(req).await?;
+        if resp.status() != StatusCode::OK {
+            return Err(OctoPrintClientError::ServerError(format!(
+                "Download of {} failed: {}",
+                name,
+                resp.status()
+            )));
+        }
+
+        let total = resp
+            .headers()
+            .get("Content-Length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok());
+        let mut received = 0;
+        while let Some(chunk) = resp.body_mut().data().await {
+            let chunk = chunk?;
+            out.write_all(&chunk)?;
+            received += chunk.len() as u64;
+            progress(received, total);
+        }
+        out.flush()?;
+        Ok(received)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::datamodel::TimelapseType;
+    use crate::octoprintclient::testing::MockServer;
+
+    #[tokio::test]
+    async fn test_timelapses() {
+        let server = MockServer::start();
+        server.with_printer_mut(|p| {
+            p.timelapses
+                .insert("benchy_20230115.mp4".to_string(), vec![7; 10000]);
+            p.unrendered_timelapses
+                .insert("whistle_20230116".to_string());
+        });
+        let c = server.client();
+
+        let list = c.get_timelapses(false).await.unwrap();
+        assert_eq!(list.files.len(), 1);
+        assert_eq!(list.files[0].bytes, 10000);
+        assert!(list.unrendered.is_empty());
+        let list = c.get_timelapses(true).await.unwrap();
+        assert_eq!(list.unrendered[0].name, "whistle_20230116");
+
+        let mut content = Vec::new();
+        let mut calls = Vec::new();
+        let size = c
+            .download_timelapse("benchy_20230115.mp4", &mut content, |r, t| {
+                calls.push((r, t))
+            })
+            .await
+            .unwrap();
+        assert_eq!(size, 10000);
+        assert_eq!(content, vec![7; 10000]);
+        assert_eq!(calls.last(), Some(&(10000, Some(10000))));
+        assert!(c
+            .download_timelapse("missing.mp4", &mut Vec::new(), |_, _| {})
+            .await
+            .is_err());
+
+        c.render_timelapse("whistle_20230116").await.unwrap();
+        let list = c.get_timelapses(true).await.unwrap();
+        assert!(list.unrendered.is_empty());
+        assert!(list.files.iter().any(|f| f.name == "whistle_20230116.mp4"));
+
+        c.delete_timelapse("benchy_20230115.mp4").await.unwrap();
+        assert!(matches!(
+            c.delete_timelapse("benchy_20230115.mp4").await,
+            Err(OctoPrintClientError::ServerError(_))
+        ));
+        server.with_printer_mut(|p| p.unrendered_timelapses.insert("a".to_string()));
+        c.delete_unrendered_timelapse("a").await.unwrap();
+        assert!(server.printer().unrendered_timelapses.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_timelapse_config() {
+        let server = MockServer::start();
+        let c = server.client();
+
+        let mut config = c.get_timelapse_config().await.unwrap();
+        assert_eq!(config.timelapse_type, TimelapseType::Off);
+        config.timelapse_type = TimelapseType::Timed;
+        config.interval = Some(10);
+        config.fps = 30;
+        c.set_timelapse_config(&config, true).await.unwrap();
+
+        let request = &server.requests_to(Method::POST, "/api/timelapse")[0];
+        assert_eq!(request.body_json()["type"], "timed");
+        assert_eq!(request.body_json()["postRoll"], 0);
+        assert_eq!(request.body_json()["save"], true);
+        assert_eq!(c.get_timelapse_config().await.unwrap(), config);
+    }
+}
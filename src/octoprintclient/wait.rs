@@ -0,0 +1,167 @@
+//! Waiting for a condition on the printer, by polling the server.
+
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::datamodel::{JobInformation, PrinterConnection, PrinterInfo, State};
+use super::events::{EventTracker, PrinterEvent};
+use super::{OctoPrintClient, OctoPrintClientError};
+
+/// Timing parameters for the `wait_for_*` methods.
+#[derive(Clone, Debug)]
+pub struct WaitOptions {
+    /// Give up after this delay, `None` waits forever.
+    pub timeout: Option<Duration>,
+    /// Delay between two polls of the server.
+    pub poll_interval: Duration,
+}
+
+impl Default for WaitOptions {
+    fn default() -> Self {
+        WaitOptions {
+            timeout: None,
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl WaitOptions {
+    pub fn with_timeout(timeout: Duration) -> Self {
+        WaitOptions {
+            timeout: Some(timeout),
+            ..Default::default()
+        }
+    }
+}
+
+/// Poll `fetch` until `condition` holds on the returned value.
+///
+/// A conflict (the 409 returned by `/api/printer` while the printer is not connected) is
+/// treated as "condition not met yet". The other errors are returned. A request still running
+/// at the deadline is abandoned.
+async fn poll_until<T, F, Fut, P>(
+    mut fetch: F,
+    mut condition: P,
+    options: &WaitOptions,
+) -> Result<T, OctoPrintClientError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, OctoPrintClientError>>,
+    P: FnMut(&T) -> bool,
+{
+    let deadline = options.timeout.map(|t| Instant::now() + t);
+
+    let timeout_error = || OctoPrintClientError::TimeoutError(options.timeout.unwrap_or_default());
+
+    loop {
+        let result = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, fetch())
+                .await
+                .map_err(|_| timeout_error())?,
+            None => fetch().await,
+        };
+        match result {
+            Ok(value) if condition(&value) => return Ok(value),
+            Ok(_) | Err(OctoPrintClientError::ConflictError(_)) => {}
+            Err(e) => return Err(e),
+        }
+
+        let mut next_poll = Instant::now() + options.poll_interval;
+        if let Some(deadline) = deadline {
+            if Instant::now() >= deadline {
+                return Err(timeout_error());
+            }
+            next_poll = next_poll.min(deadline);
+        }
+        tokio::time::sleep_until(next_poll).await;
+    }
+}
+
+impl OctoPrintClient {
+    /// Wait until `condition` holds on the printer state (`/api/printer`).
+    pub async fn wait_for_printer<P>(
+        &self,
+        condition: P,
+        options: &WaitOptions,
+    ) -> Result<PrinterInfo, OctoPrintClientError>
+    where
+        P: FnMut(&PrinterInfo) -> bool,
+    {
+        poll_until(|| self.get_printer_state(), condition, options).await
+    }
+
+    /// Wait until `condition` holds on the current job (`/api/job`).
+    pub async fn wait_for_job<P>(
+        &self,
+        condition: P,
+        options: &WaitOptions,
+    ) -> Result<JobInformation, OctoPrintClientError>
+    where
+        P: FnMut(&JobInformation) -> bool,
+    {
+        poll_until(|| self.get_current_job(), condition, options).await
+    }
+
+    /// Wait until `condition` holds on the printer connection (`/api/connection`).
+    pub async fn wait_for_connection<P>(
+        &self,
+        condition: P,
+        options: &WaitOptions,
+    ) -> Result<PrinterConnection, OctoPrintClientError>
+    where
+        P: FnMut(&PrinterConnection) -> bool,
+    {
+        poll_until(|| self.get_connection(), condition, options).await
+    }
+
+    /// Wait until the serial connection reports the given state.
+    pub async fn wait_for_connection_state(
+        &self,
+        state: &State,
+        options: &WaitOptions,
+    ) -> Result<PrinterConnection, OctoPrintClientError> {
+        self.wait_for_connection(|c| c.current.state == *state, options)
+            .await
+    }
+
+    /// Wait until no job is running anymore.
+    pub async fn wait_for_job_done(
+        &self,
+        options: &WaitOptions,
+    ) -> Result<JobInformation, OctoPrintClientError> {
+        self.wait_for_job(|job| !job.state.is_busy(), options).await
+    }
+
+    /// Wait until the current job ends, returns how it ended: `PrintDone`, `PrintCancelled` or
+    /// `PrintFailed` (see `events::state_events`).
+    ///
+    /// Without a running job, returns `None` at once, or with `wait_start` waits for a job to
+    /// start first.
+    pub async fn wait_for_job_end(
+        &self,
+        wait_start: bool,
+        options: &WaitOptions,
+    ) -> Result<(JobInformation, Option<PrinterEvent>), OctoPrintClientError> {
+        let mut tracker = EventTracker::default();
+        let mut end = None;
+        let job = self
+            .wait_for_job(
+                |job| {
+                    end = tracker.update(job).into_iter().find(|e| {
+                        matches!(
+                            e,
+                            PrinterEvent::PrintDone
+                                | PrinterEvent::PrintCancelled
+                                | PrinterEvent::PrintFailed
+                        )
+                    });
+                    end.is_some() || !(wait_start || job.state.is_busy())
+                },
+                options,
+            )
+            .await?;
+        Ok((job, end))
+    }
+}
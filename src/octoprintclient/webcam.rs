@@ -0,0 +1,556 @@
+//! Webcam configured in OctoPrint: JPEG snapshots and MJPEG stream.
+//!
+//! The URLs come from the webcam settings of the server. Relative URLs ("/webcam/?action=stream")
+//! are resolved against the server, and loopback URLs ("http://127.0.0.1:8080/...") are
+//! rewritten to the host of the server, as they are only meaningful on the OctoPrint host. The
+//! API key and the session cookies are only sent to the OctoPrint server itself.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use hyper::body::HttpBody;
+use hyper::{Body, Method, Request, StatusCode, Uri};
+use image::codecs::jpeg::JpegEncoder;
+use image::DynamicImage;
+
+use super::datamodel::WebcamSettings;
+use super::{https_client, OctoPrintClient, OctoPrintClientError};
+
+/// Quality of the JPEG images re-encoded after a change of orientation.
+const JPEG_QUALITY: u8 = 90;
+
+/// Maximum size of a frame of the MJPEG stream.
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Orientation of the webcam images, from the webcam settings.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Orientation {
+    pub flip_h: bool,
+    pub flip_v: bool,
+    /// Rotate by 90° counter-clockwise, after the flips.
+    pub rotate_90: bool,
+}
+
+impl From<&WebcamSettings> for Orientation {
+    fn from(settings: &WebcamSettings) -> Self {
+        Orientation {
+            flip_h: settings.flip_h,
+            flip_v: settings.flip_v,
+            rotate_90: settings.rotate_90,
+        }
+    }
+}
+
+impl Orientation {
+    pub fn is_identity(&self) -> bool {
+        !self.flip_h && !self.flip_v && !self.rotate_90
+    }
+
+    pub fn apply_image(&self, mut image: DynamicImage) -> DynamicImage {
+        if self.flip_h {
+            image = image.fliph();
+        }
+        if self.flip_v {
+            image = image.flipv();
+        }
+        if self.rotate_90 {
+            image = image.rotate270();
+        }
+        image
+    }
+
+    /// Apply the orientation to a JPEG image, which is returned unchanged if there is nothing to do.
+    pub fn apply(&self, jpeg: Vec<u8>) -> Result<Vec<u8>, OctoPrintClientError> {
+        if self.is_identity() {
+            return Ok(jpeg);
+        }
+        let image = image::load_from_memory_with_format(&jpeg, image::ImageFormat::Jpeg)?;
+        let image = DynamicImage::ImageRgb8(self.apply_image(image).to_rgb8());
+        let mut out = Vec::new();
+        image.write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY))?;
+        Ok(out)
+    }
+}
+
+/// Scheme and authority of an URL, e.g. "http://octopi.local:5000".
+fn origin(uri: &Uri) -> String {
+    format!(
+        "{}://{}",
+        uri.scheme_str().unwrap_or("http"),
+        uri.authority().map(|a| a.as_str()).unwrap_or("")
+    )
+}
+
+/// Resolve a webcam URL of the settings against the URL of the server.
+pub fn resolve_url(server_url: &str, url: &str) -> String {
+    let Ok(server) = server_url.parse::<Uri>() else {
+        return url.to_string();
+    };
+    let origin = origin(&server);
+    if url.starts_with('/') && !url.starts_with("//") {
+        return format!("{}{}", origin, url);
+    }
+    let Ok(uri) = url.parse::<Uri>() else {
+        return url.to_string();
+    };
+    let is_loopback = |host: Option<&str>| {
+        matches!(
+            host.map(|h| h.trim_matches(['[', ']'])),
+            Some("127.0.0.1" | "localhost" | "::1")
+        )
+    };
+    match (uri.host(), server.host()) {
+        (Some(_), Some(server_host))
+            if is_loopback(uri.host()) && !is_loopback(Some(server_host)) =>
+        {
+            let authority = match uri.port_u16() {
+                Some(port) => format!("{}:{}", server_host, port),
+                None => server_host.to_string(),
+            };
+            format!(
+                "{}://{}{}",
+                uri.scheme_str().unwrap_or("http"),
+                authority,
+                uri.path_and_query().map(|p| p.as_str()).unwrap_or("/")
+            )
+        }
+        _ => url.to_string(),
+    }
+}
+
+/// The webcam of a server, see `OctoPrintClient::get_webcam()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Webcam {
+    /// Resolved snapshot URL, `None` if not configured.
+    pub snapshot_url: Option<String>,
+    /// Resolved stream URL, `None` if not configured.
+    pub stream_url: Option<String>,
+    pub orientation: Orientation,
+    /// Origin of the server, which gets the API key and the session cookies.
+    server_origin: String,
+    api_key: String,
+    /// `Cookie` header of the session, if logged in.
+    cookie: Option<String>,
+}
+
+impl Webcam {
+    pub fn new(server_url: &str, api_key: &str, settings: &WebcamSettings) -> Self {
+        let resolve = |url: &Option<String>| {
+            url.as_deref()
+                .filter(|u| !u.is_empty())
+                .map(|u| resolve_url(server_url, u))
+        };
+        Webcam {
+            snapshot_url: resolve(&settings.snapshot_url),
+            stream_url: resolve(&settings.stream_url),
+            orientation: settings.into(),
+            server_origin: server_url
+                .parse::<Uri>()
+                .map(|u| origin(&u))
+                .unwrap_or_default(),
+            api_key: api_key.to_string(),
+            cookie: None,
+        }
+    }
+
+    async fn fetch(&self, url: &str) -> Result<hyper::Response<Body>, OctoPrintClientError> {
+        let mut req = Request::builder().method(Method::GET).uri(url);
+        let same_origin = url
+            .parse::<Uri>()
+            .is_ok_and(|u| origin(&u) == self.server_origin);
+        if same_origin {
+            if !self.api_key.is_empty() {
+                req = req.header("X-Api-Key", &self.api_key);
+            }
+            if let Some(cookie) = &self.cookie {
+                req = req.header("Cookie", cookie);
+            }
+        }
+        let resp = https_client().request(req.body(Body::empty())?).await?;
+        if resp.status() != StatusCode::OK {
+            return Err(OctoPrintClientError::ServerError(format!(
+                "Webcam request to {} failed: {}",
+                url,
+                resp.status()
+            )));
+        }
+        Ok(resp)
+    }
+
+    /// Take a JPEG snapshot, with the orientation applied if `orient`.
+    pub async fn snapshot(&self, orient: bool) -> Result<Vec<u8>, OctoPrintClientError> {
+        let url = self.snapshot_url.as_ref().ok_or_else(|| {
+            OctoPrintClientError::WebcamError("No webcam snapshot URL configured".to_string())
+        })?;
+        let resp = self.fetch(url).await?;
+        let jpeg = hyper::body::to_bytes(resp.into_body()).await?.to_vec();
+        if orient {
+            self.orientation.apply(jpeg)
+        } else {
+            Ok(jpeg)
+        }
+    }
+
+    /// Open the MJPEG stream.
+    pub async fn stream(&self) -> Result<MjpegStream, OctoPrintClientError> {
+        let url = self.stream_url.as_ref().ok_or_else(|| {
+            OctoPrintClientError::WebcamError("No webcam stream URL configured".to_string())
+        })?;
+        let resp = self.fetch(url).await?;
+        let content_type = resp
+            .headers()
+            .get("Content-Type")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+        MjpegStream::new(resp.into_body(), &content_type)
+    }
+
+    /// Save frames of the stream as `frame-00001.jpg`, `frame-00002.jpg`, ... in `dir`.
+    ///
+    /// Stops after `options.frames` frames, at the end of the stream or when `shutdown`
+    /// completes, and returns the number of frames saved. `log` is called with each new file.
+    pub async fn record<L, S>(
+        &self,
+        dir: &Path,
+        options: &RecordOptions,
+        mut log: L,
+        shutdown: S,
+    ) -> Result<usize, OctoPrintClientError>
+    where
+        L: FnMut(&Path),
+        S: Future<Output = ()>,
+    {
+        std::fs::create_dir_all(dir)?;
+        let mut stream = self.stream().await?;
+        tokio::pin!(shutdown);
+        let mut saved = 0;
+        let mut last: Option<Instant> = None;
+        while options.frames.is_none_or(|n| saved < n) {
+            let frame = tokio::select! {
+                _ = &mut shutdown => break,
+                frame = stream.next_frame() => frame?,
+            };
+            let Some(frame) = frame else {
+                break;
+            };
+            if last.is_some_and(|l| l.elapsed() < options.interval) {
+                continue;
+            }
+            last = Some(Instant::now());
+            let frame = if options.orient {
+                self.orientation.apply(frame)?
+            } else {
+                frame
+            };
+            saved += 1;
+            let path: PathBuf = dir.join(format!("frame-{:05}.jpg", saved));
+            std::fs::write(&path, frame)?;
+            log(&path);
+        }
+        Ok(saved)
+    }
+}
+
+/// Options of `Webcam::record()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordOptions {
+    /// Number of frames to save, until the stream ends if `None`.
+    pub frames: Option<usize>,
+    /// Minimum time between two saved frames, every frame is saved if zero.
+    pub interval: Duration,
+    /// Apply the orientation of the settings.
+    pub orient: bool,
+}
+
+impl Default for RecordOptions {
+    fn default() -> Self {
+        RecordOptions {
+            frames: None,
+            interval: Duration::ZERO,
+            orient: true,
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn frame_too_large() -> OctoPrintClientError {
+    OctoPrintClientError::WebcamError("MJPEG frame too large".to_string())
+}
+
+/// Reader of the JPEG frames of a "multipart/x-mixed-replace" MJPEG stream.
+pub struct MjpegStream {
+    body: Body,
+    /// "--" followed by the boundary.
+    boundary: Vec<u8>,
+    buffer: Vec<u8>,
+    ended: bool,
+}
+
+impl MjpegStream {
+    /// Read frames from `body`, `content_type` gives the boundary between the frames.
+    pub fn new(body: Body, content_type: &str) -> Result<Self, OctoPrintClientError> {
+        let boundary = content_type
+            .split(';')
+            .filter_map(|p| p.trim().strip_prefix("boundary="))
+            .next()
+            .map(|b| b.trim_matches('"').trim_start_matches("--"))
+            .filter(|b| !b.is_empty())
+            .ok_or_else(|| {
+                OctoPrintClientError::WebcamError(format!(
+                    "Not an MJPEG stream: \"{}\"",
+                    content_type
+                ))
+            })?;
+        Ok(MjpegStream {
+            body,
+            boundary: format!("--{}", boundary).into_bytes(),
+            buffer: Vec::new(),
+            ended: false,
+        })
+    }
+
+    /// A frame from the buffer, if it is complete.
+    fn parse_frame(&mut self) -> Result<Option<Vec<u8>>, OctoPrintClientError> {
+        let Some(start) = find(&self.buffer, &self.boundary) else {
+            return Ok(None);
+        };
+        let start = start + self.boundary.len();
+        let Some(header_len) = find(&self.buffer[start..], b"\r\n\r\n") else {
+            return Ok(None);
+        };
+        let headers = String::from_utf8_lossy(&self.buffer[start..start + header_len]);
+        let length = headers.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                value.trim().parse::<usize>().ok()
+            } else {
+                None
+            }
+        });
+        if length.is_some_and(|length| length > MAX_FRAME_SIZE) {
+            return Err(frame_too_large());
+        }
+        let data_start = start + header_len + 4;
+        let (data_end, consumed) = match length {
+            Some(length) if self.buffer.len() >= data_start + length => {
+                (data_start + length, data_start + length)
+            }
+            Some(_) => return Ok(None),
+            None => {
+                let Some(next) = find(&self.buffer[data_start..], &self.boundary) else {
+                    return Ok(None);
+                };
+                let next = data_start + next;
+                let mut end = next;
+                if self.buffer[data_start..end].ends_with(b"\r\n") {
+                    end -= 2;
+                }
+                (end, next)
+            }
+        };
+        let frame = self.buffer[data_start..data_end].to_vec();
+        self.buffer.drain(..consumed);
+        Ok(Some(frame))
+    }
+
+    /// Next JPEG frame, `None` at the end of the stream.
+    pub async fn next_frame(&mut self) -> Result<Option<Vec<u8>>, OctoPrintClientError> {
+        loop {
+            if let Some(frame) = self.parse_frame()? {
+                return Ok(Some(frame));
+            }
+            if self.ended {
+                return Ok(None);
+            }
+            match self.body.data().await {
+                Some(chunk) => {
+                    self.buffer.extend_from_slice(&chunk?);
+                    if self.buffer.len() > MAX_FRAME_SIZE {
+                        return Err(frame_too_large());
+                    }
+                }
+                None => {
+                    // The last part is complete if the stream ends with a boundary
+                    self.ended = true;
+                    self.buffer.extend_from_slice(&self.boundary);
+                }
+            }
+        }
+    }
+}
+
+impl OctoPrintClient {
+    /// The webcam configured in the settings of the server.
+    pub async fn get_webcam(&self) -> Result<Webcam, OctoPrintClientError> {
+        let settings = self.get_settings().await?;
+        let mut webcam = Webcam::new(
+            &self.config.server_url,
+            &self.config.api_key,
+            &settings.webcam,
+        );
+        webcam.cookie = self.cookies.lock().unwrap().header();
+        Ok(webcam)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octoprintclient::testing::{test_jpeg, MockServer};
+    use image::GenericImageView;
+
+    #[test]
+    fn test_resolve_url() {
+        let server = "http://octopi.local:5000/octoprint";
+        assert_eq!(
+            resolve_url(server, "/webcam/?action=stream"),
+            "http://octopi.local:5000/webcam/?action=stream"
+        );
+        assert_eq!(
+            resolve_url(server, "http://127.0.0.1:8080/?action=snapshot"),
+            "http://octopi.local:8080/?action=snapshot"
+        );
+        assert_eq!(
+            resolve_url(server, "http://camera.local/snap.jpg"),
+            "http://camera.local/snap.jpg"
+        );
+        assert_eq!(
+            resolve_url(
+                "http://127.0.0.1:5000",
+                "http://localhost:8080/?action=snapshot"
+            ),
+            "http://localhost:8080/?action=snapshot"
+        );
+    }
+
+    /// Red on the left half, blue on the right half.
+    fn is_red(image: &DynamicImage, x: u32, y: u32) -> bool {
+        let p = image.get_pixel(x, y);
+        p[0] > 200 && p[2] < 50
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let server = MockServer::start();
+        server.with_printer_mut(|p| {
+            p.webcam_frames = vec![test_jpeg(16, 8)];
+            p.webcam.flip_h = true;
+        });
+        let webcam = server.client().get_webcam().await.unwrap();
+        assert_eq!(
+            webcam.snapshot_url,
+            Some(format!("{}/webcam/?action=snapshot", server.url()))
+        );
+
+        let raw = webcam.snapshot(false).await.unwrap();
+        assert_eq!(raw, test_jpeg(16, 8));
+        let image = image::load_from_memory(&webcam.snapshot(true).await.unwrap()).unwrap();
+        assert_eq!(image.dimensions(), (16, 8));
+        assert!(!is_red(&image, 2, 4));
+        assert!(is_red(&image, 13, 4));
+
+        // Left half goes to the bottom
+        let orientation = Orientation {
+            rotate_90: true,
+            ..Default::default()
+        };
+        let image = image::load_from_memory(&orientation.apply(raw).unwrap()).unwrap();
+        assert_eq!(image.dimensions(), (8, 16));
+        assert!(is_red(&image, 4, 13));
+        assert!(!is_red(&image, 4, 2));
+
+        // With a session instead of the API key
+        let c = OctoPrintClient::from_config(crate::octoprintclient::Configuration {
+            server_url: server.url(),
+            ..Default::default()
+        });
+        c.login("admin", "admin", false).await.unwrap();
+        c.get_webcam().await.unwrap().snapshot(false).await.unwrap();
+        let request = server.requests_to(Method::GET, "/webcam/").pop().unwrap();
+        assert!(request.headers.get("X-Api-Key").is_none());
+        assert!(request.headers.get("Cookie").is_some());
+
+        server.with_printer_mut(|p| p.webcam.snapshot_url = None);
+        assert!(matches!(
+            server
+                .client()
+                .get_webcam()
+                .await
+                .unwrap()
+                .snapshot(true)
+                .await,
+            Err(OctoPrintClientError::WebcamError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        // Parts without Content-Length are delimited by the next boundary
+        let mut body = Vec::new();
+        for frame in [&b"first"[..], b"second\r\n--not a boundary", b"third"] {
+            body.extend_from_slice(b"--frame\r\nContent-Type: image/jpeg\r\n\r\n");
+            body.extend_from_slice(frame);
+            body.extend_from_slice(b"\r\n");
+        }
+        let mut stream =
+            MjpegStream::new(Body::from(body), "multipart/x-mixed-replace;boundary=frame").unwrap();
+        assert_eq!(stream.next_frame().await.unwrap().unwrap(), b"first");
+        assert_eq!(
+            stream.next_frame().await.unwrap().unwrap(),
+            b"second\r\n--not a boundary"
+        );
+        assert_eq!(stream.next_frame().await.unwrap().unwrap(), b"third");
+        assert_eq!(stream.next_frame().await.unwrap(), None);
+        assert!(matches!(
+            MjpegStream::new(Body::empty(), "image/jpeg"),
+            Err(OctoPrintClientError::WebcamError(_))
+        ));
+
+        // Content-Length too large for a frame
+        let body = format!("--frame\r\nContent-Length: {}\r\n\r\nabc", usize::MAX - 2);
+        let mut stream =
+            MjpegStream::new(Body::from(body), "multipart/x-mixed-replace;boundary=frame").unwrap();
+        assert!(matches!(
+            stream.next_frame().await,
+            Err(OctoPrintClientError::WebcamError(_))
+        ));
+
+        // Mock stream, with Content-Length
+        let server = MockServer::start();
+        let frames: Vec<Vec<u8>> = (1..=5).map(|i| test_jpeg(8 * i, 8)).collect();
+        server.with_printer_mut(|p| p.webcam_frames = frames.clone());
+        let webcam = server.client().get_webcam().await.unwrap();
+        let mut stream = webcam.stream().await.unwrap();
+        for frame in &frames {
+            assert_eq!(&stream.next_frame().await.unwrap().unwrap(), frame);
+        }
+        assert_eq!(stream.next_frame().await.unwrap(), None);
+
+        let dir = std::env::temp_dir().join("octoprint-client-test-record");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut logged = Vec::new();
+        let options = RecordOptions {
+            frames: Some(3),
+            ..Default::default()
+        };
+        let saved = webcam
+            .record(&dir, &options, |p| logged.push(p.to_path_buf()), async {
+                std::future::pending::<()>().await
+            })
+            .await
+            .unwrap();
+        assert_eq!(saved, 3);
+        assert_eq!(logged.len(), 3);
+        assert_eq!(
+            std::fs::read(dir.join("frame-00002.jpg")).unwrap(),
+            frames[1]
+        );
+        assert!(!dir.join("frame-00004.jpg").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}